@@ -0,0 +1,146 @@
+//! src/arch.rs
+//! Architecture names: the canonical spelling of each supported architecture and its aliases.
+
+use std::sync::OnceLock;
+
+/// Supported architectures, canonical name first, followed by the aliases meaning the same.
+/// Big- and little-endian ppc64 are different ABIs and deliberately separate entries.
+const ALIASES: &[&[&str]] = &[
+    &["x86_64", "amd64", "x64"],
+    &["aarch64", "arm64"],
+    &["arm", "armv7", "armhf", "armv7l"],
+    &["i686", "x86", "i386"],
+    &["ppc64", "powerpc64"],
+    &["ppc64le", "powerpc64le"],
+    &["any", "noarch"],
+];
+
+/// Normalize an architecture token so aliases compare equal (`amd64` == `x86_64`, `noarch` == `any`).
+/// Case, surrounding whitespace and `-` for `_` (`x86-64`) do not matter; unknown tokens are
+/// returned normalized but otherwise unchanged.
+pub fn canonical_arch(token: &str) -> String {
+    let token = token.trim().to_ascii_lowercase().replace('-', "_");
+    match ALIASES.iter().find(|names| names.contains(&token.as_str())) {
+        Some(names) => names[0].to_string(),
+        None => token,
+    }
+}
+
+/// Whether `token` is one of the architectures (or aliases) in the table above.
+pub fn is_known_arch(token: &str) -> bool {
+    let token = canonical_arch(token);
+    ALIASES.iter().any(|names| names[0] == token)
+}
+
+/// The host's architecture in canonical form. `std::env::consts::ARCH` says `powerpc64` for
+/// both ppc64 flavours, so the endianness decides.
+pub fn host_arch() -> &'static str {
+    let arch = std::env::consts::ARCH;
+    if arch == "powerpc64" && cfg!(target_endian = "little") {
+        return "ppc64le";
+    }
+    ALIASES.iter().find(|names| names.contains(&arch)).map_or(arch, |names| names[0])
+}
+
+/// The architecture `current_arch()` reports, fixed on first use.
+static CURRENT_ARCH: OnceLock<String> = OnceLock::new();
+
+/// Make `current_arch()` report `arch` (canonicalized) for the rest of the process, as the
+/// global `--assume-arch` flag does. Must run before anything asks for the arch; later calls
+/// are ignored.
+pub fn assume_arch(arch: &str) {
+    let _ = CURRENT_ARCH.set(canonical_arch(arch));
+}
+
+/// The architecture nxpkg resolves, validates and installs packages for: the one given to
+/// `assume_arch`, else `NXPKG_ARCH`, else `host_arch()`. The override is for testing the arch
+/// logic and for staging another architecture's packages; it does not change what this
+/// machine can run or what source builds produce.
+pub fn current_arch() -> &'static str {
+    CURRENT_ARCH.get_or_init(|| match std::env::var("NXPKG_ARCH") {
+        Ok(arch) if !arch.trim().is_empty() => canonical_arch(&arch),
+        _ => host_arch().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_map_to_their_canonical_arch() {
+        let table = [
+            ("x86_64", &["x86_64", "amd64", "x64", "X86-64", " AMD64 "][..]),
+            ("aarch64", &["aarch64", "arm64", "ARM64"]),
+            ("arm", &["arm", "armv7", "armhf", "armv7l"]),
+            ("i686", &["i686", "x86", "i386"]),
+            ("ppc64", &["ppc64", "powerpc64"]),
+            ("ppc64le", &["ppc64le", "powerpc64le"]),
+            ("any", &["any", "noarch", "NoArch"]),
+        ];
+        assert_eq!(table.len(), ALIASES.len());
+        for (canonical, aliases) in table {
+            for alias in aliases {
+                assert_eq!(canonical_arch(alias), canonical, "{:?}", alias);
+                assert!(is_known_arch(alias), "{:?}", alias);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_arch_is_normalized_but_not_known() {
+        assert_eq!(canonical_arch(" RISCV-64 "), "riscv_64");
+        assert!(!is_known_arch("riscv64"));
+        assert_ne!(canonical_arch("ppc64"), canonical_arch("ppc64le"));
+    }
+
+    #[test]
+    fn host_arch_is_canonical() {
+        assert_eq!(canonical_arch(host_arch()), host_arch());
+    }
+
+    /// An architecture other than the host's, as `(alias, canonical)`.
+    fn foreign_arch() -> (&'static str, &'static str) {
+        if host_arch() == "aarch64" { ("amd64", "x86_64") } else { ("arm64", "aarch64") }
+    }
+
+    /// `current_arch` is fixed for the whole process on first use, so the `--assume-arch` path
+    /// runs in a child process of its own (`assumed_arch_child`).
+    #[test]
+    fn assumed_arch_selects_foreign_assets() {
+        let child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "arch::tests::assumed_arch_child", "--ignored"])
+            .env("NXPKG_ASSUME_ARCH_CHILD", "1")
+            .env_remove("NXPKG_ARCH")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&child.stdout);
+        assert!(child.status.success(), "{}{}", stdout, String::from_utf8_lossy(&child.stderr));
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+    }
+
+    #[test]
+    #[ignore = "run by assumed_arch_selects_foreign_assets in a fresh process"]
+    fn assumed_arch_child() {
+        if std::env::var_os("NXPKG_ASSUME_ARCH_CHILD").is_none() {
+            return;
+        }
+        let (alias, foreign) = foreign_arch();
+        assume_arch(alias);
+        assume_arch(host_arch());
+        assert_eq!(current_arch(), foreign);
+
+        let cfg = crate::config::AppConfig::default();
+        assert_eq!(cfg.arch, foreign);
+        let entry: crate::db::download::PackageEntry = serde_json::from_value(serde_json::json!({
+            "latest_version": "1", "description": "",
+            "architectures": {
+                host_arch(): { "download_url": "https://r/host" },
+                foreign: { "download_url": "https://r/foreign" },
+            },
+        }))
+        .unwrap();
+        let asset = crate::db::download::resolve_asset_for_arch(&entry, &cfg.arch).unwrap();
+        assert_eq!(asset.0, "https://r/foreign");
+    }
+}
@@ -0,0 +1,70 @@
+//! src/audit.rs
+//! Append-only JSON Lines audit trail of mutating operations ([logging] audit_file).
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use colored::*;
+use nix::unistd::{getuid, User};
+use serde::Serialize;
+
+use crate::config::AppConfig;
+
+/// One line of the audit file. Field names are part of the on-disk format; only add fields.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    /// Seconds since the Unix epoch (UTC)
+    pub timestamp: u64,
+    /// Operation, e.g. `install` or `remove`
+    pub action: String,
+    pub package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Name of the invoking user (falls back to the numeric uid)
+    pub user: String,
+    pub uid: u32,
+    /// `ok` or `error`
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &str, package: &str, version: Option<&str>, outcome: Result<(), String>) -> Self {
+        let uid = getuid();
+        let user = User::from_uid(uid)
+            .ok()
+            .flatten()
+            .map(|u| u.name)
+            .unwrap_or_else(|| uid.to_string());
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        AuditEvent {
+            timestamp,
+            action: action.to_string(),
+            package: package.to_string(),
+            version: version.map(str::to_string),
+            user,
+            uid: uid.as_raw(),
+            result: if outcome.is_ok() { "ok" } else { "error" },
+            error: outcome.err(),
+        }
+    }
+}
+
+/// Append `event` to the configured audit file, if any.
+/// Best-effort: failures are reported as warnings and never fail the operation.
+pub fn record(cfg: &AppConfig, event: &AuditEvent) {
+    let Some(path) = cfg.audit_file.as_ref() else { return };
+    let write = || -> Result<(), Box<dyn std::error::Error>> {
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Single write per event so concurrent appenders don't interleave lines
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        eprintln!("{} failed to write audit log {}: {}", "Warning:".yellow(), path.display(), e);
+    }
+}
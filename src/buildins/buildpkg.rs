@@ -12,6 +12,7 @@ use crate::buildins::meta::PackageRecipe; // Use the PackageRecipe defined in bu
 /// * `staging_dir_in_chroot` - The path *inside* the chroot where artifacts were installed (e.g., "/pkg").
 /// * `output_dir` - Where to save the final .nxpkg file.
 /// * `recipe` - The package metadata.
+/// * `opts` - Packaging options (e.g. permission normalization).
 ///
 /// # Returns
 /// The path to the created .nxpkg file.
@@ -20,6 +21,7 @@ pub fn create_package(
     staging_dir_in_chroot: &Path,
     output_dir: &Path,
     recipe: &PackageRecipe,
+    opts: &compress::PackOptions,
 ) -> Result<PathBuf, String> {
     println!("Packaging build artifacts into a .nxpkg file...");
 
@@ -42,7 +44,7 @@ pub fn create_package(
     // 2. Use the existing compress::create_nxpkg function
     // This function will handle creating data.tar.gz from the staging path and packaging
     // it with the recipe.
-    match compress::create_nxpkg(&staging_path, recipe, &output_filepath) {
+    match compress::create_nxpkg(&staging_path, recipe, &output_filepath, opts) {
         Ok(_) => {
             println!(
                 "Successfully created package: {}",
@@ -4,22 +4,98 @@
 
 use std::collections::HashSet;
 use std::ffi::CString;
-use std::io;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::os::unix::process::ExitStatusExt;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use colored::*;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use nix::sched::{unshare, CloneFlags};
 use nix::sys::wait::{waitpid, WaitStatus};
-use nix::unistd::{chdir, chroot, fork, setgid, setuid, ForkResult, Gid, Uid};
+use nix::unistd::{chdir, chroot, fork, getgroups, pipe2, setgid, setgroups, setuid, ForkResult, Gid, Uid, User};
+
+/// Byte the build child writes to its setup pipe when unshare, a mount or chroot is refused
+/// with EPERM. The pipe is close-on-exec, so the build command can never send it, whatever
+/// status it exits with.
+const NO_PRIVILEGE: u8 = b'P';
+
+/// Exit the build child with `code` after a failed setup call, first telling the parent over
+/// `setup_pipe` when the kernel refused it for lack of privilege.
+fn exit_setup_failed(mut setup_pipe: &File, e: Errno, code: i32) -> ! {
+    if e == Errno::EPERM {
+        let _ = setup_pipe.write_all(&[NO_PRIVILEGE]);
+    }
+    std::process::exit(code)
+}
+
+/// Error returned by `ChrootEnv::run_command` when nxpkg lacks the privileges to set up the
+/// chroot (not root, or root without CAP_SYS_ADMIN, as in many containers).
+#[derive(Debug)]
+pub struct NeedsPrivilege;
+
+impl fmt::Display for NeedsPrivilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chroot build requires root or CAP_SYS_ADMIN")
+    }
+}
 
+impl std::error::Error for NeedsPrivilege {}
+
+impl NeedsPrivilege {
+    /// Whether `e` is this error.
+    pub fn is(e: &io::Error) -> bool {
+        e.get_ref().is_some_and(|inner| inner.is::<NeedsPrivilege>())
+    }
+}
 
 /// Represents a chroot environment.
 pub struct ChrootEnv {
     root_path: PathBuf,
+    build_user: BuildUser,
+    /// Number of binaries resolved and copied concurrently by `prepare`
+    jobs: usize,
+}
+
+/// Unprivileged account that build commands run as inside the chroot.
+#[derive(Debug, Clone, Copy)]
+pub struct BuildUser {
+    pub uid: Uid,
+    pub gid: Gid,
+}
+
+impl BuildUser {
+    /// uid/gid 65534, used when no build user is configured and `nobody` is not in /etc/passwd.
+    pub const FALLBACK: BuildUser = BuildUser { uid: Uid::from_raw(65534), gid: Gid::from_raw(65534) };
+
+    /// Resolve `[build] build_user` (a user name or numeric uid) against the host's passwd database.
+    /// Unset means `nobody`, falling back to 65534; a configured user must exist and must not be root.
+    pub fn resolve(spec: Option<&str>) -> Result<BuildUser, String> {
+        let Some(spec) = spec.map(str::trim).filter(|s| !s.is_empty()) else {
+            return Ok(match User::from_name("nobody") {
+                Ok(Some(u)) => BuildUser { uid: u.uid, gid: u.gid },
+                _ => Self::FALLBACK,
+            });
+        };
+        let lookup = match spec.parse::<u32>() {
+            Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+            Err(_) => User::from_name(spec),
+        };
+        let user = lookup
+            .map_err(|e| format!("failed to look up build user '{}': {}", spec, e))?
+            .ok_or_else(|| format!("build user '{}' does not exist", spec))?;
+        if user.uid.is_root() {
+            return Err(format!("build user '{}' is root; builds must run unprivileged", spec));
+        }
+        Ok(BuildUser { uid: user.uid, gid: user.gid })
+    }
 }
 
 // Helper to convert nix::sys::wait::WaitStatus to std::process::ExitStatus
@@ -31,14 +107,49 @@ fn wait_status_to_exit_status(status: WaitStatus) -> ExitStatus {
     }
 }
 
+/// Permanently drop root to `uid`/`gid` in the build child.
+///
+/// Order matters: supplementary groups and the gid can only be changed while still root,
+/// so `setgroups` and `setgid` must run before `setuid`. Doing `setuid` first would make
+/// the later calls fail and leave root's groups attached to the build. Each step is fatal;
+/// the result is checked afterwards so a silently ignored failure cannot keep privileges.
+/// Returns the exit code and message to report on failure.
+fn drop_privileges(uid: Uid, gid: Gid) -> Result<(), (i32, String)> {
+    setgroups(&[]).map_err(|e| (113, format!("setgroups failed: {}", e)))?;
+    setgid(gid).map_err(|e| (108, format!("setgid failed: {}", e)))?;
+    setuid(uid).map_err(|e| (109, format!("setuid failed: {}", e)))?;
+
+    if uid.as_raw() != 0 && setuid(Uid::from_raw(0)).is_ok() {
+        return Err((112, "privilege drop ineffective: root could be regained".to_string()));
+    }
+    if !getgroups().map(|g| g.is_empty() || g == [gid]).unwrap_or(false) {
+        return Err((112, "privilege drop ineffective: supplementary groups retained".to_string()));
+    }
+    Ok(())
+}
+
 impl ChrootEnv {
     /// Creates a new chroot environment at the specified path.
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         ChrootEnv {
             root_path: path.as_ref().to_path_buf(),
+            build_user: BuildUser::FALLBACK,
+            jobs: 1,
         }
     }
 
+    /// Run build commands as `user` instead of uid/gid 65534.
+    pub fn with_build_user(mut self, user: BuildUser) -> Self {
+        self.build_user = user;
+        self
+    }
+
+    /// Resolve and copy up to `jobs` binaries at once while preparing the chroot.
+    pub fn with_parallelism(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
 
     /// Prepares the chroot directory by finding binaries in PATH and copying them with their dependencies.
     pub fn prepare(&self) -> io::Result<()> {
@@ -83,22 +194,28 @@ impl ChrootEnv {
 
 
 
-        // 3. Find and copy them with dependencies
-        let mut copied_files = HashSet::new();
-        for bin_name in &binaries_to_find {
-            println!("  Resolving dependencies for '{}'...", bin_name);
-            match self.copy_binary_with_deps(bin_name, &mut copied_files) {
-                Ok(_) => {},
-                Err(e) => println!("    {} Could not resolve '{}': {}", "Warning:".yellow(), bin_name, e),
+        // 3. Find and copy them with dependencies (ldd and the copies dominate; run them in parallel)
+        let copied_files = Mutex::new(HashSet::new());
+        let next = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..self.jobs.min(binaries_to_find.len()) {
+                scope.spawn(|| {
+                    while let Some(bin_name) = binaries_to_find.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        println!("  Resolving dependencies for '{}'...", bin_name);
+                        if let Err(e) = self.copy_binary_with_deps(bin_name, &copied_files) {
+                            println!("    {} Could not resolve '{}': {}", "Warning:".yellow(), bin_name, e);
+                        }
+                    }
+                });
             }
-        }
+        });
 
         println!("{}", "Chroot environment prepared.".green());
         Ok(())
     }
 
     /// Finds a binary, its library dependencies (via ldd), and copies them into the chroot.
-    fn copy_binary_with_deps(&self, bin_name: &str, copied_files: &mut HashSet<PathBuf>) -> io::Result<()> {
+    fn copy_binary_with_deps(&self, bin_name: &str, copied_files: &Mutex<HashSet<PathBuf>>) -> io::Result<()> {
         // Find the binary's full path
         let output = Command::new("which").arg(bin_name).output()?;
         if !output.status.success() {
@@ -132,55 +249,57 @@ impl ChrootEnv {
             }
         }
 
-        // Copy all found files (binary + libs) into the chroot
+        // Copy all found files (binary + libs) into the chroot; shared libraries are claimed
+        // in `copied_files` first so concurrent workers copy each one only once
         for file_path in files_to_copy {
-            if !copied_files.contains(&file_path) {
-                let dest_path = self.root_path.join(file_path.strip_prefix("/").unwrap());
-                if let Some(parent) = dest_path.parent() {
-                    std::fs::create_dir_all(parent)?;
-                }
-
-
-
-
-                
-                if file_path.exists() {
-                    std::fs::copy(&file_path, &dest_path)?;
-                    //println!("    Copied {}", file_path.display());
-                    copied_files.insert(file_path);
-                }
+            if !file_path.exists() || !copied_files.lock().unwrap().insert(file_path.clone()) {
+                continue;
+            }
+            let dest_path = self.root_path.join(file_path.strip_prefix("/").unwrap());
+            if let Some(parent) = dest_path.parent() {
+                std::fs::create_dir_all(parent)?;
             }
+            std::fs::copy(&file_path, &dest_path)?;
         }
 
-
-
         Ok(())
     }
 
     /// Runs a command inside the prepared chroot environment using fork, unshare, and chroot.
-    /// **Warning:** This function must be run with root privileges.
+    /// **Warning:** This function must be run with root privileges; without them it fails with
+    /// `NeedsPrivilege`.
     pub fn run_command(&self, command: &str, args: &[&str], cwd: Option<&Path>) -> io::Result<ExitStatus> {
         if nix::unistd::geteuid().as_raw() != 0 {
-            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "run_command requires root privileges (chroot)"));
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, NeedsPrivilege));
         }
         let c_args: Vec<CString> = args.iter().map(|a| CString::new(*a).unwrap()).collect();
+        // Setup failures for lack of privilege are reported here rather than by exit status,
+        // which the build command controls once it runs
+        let (setup_rx, setup_tx) = pipe2(OFlag::O_CLOEXEC).map_err(io::Error::other)?;
+        let (mut setup_rx, setup_tx) = (File::from(setup_rx), File::from(setup_tx));
 
         match unsafe { fork() } {
             Ok(ForkResult::Parent { child, .. }) => {
                 // Parent process: wait for the child to finish
+                drop(setup_tx);
                 let wait_status = waitpid(child, None)
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    .map_err(io::Error::other)?;
+                let mut report = [0u8; 1];
+                if matches!(setup_rx.read(&mut report), Ok(1)) && report[0] == NO_PRIVILEGE {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, NeedsPrivilege));
+                }
                 Ok(wait_status_to_exit_status(wait_status))
             }
             Ok(ForkResult::Child) => {
                 // --- Child Process ---
                 // This code runs in the child. If anything fails, we exit with a non-zero code.
+                drop(setup_rx);
                 
                 // 1. Unshare namespaces
                 unshare(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWUTS)
                     .unwrap_or_else(|e| {
                         eprintln!("Fatal: unshare failed: {}", e);
-                        std::process::exit(101);
+                        exit_setup_failed(&setup_tx, e, 101);
                     });
 
                 // 2. Make mounts private to avoid leaking mounts to the host
@@ -192,7 +311,7 @@ impl ChrootEnv {
                     None::<&str>,
                 ).unwrap_or_else(|e| {
                     eprintln!("Fatal: mount propagation change failed: {}", e);
-                    std::process::exit(106);
+                    exit_setup_failed(&setup_tx, e, 106);
                 });
 
                 // 3. Bind-mount /dev and /sys into the chroot root (in this new mount namespace)
@@ -249,14 +368,14 @@ impl ChrootEnv {
                     None::<&str>,
                 ).unwrap_or_else(|e| {
                     eprintln!("Fatal: mount /proc failed: {}", e);
-                    std::process::exit(102);
+                    exit_setup_failed(&setup_tx, e, 102);
                 });
                 
                 // 5. Chroot into the new root directory
                 chroot(&self.root_path)
                     .unwrap_or_else(|e| {
                         eprintln!("Fatal: chroot failed: {}", e);
-                        std::process::exit(103);
+                        exit_setup_failed(&setup_tx, e, 103);
                     });
                 
                 // 6. Change directory to the new root
@@ -265,17 +384,10 @@ impl ChrootEnv {
                     std::process::exit(104);
                 });
 
-                // 7. Drop privileges
-                // Using 'nobody' user (often UID/GID 65534) or a fallback
-                let nobody_uid = Uid::from_raw(65534);
-                let nobody_gid = Gid::from_raw(65534);
-                if let Err(e) = setgid(nobody_gid) {
-                    eprintln!("Fatal: setgid failed: {}", e);
-                    std::process::exit(108);
-                }
-                if let Err(e) = setuid(nobody_uid) {
-                    eprintln!("Fatal: setuid failed: {}", e);
-                    std::process::exit(109);
+                // 7. Drop privileges to the build user (groups, then gid, then uid; see drop_privileges)
+                if let Err((code, msg)) = drop_privileges(self.build_user.uid, self.build_user.gid) {
+                    eprintln!("Fatal: {}", msg);
+                    std::process::exit(code);
                 }
 
                 // 8. Optionally change to the requested working directory
@@ -322,7 +434,7 @@ impl ChrootEnv {
             }
             Err(e) => {
                 // Fork failed
-                Err(io::Error::new(io::ErrorKind::Other, format!("fork failed: {}", e)))
+                Err(io::Error::other(format!("fork failed: {}", e)))
             }
         }
     }
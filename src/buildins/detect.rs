@@ -0,0 +1,163 @@
+//! src/buildins/detect.rs
+//! Detects which build system a source tree uses (cargo, meson, cmake, scons, make).
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BuildSystemKind {
+    Cargo,
+    Meson,
+    Cmake,
+    Scons,
+    Make,
+}
+
+impl BuildSystemKind {
+    pub fn priority(self) -> u8 {
+        match self {
+            BuildSystemKind::Cargo => 0,
+            BuildSystemKind::Meson => 1,
+            BuildSystemKind::Cmake => 2,
+            BuildSystemKind::Scons => 3,
+            BuildSystemKind::Make => 4,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BuildSystemKind::Cargo => "cargo",
+            BuildSystemKind::Meson => "meson",
+            BuildSystemKind::Cmake => "cmake",
+            BuildSystemKind::Scons => "scons",
+            BuildSystemKind::Make => "make",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildSystemMatch {
+    pub kind: BuildSystemKind,
+    /// Directory containing the build file
+    pub path: PathBuf,
+    /// Depth of `path` below the scanned root (0 = the root itself)
+    pub depth: usize,
+}
+
+/// Directories holding vendored or third-party code, whose build files never describe the project.
+const SKIP_DIRS: &[&str] = &["node_modules", "vendor", "third_party", ".git"];
+
+pub fn parse_build_system(s: &str) -> Option<BuildSystemKind> {
+    match s.trim().to_lowercase().as_str() {
+        "cargo" => Some(BuildSystemKind::Cargo),
+        "meson" => Some(BuildSystemKind::Meson),
+        "cmake" => Some(BuildSystemKind::Cmake),
+        "scons" => Some(BuildSystemKind::Scons),
+        "make" => Some(BuildSystemKind::Make),
+        _ => None,
+    }
+}
+
+/// Recursively collects every build file found under `root_path`, skipping vendor directories.
+pub fn find_build_systems(root_path: &Path) -> Vec<BuildSystemMatch> {
+    let mut candidates: Vec<BuildSystemMatch> = Vec::new();
+
+    let walker = WalkDir::new(root_path).into_iter().filter_entry(|e| {
+        e.depth() == 0
+            || !e.file_type().is_dir()
+            || !e.file_name().to_str().is_some_and(|n| SKIP_DIRS.contains(&n))
+    });
+    for entry in walker.filter_map(Result::ok) {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = match path.file_name().and_then(|s| s.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let kind = match file_name {
+            "Cargo.toml" => Some(BuildSystemKind::Cargo),
+            "meson.build" => Some(BuildSystemKind::Meson),
+            "CMakeLists.txt" => Some(BuildSystemKind::Cmake),
+            "SConstruct" | "SConscript" => Some(BuildSystemKind::Scons),
+            "Makefile" | "makefile" | "GNUmakefile" => Some(BuildSystemKind::Make),
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            if let Some(parent) = path.parent() {
+                let depth = parent.strip_prefix(root_path)
+                    .map(|p| p.components().count())
+                    .unwrap_or(usize::MAX);
+                candidates.push(BuildSystemMatch {
+                    kind,
+                    path: parent.to_path_buf(),
+                    depth,
+                });
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Choose the build system to use: the shallowest match of `preferred` if given,
+/// otherwise the shallowest build file, breaking ties by kind priority.
+pub fn pick_build_system(
+    candidates: &[BuildSystemMatch],
+    preferred: Option<BuildSystemKind>,
+) -> Option<BuildSystemMatch> {
+    if let Some(kind) = preferred {
+        let mut matches: Vec<BuildSystemMatch> = candidates
+            .iter()
+            .filter(|c| c.kind == kind)
+            .cloned()
+            .collect();
+        matches.sort_by_key(|c| c.depth);
+        return matches.into_iter().next();
+    }
+
+    let mut matches = candidates.to_vec();
+    matches.sort_by_key(|c| (c.depth, c.kind.priority()));
+    matches.into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn root_build_file_wins_over_nested_ones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("Makefile"), "all:\n").unwrap();
+        fs::create_dir_all(root.join("vendor/dep")).unwrap();
+        fs::write(root.join("vendor/dep/Cargo.toml"), "[package]\n").unwrap();
+        fs::create_dir_all(root.join("tools/helper")).unwrap();
+        fs::write(root.join("tools/helper/Cargo.toml"), "[package]\n").unwrap();
+
+        let found = find_build_systems(root);
+        // Vendored trees are not scanned at all
+        assert!(found.iter().all(|m| !m.path.starts_with(root.join("vendor"))));
+        let picked = pick_build_system(&found, None).unwrap();
+        assert_eq!(picked.kind, BuildSystemKind::Make);
+        assert_eq!(picked.path, root);
+        assert_eq!(picked.depth, 0);
+    }
+
+    #[test]
+    fn priority_breaks_ties_at_the_same_depth() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(tmp.path().join("Makefile"), "all:\n").unwrap();
+        fs::write(tmp.path().join("Cargo.toml"), "[package]\n").unwrap();
+
+        let found = find_build_systems(tmp.path());
+        assert_eq!(pick_build_system(&found, None).unwrap().kind, BuildSystemKind::Cargo);
+        assert_eq!(pick_build_system(&found, Some(BuildSystemKind::Make)).unwrap().kind, BuildSystemKind::Make);
+    }
+}
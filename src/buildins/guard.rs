@@ -0,0 +1,134 @@
+//! src/buildins/guard.rs
+//! Best-effort screening of build commands before they run (`[build] command_guard`).
+//!
+//! This looks for a few obviously dangerous shapes (deleting a system directory, piping a
+//! download into a shell, writing outside the build and staging directories) to catch
+//! accidents and careless recipes. It is a heuristic over the command text, trivially
+//! evaded by a determined recipe, and no replacement for the sandbox.
+
+use std::path::Path;
+
+/// Top-level directories whose recursive removal is never part of a build.
+const SYSTEM_DIRS: &[&str] = &[
+    "/", "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/lib64", "/opt", "/proc", "/root",
+    "/sbin", "/srv", "/sys", "/usr", "/var", "~", "$HOME", "${HOME}",
+];
+
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh", "fish"];
+
+const FETCHERS: &[&str] = &["curl", "wget", "fetch"];
+
+/// Commands that write to their last argument.
+const COPIERS: &[&str] = &["cp", "mv", "install", "ln", "tee", "rsync"];
+
+/// A build command flagged by the guard.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub command: String,
+    pub reason: String,
+}
+
+/// Flag the `commands` that match a dangerous pattern. `writable` are the directories (inside
+/// the sandbox) a build may write to besides `/tmp`; a command containing any of the `allow`
+/// strings is never flagged.
+pub fn scan(commands: &[String], writable: &[&Path], allow: &[String]) -> Vec<Finding> {
+    commands
+        .iter()
+        .filter(|c| !allow.iter().any(|a| !a.is_empty() && c.contains(a.as_str())))
+        .filter_map(|c| check(c, writable).map(|reason| Finding { command: c.clone(), reason }))
+        .collect()
+}
+
+fn check(command: &str, writable: &[&Path]) -> Option<String> {
+    let words: Vec<String> = command
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c| c == '\'' || c == '"').to_string())
+        .collect();
+
+    for (i, word) in words.iter().enumerate() {
+        // rm -rf / and friends
+        if basename(word) == "rm" {
+            let args = words[i + 1..].iter().take_while(|w| !is_separator(w));
+            let (flags, targets): (Vec<&String>, Vec<&String>) = args.partition(|w| w.starts_with('-'));
+            let recursive = flags.iter().any(|f| f.starts_with("--recursive") || (!f.starts_with("--") && f.contains(['r', 'R'])));
+            if let Some(target) = targets.iter().find(|t| recursive && is_system_dir(t)) {
+                return Some(format!("recursively deletes {}", target));
+            }
+        }
+        // dd of=/dev/sda, mkfs
+        if let Some(target) = word.strip_prefix("of=").filter(|t| t.starts_with("/dev/") && !is_harmless_device(t)) {
+            return Some(format!("writes to device {}", target));
+        }
+        if basename(word).starts_with("mkfs") {
+            return Some("creates a filesystem".to_string());
+        }
+        // Redirections: `> /etc/x`, `>>/etc/x`
+        if let Some(rest) = word.strip_prefix(">>").or_else(|| word.strip_prefix('>')) {
+            let target = if rest.is_empty() { words.get(i + 1).map(String::as_str) } else { Some(rest) };
+            if let Some(target) = target.filter(|t| outside(t, writable)) {
+                return Some(format!("writes to {} outside the build directories", target));
+            }
+        }
+        // cp/mv/install/ln/tee with an absolute destination
+        if COPIERS.contains(&basename(word)) {
+            let operands: Vec<&String> = words[i + 1..]
+                .iter()
+                .take_while(|w| !is_separator(w))
+                .filter(|w| !w.starts_with('-'))
+                .collect();
+            // tee writes to every operand; the others need a source and a destination
+            let dest = if basename(word) == "tee" { operands.first() } else { operands.last().filter(|_| operands.len() > 1) };
+            if let Some(dest) = dest.filter(|d| outside(d, writable)) {
+                return Some(format!("{} writes to {} outside the build directories", basename(word), dest));
+            }
+        }
+    }
+
+    // curl ... | sh, sh -c "$(curl ...)", bash <(wget ...)
+    let pipelines: Vec<&str> = command.split(['|', ';', '&']).collect();
+    for pair in pipelines.windows(2) {
+        let fetches = pair[0].split_whitespace().any(|w| FETCHERS.contains(&basename(w)));
+        let mut next = pair[1].split_whitespace().map(|w| basename(w.trim_matches(['\'', '"'])));
+        let runner = match next.next() {
+            Some("sudo") | Some("env") => next.next(),
+            other => other,
+        };
+        if fetches && runner.is_some_and(|r| SHELLS.contains(&r)) {
+            return Some("pipes a download into a shell".to_string());
+        }
+    }
+    for fetcher in FETCHERS {
+        if command.contains(&format!("$({}", fetcher)) || command.contains(&format!("<({}", fetcher)) || command.contains(&format!("`{}", fetcher)) {
+            return Some("runs a download as a script".to_string());
+        }
+    }
+    None
+}
+
+fn basename(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+fn is_separator(word: &str) -> bool {
+    matches!(word, ";" | "&&" | "||" | "|" | "&")
+}
+
+fn is_system_dir(target: &str) -> bool {
+    let target = target.trim_end_matches("/*");
+    let target = if target.len() > 1 { target.trim_end_matches('/') } else { target };
+    target.is_empty() || SYSTEM_DIRS.contains(&target)
+}
+
+fn is_harmless_device(path: &str) -> bool {
+    matches!(path, "/dev/null" | "/dev/stdout" | "/dev/stderr" | "/dev/zero")
+}
+
+/// An absolute path outside `/tmp`, the harmless devices and the `writable` directories.
+fn outside(path: &str, writable: &[&Path]) -> bool {
+    let path = path.trim_end_matches(';');
+    if !path.starts_with('/') || is_harmless_device(path) {
+        return false;
+    }
+    let path = Path::new(path);
+    !path.starts_with("/tmp") && !writable.iter().any(|dir| path.starts_with(dir))
+}
@@ -1,8 +1,12 @@
 //! src/buildins/meta.rs
 //! Handles parsing of package recipe files (.cfg) without external dependencies.
 
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use crate::arch::canonical_arch;
+use crate::version::{Constraint, Op};
 
 // --- Data Structures ---
 #[derive(Debug, Default, Clone)]
@@ -10,19 +14,86 @@ pub struct PackageInfo {
     pub name: String,
     pub version: String,
     pub architectures: Vec<String>,
+    /// Packages this one supersedes (e.g. the old name after a rename); installing it removes them
+    pub replaces: Vec<String>,
+    /// Oldest nxpkg release that can install this package correctly; None = any
+    pub min_nxpkg_version: Option<String>,
+    // Taken from the repository index at install time, not read from the .cfg; empty if unknown
+    pub description: String,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct BuildInfo {
     pub dependencies: Vec<String>,
+    /// Extra dependencies on one architecture (`dependencies.<arch>`), keyed by canonical arch
+    pub arch_dependencies: BTreeMap<String, Vec<String>>,
     pub commands: Vec<String>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct InstallInfo {
     pub install_params: Vec<String>,
-    // This field is populated at install time, not read from the .cfg
+    /// Glob patterns (`[install] config_files`) naming the package's configuration files;
+    /// `create_nxpkg` resolves them against the staged files into the `conffiles` member
+    pub config_files: Vec<String>,
+    // Configuration files as listed by the package's `conffiles` member, at their install paths
+    pub conffiles: Vec<String>,
+    // These fields are populated at install time, not read from the .cfg
     pub installed_files: Vec<String>, 
+    pub installed_dirs: Vec<String>,
+    // SHA-256 of each installed regular file at install time (path -> hex)
+    pub file_hashes: BTreeMap<String, String>,
+    pub deps_bypassed: bool,
+}
+
+/// A parsed `build.dependencies` entry: a package name with an optional version constraint,
+/// e.g. `libfoo`, `libfoo >= 2.0` or `bar = 1.3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub constraint: Option<Constraint>,
+}
+
+impl Dependency {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let Some(op_start) = spec.find(['<', '>', '=']) else {
+            if spec.is_empty() || spec.contains(char::is_whitespace) {
+                return Err(format!("invalid dependency '{}'", spec));
+            }
+            return Ok(Dependency { name: spec.to_string(), constraint: None });
+        };
+        let name = spec[..op_start].trim();
+        let rest = &spec[op_start..];
+        let op_len = rest.find(|c| !matches!(c, '<' | '>' | '=')).unwrap_or(rest.len());
+        let op = Op::parse(&rest[..op_len])
+            .ok_or_else(|| format!("invalid operator '{}' in dependency '{}'", &rest[..op_len], spec))?;
+        let version = rest[op_len..].trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("invalid package name in dependency '{}'", spec));
+        }
+        if version.is_empty() || version.contains(char::is_whitespace) {
+            return Err(format!("missing version in dependency '{}'", spec));
+        }
+        Ok(Dependency {
+            name: name.to_string(),
+            constraint: Some(Constraint { op, version: version.to_string() }),
+        })
+    }
+
+    /// True if `version` of this dependency satisfies the constraint (always true when unconstrained).
+    pub fn satisfied_by(&self, version: &str) -> bool {
+        self.constraint.as_ref().is_none_or(|c| c.matches(version))
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.constraint {
+            Some(c) => write!(f, "{} {}", self.name, c),
+            None => write!(f, "{}", self.name),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -30,6 +101,28 @@ pub struct PackageRecipe {
     pub package: PackageInfo,
     pub build: BuildInfo,
     pub install: InstallInfo,
+    /// Keys nxpkg does not know, by section (`""` for keys before the first section). They are
+    /// written back by `create_nxpkg`, so repacking keeps metadata meant for newer versions.
+    pub extra: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// `generic` followed by the entries of `by_arch` (keyed by canonical arch) that apply to `arch`,
+/// without duplicates.
+pub fn merge_arch_dependencies<'a>(
+    generic: &[String],
+    by_arch: impl IntoIterator<Item = (&'a String, &'a Vec<String>)>,
+    arch: &str,
+) -> Vec<String> {
+    let arch = canonical_arch(arch);
+    let mut deps = generic.to_vec();
+    for (_, extra) in by_arch.into_iter().filter(|(k, _)| **k == arch) {
+        for dep in extra {
+            if !deps.contains(dep) {
+                deps.push(dep.clone());
+            }
+        }
+    }
+    deps
 }
 
 // --- Zero-Dependency Parser Implementation ---
@@ -60,24 +153,50 @@ impl PackageRecipe {
                         "architectures" => {
                             recipe.package.architectures = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
                         }
-                        _ => {}
+                        "replaces" => {
+                            recipe.package.replaces = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+                            if let Some(bad) = recipe.package.replaces.iter().find(|r| r.contains(char::is_whitespace)) {
+                                return Err(format!("invalid package name '{}' in replaces", bad));
+                            }
+                        }
+                        "min_nxpkg_version" => {
+                            if value.is_empty() || value.contains(char::is_whitespace) {
+                                return Err(format!("invalid min_nxpkg_version '{}'", value));
+                            }
+                            recipe.package.min_nxpkg_version = Some(value.to_string());
+                        }
+                        _ => recipe.keep_extra(current_section, key, value),
                     },
                     "build" => match key {
                         "dependencies" => {
                             recipe.build.dependencies = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+                            for dep in &recipe.build.dependencies {
+                                Dependency::parse(dep)?;
+                            }
+                        }
+                        k if k.starts_with("dependencies.") => {
+                            let arch = k["dependencies.".len()..].trim();
+                            if arch.is_empty() {
+                                return Err(format!("missing architecture in '{}'", k));
+                            }
+                            let deps: Vec<String> = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+                            for dep in &deps {
+                                Dependency::parse(dep)?;
+                            }
+                            recipe.build.arch_dependencies.entry(canonical_arch(arch)).or_default().extend(deps);
                         }
                         "commands" => {
                             recipe.build.commands = value.split(';').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
                         }
-                        _ => {}
-                    },
-                    "install" => match key {
-                        "install_params" => {
-                            recipe.install.install_params = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
-                        }
-                        _ => {}
+                        _ => recipe.keep_extra(current_section, key, value),
                     },
-                    _ => {}
+                    "install" if key == "install_params" => {
+                        recipe.install.install_params = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+                    }
+                    "install" if key == "config_files" => {
+                        recipe.install.config_files = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+                    }
+                    _ => recipe.keep_extra(current_section, key, value),
                 }
             }
         }
@@ -92,9 +211,67 @@ impl PackageRecipe {
         Ok(recipe)
     }
 
+    fn keep_extra(&mut self, section: &str, key: &str, value: &str) {
+        self.extra.entry(section.to_string()).or_default().insert(key.to_string(), value.to_string());
+    }
+
+    /// Parsed dependencies on `arch`: `build.dependencies` plus `dependencies.<arch>`. Entries
+    /// were validated by `from_str`; recipes constructed in code skip anything that does not parse.
+    pub fn parsed_dependencies(&self, arch: &str) -> Vec<Dependency> {
+        self.dependencies_for(arch).iter().filter_map(|d| Dependency::parse(d).ok()).collect()
+    }
+
+    /// The generic dependencies followed by those specific to `arch`.
+    pub fn dependencies_for(&self, arch: &str) -> Vec<String> {
+        merge_arch_dependencies(&self.build.dependencies, &self.build.arch_dependencies, arch)
+    }
+
+    /// Replace the generic and per-architecture lists by the effective list on `arch`, which is
+    /// what gets recorded for an installed package.
+    pub fn flatten_dependencies(&mut self, arch: &str) {
+        self.build.dependencies = self.dependencies_for(arch);
+        self.build.arch_dependencies.clear();
+    }
+
     pub fn from_file(path: &Path) -> Result<Self, String> {
         let content = fs::read_to_string(path)
             .map_err(|e| format!("Could not read recipe file '{}': {}", path.display(), e))?;
         Self::from_str(&content)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPE: &str = "[package]\nname = app\nversion = 1\n\n[build]\ndependencies = libc\n\
+                          dependencies.amd64 = libx86 >= 2\ndependencies.arm64 = libarm, libc\n";
+
+    fn names(deps: Vec<Dependency>) -> Vec<String> {
+        deps.iter().map(Dependency::to_string).collect()
+    }
+
+    #[test]
+    fn arch_dependencies_are_keyed_by_canonical_arch() {
+        let recipe = PackageRecipe::from_str(RECIPE).unwrap();
+        let keys: Vec<_> = recipe.build.arch_dependencies.keys().collect();
+        assert_eq!(keys, ["aarch64", "x86_64"]);
+    }
+
+    #[test]
+    fn parsed_dependencies_add_the_target_arch_extras() {
+        let recipe = PackageRecipe::from_str(RECIPE).unwrap();
+        assert_eq!(names(recipe.parsed_dependencies("x86_64")), ["libc", "libx86 >= 2"]);
+        assert_eq!(names(recipe.parsed_dependencies("amd64")), ["libc", "libx86 >= 2"]);
+        assert_eq!(names(recipe.parsed_dependencies("aarch64")), ["libc", "libarm"]);
+        assert_eq!(names(recipe.parsed_dependencies("riscv64")), ["libc"]);
+    }
+
+    #[test]
+    fn flattened_dependencies_keep_only_the_target_arch() {
+        let mut recipe = PackageRecipe::from_str(RECIPE).unwrap();
+        recipe.flatten_dependencies("arm64");
+        assert_eq!(recipe.build.dependencies, ["libc", "libarm"]);
+        assert!(recipe.build.arch_dependencies.is_empty());
+    }
+}
@@ -5,6 +5,8 @@ pub struct BuildProfile {
     pub configure_args: Vec<String>,
     pub build_args: Vec<String>,
     pub install_args: Vec<String>,
+    /// Recorded as `build.dependencies` of the built package (`--depends`)
+    pub dependencies: Vec<String>,
 }
 
 impl BuildProfile {
@@ -15,6 +17,7 @@ impl BuildProfile {
             configure_args: Vec::new(),
             build_args: Vec::new(),
             install_args: Vec::new(),
+            dependencies: Vec::new(),
         }
     }
 }
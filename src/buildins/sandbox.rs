@@ -0,0 +1,138 @@
+//! src/buildins/sandbox.rs
+//! Isolation layer for source builds: nxpkg's own chroot or a podman/docker container.
+//!
+//! Both present the same layout to the build commands. A host directory (`root`) stands for
+//! the sandbox's `/`: sources are copied to `root/build/<name>` and artifacts are installed
+//! into the staging directory, so packaging reads them from `root/<staging>` on the host.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use crate::buildins::chroot::ChrootEnv;
+
+/// Where build commands run.
+pub trait BuildSandbox {
+    /// Host directory holding the sandbox's `/build` and staging directory.
+    fn root(&self) -> &Path;
+    fn prepare(&self) -> io::Result<()>;
+    /// Run `command` inside the sandbox; `cwd` is a path inside the sandbox.
+    fn run_command(&self, command: &str, args: &[&str], cwd: Option<&Path>) -> io::Result<ExitStatus>;
+    fn cleanup(&self) -> io::Result<()>;
+    /// Short name for progress messages, e.g. "chroot".
+    fn label(&self) -> &'static str;
+}
+
+/// The hand-built chroot (`ChrootEnv`); requires root.
+pub struct ChrootSandbox {
+    env: ChrootEnv,
+    root: PathBuf,
+}
+
+impl ChrootSandbox {
+    pub fn new(env: ChrootEnv, root: &Path) -> Self {
+        ChrootSandbox { env, root: root.to_path_buf() }
+    }
+}
+
+impl BuildSandbox for ChrootSandbox {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn prepare(&self) -> io::Result<()> {
+        self.env.prepare()
+    }
+
+    fn run_command(&self, command: &str, args: &[&str], cwd: Option<&Path>) -> io::Result<ExitStatus> {
+        self.env.run_command(command, args, cwd)
+    }
+
+    fn cleanup(&self) -> io::Result<()> {
+        self.env.cleanup()
+    }
+
+    fn label(&self) -> &'static str {
+        "chroot"
+    }
+}
+
+/// A throwaway container per command (`<runtime> run --rm`) from a user-supplied image that
+/// already has the toolchain. `/build` and the staging directory are bind-mounted from `root`,
+/// so files persist between commands and the artifacts end up on the host.
+pub struct ContainerSandbox {
+    runtime: String,
+    image: String,
+    root: PathBuf,
+    /// Directories inside the container backed by the same path under `root`
+    mounts: Vec<PathBuf>,
+}
+
+impl ContainerSandbox {
+    pub fn new(runtime: &str, image: &str, root: &Path) -> Self {
+        ContainerSandbox {
+            runtime: runtime.to_string(),
+            image: image.to_string(),
+            root: root.to_path_buf(),
+            mounts: vec![PathBuf::from("/build")],
+        }
+    }
+
+    /// Also bind-mount `dir` (absolute, inside the container), e.g. the staging directory.
+    pub fn with_mount(mut self, dir: &Path) -> Self {
+        if !self.mounts.iter().any(|m| m == dir) {
+            self.mounts.push(dir.to_path_buf());
+        }
+        self
+    }
+
+    /// The configured runtime, or the first of podman and docker found in PATH.
+    pub fn detect_runtime(configured: Option<&str>) -> Option<String> {
+        let candidates: Vec<&str> = match configured {
+            Some(r) => vec![r],
+            None => vec!["podman", "docker"],
+        };
+        candidates.into_iter()
+            .find(|r| Command::new(r).arg("--version").output().is_ok_and(|o| o.status.success()))
+            .map(str::to_string)
+    }
+
+    fn host_path(&self, dir: &Path) -> PathBuf {
+        self.root.join(dir.strip_prefix("/").unwrap_or(dir))
+    }
+}
+
+impl BuildSandbox for ContainerSandbox {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn prepare(&self) -> io::Result<()> {
+        for dir in &self.mounts {
+            std::fs::create_dir_all(self.host_path(dir))?;
+        }
+        Ok(())
+    }
+
+    fn run_command(&self, command: &str, args: &[&str], cwd: Option<&Path>) -> io::Result<ExitStatus> {
+        let mut cmd = Command::new(&self.runtime);
+        cmd.args(["run", "--rm"]);
+        for dir in &self.mounts {
+            let host = self.host_path(dir);
+            cmd.arg("-v").arg(format!("{}:{}", host.display(), dir.display()));
+        }
+        if let Some(dir) = cwd {
+            cmd.arg("-w").arg(dir);
+        }
+        cmd.arg(&self.image).arg(command).args(args);
+        cmd.status()
+    }
+
+    fn cleanup(&self) -> io::Result<()> {
+        std::fs::remove_dir_all(&self.root)
+    }
+
+    fn label(&self) -> &'static str {
+        "container"
+    }
+}
@@ -1,14 +1,17 @@
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::{Component, Path, PathBuf};
 use tar::{Archive, Builder, EntryType};
 use tempfile::{NamedTempFile, TempDir};
 use walkdir::WalkDir;
+use sha2::{Digest, Sha256};
 use crate::buildins::meta::PackageRecipe; // Import the recipe struct
+use crate::config::PrefixMap;
+use crate::arch::{canonical_arch, current_arch};
 
 #[cfg(unix)]
 use std::os::unix::fs::{PermissionsExt, symlink};
@@ -24,19 +27,221 @@ pub fn extract_tar_gz(source_file: &Path, dest_dir: &Path) -> Result<(), Box<dyn
     let reader = BufReader::new(file);
     let decompressor = GzDecoder::new(reader);
     let mut archive = Archive::new(decompressor);
-    let _ = unpack_archive_safe(&mut archive, dest_dir)?;
+    let _ = unpack_archive_safe(&mut archive, dest_dir, &ExtractOptions::default(), &|_| true, &HashSet::new())?;
 
     Ok(())
 }
 
+/// Options controlling how package payloads are placed on disk.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Destination prefix rewrites (e.g. `/usr` -> `/usr/local`). The most specific match wins.
+    pub prefix_map: Vec<PrefixMap>,
+    /// Directory the payload is installed under; empty means `/`.
+    pub root: PathBuf,
+    /// Architecture the package must be built for; `None` means `current_arch()`.
+    pub arch: Option<String>,
+    /// Hashes recorded for the installed version being replaced (install path -> hex). A
+    /// configuration file whose copy on disk differs from this, or exists without a record, is
+    /// kept; the packaged version is written next to it as `<path>.nxnew`.
+    pub installed_hashes: BTreeMap<String, String>,
+}
+
+impl ExtractOptions {
+    fn target_arch(&self) -> &str {
+        self.arch.as_deref().unwrap_or(current_arch())
+    }
+}
+
+/// Where a recorded package path (absolute, as seen from inside the install root) lives on disk
+/// when `root` is the active install root.
+pub fn path_in_root(root: &Path, logical: &Path) -> PathBuf {
+    root.join(logical.strip_prefix("/").unwrap_or(logical))
+}
+
+/// Options controlling how a staging directory is packaged.
+#[derive(Debug, Clone, Default)]
+pub struct PackOptions {
+    /// Normalize permissions with this base file mode (e.g. `0o644`) instead of copying the
+    /// staging modes. Directories and user-executable files get the matching exec bits.
+    pub file_mode: Option<u32>,
+}
+
+/// Paths placed by an extraction, after any prefix remap. `extract_nxpkg` reports them absolute
+/// as seen from inside the install root, so they stay valid whatever root they are resolved against.
+#[derive(Debug, Default)]
+pub struct Unpacked {
+    /// Regular files and symlinks written by the archive.
+    pub files: Vec<PathBuf>,
+    /// Directory entries of the archive that did not exist before and were created.
+    pub dirs: Vec<PathBuf>,
+    /// SHA-256 of each regular file as written.
+    pub hashes: BTreeMap<PathBuf, String>,
+    /// Configuration files left as they were; the packaged version is in `<path>.nxnew`.
+    pub kept_config: Vec<PathBuf>,
+}
+
+/// Kind of a payload entry in a package manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// One payload entry of a package, at its install destination (after prefix remap).
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Content hash, for regular files only.
+    pub sha256: Option<String>,
+    /// Target, for symlinks only.
+    pub link_target: Option<PathBuf>,
+}
+
 /// Extracts a .nxpkg, parses its recipe, and installs files to their final destinations.
 ///
-/// Returns a tuple containing:
-/// 1. The parsed `PackageRecipe`.
-/// 2. A `Vec<PathBuf>` of the absolute paths of the installed files.
-pub fn extract_nxpkg(nxpkg_path: &Path) -> Result<(PackageRecipe, Vec<PathBuf>), Box<dyn std::error::Error>> {
+/// Returns the parsed `PackageRecipe` and the files and directories that were installed.
+/// The package's `conffiles` are moved to their install paths (after prefix remap), and those
+/// changed locally are kept rather than overwritten (see `ExtractOptions::installed_hashes`).
+pub fn extract_nxpkg(nxpkg_path: &Path, opts: &ExtractOptions) -> Result<(PackageRecipe, Unpacked), Box<dyn std::error::Error>> {
+    let (mut recipe, data_file) = read_nxpkg_parts(nxpkg_path, Some(opts.target_arch()))?;
+    let mut archive = Archive::new(open_tar_stream(data_file.path(), "data.tar.gz")?);
+    let root = if opts.root.as_os_str().is_empty() { Path::new("/") } else { opts.root.as_path() };
+
+    let mut conffiles = Vec::new();
+    for listed in &recipe.install.conffiles {
+        let rel = remap_entry_path(&sanitize_entry_path(Path::new(listed.trim_start_matches('/')))?, &opts.prefix_map)?;
+        conffiles.push(Path::new("/").join(rel).to_string_lossy().to_string());
+    }
+    let preserve: HashSet<PathBuf> = conffiles
+        .iter()
+        .map(|c| path_in_root(root, Path::new(c)))
+        .filter(|disk| disk.is_file())
+        .filter(|disk| {
+            let recorded = opts.installed_hashes.get(&*Path::new("/").join(disk.strip_prefix(root).unwrap_or(disk)).to_string_lossy());
+            recorded.is_none_or(|r| sha256_path(disk).ok().as_ref() != Some(r))
+        })
+        .collect();
+    recipe.install.conffiles = conffiles;
+
+    let unpacked = unpack_archive_safe(&mut archive, root, opts, &|_| true, &preserve)?;
+    let logical = |p: PathBuf| p.strip_prefix(root).map(|rel| Path::new("/").join(rel)).unwrap_or(p);
+
+    Ok((recipe, Unpacked {
+        files: unpacked.files.into_iter().map(logical).collect(),
+        dirs: unpacked.dirs.into_iter().map(logical).collect(),
+        hashes: unpacked.hashes.into_iter().map(|(p, h)| (logical(p), h)).collect(),
+        kept_config: unpacked.kept_config.into_iter().map(logical).collect(),
+    }))
+}
+
+/// Unpack the payload of a .nxpkg under `dest` for inspection (`nxpkg extract`), keeping only
+/// members that match one of the `only` glob patterns (all members when empty). Nothing is
+/// registered, and the package may be built for another architecture.
+///
+/// A pattern matches a member's path inside the package (`usr/bin/tool`; a leading `/` is
+/// ignored) or one of its parent directories. `*` matches any run of characters, including
+/// `/`, and `?` a single one.
+pub fn extract_nxpkg_members(nxpkg_path: &Path, dest: &Path, only: &[String]) -> Result<(PackageRecipe, Unpacked), Box<dyn std::error::Error>> {
+    let (recipe, data_file) = read_nxpkg_parts(nxpkg_path, None)?;
+    let mut archive = Archive::new(open_tar_stream(data_file.path(), "data.tar.gz")?);
+    let patterns: Vec<&str> = only.iter().map(|p| p.trim_start_matches('/').trim_end_matches('/')).collect();
+    let selected = |rel: &Path| patterns.is_empty() || patterns.iter().any(|p| member_matches(p, rel));
+    fs::create_dir_all(dest)?;
+    let unpacked = unpack_archive_safe(&mut archive, dest, &ExtractOptions::default(), &selected, &HashSet::new())?;
+    Ok((recipe, unpacked))
+}
+
+/// Whether `pattern` (without a leading `/`) matches the package member `rel` or one of its
+/// parent directories.
+fn member_matches(pattern: &str, rel: &Path) -> bool {
+    rel.ancestors()
+        .filter(|a| !a.as_os_str().is_empty())
+        .any(|a| glob_match(pattern, &a.to_string_lossy()))
+}
+
+/// Shell-style wildcard match of a whole string: `*` is any sequence, `?` any one character.
+/// Unlike a shell, both also match `/`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// List what installing a .nxpkg would place on disk, without writing anything.
+pub fn read_manifest(nxpkg_path: &Path, opts: &ExtractOptions) -> Result<(PackageRecipe, Vec<ManifestEntry>), Box<dyn std::error::Error>> {
+    manifest_of(nxpkg_path, Some(opts.target_arch()), &opts.prefix_map)
+}
+
+/// The payload of a .nxpkg as packaged (no prefix remap), whatever architecture it is for.
+pub fn read_package_manifest(nxpkg_path: &Path) -> Result<(PackageRecipe, Vec<ManifestEntry>), Box<dyn std::error::Error>> {
+    manifest_of(nxpkg_path, None, &[])
+}
+
+fn manifest_of(
+    nxpkg_path: &Path,
+    check_arch: Option<&str>,
+    prefix_map: &[PrefixMap],
+) -> Result<(PackageRecipe, Vec<ManifestEntry>), Box<dyn std::error::Error>> {
+    let (recipe, data_file) = read_nxpkg_parts(nxpkg_path, check_arch)?;
+    let mut archive = Archive::new(open_tar_stream(data_file.path(), "data.tar.gz")?);
+    let mut manifest = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = match entry.header().entry_type() {
+            EntryType::Directory => EntryKind::Dir,
+            EntryType::Symlink => EntryKind::Symlink,
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => EntryKind::File,
+            _ => continue,
+        };
+        let rel = remap_entry_path(&sanitize_entry_path(&entry.path()?)?, prefix_map)?;
+        if rel.as_os_str().is_empty() {
+            continue;
+        }
+        let link_target = if kind == EntryKind::Symlink { entry.link_name()?.map(|t| t.into_owned()) } else { None };
+        let sha256 = if kind == EntryKind::File {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut entry, &mut hasher)?;
+            Some(hex::encode(hasher.finalize()))
+        } else {
+            None
+        };
+        manifest.push(ManifestEntry { path: Path::new("/").join(rel), kind, sha256, link_target });
+    }
+    Ok((recipe, manifest))
+}
+
+/// SHA-256 of a file on disk, lowercase hex.
+pub fn sha256_path(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Read the recipe and spool data.tar.gz out of a .nxpkg, checking that the package supports
+/// `check_arch` when one is given.
+fn read_nxpkg_parts(nxpkg_path: &Path, check_arch: Option<&str>) -> Result<(PackageRecipe, NamedTempFile), Box<dyn std::error::Error>> {
     let mut archive = open_nxpkg_archive(nxpkg_path)?;
     let mut recipe_text: Option<String> = None;
+    let mut conffiles_text = String::new();
     let mut data_file: Option<NamedTempFile> = None;
 
     for entry in archive.entries()? {
@@ -52,6 +257,8 @@ pub fn extract_nxpkg(nxpkg_path: &Path) -> Result<(PackageRecipe, Vec<PathBuf>),
             let mut buf = String::new();
             entry.read_to_string(&mut buf)?;
             recipe_text = Some(buf);
+        } else if rel == Path::new("conffiles") {
+            entry.read_to_string(&mut conffiles_text)?;
         } else if rel == Path::new("data.tar.gz") {
             let mut tmp = NamedTempFile::new()?;
             std::io::copy(&mut entry, &mut tmp)?;
@@ -61,57 +268,52 @@ pub fn extract_nxpkg(nxpkg_path: &Path) -> Result<(PackageRecipe, Vec<PathBuf>),
     }
 
     let recipe_text = recipe_text.ok_or("Invalid .nxpkg: 'package.cfg' not found.")?;
-    let recipe = PackageRecipe::from_str(&recipe_text)
+    let mut recipe = PackageRecipe::from_str(&recipe_text)
         .map_err(|e| format!("Failed to parse package.cfg: {}", e))?;
+    recipe.install.conffiles = parse_conffiles(&conffiles_text);
 
     // Architecture validation BEFORE installing anything.
-    let supports_current_arch = {
-        fn norm(s: &str) -> String { s.trim().to_lowercase().replace('-', "_") }
-        if recipe.package.architectures.is_empty() {
-            true
-        } else {
-            let declared: Vec<String> = recipe.package.architectures.iter().map(|s| norm(s)).collect();
-            let mut aliases: Vec<&'static str> = match std::env::consts::ARCH {
-                "x86_64" => vec!["x86_64", "amd64", "x64"],
-                "aarch64" => vec!["aarch64", "arm64"],
-                "arm" => vec!["arm", "armv7", "armhf", "armv7l"],
-                "x86" | "i686" => vec!["x86", "i686", "i386"],
-                "powerpc64" | "powerpc64le" => vec!["ppc64", "ppc64le"],
-                other => vec![other],
-            };
-            aliases.extend(["any", "noarch"].iter().copied());
-            let aliases: Vec<String> = aliases.into_iter().map(|s| s.to_string()).collect();
-            declared.iter().any(|d| aliases.iter().any(|a| a == d))
+    if let Some(arch) = check_arch {
+        let wanted = canonical_arch(arch);
+        let supported = recipe.package.architectures.is_empty()
+            || recipe.package.architectures.iter()
+                .map(|d| canonical_arch(d))
+                .any(|d| d == wanted || d == "any");
+        if !supported {
+            let whose = if wanted == current_arch() { "host" } else { "target" };
+            return Err(format!(
+                "Package is not built for this architecture ({}: {}, package: {:?})",
+                whose, arch, recipe.package.architectures
+            ).into());
         }
-    };
-
-    if !supports_current_arch {
-        return Err(format!(
-            "Package is not built for this architecture (host: {}, package: {:?})",
-            std::env::consts::ARCH,
-            recipe.package.architectures
-        ).into());
     }
 
     let data_file = data_file.ok_or("Invalid .nxpkg: 'data.tar.gz' not found.")?;
-    let file = File::open(data_file.path())?;
-    let reader = BufReader::new(file);
-    let decompressor = GzDecoder::new(reader);
-    let mut archive = Archive::new(decompressor);
-    let installed_files = unpack_archive_safe(&mut archive, Path::new("/"))?;
+    Ok((recipe, data_file))
+}
 
-    Ok((recipe, installed_files))
+/// Paths listed in a `conffiles` member: one absolute path per line.
+fn parse_conffiles(text: &str) -> Vec<String> {
+    text.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect()
 }
 
 /// Creates a .nxpkg archive from a staging directory and a recipe file.
-/// The resulting archive contains two entries:
+/// The resulting archive contains:
 /// - package.cfg (the recipe in INI-like format)
+/// - conffiles (the staged files matched by `[install] config_files`, when there are any)
 /// - data.tar.gz (tarball of the staged filesystem)
-pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Path) -> Result<(), String> {
+pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Path, opts: &PackOptions) -> Result<(), String> {
     if !staging_dir.is_dir() {
         return Err(format!("Staging directory does not exist or is not a directory: {}", staging_dir.display()));
     }
 
+    let config_patterns: Vec<&str> = recipe.install.config_files
+        .iter()
+        .map(|p| p.trim_start_matches('/').trim_end_matches('/'))
+        .collect();
+    let mut config_used = vec![false; config_patterns.len()];
+    let mut conffiles: Vec<String> = Vec::new();
+
     // 1) Build data.tar.gz from the staging directory
     let tmp_dir = TempDir::new().map_err(|e| e.to_string())?;
     let data_tar_gz_path = tmp_dir.path().join("data.tar.gz");
@@ -126,7 +328,32 @@ pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Pa
             if rel.as_os_str().is_empty() {
                 continue;
             }
-            if entry.file_type().is_dir() {
+            if entry.file_type().is_file() {
+                let mut matched = false;
+                for (i, pattern) in config_patterns.iter().enumerate() {
+                    if member_matches(pattern, rel) {
+                        config_used[i] = true;
+                        matched = true;
+                    }
+                }
+                if matched {
+                    conffiles.push(Path::new("/").join(rel).to_string_lossy().to_string());
+                }
+            }
+            let normalize = opts.file_mode.filter(|_| entry.file_type().is_dir() || entry.file_type().is_file());
+            if let Some(base) = normalize {
+                let meta = entry.metadata().map_err(|e| e.to_string())?;
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(&meta);
+                header.set_mode(normalized_mode(&meta, base));
+                header.set_cksum();
+                if entry.file_type().is_dir() {
+                    tar_builder.append_data(&mut header, rel, std::io::empty()).map_err(|e| e.to_string())?;
+                } else {
+                    let file = File::open(entry.path()).map_err(|e| e.to_string())?;
+                    tar_builder.append_data(&mut header, rel, file).map_err(|e| e.to_string())?;
+                }
+            } else if entry.file_type().is_dir() {
                 tar_builder.append_dir(rel, entry.path()).map_err(|e| e.to_string())?;
             } else if entry.file_type().is_file() {
                 tar_builder.append_path_with_name(entry.path(), rel).map_err(|e| e.to_string())?;
@@ -149,10 +376,21 @@ pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Pa
         let enc = tar_builder.into_inner().map_err(|e| e.to_string())?;
         enc.finish().map_err(|e| e.to_string())?;
     }
+    if let Some(i) = config_used.iter().position(|used| !used) {
+        return Err(format!("config_files pattern '{}' matches no file in {}", recipe.install.config_files[i], staging_dir.display()));
+    }
+    conffiles.sort();
 
     // 2) Render package.cfg content from the recipe
     let cfg = {
         let mut s = String::new();
+        // Keys the parser did not recognise go back into their section unchanged
+        let push_extra = |s: &mut String, section: &str| {
+            for (key, value) in recipe.extra.get(section).into_iter().flatten() {
+                s.push_str(&format!("{} = {}\n", key, value));
+            }
+        };
+        push_extra(&mut s, "");
         s.push_str("[package]\n");
         s.push_str(&format!("name = {}\n", recipe.package.name));
         s.push_str(&format!("version = {}\n", recipe.package.version));
@@ -162,6 +400,13 @@ pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Pa
                 recipe.package.architectures.join(", ")
             ));
         }
+        if !recipe.package.replaces.is_empty() {
+            s.push_str(&format!("replaces = {}\n", recipe.package.replaces.join(", ")));
+        }
+        if let Some(min) = &recipe.package.min_nxpkg_version {
+            s.push_str(&format!("min_nxpkg_version = {}\n", min));
+        }
+        push_extra(&mut s, "package");
         s.push_str("\n[build]\n");
         if !recipe.build.dependencies.is_empty() {
             s.push_str(&format!(
@@ -169,12 +414,16 @@ pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Pa
                 recipe.build.dependencies.join(", ")
             ));
         }
+        for (arch, deps) in &recipe.build.arch_dependencies {
+            s.push_str(&format!("dependencies.{} = {}\n", arch, deps.join(", ")));
+        }
         if !recipe.build.commands.is_empty() {
             s.push_str(&format!(
                 "commands = {}\n",
                 recipe.build.commands.join("; ")
             ));
         }
+        push_extra(&mut s, "build");
         s.push_str("\n[install]\n");
         if !recipe.install.install_params.is_empty() {
             s.push_str(&format!(
@@ -182,6 +431,13 @@ pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Pa
                 recipe.install.install_params.join(", ")
             ));
         }
+        push_extra(&mut s, "install");
+        for (section, keys) in recipe.extra.iter().filter(|(section, _)| !matches!(section.as_str(), "" | "package" | "build" | "install")) {
+            s.push_str(&format!("\n[{}]\n", section));
+            for (key, value) in keys {
+                s.push_str(&format!("{} = {}\n", key, value));
+            }
+        }
         s
     };
 
@@ -197,6 +453,16 @@ pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Pa
         header.set_cksum();
         outer.append_data(&mut header, "package.cfg", cfg_bytes).map_err(|e| e.to_string())?;
 
+        // Append conffiles, ahead of the payload so readers of the metadata can stop early
+        if !conffiles.is_empty() {
+            let list = conffiles.iter().map(|c| format!("{}\n", c)).collect::<String>();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(list.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            outer.append_data(&mut header, "conffiles", list.as_bytes()).map_err(|e| e.to_string())?;
+        }
+
         // Append data.tar.gz
         let mut header = tar::Header::new_gnu();
         let data_meta = fs::metadata(&data_tar_gz_path).map_err(|e| e.to_string())?;
@@ -214,10 +480,25 @@ pub fn create_nxpkg(staging_dir: &Path, recipe: &PackageRecipe, output_path: &Pa
     Ok(())
 }
 
+/// Packaged mode for a staged file or directory under `PackOptions::file_mode`.
+/// Directories and files with the user-exec bit get exec bits wherever `base` grants read;
+/// group/other write is always stripped.
+fn normalized_mode(meta: &fs::Metadata, base: u32) -> u32 {
+    #[cfg(unix)]
+    let user_exec = meta.permissions().mode() & 0o100 != 0;
+    #[cfg(not(unix))]
+    let user_exec = false;
+    let mode = if meta.is_dir() || user_exec { base | ((base & 0o444) >> 2) } else { base };
+    mode & 0o755
+}
+
 /// Read only the package.cfg (recipe) from a .nxpkg without installing anything.
 /// Supports both plain tar and gzipped outer container.
+/// The recipe's `conffiles` are those listed in the package, before any prefix remap.
 pub fn read_recipe_from_nxpkg(nxpkg_path: &Path) -> Result<PackageRecipe, Box<dyn std::error::Error>> {
     let mut archive = open_nxpkg_archive(nxpkg_path)?;
+    let mut recipe: Option<PackageRecipe> = None;
+    let mut conffiles = String::new();
     for entry in archive.entries()? {
         let mut entry = entry?;
         let entry_type = entry.header().entry_type();
@@ -229,11 +510,18 @@ pub fn read_recipe_from_nxpkg(nxpkg_path: &Path) -> Result<PackageRecipe, Box<dy
         if rel == Path::new("package.cfg") {
             let mut recipe_content = String::new();
             entry.read_to_string(&mut recipe_content)?;
-            return PackageRecipe::from_str(&recipe_content)
-                .map_err(|e| format!("Failed to parse package.cfg: {}", e).into());
+            recipe = Some(PackageRecipe::from_str(&recipe_content)
+                .map_err(|e| format!("Failed to parse package.cfg: {}", e))?);
+        } else if rel == Path::new("conffiles") {
+            entry.read_to_string(&mut conffiles)?;
+        } else if rel == Path::new("data.tar.gz") && recipe.is_some() {
+            // conffiles is written before the payload
+            break;
         }
     }
-    Err("package.cfg not found in .nxpkg".into())
+    let mut recipe = recipe.ok_or("package.cfg not found in .nxpkg")?;
+    recipe.install.conffiles = parse_conffiles(&conffiles);
+    Ok(recipe)
 }
 
 // Keep the old function for compatibility with the Debug1 command, but have it use the new helper.
@@ -244,19 +532,23 @@ pub fn decompress_tarball(input_file: &str) -> Result<(), Box<dyn std::error::Er
 }
 
 fn open_nxpkg_archive(nxpkg_path: &Path) -> Result<Archive<Box<dyn Read>>, Box<dyn std::error::Error>> {
-    let file = File::open(nxpkg_path)?;
-    let mut reader = BufReader::new(file);
-    let mut magic = [0u8; 2];
-    let _ = reader.read(&mut magic)?;
-    reader.seek(SeekFrom::Start(0))?;
-
-    let boxed: Box<dyn Read> = if magic == [0x1f, 0x8b] {
-        Box::new(GzDecoder::new(reader))
-    } else {
-        Box::new(reader)
-    };
+    Ok(Archive::new(open_tar_stream(nxpkg_path, &nxpkg_path.display().to_string())?))
+}
 
-    Ok(Archive::new(boxed))
+/// Open a tar stream that may be gzip-compressed or plain. The decoder is chosen from the
+/// file's magic bytes, never from its name: the outer .nxpkg and the inner `data.tar.gz` can
+/// each be either. `label` names the stream in errors.
+fn open_tar_stream(path: &Path, label: &str) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?;
+    if head.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Box::new(GzDecoder::new(reader)));
+    }
+    const UNSUPPORTED: [(&[u8], &str); 3] = [(b"\xfd7zXZ\x00", "xz"), (b"\x28\xb5\x2f\xfd", "zstd"), (b"BZh", "bzip2")];
+    if let Some((_, name)) = UNSUPPORTED.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Err(format!("{}: {} compression is not supported", label, name).into());
+    }
+    Ok(Box::new(reader))
 }
 
 fn sanitize_entry_path(path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
@@ -276,6 +568,27 @@ fn sanitize_entry_path(path: &Path) -> Result<PathBuf, Box<dyn std::error::Error
     Ok(clean)
 }
 
+/// Rewrite an archive-relative path according to the configured prefix map.
+/// The result is re-sanitized so a mapping can never place files outside the destination root.
+/// Returns an empty path when the entry maps onto the root itself.
+fn remap_entry_path(rel: &Path, prefix_map: &[PrefixMap]) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let abs = Path::new("/").join(rel);
+    let best = prefix_map
+        .iter()
+        .filter_map(|m| abs.strip_prefix(&m.from).ok().map(|rest| (m, rest)))
+        .max_by_key(|(m, _)| m.from.components().count());
+    let Some((m, rest)) = best else {
+        return Ok(rel.to_path_buf());
+    };
+    let mapped = m.to.join(rest);
+    let mapped_rel = mapped.strip_prefix("/").unwrap_or(&mapped);
+    if mapped_rel.as_os_str().is_empty() {
+        return Ok(PathBuf::new());
+    }
+    sanitize_entry_path(mapped_rel)
+        .map_err(|_| format!("Prefix mapping {} => {} escapes the install root for {}", m.from.display(), m.to.display(), rel.display()).into())
+}
+
 fn validate_link_target(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
     for comp in path.components() {
         match comp {
@@ -314,8 +627,14 @@ fn ensure_no_symlink_parents(
     Ok(())
 }
 
-fn unpack_archive_safe<R: Read>(archive: &mut Archive<R>, dest_root: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-    let mut installed = Vec::new();
+fn unpack_archive_safe<R: Read>(
+    archive: &mut Archive<R>,
+    dest_root: &Path,
+    opts: &ExtractOptions,
+    select: &dyn Fn(&Path) -> bool,
+    preserve: &HashSet<PathBuf>,
+) -> Result<Unpacked, Box<dyn std::error::Error>> {
+    let mut unpacked = Unpacked::default();
     let mut created_symlinks: HashSet<PathBuf> = HashSet::new();
 
     for entry in archive.entries()? {
@@ -331,6 +650,16 @@ fn unpack_archive_safe<R: Read>(archive: &mut Archive<R>, dest_root: &Path) -> R
 
         let entry_path = entry.path()?;
         let rel = sanitize_entry_path(&entry_path)?;
+        if !select(&rel) {
+            continue;
+        }
+        let rel = remap_entry_path(&rel, &opts.prefix_map)?;
+        if rel.as_os_str().is_empty() {
+            if entry_type == EntryType::Directory {
+                continue;
+            }
+            return Err(format!("Prefix mapping places {} onto the install root", entry_path.display()).into());
+        }
         let dest_path = dest_root.join(&rel);
 
         ensure_no_symlink_parents(dest_root, &dest_path, &created_symlinks)?;
@@ -344,11 +673,12 @@ fn unpack_archive_safe<R: Read>(archive: &mut Archive<R>, dest_root: &Path) -> R
                 }
                 let existed = dest_path.exists();
                 fs::create_dir_all(&dest_path)?;
-                #[cfg(unix)]
                 if !existed {
+                    #[cfg(unix)]
                     if let Ok(mode) = entry.header().mode() {
                         fs::set_permissions(&dest_path, fs::Permissions::from_mode(mode & 0o777))?;
                     }
+                    unpacked.dirs.push(dest_path);
                 }
             }
             EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
@@ -356,20 +686,46 @@ fn unpack_archive_safe<R: Read>(archive: &mut Archive<R>, dest_root: &Path) -> R
                     fs::create_dir_all(parent)?;
                 }
 
-                if let Ok(meta) = fs::symlink_metadata(&dest_path) {
+                // A kept configuration file stays; the packaged copy goes next to it
+                let kept = preserve.contains(&dest_path);
+                let write_path = if kept {
+                    let mut name = dest_path.clone().into_os_string();
+                    name.push(".nxnew");
+                    PathBuf::from(name)
+                } else {
+                    dest_path.clone()
+                };
+                if let Ok(meta) = fs::symlink_metadata(&write_path) {
                     if meta.file_type().is_dir() {
-                        return Err(format!("Refusing to overwrite directory with file: {}", dest_path.display()).into());
+                        return Err(format!("Refusing to overwrite directory with file: {}", write_path.display()).into());
                     }
-                    let _ = fs::remove_file(&dest_path);
+                    let _ = fs::remove_file(&write_path);
                 }
 
-                let mut out = OpenOptions::new().create(true).truncate(true).write(true).open(&dest_path)?;
-                std::io::copy(&mut entry, &mut out)?;
+                let mut out = OpenOptions::new().create(true).truncate(true).write(true).open(&write_path)?;
+                let mut hasher = Sha256::new();
+                let mut buf = [0u8; 64 * 1024];
+                loop {
+                    let n = entry.read(&mut buf)?;
+                    if n == 0 { break; }
+                    hasher.update(&buf[..n]);
+                    out.write_all(&buf[..n])?;
+                }
+                let hash = hex::encode(hasher.finalize());
                 #[cfg(unix)]
                 if let Ok(mode) = entry.header().mode() {
-                    fs::set_permissions(&dest_path, fs::Permissions::from_mode(mode & 0o777))?;
+                    fs::set_permissions(&write_path, fs::Permissions::from_mode(mode & 0o777))?;
+                }
+                if kept {
+                    // Nothing to merge when the local copy already matches the new version
+                    if sha256_path(&dest_path).is_ok_and(|local| local == hash) {
+                        let _ = fs::remove_file(&write_path);
+                    } else {
+                        unpacked.kept_config.push(dest_path.clone());
+                    }
                 }
-                installed.push(dest_path);
+                unpacked.hashes.insert(dest_path.clone(), hash);
+                unpacked.files.push(dest_path);
             }
             EntryType::Symlink => {
                 let link_target = entry.link_name()?
@@ -393,7 +749,7 @@ fn unpack_archive_safe<R: Read>(archive: &mut Archive<R>, dest_root: &Path) -> R
                 return Err("Symlink entries are not supported on this platform".into());
 
                 created_symlinks.insert(dest_path.clone());
-                installed.push(dest_path);
+                unpacked.files.push(dest_path);
             }
             EntryType::Link => {
                 return Err("Hard link entries are not supported for security reasons".into());
@@ -407,5 +763,229 @@ fn unpack_archive_safe<R: Read>(archive: &mut Archive<R>, dest_root: &Path) -> R
         }
     }
 
-    Ok(installed)
+    Ok(unpacked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(spec: &str) -> PrefixMap {
+        PrefixMap::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn remap_rewrites_a_matching_prefix() {
+        let maps = [map("/usr=>/usr/local")];
+        assert_eq!(remap_entry_path(Path::new("usr/bin/tool"), &maps).unwrap(), Path::new("usr/local/bin/tool"));
+        assert_eq!(remap_entry_path(Path::new("etc/tool.conf"), &maps).unwrap(), Path::new("etc/tool.conf"));
+        // Whole components only: /usrx is not under /usr
+        assert_eq!(remap_entry_path(Path::new("usrx/a"), &maps).unwrap(), Path::new("usrx/a"));
+    }
+
+    #[test]
+    fn remap_prefers_the_longest_prefix() {
+        let maps = [map("/usr=>/opt/a"), map("/usr/share=>/opt/b")];
+        assert_eq!(remap_entry_path(Path::new("usr/share/doc/x"), &maps).unwrap(), Path::new("opt/b/doc/x"));
+        assert_eq!(remap_entry_path(Path::new("usr/bin/x"), &maps).unwrap(), Path::new("opt/a/bin/x"));
+    }
+
+    #[test]
+    fn remap_never_leaves_the_root() {
+        let maps = [PrefixMap { from: PathBuf::from("/usr"), to: PathBuf::from("/../etc") }];
+        assert!(remap_entry_path(Path::new("usr/bin/x"), &maps).is_err());
+        let maps = [map("/usr=>/")];
+        assert_eq!(remap_entry_path(Path::new("usr"), &maps).unwrap(), Path::new(""));
+    }
+
+    /// Mode of every member of a package's data tarball, by path.
+    fn payload_modes(pkg: &Path) -> BTreeMap<String, u32> {
+        let (_, data) = read_nxpkg_parts(pkg, None).unwrap();
+        let mut archive = Archive::new(open_tar_stream(data.path(), "data.tar.gz").unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                let path = e.path().unwrap().to_string_lossy().trim_end_matches('/').to_string();
+                (path, e.header().mode().unwrap() & 0o7777)
+            })
+            .collect()
+    }
+
+    fn stage_with_modes(dir: &Path) -> PathBuf {
+        let staging = dir.join("stage");
+        fs::create_dir_all(staging.join("usr/bin")).unwrap();
+        fs::create_dir_all(staging.join("etc")).unwrap();
+        fs::write(staging.join("usr/bin/tool"), "#!/bin/sh\n").unwrap();
+        fs::write(staging.join("etc/tool.conf"), "x\n").unwrap();
+        fs::set_permissions(staging.join("usr/bin/tool"), fs::Permissions::from_mode(0o775)).unwrap();
+        fs::set_permissions(staging.join("etc/tool.conf"), fs::Permissions::from_mode(0o666)).unwrap();
+        fs::set_permissions(staging.join("etc"), fs::Permissions::from_mode(0o777)).unwrap();
+        staging
+    }
+
+    #[test]
+    fn pack_normalizes_modes_when_asked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let staging = stage_with_modes(tmp.path());
+        let recipe = PackageRecipe::from_str("[package]\nname = tool\nversion = 1\n").unwrap();
+        let out = tmp.path().join("tool.nxpkg");
+        create_nxpkg(&staging, &recipe, &out, &PackOptions { file_mode: Some(0o644) }).unwrap();
+
+        let modes = payload_modes(&out);
+        assert_eq!(modes["usr/bin/tool"], 0o755);
+        assert_eq!(modes["etc/tool.conf"], 0o644);
+        assert_eq!(modes["etc"], 0o755);
+    }
+
+    #[test]
+    fn pack_keeps_staging_modes_by_default() {
+        let tmp = tempfile::tempdir().unwrap();
+        let staging = stage_with_modes(tmp.path());
+        let recipe = PackageRecipe::from_str("[package]\nname = tool\nversion = 1\n").unwrap();
+        let out = tmp.path().join("tool.nxpkg");
+        create_nxpkg(&staging, &recipe, &out, &PackOptions::default()).unwrap();
+
+        let modes = payload_modes(&out);
+        assert_eq!(modes["usr/bin/tool"], 0o775);
+        assert_eq!(modes["etc/tool.conf"], 0o666);
+    }
+
+    /// Append `data` as a regular file member `path` of `builder`.
+    fn append_member<W: Write>(builder: &mut Builder<W>, path: &str, data: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+
+    /// A package written by hand: plain outer tar, `data.tar.gz` member holding `data` as is.
+    fn package_with_raw_data(dir: &Path, data: Vec<u8>) -> PathBuf {
+        let out = dir.join("plain.nxpkg");
+        let mut outer = Builder::new(File::create(&out).unwrap());
+        append_member(&mut outer, "package.cfg", b"[package]\nname = plain\nversion = 1\narchitectures = any\n");
+        append_member(&mut outer, "data.tar.gz", &data);
+        outer.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn uncompressed_data_tar_round_trips() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut inner = Builder::new(Vec::new());
+        append_member(&mut inner, "usr/share/plain/readme", b"not gzipped\n");
+        let pkg = package_with_raw_data(tmp.path(), inner.into_inner().unwrap());
+
+        let root = tmp.path().join("root");
+        let opts = ExtractOptions { root: root.clone(), ..ExtractOptions::default() };
+        let (recipe, unpacked) = extract_nxpkg(&pkg, &opts).unwrap();
+        assert_eq!(recipe.package.name, "plain");
+        assert_eq!(unpacked.files, vec![PathBuf::from("/usr/share/plain/readme")]);
+        assert_eq!(fs::read_to_string(root.join("usr/share/plain/readme")).unwrap(), "not gzipped\n");
+    }
+
+    #[test]
+    fn unsupported_data_compression_is_named() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pkg = package_with_raw_data(tmp.path(), b"\xfd7zXZ\x00rest of an xz stream".to_vec());
+        let opts = ExtractOptions { root: tmp.path().join("root"), ..ExtractOptions::default() };
+        let err = extract_nxpkg(&pkg, &opts).unwrap_err().to_string();
+        assert_eq!(err, "data.tar.gz: xz compression is not supported");
+    }
+
+    #[test]
+    fn config_files_round_trip_and_keep_local_edits() {
+        use crate::test_support::{build_package, Staged};
+        let tmp = tempfile::tempdir().unwrap();
+        let recipe = |version| format!(
+            "[package]\nname = conf\nversion = {}\narchitectures = any\n[install]\nconfig_files = /etc/conf/*.conf\n",
+            version
+        );
+        let v1 = build_package(tmp.path(), &recipe(1), &[
+            Staged::File("etc/conf/main.conf", "port = 1\n"),
+            Staged::File("etc/conf/extra.conf", "extra = 1\n"),
+            Staged::File("usr/bin/conf", "#!/bin/sh\n"),
+        ]);
+        let v2 = build_package(tmp.path(), &recipe(2), &[
+            Staged::File("etc/conf/main.conf", "port = 2\n"),
+            Staged::File("etc/conf/extra.conf", "extra = 2\n"),
+            Staged::File("usr/bin/conf", "#!/bin/sh\n"),
+        ]);
+        assert_eq!(read_recipe_from_nxpkg(&v1).unwrap().install.conffiles, ["/etc/conf/extra.conf", "/etc/conf/main.conf"]);
+
+        let root = tmp.path().join("root");
+        let opts = ExtractOptions { root: root.clone(), ..ExtractOptions::default() };
+        let (recipe, unpacked) = extract_nxpkg(&v1, &opts).unwrap();
+        assert_eq!(recipe.install.conffiles, ["/etc/conf/extra.conf", "/etc/conf/main.conf"]);
+        assert!(unpacked.kept_config.is_empty());
+
+        // The admin edits main.conf; extra.conf is untouched
+        fs::write(root.join("etc/conf/main.conf"), "port = 8080\n").unwrap();
+        let installed_hashes = unpacked.hashes.iter().map(|(p, h)| (p.to_string_lossy().to_string(), h.clone())).collect();
+        let (_, unpacked) = extract_nxpkg(&v2, &ExtractOptions { installed_hashes, ..opts }).unwrap();
+
+        assert_eq!(unpacked.kept_config, [PathBuf::from("/etc/conf/main.conf")]);
+        assert_eq!(fs::read_to_string(root.join("etc/conf/main.conf")).unwrap(), "port = 8080\n");
+        assert_eq!(fs::read_to_string(root.join("etc/conf/main.conf.nxnew")).unwrap(), "port = 2\n");
+        assert_eq!(fs::read_to_string(root.join("etc/conf/extra.conf")).unwrap(), "extra = 2\n");
+        assert!(!root.join("etc/conf/extra.conf.nxnew").exists());
+    }
+
+    #[test]
+    fn unknown_recipe_keys_survive_packing() {
+        use crate::test_support::{build_package, Staged};
+        let tmp = tempfile::tempdir().unwrap();
+        let pkg = build_package(
+            tmp.path(),
+            "schema = 2\n[package]\nname = future\nversion = 1\nlicense = MIT\n\
+             [build]\ndependencies = libc\n[custom]\nchannel = beta\nowner = ops\n",
+            &[Staged::File("usr/share/future/readme", "hi\n")],
+        );
+        let recipe = read_recipe_from_nxpkg(&pkg).unwrap();
+        let extra: Vec<(&str, &str, &str)> = recipe.extra.iter()
+            .flat_map(|(section, keys)| keys.iter().map(move |(k, v)| (section.as_str(), k.as_str(), v.as_str())))
+            .collect();
+        assert_eq!(extra, [
+            ("", "schema", "2"),
+            ("custom", "channel", "beta"),
+            ("custom", "owner", "ops"),
+            ("package", "license", "MIT"),
+        ]);
+        assert_eq!(recipe.build.dependencies, ["libc"]);
+    }
+
+    #[test]
+    fn glob_star_and_question_mark() {
+        assert!(glob_match("*.conf", "tool.conf"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("lib*.so*", "libfoo.so.1"));
+        assert!(!glob_match("*.conf", "tool.conf.bak"));
+        assert!(glob_match("tool.?", "tool.1"));
+        assert!(!glob_match("tool.?", "tool."));
+        assert!(!glob_match("tool.?", "tool.10"));
+    }
+
+    #[test]
+    fn glob_matches_the_whole_string() {
+        assert!(glob_match("usr/bin/tool", "usr/bin/tool"));
+        assert!(!glob_match("bin/tool", "usr/bin/tool"));
+        assert!(!glob_match("usr/bin", "usr/bin/tool"));
+        assert!(!glob_match("", "x"));
+    }
+
+    #[test]
+    fn glob_wildcards_cross_path_separators() {
+        assert!(glob_match("usr/*", "usr/share/doc/tool"));
+        assert!(glob_match("*/tool", "usr/bin/tool"));
+        assert!(glob_match("usr?bin", "usr/bin"));
+    }
+
+    #[test]
+    fn member_pattern_matches_an_entry_or_its_parent_directories() {
+        assert!(member_matches("usr/share/doc", Path::new("usr/share/doc/tool/README")));
+        assert!(member_matches("*.conf", Path::new("tool.conf")));
+        assert!(!member_matches("share", Path::new("usr/share/doc")));
+    }
 }
@@ -4,7 +4,130 @@
 use std::env;
 use std::fs;
 use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Serialize;
+use crate::db::backend::normalize_repo_url;
+
+/// Database and cache locations for the `/` root; other roots get the same paths inside them.
+const DEFAULT_DB_PATH: &str = "/var/lib/nxpkg/nxpkg_meta.db";
+const DEFAULT_CACHE_DIR: &str = "/var/cache/nxpkg";
+
+/// How package files are uploaded to HTTP repositories once they exceed the multipart threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UploadStrategy {
+    /// Always a single PUT request
+    #[default]
+    Put,
+    /// Sequential PUTs of each part to the same URL, each with a `Content-Range` header
+    Chunked,
+    /// S3-style multipart upload: initiate, PUT numbered parts, then complete
+    S3,
+}
+
+impl UploadStrategy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "put" => Some(UploadStrategy::Put),
+            "chunked" => Some(UploadStrategy::Chunked),
+            "s3" | "multipart" => Some(UploadStrategy::S3),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UploadStrategy::Put => "put",
+            UploadStrategy::Chunked => "chunked",
+            UploadStrategy::S3 => "s3",
+        }
+    }
+}
+
+/// Isolation used for `buildins`/`buildpkg` builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SandboxKind {
+    /// nxpkg's own chroot with host binaries copied in; requires root
+    #[default]
+    Chroot,
+    /// A podman or docker container from `[build] container_image`
+    Container,
+}
+
+impl SandboxKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "chroot" => Some(SandboxKind::Chroot),
+            "container" => Some(SandboxKind::Container),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SandboxKind::Chroot => "chroot",
+            SandboxKind::Container => "container",
+        }
+    }
+}
+
+/// Install-time destination prefix rewrite, e.g. `/usr=>/usr/local`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixMap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl PrefixMap {
+    /// Parse a single `from=>to` mapping. Both sides must be absolute and free of `..`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (from, to) = spec.split_once("=>")
+            .ok_or_else(|| format!("invalid prefix mapping '{}': expected <from>=><to>", spec))?;
+        let from = PathBuf::from(from.trim());
+        let to = PathBuf::from(to.trim());
+        for p in [&from, &to] {
+            if !p.is_absolute() {
+                return Err(format!("invalid prefix mapping '{}': {} is not absolute", spec, p.display()));
+            }
+            if p.components().any(|c| matches!(c, Component::ParentDir)) {
+                return Err(format!("invalid prefix mapping '{}': {} escapes the install root", spec, p.display()));
+            }
+        }
+        Ok(PrefixMap { from, to })
+    }
+}
+
+/// Where a resolved configuration value came from, lowest to highest precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+    Default,
+    System,
+    User,
+    /// Selected from repo_remotes (the active remote, or the only one defined)
+    Remote,
+    /// The file given with `--config`, read instead of the system and user config.cfg
+    File,
+    Env,
+    /// Command-line flag (e.g. `-j`)
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ConfigSource::Default => "default",
+            ConfigSource::System => "system",
+            ConfigSource::User => "user",
+            ConfigSource::Remote => "remote",
+            ConfigSource::File => "file",
+            ConfigSource::Env => "env",
+            ConfigSource::Cli => "cli",
+        };
+        f.write_str(s)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
@@ -13,9 +136,66 @@ pub struct AppConfig {
     pub cache_dir: PathBuf,
     pub require_signed_index: bool,
     pub pubkey_path: PathBuf,
+    // Where index.json's signature is published and fetched: relative to the repo, or a full URL
+    pub sig_path: String,
+    // Sign and verify index.json over its canonical JSON form instead of the raw bytes only
+    pub canonical_signing: bool,
+    // Reject indexes whose arch assets declare a different arch than their map key
+    pub strict_arch_assets: bool,
+    // Program run on each downloaded package before extraction; non-zero exit aborts the install
+    pub verify_hook: Option<PathBuf>,
+    // Other base URLs serving the same repository as repo_url, tried in order when it fails
+    pub mirrors: Vec<String>,
+    // Order repo_url and its mirrors by a latency probe before fetching the index
+    pub rank_mirrors: bool,
     // Multiple binary repository remotes and active selection
     pub repo_remotes: BTreeMap<String, String>, // name -> url
     pub active_repo: Option<String>,           // name
+    // Directory that package paths are installed under and removed from; "/" is the live system
+    pub root: PathBuf,
+    // Architecture packages are resolved and installed for; the host unless install --arch is given
+    pub arch: String,
+    // Destination prefix rewrites applied when installing package files
+    pub prefix_map: Vec<PrefixMap>,
+    // Warn about installed symlinks whose target is missing once an install finishes
+    pub check_links: bool,
+    // Build a package from its source repo when the index has no binary asset for the arch
+    pub source_fallback: bool,
+    // Check all remotes for conflicting entries of the same package name on install
+    pub detect_duplicates: bool,
+    // Skip malformed package entries in index.json instead of rejecting the whole index
+    pub lenient_index: bool,
+    // Verified index snapshots kept per repository for rollback-index; 0 disables them
+    pub index_history: usize,
+    // Base mode for normalizing packaged file permissions; None keeps staging modes
+    pub package_file_mode: Option<u32>,
+    // Unprivileged user (name or uid) that chroot build commands run as; None = nobody
+    pub build_user: Option<String>,
+    // Where build commands run; Container needs container_image and a runtime
+    pub sandbox: SandboxKind,
+    // Image for container builds; must already contain the toolchain
+    pub container_image: Option<String>,
+    // Container runtime command; None = podman, else docker, whichever is installed
+    pub container_runtime: Option<String>,
+    // Screen build commands for dangerous patterns before running them (best effort)
+    pub command_guard: bool,
+    // Build commands containing any of these strings are never flagged by the guard
+    pub command_guard_allow: Vec<String>,
+    // JSON Lines file that receives one event per install/remove; None disables auditing
+    pub audit_file: Option<PathBuf>,
+    // How uploads above multipart_threshold are split; Put never splits
+    pub upload_strategy: UploadStrategy,
+    // Size in bytes above which uploads are split into parts of at most this size
+    pub multipart_threshold: u64,
+    // Rolling copies of the package database kept before modifying commands; 0 disables them
+    pub db_backups: usize,
+    // Upper bound on concurrent work (chroot prepare, downloads, forge searches); defaults to the CPU count
+    pub max_parallelism: usize,
+    // Skip index signature verification for this invocation only; set by --insecure-skip-verify,
+    // deliberately never read from config files or the environment
+    pub insecure_skip_verify: bool,
+    // Origin of each value set by a config file, remote selection or env var; absent = default
+    pub sources: BTreeMap<&'static str, ConfigSource>,
 }
 
 impl Default for AppConfig {
@@ -23,48 +203,139 @@ impl Default for AppConfig {
         AppConfig {
             // Leave empty by default; will be resolved from repo_remotes/active or env/config
             repo_url: String::new(),
-            db_path: PathBuf::from("/var/lib/nxpkg/nxpkg_meta.db"),
-            cache_dir: PathBuf::from("/var/cache/nxpkg"),
+            db_path: PathBuf::from(DEFAULT_DB_PATH),
+            cache_dir: PathBuf::from(DEFAULT_CACHE_DIR),
             require_signed_index: true,
             pubkey_path: PathBuf::from("/etc/nxpkg/nxpkg.pub"),
+            sig_path: crate::db::backend::DEFAULT_SIG_PATH.to_string(),
+            canonical_signing: false,
+            strict_arch_assets: false,
+            verify_hook: None,
+            mirrors: Vec::new(),
+            rank_mirrors: false,
             repo_remotes: BTreeMap::new(),
             active_repo: None,
+            root: PathBuf::from("/"),
+            arch: crate::arch::current_arch().to_string(),
+            prefix_map: Vec::new(),
+            check_links: false,
+            source_fallback: false,
+            detect_duplicates: false,
+            lenient_index: false,
+            index_history: 0,
+            package_file_mode: None,
+            build_user: None,
+            sandbox: SandboxKind::Chroot,
+            container_image: None,
+            container_runtime: None,
+            command_guard: false,
+            command_guard_allow: Vec::new(),
+            audit_file: None,
+            upload_strategy: UploadStrategy::Put,
+            multipart_threshold: 64 * 1024 * 1024,
+            db_backups: 3,
+            max_parallelism: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            insecure_skip_verify: false,
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+/// Where `AppConfig::load_with` reads configuration from. `standard()` is what nxpkg uses;
+/// `--config` and tests substitute their own files and environment.
+#[derive(Debug, Clone)]
+pub struct ConfigSources {
+    /// config.cfg files, lowest precedence first, with the source their values are reported as.
+    /// Missing files are skipped.
+    pub config_files: Vec<(PathBuf, ConfigSource)>,
+    /// repo_remotes.cfg files, lowest precedence first. Missing files are skipped.
+    pub remotes_files: Vec<PathBuf>,
+    /// Variables for the `NXPKG_*` overrides; None reads the process environment
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+impl ConfigSources {
+    /// /etc/nxpkg and the user's config directory ($XDG_CONFIG_HOME/nxpkg or ~/.config/nxpkg),
+    /// with the process environment.
+    pub fn standard() -> Self {
+        let user_dir = user_config_dir();
+        ConfigSources {
+            config_files: vec![
+                (PathBuf::from("/etc/nxpkg/config.cfg"), ConfigSource::System),
+                (user_dir.join("config.cfg"), ConfigSource::User),
+            ],
+            remotes_files: vec![PathBuf::from("/etc/nxpkg/repo_remotes.cfg"), user_dir.join("repo_remotes.cfg")],
+            env: None,
+        }
+    }
+
+    fn var(&self, key: &str) -> Option<String> {
+        match &self.env {
+            Some(vars) => vars.get(key).cloned(),
+            None => env::var(key).ok(),
         }
     }
 }
 
+/// $XDG_CONFIG_HOME/nxpkg, or ~/.config/nxpkg when it is unset.
+fn user_config_dir() -> PathBuf {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("~/.config").expand_home())
+        .join("nxpkg")
+}
+
 impl AppConfig {
+    /// Configuration from /etc/nxpkg/config.cfg, the user's config.cfg, the repo remote files
+    /// and the environment, in increasing precedence.
     pub fn load() -> Self {
-        let mut cfg = AppConfig::default();
+        Self::load_with(&ConfigSources::standard())
+    }
 
-        // 1) Load system config: /etc/nxpkg/config.cfg
-        let sys_cfg = Path::new("/etc/nxpkg/config.cfg");
-        if sys_cfg.exists() {
-            if let Err(e) = Self::apply_cfg_file(&mut cfg, sys_cfg) {
-                eprintln!("Warning: failed to load {}: {}", sys_cfg.display(), e);
-            }
+    /// Like `load`, but reads only `path` (`--config`) instead of the system and user
+    /// config.cfg and repo remote files, so the host's setup cannot leak in. Environment variables
+    /// (and CLI flags, applied by the caller) still override it.
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        if !path.is_file() {
+            return Err(format!("config file {} does not exist", path.display()));
         }
+        fs::File::open(path).map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+        Ok(Self::load_with(&ConfigSources {
+            config_files: vec![(path.to_path_buf(), ConfigSource::File)],
+            remotes_files: Vec::new(),
+            env: None,
+        }))
+    }
+
+    /// Load configuration from `sources`: defaults, then each config file, the repo remote
+    /// files and finally the environment overrides. Nothing is created on disk; the caller makes
+    /// the database and cache directories once the final root is known (see `set_root`).
+    pub fn load_with(sources: &ConfigSources) -> Self {
+        let mut cfg = AppConfig::default();
 
-        // 2) Load user config: $XDG_CONFIG_HOME/nxpkg/config.cfg or ~/.config/nxpkg/config.cfg
-        let user_cfg = env::var_os("XDG_CONFIG_HOME")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| PathBuf::from("~/.config").expand_home());
-        let user_cfg_path = user_cfg.join("nxpkg/config.cfg");
-        if user_cfg_path.exists() {
-            if let Err(e) = Self::apply_cfg_file(&mut cfg, &user_cfg_path) {
-                eprintln!("Warning: failed to load {}: {}", user_cfg_path.display(), e);
+        // 1) config.cfg files: system, then user (or the single --config file)
+        for (path, source) in &sources.config_files {
+            if path.exists() {
+                if let Err(e) = Self::apply_cfg_file(&mut cfg, path, *source) {
+                    eprintln!("Warning: failed to load {}: {}", path.display(), e);
+                }
             }
         }
 
-        // 2.5) Load repo remotes from files and apply active
-        Self::apply_repo_remotes_files(&mut cfg);
+        // 2) Load repo remotes from files and apply active
+        Self::apply_repo_remotes_files(&mut cfg, &sources.remotes_files);
 
         // 3) Environment overrides (highest priority)
-        if let Ok(v) = env::var("NXPKG_REPO_URL") { cfg.repo_url = v; }
-        if let Ok(v) = env::var("NXPKG_DB_PATH") { cfg.db_path = PathBuf::from(v); }
-        if let Ok(v) = env::var("NXPKG_CACHE_DIR") { cfg.cache_dir = PathBuf::from(v); }
-        if let Ok(v) = env::var("NXPKG_REQUIRE_SIGNED_INDEX") { cfg.require_signed_index = v == "1" || v.eq_ignore_ascii_case("true"); }
-        if let Ok(v) = env::var("NXPKG_PUBKEY_PATH") { cfg.pubkey_path = PathBuf::from(v); }
+        if let Some(v) = sources.var("NXPKG_REPO_URL") { cfg.repo_url = v; cfg.sources.insert("repo_url", ConfigSource::Env); }
+        if let Some(v) = sources.var("NXPKG_DB_PATH") { cfg.db_path = PathBuf::from(v); cfg.sources.insert("db_path", ConfigSource::Env); }
+        if let Some(v) = sources.var("NXPKG_CACHE_DIR") { cfg.cache_dir = PathBuf::from(v); cfg.sources.insert("cache_dir", ConfigSource::Env); }
+        if let Some(v) = sources.var("NXPKG_REQUIRE_SIGNED_INDEX") {
+            cfg.require_signed_index = v == "1" || v.eq_ignore_ascii_case("true");
+            cfg.sources.insert("require_signed_index", ConfigSource::Env);
+        }
+        if let Some(v) = sources.var("NXPKG_PUBKEY_PATH") { cfg.pubkey_path = PathBuf::from(v); cfg.sources.insert("pubkey_path", ConfigSource::Env); }
+        if let Some(v) = sources.var("NXPKG_ROOT") { if !v.is_empty() { cfg.root = PathBuf::from(v); cfg.sources.insert("root", ConfigSource::Env); } }
+        cfg.derive_root_paths();
 
         // 3.5) Final fallback: if repo_url still empty, try to resolve from remotes
         if cfg.repo_url.trim().is_empty() {
@@ -75,18 +346,47 @@ impl AppConfig {
             if let Some(name) = candidate {
                 if let Some(url) = cfg.repo_remotes.get(&name) {
                     cfg.repo_url = url.clone();
+                    cfg.sources.insert("repo_url", ConfigSource::Remote);
                 }
             }
         }
 
-        // 4) Ensure directories exist for db parent and cache dir
-        if let Some(parent) = cfg.db_path.parent() { let _ = fs::create_dir_all(parent); }
-        let _ = fs::create_dir_all(&cfg.cache_dir);
+        // Every consumer sees repository URLs in one form (scheme, no duplicate or trailing slashes)
+        cfg.repo_url = normalize_repo_url(&cfg.repo_url);
+        for url in cfg.repo_remotes.values_mut() {
+            *url = normalize_repo_url(url);
+        }
 
         cfg
     }
 
-    fn apply_cfg_file(cfg: &mut AppConfig, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    /// Source of a resolved value, keyed by the field name (e.g. `"repo_url"`).
+    pub fn source_of(&self, key: &str) -> ConfigSource {
+        self.sources.get(key).copied().unwrap_or(ConfigSource::Default)
+    }
+
+    /// Install into `root` from now on, moving the state that belongs to it along (see
+    /// `derive_root_paths`).
+    pub fn set_root(&mut self, root: PathBuf, source: ConfigSource) {
+        self.root = root;
+        self.sources.insert("root", source);
+        self.derive_root_paths();
+    }
+
+    /// Place the database and cache of a non-`/` root inside it, at `<root>/var/lib/nxpkg` and
+    /// `<root>/var/cache/nxpkg`, so a staged tree never shares the host's state. The index
+    /// cache, index history and database backups live under these two. An explicitly
+    /// configured `db_path` or `cache_dir` is left alone.
+    fn derive_root_paths(&mut self) {
+        if self.source_of("db_path") == ConfigSource::Default {
+            self.db_path = self.root.join(DEFAULT_DB_PATH.trim_start_matches('/'));
+        }
+        if self.source_of("cache_dir") == ConfigSource::Default {
+            self.cache_dir = self.root.join(DEFAULT_CACHE_DIR.trim_start_matches('/'));
+        }
+    }
+
+    fn apply_cfg_file(cfg: &mut AppConfig, path: &Path, source: ConfigSource) -> Result<(), Box<dyn std::error::Error>> {
         let content = fs::read_to_string(path)?;
         let mut section = String::new();
         for line in content.lines() {
@@ -101,43 +401,182 @@ impl AppConfig {
             if let Some((key, value)) = line.split_once('=') {
                 let key = key.trim();
                 let value = value.trim();
-                match section.as_str() {
-                    "repo" => {
-                        if key == "url" { cfg.repo_url = value.to_string(); }
-                    }
-                    "storage" => {
-                        if key == "db_path" { cfg.db_path = PathBuf::from(value); }
-                        else if key == "cache_dir" { cfg.cache_dir = PathBuf::from(value); }
-                    }
-                    "security" => {
-                        if key == "require_signed_index" {
-                            cfg.require_signed_index = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
-                        } else if key == "pubkey_path" {
-                            cfg.pubkey_path = PathBuf::from(value);
+                let set: Option<&'static str> = match section.as_str() {
+                    "repo" if key == "url" => {
+                        cfg.repo_url = value.to_string();
+                        Some("repo_url")
+                    }
+                    "repo" if key == "mirrors" => {
+                        cfg.mirrors = value.split(',')
+                            .map(str::trim)
+                            .filter(|u| !u.is_empty())
+                            .map(crate::db::backend::normalize_repo_url)
+                            .collect();
+                        Some("mirrors")
+                    }
+                    "repo" if key == "detect_duplicates" => {
+                        cfg.detect_duplicates = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("detect_duplicates")
+                    }
+                    "repo" if key == "lenient_index" => {
+                        cfg.lenient_index = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("lenient_index")
+                    }
+                    "repo" if key == "index_history" => {
+                        match value.parse::<usize>() {
+                            Ok(n) => cfg.index_history = n,
+                            Err(_) => eprintln!("Warning: {}: index_history must be a non-negative integer, got '{}'", path.display(), value),
                         }
+                        Some("index_history")
                     }
-                    _ => {}
+                    "storage" if key == "db_path" => {
+                        cfg.db_path = PathBuf::from(value);
+                        Some("db_path")
+                    }
+                    "storage" if key == "cache_dir" => {
+                        cfg.cache_dir = PathBuf::from(value);
+                        Some("cache_dir")
+                    }
+                    "storage" if key == "db_backups" => {
+                        match value.parse::<usize>() {
+                            Ok(n) => cfg.db_backups = n,
+                            Err(_) => eprintln!("Warning: {}: db_backups must be a non-negative integer, got '{}'", path.display(), value),
+                        }
+                        Some("db_backups")
+                    }
+                    "security" if key == "require_signed_index" => {
+                        cfg.require_signed_index = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("require_signed_index")
+                    }
+                    "security" if key == "pubkey_path" => {
+                        cfg.pubkey_path = PathBuf::from(value);
+                        Some("pubkey_path")
+                    }
+                    "security" if key == "sig_path" => {
+                        cfg.sig_path = if value.is_empty() { crate::db::backend::DEFAULT_SIG_PATH.to_string() } else { value.to_string() };
+                        Some("sig_path")
+                    }
+                    "security" if key == "canonical_signing" => {
+                        cfg.canonical_signing = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("canonical_signing")
+                    }
+                    "security" if key == "strict_arch_assets" => {
+                        cfg.strict_arch_assets = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("strict_arch_assets")
+                    }
+                    "security" if key == "verify_hook" => {
+                        cfg.verify_hook = if value.is_empty() { None } else { Some(PathBuf::from(value)) };
+                        Some("verify_hook")
+                    }
+                    "install" if key == "root" => {
+                        cfg.root = if value.is_empty() { PathBuf::from("/") } else { PathBuf::from(value) };
+                        Some("root")
+                    }
+                    "install" if key == "check_links" => {
+                        cfg.check_links = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("check_links")
+                    }
+                    "install" if key == "source_fallback" => {
+                        cfg.source_fallback = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("source_fallback")
+                    }
+                    "install" if key == "prefix_map" => {
+                        cfg.prefix_map.clear();
+                        for spec in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                            match PrefixMap::parse(spec) {
+                                Ok(m) => cfg.prefix_map.push(m),
+                                Err(e) => eprintln!("Warning: {}: {}", path.display(), e),
+                            }
+                        }
+                        Some("prefix_map")
+                    }
+                    "network" if key == "rank_mirrors" => {
+                        cfg.rank_mirrors = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("rank_mirrors")
+                    }
+                    "network" if key == "upload_strategy" => {
+                        match UploadStrategy::parse(value) {
+                            Some(s) => cfg.upload_strategy = s,
+                            None => eprintln!("Warning: {}: invalid upload_strategy '{}': expected put, chunked or s3", path.display(), value),
+                        }
+                        Some("upload_strategy")
+                    }
+                    "network" if key == "multipart_threshold" => {
+                        match parse_size(value) {
+                            Ok(n) => cfg.multipart_threshold = n,
+                            Err(e) => eprintln!("Warning: {}: {}", path.display(), e),
+                        }
+                        Some("multipart_threshold")
+                    }
+                    "package" if key == "default_file_mode" => {
+                        match parse_file_mode(value) {
+                            Ok(m) => cfg.package_file_mode = Some(m),
+                            Err(e) => eprintln!("Warning: {}: {}", path.display(), e),
+                        }
+                        Some("default_file_mode")
+                    }
+                    "package" if key == "normalize_modes" => {
+                        if matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes") {
+                            cfg.package_file_mode.get_or_insert(0o644);
+                        } else {
+                            cfg.package_file_mode = None;
+                        }
+                        Some("default_file_mode")
+                    }
+                    "build" if key == "build_user" => {
+                        cfg.build_user = if value.is_empty() { None } else { Some(value.to_string()) };
+                        Some("build_user")
+                    }
+                    "build" if key == "sandbox" => {
+                        match SandboxKind::parse(value) {
+                            Some(k) => cfg.sandbox = k,
+                            None => eprintln!("Warning: {}: invalid sandbox '{}': expected chroot or container", path.display(), value),
+                        }
+                        Some("sandbox")
+                    }
+                    "build" if key == "container_image" => {
+                        cfg.container_image = if value.is_empty() { None } else { Some(value.to_string()) };
+                        Some("container_image")
+                    }
+                    "build" if key == "container_runtime" => {
+                        cfg.container_runtime = if value.is_empty() { None } else { Some(value.to_string()) };
+                        Some("container_runtime")
+                    }
+                    "build" if key == "command_guard" => {
+                        cfg.command_guard = matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+                        Some("command_guard")
+                    }
+                    "build" if key == "command_guard_allow" => {
+                        cfg.command_guard_allow = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+                        Some("command_guard_allow")
+                    }
+                    "logging" if key == "audit_file" => {
+                        cfg.audit_file = if value.is_empty() { None } else { Some(PathBuf::from(value)) };
+                        Some("audit_file")
+                    }
+                    "performance" if key == "max_parallelism" => {
+                        match value.parse::<usize>() {
+                            Ok(n) if n > 0 => cfg.max_parallelism = n,
+                            _ => eprintln!("Warning: {}: max_parallelism must be a positive integer, got '{}'", path.display(), value),
+                        }
+                        Some("max_parallelism")
+                    }
+                    _ => None,
+                };
+                if let Some(field) = set {
+                    cfg.sources.insert(field, source);
                 }
             }
         }
         Ok(())
     }
-    fn apply_repo_remotes_files(cfg: &mut AppConfig) {
-        // Read system-wide repo remotes
-        let sys_file = Path::new("/etc/nxpkg/repo_remotes.cfg");
-        if sys_file.exists() {
-            if let Err(e) = Self::apply_repo_remotes_from_file(cfg, sys_file) {
-                eprintln!("Warning: failed to load {}: {}", sys_file.display(), e);
-            }
-        }
-        // Read user repo remotes
-        let user_base = env::var_os("XDG_CONFIG_HOME")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| PathBuf::from("~/.config").expand_home());
-        let user_file = user_base.join("nxpkg/repo_remotes.cfg");
-        if user_file.exists() {
-            if let Err(e) = Self::apply_repo_remotes_from_file(cfg, &user_file) {
-                eprintln!("Warning: failed to load {}: {}", user_file.display(), e);
+    fn apply_repo_remotes_files(cfg: &mut AppConfig, files: &[PathBuf]) {
+        // System-wide first, then the user's
+        for file in files {
+            if file.exists() {
+                if let Err(e) = Self::apply_repo_remotes_from_file(cfg, file) {
+                    eprintln!("Warning: failed to load {}: {}", file.display(), e);
+                }
             }
         }
 
@@ -145,6 +584,7 @@ impl AppConfig {
         if let Some(active) = cfg.active_repo.clone() {
             if let Some(url) = cfg.repo_remotes.get(&active) {
                 cfg.repo_url = url.clone();
+                cfg.sources.insert("repo_url", ConfigSource::Remote);
             }
         }
     }
@@ -164,10 +604,8 @@ impl AppConfig {
                 let value = value.trim();
                 match section.as_str() {
                     "repo_remotes" => { cfg.repo_remotes.insert(key.to_string(), value.to_string()); }
-                    "active" => {
-                        if key.eq_ignore_ascii_case("name") || key.eq_ignore_ascii_case("active") {
-                            cfg.active_repo = Some(value.to_string());
-                        }
+                    "active" if key.eq_ignore_ascii_case("name") || key.eq_ignore_ascii_case("active") => {
+                        cfg.active_repo = Some(value.to_string());
                     }
                     _ => {}
                 }
@@ -178,10 +616,7 @@ impl AppConfig {
 
     // User-facing helpers to manage repo_remotes in user config file
     pub fn user_repo_remotes_path() -> PathBuf {
-        env::var_os("XDG_CONFIG_HOME")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| PathBuf::from("~/.config").expand_home())
-            .join("nxpkg/repo_remotes.cfg")
+        user_config_dir().join("repo_remotes.cfg")
     }
 
     pub fn save_repo_remotes(map: &BTreeMap<String,String>, active: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
@@ -200,7 +635,7 @@ impl AppConfig {
         let mut map: BTreeMap<String, String> = BTreeMap::new();
         // Merge system and user for context (we only write user)
         let mut tmp = AppConfig::default();
-        Self::apply_repo_remotes_files(&mut tmp);
+        Self::apply_repo_remotes_files(&mut tmp, &ConfigSources::standard().remotes_files);
         map.extend(tmp.repo_remotes);
         map.insert(name.trim().to_string(), url.trim().to_string());
         let active = tmp.active_repo.as_deref();
@@ -209,7 +644,7 @@ impl AppConfig {
 
     pub fn remove_repo_remote(name: &str) -> Result<(), Box<dyn std::error::Error>> {
         let mut tmp = AppConfig::default();
-        Self::apply_repo_remotes_files(&mut tmp);
+        Self::apply_repo_remotes_files(&mut tmp, &ConfigSources::standard().remotes_files);
         let mut map = tmp.repo_remotes;
         let was_active = tmp.active_repo.clone();
         map.remove(name);
@@ -219,7 +654,7 @@ impl AppConfig {
 
     pub fn set_active_repo(name: &str) -> Result<(), Box<dyn std::error::Error>> {
         let mut tmp = AppConfig::default();
-        Self::apply_repo_remotes_files(&mut tmp);
+        Self::apply_repo_remotes_files(&mut tmp, &ConfigSources::standard().remotes_files);
         if !tmp.repo_remotes.contains_key(name) {
             return Err(format!("repo remote '{}' not found", name).into());
         }
@@ -227,6 +662,34 @@ impl AppConfig {
     }
 }
 
+/// Parse an octal permission mode such as `0644` or `0o644`.
+fn parse_file_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(m) if m <= 0o777 => Ok(m),
+        _ => Err(format!("invalid default_file_mode '{}': expected an octal mode like 0644", value)),
+    }
+}
+
+/// Parse a byte size such as `8388608`, `512K`, `64M` or `1G` (binary units).
+fn parse_size(value: &str) -> Result<u64, String> {
+    let err = || format!("invalid multipart_threshold '{}': expected a size like 64M", value);
+    let v = value.trim();
+    let (digits, unit) = match v.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => v.split_at(i),
+        None => (v, ""),
+    };
+    let shift = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => return Err(err()),
+    };
+    let n: u64 = digits.parse().map_err(|_| err())?;
+    n.checked_mul(1u64 << shift).filter(|&n| n > 0).ok_or_else(err)
+}
+
 // Small helper to expand leading ~ in paths
 trait ExpandHome {
     fn expand_home(self) -> PathBuf;
@@ -243,3 +706,109 @@ impl ExpandHome for PathBuf {
         PathBuf::from(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_map_parses_absolute_mappings() {
+        let m = PrefixMap::parse(" /usr => /usr/local ").unwrap();
+        assert_eq!(m, PrefixMap { from: PathBuf::from("/usr"), to: PathBuf::from("/usr/local") });
+    }
+
+    #[test]
+    fn prefix_map_rejects_relative_and_escaping_paths() {
+        assert!(PrefixMap::parse("/usr").is_err());
+        assert!(PrefixMap::parse("usr=>/usr/local").is_err());
+        assert!(PrefixMap::parse("/usr=>local").is_err());
+        assert!(PrefixMap::parse("/usr=>/opt/../..").is_err());
+        assert!(PrefixMap::parse("/usr/../etc=>/opt").is_err());
+    }
+
+    /// Sources reading `system` and `user` config.cfg from `dir`, with `env` as the environment.
+    fn sources_in(dir: &Path, system: &str, user: &str, env: &[(&str, &str)]) -> ConfigSources {
+        fs::write(dir.join("system.cfg"), system).unwrap();
+        fs::write(dir.join("user.cfg"), user).unwrap();
+        ConfigSources {
+            config_files: vec![(dir.join("system.cfg"), ConfigSource::System), (dir.join("user.cfg"), ConfigSource::User)],
+            remotes_files: vec![dir.join("missing-remotes.cfg")],
+            env: Some(env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+        }
+    }
+
+    #[test]
+    fn later_sources_override_earlier_ones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let state = tmp.path().join("state");
+        let system = format!(
+            "[repo]\nurl = https://system.example/nx\n[storage]\ndb_path = {0}/system.db\ncache_dir = {0}/system-cache\n\
+             [performance]\nmax_parallelism = 3\n",
+            state.display()
+        );
+        let user = format!("[repo]\nurl = https://user.example/nx\n[storage]\ncache_dir = {}/user-cache\n", state.display());
+        let env = [("NXPKG_REPO_URL", "https://env.example/nx")];
+        let cfg = AppConfig::load_with(&sources_in(tmp.path(), &system, &user, &env));
+
+        assert_eq!((cfg.repo_url.as_str(), cfg.source_of("repo_url")), ("https://env.example/nx", ConfigSource::Env));
+        assert_eq!((cfg.cache_dir.clone(), cfg.source_of("cache_dir")), (state.join("user-cache"), ConfigSource::User));
+        assert_eq!((cfg.db_path.clone(), cfg.source_of("db_path")), (state.join("system.db"), ConfigSource::System));
+        assert_eq!((cfg.max_parallelism, cfg.source_of("max_parallelism")), (3, ConfigSource::System));
+        assert_eq!(cfg.source_of("pubkey_path"), ConfigSource::Default);
+
+        let cfg = AppConfig::load_with(&sources_in(tmp.path(), &system, &user, &[]));
+        assert_eq!((cfg.repo_url.as_str(), cfg.source_of("repo_url")), ("https://user.example/nx", ConfigSource::User));
+    }
+
+    #[test]
+    fn loading_creates_no_directories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db_path = tmp.path().join("db/nxpkg_meta.db");
+        let env = [("NXPKG_DB_PATH", db_path.to_str().unwrap())];
+        let user = format!("[storage]\ncache_dir = {}\n", tmp.path().join("cache").display());
+        let cfg = AppConfig::load_with(&sources_in(tmp.path(), "", &user, &env));
+        assert_eq!((&cfg.db_path, cfg.source_of("db_path")), (&db_path, ConfigSource::Env));
+        assert!(!tmp.path().join("db").exists());
+        assert!(!tmp.path().join("cache").exists());
+    }
+
+    #[test]
+    fn load_from_reads_only_the_given_file_and_the_environment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("profile.cfg");
+        fs::write(&path, "[repo]\nurl = https://profile.example/nx\n[performance]\nmax_parallelism = 5\n").unwrap();
+        let cfg = AppConfig::load_from(&path).unwrap();
+        assert_eq!((cfg.repo_url.as_str(), cfg.source_of("repo_url")), ("https://profile.example/nx", ConfigSource::File));
+        assert_eq!((cfg.max_parallelism, cfg.source_of("max_parallelism")), (5, ConfigSource::File));
+        assert!(cfg.repo_remotes.is_empty());
+        assert!(cfg.active_repo.is_none());
+
+        assert!(AppConfig::load_from(&tmp.path().join("missing.cfg")).is_err());
+    }
+
+    #[test]
+    fn set_root_moves_default_state_under_the_root() {
+        let mut cfg = AppConfig::default();
+        cfg.set_root(PathBuf::from("/tmp/x"), ConfigSource::Cli);
+        assert_eq!(cfg.db_path, Path::new("/tmp/x/var/lib/nxpkg/nxpkg_meta.db"));
+        assert_eq!(cfg.cache_dir, Path::new("/tmp/x/var/cache/nxpkg"));
+        assert_eq!(cfg.source_of("root"), ConfigSource::Cli);
+        assert_eq!(cfg.source_of("db_path"), ConfigSource::Default);
+    }
+
+    #[test]
+    fn set_root_keeps_configured_state_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let env = [("NXPKG_DB_PATH", "/srv/nxpkg/meta.db")];
+        let mut cfg = AppConfig::load_with(&sources_in(tmp.path(), "", "", &env));
+        cfg.set_root(PathBuf::from("/tmp/x"), ConfigSource::Cli);
+        assert_eq!(cfg.db_path, Path::new("/srv/nxpkg/meta.db"));
+        assert!(cfg.cache_dir.starts_with("/tmp/x"));
+
+        let user = "[storage]\ncache_dir = /srv/nxpkg/cache\n";
+        let mut cfg = AppConfig::load_with(&sources_in(tmp.path(), "", user, &[]));
+        cfg.set_root(PathBuf::from("/tmp/x"), ConfigSource::Cli);
+        assert_eq!(cfg.cache_dir, Path::new("/srv/nxpkg/cache"));
+        assert!(cfg.db_path.starts_with("/tmp/x"));
+    }
+}
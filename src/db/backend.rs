@@ -0,0 +1,222 @@
+//! src/db/backend.rs
+//! Storage backends for binary repositories: HTTP(S) servers and local `file://` directories.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::StatusCode;
+
+use crate::db::upload::{upload_file_put, UploadOptions};
+
+/// Where a repository's index.json and package files live.
+pub enum RepoBackend {
+    /// Read with GET, written with PUT (optionally with a Bearer token).
+    Http { base: String, token: Option<String>, upload: UploadOptions },
+    /// A directory on the local filesystem (`file:///srv/nxpkg`).
+    File { root: PathBuf },
+}
+
+/// Canonical form of a configured repository URL: surrounding whitespace removed, a scheme
+/// added when missing (`file://` for an absolute path, `https://` otherwise), runs of `/` in
+/// the path collapsed and trailing slashes stripped. `host//repo/` becomes `https://host/repo`.
+pub fn normalize_repo_url(url: &str) -> String {
+    let url = url.trim();
+    if url.is_empty() {
+        return String::new();
+    }
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+        None if url.starts_with('/') => ("file".to_string(), url),
+        None => ("https".to_string(), url),
+    };
+    let mut path = String::with_capacity(rest.len());
+    for c in rest.chars() {
+        if !(c == '/' && path.ends_with('/')) {
+            path.push(c);
+        }
+    }
+    let trimmed = path.trim_end_matches('/');
+    // A file:// repo at the filesystem root keeps its "/"
+    let path = if trimmed.is_empty() && scheme == "file" { "/" } else { trimmed };
+    format!("{}://{}", scheme, path)
+}
+
+/// Default `[security] sig_path`: the signature next to index.json.
+pub const DEFAULT_SIG_PATH: &str = "index.json.sig";
+
+/// Backend and file name of the index signature for `[security] sig_path`. A full URL
+/// (`https://sign.example/nxpkg/index.json.sig`, `file:///srv/sigs/index.json.sig`) is used as is,
+/// anything else is relative to the repository root. Publishing and fetching both go through
+/// this, so they always address the same location.
+pub fn signature_target(repo_url: &str, sig_path: &str, token: Option<&str>) -> Result<(RepoBackend, String), String> {
+    let sig_path = sig_path.trim();
+    let full = if sig_path.contains("://") {
+        sig_path.to_string()
+    } else {
+        format!("{}/{}", normalize_repo_url(repo_url), sig_path.trim_start_matches('/'))
+    };
+    match full.rsplit_once('/') {
+        Some((dir, name)) if !name.is_empty() && name != "." && name != ".." => {
+            Ok((RepoBackend::from_url(dir, token), name.to_string()))
+        }
+        _ => Err(format!("invalid sig_path '{}': it must name a file", sig_path)),
+    }
+}
+
+/// Local directory for a `file://` repo URL, or None for any other scheme.
+pub fn file_url_path(url: &str) -> Option<PathBuf> {
+    url.strip_prefix("file://").map(PathBuf::from)
+}
+
+impl RepoBackend {
+    pub fn from_url(repo_url: &str, token: Option<&str>) -> Self {
+        let base = normalize_repo_url(repo_url);
+        match file_url_path(&base) {
+            Some(root) => RepoBackend::File { root },
+            None => RepoBackend::Http { base, token: token.map(str::to_string), upload: UploadOptions::default() },
+        }
+    }
+
+    /// Use `opts` for `put_file` uploads; local repos ignore it.
+    pub fn with_upload(mut self, opts: UploadOptions) -> Self {
+        if let RepoBackend::Http { upload, .. } = &mut self {
+            *upload = opts;
+        }
+        self
+    }
+
+    /// URL of `name` inside the repository, as recorded in index.json.
+    pub fn url_for(&self, name: &str) -> String {
+        match self {
+            RepoBackend::Http { base, .. } => format!("{}/{}", base, name),
+            RepoBackend::File { root } => format!("file://{}", root.join(name).display()),
+        }
+    }
+
+    /// Fetch `name` from the repository root. Returns Ok(None) when it does not exist.
+    pub async fn get(&self, name: &str) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        match self {
+            RepoBackend::Http { base, .. } => {
+                let resp = reqwest::Client::new().get(format!("{}/{}", base, name)).send().await?;
+                if resp.status() == StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                Ok(Some(resp.error_for_status()?.bytes().await?.to_vec()))
+            }
+            RepoBackend::File { root } => match fs::read(root.join(name)) {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+        }
+    }
+
+    /// Store `body` as `name`. Local repos write `<name>.new` and rename it into place,
+    /// so readers never see a partially written file.
+    pub async fn put(&self, name: &str, body: Vec<u8>, content_type: Option<&'static str>) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            RepoBackend::Http { base, token, .. } => {
+                let mut headers = HeaderMap::new();
+                if let Some(ct) = content_type {
+                    headers.insert(CONTENT_TYPE, HeaderValue::from_static(ct));
+                }
+                if let Some(tok) = token {
+                    headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", tok))?);
+                }
+                let resp = reqwest::Client::new()
+                    .put(format!("{}/{}", base, name))
+                    .headers(headers)
+                    .body(body)
+                    .send()
+                    .await?;
+                if !resp.status().is_success() {
+                    return Err(format!(
+                        "Failed to upload {} (HTTP {}): {}",
+                        name,
+                        resp.status(),
+                        resp.text().await.unwrap_or_default()
+                    ).into());
+                }
+                Ok(())
+            }
+            RepoBackend::File { root } => {
+                let dest = local_dest(root, name)?;
+                replace_atomic(&dest, |tmp| {
+                    let mut f = File::create(tmp)?;
+                    f.write_all(&body)?;
+                    f.sync_all()
+                })?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Upload the local file at `path` as `name` (same atomicity as `put`).
+    pub async fn put_file(&self, name: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            RepoBackend::Http { token, upload, .. } => upload_file_put(&self.url_for(name), path, token.as_deref(), upload).await,
+            RepoBackend::File { root } => {
+                let dest = local_dest(root, name)?;
+                replace_atomic(&dest, |tmp| {
+                    fs::copy(path, tmp)?;
+                    File::open(tmp)?.sync_all()
+                })?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn local_dest(root: &Path, name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(format!("invalid repository file name '{}'", name));
+    }
+    fs::create_dir_all(root).map_err(|e| format!("cannot create repository dir {}: {}", root.display(), e))?;
+    Ok(root.join(name))
+}
+
+/// Write `<dest>.new` with `write`, then rename it over `dest` within the same directory.
+fn replace_atomic(dest: &Path, write: impl FnOnce(&Path) -> std::io::Result<()>) -> std::io::Result<()> {
+    let mut tmp_name = dest.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".new");
+    let tmp = dest.with_file_name(tmp_name);
+    if let Err(e) = write(&tmp) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, dest)?;
+    // Persist the rename itself; best-effort since not every filesystem supports it
+    if let Some(dir) = dest.parent() {
+        if let Ok(d) = File::open(dir) {
+            let _ = d.sync_all();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_repo_url_canonicalizes_configured_urls() {
+        let cases = [
+            ("repo.example/nx", "https://repo.example/nx"),
+            ("  repo.example/nx  ", "https://repo.example/nx"),
+            ("/srv/repo", "file:///srv/repo"),
+            ("https://repo.example/nx/", "https://repo.example/nx"),
+            ("https://repo.example/nx///", "https://repo.example/nx"),
+            ("https://repo.example//nx//stable", "https://repo.example/nx/stable"),
+            ("HTTP://repo.example/nx", "http://repo.example/nx"),
+            ("file:///srv/repo/", "file:///srv/repo"),
+            ("file:////srv//repo", "file:///srv/repo"),
+            ("file:///", "file:///"),
+            ("", ""),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(normalize_repo_url(url), expected, "{:?}", url);
+        }
+    }
+}
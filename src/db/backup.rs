@@ -0,0 +1,142 @@
+//! src/db/backup.rs
+//! Rolling copies of the package database, taken before commands that modify it.
+//!
+//! The newest backup is `<db_path>.bak` (e.g. `nxpkg_meta.db.bak`), older ones
+//! `<db_path>.bak.1`, `<db_path>.bak.2`, ... up to the configured count.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+
+/// Path of backup number `n` (0 = newest).
+pub fn backup_path(db_path: &Path, n: usize) -> PathBuf {
+    let mut name = db_path.as_os_str().to_os_string();
+    name.push(".bak");
+    if n > 0 {
+        name.push(format!(".{}", n));
+    }
+    PathBuf::from(name)
+}
+
+/// Existing backups, newest first, as (number, path).
+pub fn list_backups(db_path: &Path, keep: usize) -> Vec<(usize, PathBuf)> {
+    (0..keep.max(1))
+        .map(|n| (n, backup_path(db_path, n)))
+        .filter(|(_, p)| p.is_file())
+        .collect()
+}
+
+/// Shift the existing backups one place (dropping the oldest beyond `keep`) and write a
+/// consistent copy of the open database as the newest. Does nothing when `keep` is 0.
+pub fn rotate_and_backup(db: &Connection, db_path: &Path, keep: usize) -> Result<Option<PathBuf>, String> {
+    if keep == 0 {
+        return Ok(None);
+    }
+    let _ = fs::remove_file(backup_path(db_path, keep - 1));
+    for n in (0..keep - 1).rev() {
+        let from = backup_path(db_path, n);
+        if from.is_file() {
+            fs::rename(&from, backup_path(db_path, n + 1)).map_err(|e| format!("cannot rotate {}: {}", from.display(), e))?;
+        }
+    }
+    let newest = backup_path(db_path, 0);
+    // VACUUM INTO writes a complete, consistent database even if a copy of the file would not be
+    db.execute("VACUUM INTO ?1", [newest.to_string_lossy()])
+        .map_err(|e| format!("cannot write {}: {}", newest.display(), e))?;
+    Ok(Some(newest))
+}
+
+/// Replace the database at `db_path` with `backup`. The backup must open as an nxpkg database.
+/// The current database is first saved as the newest backup, so a restore can itself be undone.
+pub fn restore(db: &Connection, db_path: &Path, backup: &Path, keep: usize) -> Result<(), String> {
+    let staged = backup_path(db_path, 0).with_extension("restore");
+    fs::copy(backup, &staged).map_err(|e| format!("cannot read {}: {}", backup.display(), e))?;
+    let valid = Connection::open(&staged)
+        .and_then(|c| c.query_row("SELECT count(*) FROM packages", [], |row| row.get::<_, i64>(0)));
+    if let Err(e) = valid {
+        let _ = fs::remove_file(&staged);
+        return Err(format!("{} is not a usable package database: {}", backup.display(), e));
+    }
+    if let Err(e) = rotate_and_backup(db, db_path, keep) {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    fs::rename(&staged, db_path).map_err(|e| format!("cannot replace {}: {}", db_path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A package database at `<dir>/nxpkg_meta.db` holding one package named `name`.
+    fn db_with(dir: &Path, name: &str) -> (Connection, PathBuf) {
+        let path = dir.join("nxpkg_meta.db");
+        let _ = fs::remove_file(&path);
+        let db = Connection::open(&path).unwrap();
+        db.execute("CREATE TABLE packages (name TEXT PRIMARY KEY)", []).unwrap();
+        db.execute("INSERT INTO packages (name) VALUES (?1)", [name]).unwrap();
+        (db, path)
+    }
+
+    fn package_in(path: &Path) -> String {
+        Connection::open(path).unwrap().query_row("SELECT name FROM packages", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_backups_first() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (db, path) = db_with(tmp.path(), "first");
+        for name in ["second", "third", "fourth", "fifth"] {
+            rotate_and_backup(&db, &path, 3).unwrap();
+            db.execute("UPDATE packages SET name = ?1", [name]).unwrap();
+        }
+
+        let backups = list_backups(&path, 3);
+        assert_eq!(backups.iter().map(|(n, _)| *n).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(package_in(&backup_path(&path, 0)), "fourth");
+        assert_eq!(package_in(&backup_path(&path, 1)), "third");
+        assert_eq!(package_in(&backup_path(&path, 2)), "second");
+        assert!(!backup_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn keep_zero_writes_no_backup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (db, path) = db_with(tmp.path(), "app");
+
+        assert_eq!(rotate_and_backup(&db, &path, 0).unwrap(), None);
+        assert!(list_backups(&path, 0).is_empty());
+        assert!(!backup_path(&path, 0).exists());
+    }
+
+    #[test]
+    fn restore_swaps_in_the_backup_and_keeps_the_current_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (db, path) = db_with(tmp.path(), "old");
+        let saved = rotate_and_backup(&db, &path, 2).unwrap().unwrap();
+        let kept = tmp.path().join("kept.db");
+        fs::rename(&saved, &kept).unwrap();
+        db.execute("UPDATE packages SET name = 'new'", []).unwrap();
+
+        restore(&db, &path, &kept, 2).unwrap();
+        drop(db);
+
+        assert_eq!(package_in(&path), "old");
+        assert_eq!(package_in(&backup_path(&path, 0)), "new");
+        assert!(!backup_path(&path, 0).with_extension("restore").exists());
+    }
+
+    #[test]
+    fn restore_refuses_a_file_that_is_not_a_package_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (db, path) = db_with(tmp.path(), "app");
+        let junk = tmp.path().join("junk.db");
+        fs::write(&junk, "not sqlite").unwrap();
+
+        assert!(restore(&db, &path, &junk, 2).is_err());
+        assert_eq!(package_in(&path), "app");
+        assert!(!backup_path(&path, 0).exists());
+        assert!(!backup_path(&path, 0).with_extension("restore").exists());
+    }
+}
@@ -1,14 +1,19 @@
 //! src/download.rs
 //! Handles fetching the repository index and downloading package files.
 
-use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use sha2::{Digest, Sha256};
 use base64::{engine::general_purpose, Engine as _};
+use crate::arch::canonical_arch;
+use crate::config::AppConfig;
+use crate::db::backend::{self, signature_target, RepoBackend, DEFAULT_SIG_PATH};
+use crate::progress::{NoProgress, ProgressSink};
 
 // --- Data Structures for index.json ---
 // These structs mirror the structure of our repository index file.
@@ -19,6 +24,20 @@ pub struct ArchAsset {
     pub download_url: String,
     #[serde(default)]
     pub sha256: Option<String>,
+    /// Architecture the asset was built for; cross-checked against its key in `architectures`.
+    /// Absent in indexes written before it was introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// Size of the asset in bytes; unknown in indexes written before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl ArchAsset {
+    /// True when the asset declares an architecture that differs from the map key it is filed under.
+    pub fn arch_mismatch(&self, key: &str) -> bool {
+        self.arch.as_deref().is_some_and(|a| canonical_arch(a) != canonical_arch(key))
+    }
 }
 
 /// Represents a single package entry in the index.
@@ -31,8 +50,40 @@ pub struct PackageEntry {
     pub download_url: Option<String>,
     #[serde(default)]
     pub sha256: Option<String>,
+    /// Size in bytes of the legacy `download_url` asset, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
     #[serde(default)]
     pub architectures: Option<HashMap<String, ArchAsset>>, // key: arch token (e.g., x86_64, aarch64)
+    /// Runtime dependencies of the latest version (`name` or `name <op> version`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// Additional dependencies of the latest version on one architecture (key: canonical arch)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub arch_dependencies: HashMap<String, Vec<String>>,
+    /// Oldest nxpkg that can install the latest version (the recipe's `min_nxpkg_version`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_nxpkg_version: Option<String>,
+    /// Git URL of the package's source, for `install --prefer-source` when no binary asset fits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_repo: Option<String>,
+    /// Every published version (including the latest), for `install name@version`.
+    /// Indexes written before it was introduced only describe the latest version.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub versions: HashMap<String, VersionAssets>,
+}
+
+/// Assets and metadata of one published version of a package.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VersionAssets {
+    #[serde(default)]
+    pub architectures: HashMap<String, ArchAsset>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub arch_dependencies: HashMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_nxpkg_version: Option<String>,
 }
 
 /// Represents the entire repository index file (index.json).
@@ -44,49 +95,297 @@ pub struct RepoIndex {
 // --- Public API ---
 
 /// Fetches and parses the repository index from a given base URL (async).
+#[allow(dead_code)]
 pub async fn fetch_index(repo_url: &str) -> Result<RepoIndex, Box<dyn std::error::Error>> {
-    fetch_index_verified(repo_url, None, false).await
+    fetch_index_verified(repo_url, DEFAULT_SIG_PATH, None, false).await
 }
 
 /// Fetch index.json and, optionally, verify Ed25519 signature using a base64 public key file.
+/// The signature is fetched from `sig_path` (see `backend::signature_target`).
+/// Parsing is strict: any malformed entry fails the whole index.
 pub async fn fetch_index_verified(
     repo_url: &str,
+    sig_path: &str,
     pubkey_path: Option<&Path>,
     require_signature: bool,
 ) -> Result<RepoIndex, Box<dyn std::error::Error>> {
-    let base = repo_url.trim_end_matches('/');
-    let index_url = format!("{}/index.json", base);
-    let sig_url = format!("{}/index.json.sig", base);
-    let client = reqwest::Client::new();
+    fetch_index_with(repo_url, sig_path, pubkey_path, require_signature, false, false).await
+}
+
+/// Fetch a repository index with the signature and parsing policy from `cfg`.
+///
+/// Every verified download is cached (index and signature together) under `cache_dir`, and
+/// with `[repo] index_history` also kept as a numbered snapshot when it changed.
+/// With `cfg.insecure_skip_verify` the signature is not checked and nothing is cached. When the
+/// repository cannot be reached the cached copy is used instead, after verifying it again exactly
+/// like a fresh download, so a tampered cache file is rejected.
+///
+/// For the configured repository, `[repo] mirrors` are tried in turn when a fetch fails (see
+/// `index_candidates`), and download URLs of an index served by a mirror are pointed at it.
+pub async fn fetch_index_for(cfg: &AppConfig, repo_url: &str) -> Result<RepoIndex, Box<dyn std::error::Error>> {
+    let cache_path = index_cache_path(cfg, repo_url);
+    let candidates = index_candidates(cfg, repo_url).await;
+    let mut fetched = Err("no repository URL to fetch from".into());
+    for (i, url) in candidates.iter().enumerate() {
+        fetched = fetch_index_artifacts(url, Some(&cfg.sig_path)).await.map(|a| (url.as_str(), a));
+        match &fetched {
+            Ok(_) => break,
+            Err(e) if i + 1 < candidates.len() => {
+                eprintln!("Warning: cannot fetch index from {} ({}); trying {}", url, e, candidates[i + 1]);
+            }
+            Err(_) => {}
+        }
+    }
+    let (served_by, artifacts, fresh) = match fetched {
+        Ok((url, a)) => (url, a, true),
+        Err(e) => match read_index_cache(&cache_path) {
+            Some(cached) => {
+                eprintln!("Warning: cannot fetch index from {} ({}); using cached copy", repo_url, e);
+                (repo_url, cached, false)
+            }
+            None => return Err(e),
+        },
+    };
+    let (pubkey, require) = if cfg.insecure_skip_verify {
+        (None, false)
+    } else {
+        (Some(cfg.pubkey_path.as_path()), cfg.require_signed_index)
+    };
+    let mut index = verify_index_artifacts(&artifacts, pubkey, require, cfg.lenient_index, cfg.canonical_signing)
+        .await
+        .map_err(|e| if fresh { e } else { format!("cached index {}: {}", cache_path.display(), e).into() })?;
+    // An unverified index must not replace a verified cached copy
+    if fresh && !cfg.insecure_skip_verify {
+        if let Err(e) = write_index_cache(&cache_path, &artifacts) {
+            eprintln!("Warning: could not cache index at {}: {}", cache_path.display(), e);
+        }
+        if cfg.index_history > 0 {
+            let dir = index_history_dir(cfg, repo_url);
+            if let Err(e) = super::history::record(&dir, &artifacts.bytes, artifacts.sig.as_deref(), cfg.index_history) {
+                eprintln!("Warning: could not snapshot index in {}: {}", dir.display(), e);
+            }
+        }
+    }
+    if cfg.strict_arch_assets {
+        check_arch_assets(&index)?;
+    }
+    if served_by != repo_url {
+        redirect_downloads(&mut index, repo_url, served_by);
+    }
+    Ok(index)
+}
+
+/// Latency rankings of repositories with mirrors, computed once per process.
+static MIRROR_RANKING: OnceLock<Mutex<HashMap<String, Vec<String>>>> = OnceLock::new();
+
+/// How long a mirror may take to answer the latency probe before it is ranked unreachable.
+const MIRROR_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
 
-    let index_bytes = client
-        .get(&index_url)
-        .send()
+/// Base URLs to fetch `repo_url`'s index from, in the order to try them. For the configured
+/// repository that is `repo_url` followed by `[repo] mirrors`, or with `[network] rank_mirrors`
+/// all of them ordered by `rank_by_latency`. Any other repository has no mirrors.
+async fn index_candidates(cfg: &AppConfig, repo_url: &str) -> Vec<String> {
+    let key = backend::normalize_repo_url(repo_url);
+    if cfg.mirrors.is_empty() || key != backend::normalize_repo_url(&cfg.repo_url) {
+        return vec![repo_url.to_string()];
+    }
+    let mut urls = vec![repo_url.to_string()];
+    for mirror in &cfg.mirrors {
+        if !urls.iter().any(|u| backend::normalize_repo_url(u) == *mirror) {
+            urls.push(mirror.clone());
+        }
+    }
+    if !cfg.rank_mirrors {
+        return urls;
+    }
+    let ranking = MIRROR_RANKING.get_or_init(Default::default);
+    if let Some(ranked) = ranking.lock().unwrap().get(&key) {
+        return ranked.clone();
+    }
+    let ranked = rank_by_latency(urls).await;
+    ranking.lock().unwrap().insert(key, ranked.clone());
+    ranked
+}
+
+/// Order `urls` by how quickly a HEAD request for their index.json succeeds, probing them all
+/// at once. URLs that fail or time out keep their relative order after the reachable ones.
+async fn rank_by_latency(urls: Vec<String>) -> Vec<String> {
+    let client = match reqwest::Client::builder().timeout(MIRROR_PROBE_TIMEOUT).build() {
+        Ok(c) => c,
+        Err(_) => return urls,
+    };
+    let probes: Vec<_> = urls
+        .iter()
+        .map(|url| {
+            let target = RepoBackend::from_url(url, None).url_for("index.json");
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Some(path) = backend::file_url_path(&target) {
+                    return path.is_file().then_some(Duration::ZERO);
+                }
+                let start = Instant::now();
+                client.head(&target).send().await.ok()?.error_for_status().ok()?;
+                Some(start.elapsed())
+            })
+        })
+        .collect();
+    let mut timed = Vec::with_capacity(urls.len());
+    for (url, probe) in urls.into_iter().zip(probes) {
+        timed.push((probe.await.ok().flatten(), url));
+    }
+    timed.sort_by_key(|(latency, _)| (latency.is_none(), latency.unwrap_or_default()));
+    timed.into_iter().map(|(_, url)| url).collect()
+}
+
+/// Point download URLs under `repo_url` at `mirror`, for an index that `mirror` served.
+/// URLs elsewhere (another host, an absolute CDN link) are left alone.
+fn redirect_downloads(index: &mut RepoIndex, repo_url: &str, mirror: &str) {
+    let from = format!("{}/", backend::normalize_repo_url(repo_url));
+    let to = format!("{}/", backend::normalize_repo_url(mirror));
+    let rewrite = |url: &mut String| {
+        if let Some(rest) = url.strip_prefix(&from) {
+            *url = format!("{}{}", to, rest);
+        }
+    };
+    for entry in index.packages.values_mut() {
+        if let Some(url) = entry.download_url.as_mut() {
+            rewrite(url);
+        }
+        for asset in entry.architectures.iter_mut().flat_map(|a| a.values_mut()) {
+            rewrite(&mut asset.download_url);
+        }
+        for asset in entry.versions.values_mut().flat_map(|v| v.architectures.values_mut()) {
+            rewrite(&mut asset.download_url);
+        }
+    }
+}
+
+/// Like `fetch_index_verified`; with `lenient`, package entries that fail to deserialize are
+/// skipped with a warning instead of failing the whole index. The signature still covers the
+/// raw bytes, so leniency never weakens verification. With `canonical`, a signature over the
+/// canonical JSON form of the index is accepted as well (see `trust::canonical_json`).
+pub async fn fetch_index_with(
+    repo_url: &str,
+    sig_path: &str,
+    pubkey_path: Option<&Path>,
+    require_signature: bool,
+    lenient: bool,
+    canonical: bool,
+) -> Result<RepoIndex, Box<dyn std::error::Error>> {
+    let artifacts = fetch_index_artifacts(repo_url, pubkey_path.map(|_| sig_path)).await?;
+    verify_index_artifacts(&artifacts, pubkey_path, require_signature, lenient, canonical).await
+}
+
+/// index.json exactly as served, with the text of its signature when it was fetched.
+struct IndexArtifacts {
+    bytes: Vec<u8>,
+    sig: Option<String>,
+}
+
+/// Fetch index.json and, when `sig_path` is given, the signature stored there.
+async fn fetch_index_artifacts(repo_url: &str, sig_path: Option<&str>) -> Result<IndexArtifacts, Box<dyn std::error::Error>> {
+    let backend = RepoBackend::from_url(repo_url, None);
+    let bytes = backend
+        .get("index.json")
         .await?
-        .error_for_status()? // Fail on HTTP errors like 404
-        .bytes()
-        .await?;
+        .ok_or_else(|| format!("index.json not found at {}", backend.url_for("index.json")))?;
 
+    // Any failure to fetch the signature counts as "no signature"; require_signature decides if that is fatal
+    let sig = match sig_path {
+        Some(sig_path) => {
+            let (sig_backend, name) = signature_target(repo_url, sig_path, None)?;
+            sig_backend.get(&name).await.ok().flatten().map(|b| String::from_utf8_lossy(&b).into_owned())
+        }
+        None => None,
+    };
+    Ok(IndexArtifacts { bytes, sig })
+}
+
+async fn verify_index_artifacts(
+    artifacts: &IndexArtifacts,
+    pubkey_path: Option<&Path>,
+    require_signature: bool,
+    lenient: bool,
+    canonical: bool,
+) -> Result<RepoIndex, Box<dyn std::error::Error>> {
+    // Signature check and JSON parse are CPU-bound on large indexes; keep them off the runtime workers
+    let bytes = artifacts.bytes.clone();
+    let sig = artifacts.sig.clone();
+    let pubkey_path = pubkey_path.map(Path::to_path_buf);
+    let idx = tokio::task::spawn_blocking(move || {
+        verify_and_parse_index(&bytes, sig.as_deref(), pubkey_path.as_deref(), require_signature, lenient, canonical)
+    })
+    .await??;
+    Ok(idx)
+}
+
+/// Cache file for a repository's index: `<cache_dir>/index/<sha256 of the URL>.idx`.
+fn index_cache_path(cfg: &AppConfig, repo_url: &str) -> PathBuf {
+    cfg.cache_dir.join("index").join(format!("{}.idx", repo_cache_key(repo_url)))
+}
+
+/// Snapshot directory of a repository's verified indexes (`[repo] index_history`):
+/// `<cache_dir>/index-history/<sha256 of the URL>/`.
+pub fn index_history_dir(cfg: &AppConfig, repo_url: &str) -> PathBuf {
+    cfg.cache_dir.join("index-history").join(repo_cache_key(repo_url))
+}
+
+fn repo_cache_key(repo_url: &str) -> String {
+    let key = hex::encode(Sha256::digest(backend::normalize_repo_url(repo_url).as_bytes()));
+    key[..32].to_string()
+}
+
+// Cache format: the signature's base64 text (or "-" when there was none) on the first line,
+// followed by the index bytes unchanged, so the signature can be checked against them again.
+fn write_index_cache(path: &Path, artifacts: &IndexArtifacts) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("idx.new");
+    {
+        let mut f = File::create(&tmp)?;
+        writeln!(f, "{}", artifacts.sig.as_deref().map(str::trim).unwrap_or("-"))?;
+        f.write_all(&artifacts.bytes)?;
+        f.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn read_index_cache(path: &Path) -> Option<IndexArtifacts> {
+    let data = fs::read(path).ok()?;
+    let newline = data.iter().position(|&b| b == b'\n')?;
+    let sig = String::from_utf8_lossy(&data[..newline]).trim().to_string();
+    Some(IndexArtifacts {
+        bytes: data[newline + 1..].to_vec(),
+        sig: if sig == "-" { None } else { Some(sig) },
+    })
+}
+
+fn verify_and_parse_index(
+    index_bytes: &[u8],
+    sig_text: Option<&str>,
+    pubkey_path: Option<&Path>,
+    require_signature: bool,
+    lenient: bool,
+    canonical: bool,
+) -> Result<RepoIndex, String> {
     if let Some(pubkey_path) = pubkey_path {
-        // Try signature verification
-        let sig_bytes_b64 = client
-            .get(&sig_url)
-            .send()
-            .await?;
-        if sig_bytes_b64.status().is_success() {
-            let sig_text = sig_bytes_b64.text().await?;
+        if let Some(sig_text) = sig_text {
             let sig_raw = general_purpose::STANDARD
                 .decode(sig_text.trim())
-                .map_err(|e| format!("invalid base64 in index.json.sig: {}", e))?;
-            let pk_b64 = std::fs::read_to_string(pubkey_path)?;
+                .map_err(|e| format!("invalid base64 in index signature: {}", e))?;
+            let pk_b64 = std::fs::read_to_string(pubkey_path)
+                .map_err(|e| format!("failed to read pubkey file {}: {}", pubkey_path.display(), e))?;
             let pk_raw = general_purpose::STANDARD
                 .decode(pk_b64.trim())
                 .map_err(|e| format!("invalid base64 in pubkey file {}: {}", pubkey_path.display(), e))?;
-            let verified = crate::trust::verify_ed25519_index(&index_bytes, &sig_raw, &pk_raw);
-            if !verified {
-                if require_signature {
-                    return Err("index signature verification failed".into());
-                }
+            let mut verified = crate::trust::verify_ed25519_index(index_bytes, &sig_raw, &pk_raw);
+            if !verified && canonical {
+                let canonical_bytes = crate::trust::canonical_json(index_bytes)?;
+                verified = crate::trust::verify_ed25519_index(&canonical_bytes, &sig_raw, &pk_raw);
+            }
+            if !verified && require_signature {
+                return Err("index signature verification failed".into());
             }
         } else if require_signature {
             return Err("index signature not found and signature required".into());
@@ -95,95 +394,549 @@ pub async fn fetch_index_verified(
         return Err("signature required but no pubkey configured".into());
     }
 
-    let idx: RepoIndex = serde_json::from_slice(&index_bytes)?;
-    Ok(idx)
+    if lenient {
+        parse_index_lenient(index_bytes)
+    } else {
+        serde_json::from_slice(index_bytes).map_err(|e| e.to_string())
+    }
 }
 
-/// Select the most appropriate asset for the current host architecture.
-/// Returns (url, sha256)
-pub fn resolve_asset_for_current_arch(entry: &PackageEntry) -> Option<(String, Option<String>)> {
-    // If per-arch assets exist, prefer them
-    if let Some(map) = &entry.architectures {
-        // Build alias set for current arch
-        let host = std::env::consts::ARCH;
-        let aliases: Vec<&'static str> = match host {
-            "x86_64" => vec!["x86_64", "amd64", "x64"],
-            "aarch64" => vec!["aarch64", "arm64"],
-            "arm" => vec!["arm", "armv7", "armhf", "armv7l"],
-            "x86" | "i686" => vec!["x86", "i686", "i386"],
-            "powerpc64" => vec!["ppc64", "ppc64le"],
-            other => vec![other],
-        };
-        // Try exact/alias matches (case-insensitive)
-        for alias in aliases {
-            for (k, v) in map.iter() {
-                if k.eq_ignore_ascii_case(alias) {
-                    return Some((v.download_url.clone(), v.sha256.clone()));
-                }
+fn parse_index_lenient(index_bytes: &[u8]) -> Result<RepoIndex, String> {
+    #[derive(Deserialize)]
+    struct RawIndex {
+        packages: serde_json::Map<String, serde_json::Value>,
+    }
+    let raw: RawIndex = serde_json::from_slice(index_bytes).map_err(|e| e.to_string())?;
+    let mut packages = HashMap::new();
+    let mut skipped = 0;
+    for (name, value) in raw.packages {
+        match serde_json::from_value::<PackageEntry>(value) {
+            Ok(entry) => { packages.insert(name, entry); }
+            Err(e) => {
+                eprintln!("Warning: skipping malformed index entry '{}': {}", name, e);
+                skipped += 1;
             }
         }
-        // Also consider universal tokens
-        for uni in ["any", "noarch"] {
-            for (k, v) in map.iter() {
-                if k.eq_ignore_ascii_case(uni) {
-                    return Some((v.download_url.clone(), v.sha256.clone()));
-                }
+    }
+    if skipped > 0 {
+        eprintln!("Warning: {} malformed index entr{} skipped ([repo] lenient_index)", skipped, if skipped == 1 { "y" } else { "ies" });
+    }
+    Ok(RepoIndex { packages })
+}
+
+/// `[security] strict_arch_assets`: reject an index in which any asset declares a different
+/// architecture than the key it is listed under.
+fn check_arch_assets(index: &RepoIndex) -> Result<(), String> {
+    for (name, entry) in &index.packages {
+        for (key, asset) in entry.architectures.iter().flatten() {
+            if asset.arch_mismatch(key) {
+                return Err(format!(
+                    "index entry '{}' lists an asset for '{}' under '{}' (strict_arch_assets)",
+                    name, asset.arch.as_deref().unwrap_or_default(), key
+                ));
             }
         }
     }
+    Ok(())
+}
+
+/// Select the most appropriate asset for `arch` (normally the host, `arch::current_arch()`).
+/// Assets whose declared `arch` contradicts their key are never selected.
+/// Returns (url, sha256, size)
+pub fn resolve_asset_for_arch(entry: &PackageEntry, arch: &str) -> Option<(String, Option<String>, Option<u64>)> {
+    // If per-arch assets exist, prefer them
+    if let Some(v) = entry.architectures.as_ref().and_then(|map| pick_arch_asset(map, arch)) {
+        return Some((v.download_url.clone(), v.sha256.clone(), v.size));
+    }
     // Fallback to legacy fields
     if let Some(url) = entry.download_url.clone() {
-        return Some((url, entry.sha256.clone()));
+        return Some((url, entry.sha256.clone(), entry.size));
+    }
+    None
+}
+
+/// The asset of a pinned version (`PackageEntry::versions`) to download for `arch`.
+pub fn resolve_version_asset(assets: &VersionAssets, arch: &str) -> Option<(String, Option<String>, Option<u64>)> {
+    pick_arch_asset(&assets.architectures, arch).map(|v| (v.download_url.clone(), v.sha256.clone(), v.size))
+}
+
+fn pick_arch_asset<'m>(map: &'m HashMap<String, ArchAsset>, arch: &str) -> Option<&'m ArchAsset> {
+    let wanted = canonical_arch(arch);
+    // Keys naming the architecture (under any alias) first, then the universal tokens
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    let exact = keys.iter().filter(|k| canonical_arch(k) == wanted);
+    let universal = keys.iter().filter(|k| canonical_arch(k) == "any");
+    for k in exact.chain(universal) {
+        let v = &map[*k];
+        if v.arch_mismatch(k) {
+            eprintln!(
+                "Warning: ignoring '{}' asset that declares arch '{}'; the index may have been tampered with",
+                k, v.arch.as_deref().unwrap_or_default()
+            );
+            continue;
+        }
+        return Some(v);
     }
     None
 }
 
-/// Downloads a file from a URL to a destination path, showing a progress bar.
+/// A package entry as served by one named binary remote.
+#[derive(Debug, Clone)]
+pub struct RemoteHit {
+    pub remote: String,
+    pub version: String,
+    pub sha256: Option<String>,
+}
+
+/// Every configured remote's index, by remote name, in name order.
+/// Remotes that cannot be fetched are skipped with a warning.
+pub async fn fetch_remote_indexes(cfg: &AppConfig) -> Vec<(String, RepoIndex)> {
+    let mut indexes = Vec::new();
+    for (remote, url) in &cfg.repo_remotes {
+        match fetch_index_for(cfg, url).await {
+            Ok(index) => indexes.push((remote.clone(), index)),
+            Err(e) => eprintln!("Warning: could not fetch index from remote '{}': {}", remote, e),
+        }
+    }
+    indexes
+}
+
+/// Look up `name` in every configured remote's index.
+/// Remotes that cannot be fetched are skipped with a warning.
+pub async fn find_in_remotes(cfg: &AppConfig, name: &str) -> Vec<RemoteHit> {
+    fetch_remote_indexes(cfg)
+        .await
+        .into_iter()
+        .filter_map(|(remote, index)| {
+            let entry = index.packages.get(name)?;
+            let sha256 = resolve_asset_for_arch(entry, &cfg.arch).and_then(|(_, sha, _)| sha);
+            Some(RemoteHit { remote, version: entry.latest_version.clone(), sha256 })
+        })
+        .collect()
+}
+
+/// True when the hits disagree on version or checksum, i.e. the name is shadowed across remotes.
+pub fn hits_conflict(hits: &[RemoteHit]) -> bool {
+    hits.windows(2).any(|w| w[0].version != w[1].version || w[0].sha256 != w[1].sha256)
+}
+
+/// Size of the file at `url` without downloading it: the file length for local repositories,
+/// otherwise the `Content-Length` of a HEAD request. None when the server does not say.
+pub async fn remote_size(url: &str) -> Option<u64> {
+    if let Some(src) = backend::file_url_path(url) {
+        return fs::metadata(src).ok().map(|m| m.len());
+    }
+    let response = reqwest::Client::new().head(url).send().await.ok()?.error_for_status().ok()?;
+    // reqwest reports a zero body length for HEAD responses; read the header itself
+    response.headers()
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str().ok()?
+        .parse().ok()
+}
+
+/// Downloads a file from a URL to a destination path, reporting progress to `progress`
+/// (nothing when None). Returns the SHA-256 of the written file (lowercase hex), computed while streaming.
+///
+/// The data is written to `<dest_path>.part` and renamed to `dest_path` only once it is
+/// complete and its checksum matches, so `dest_path` never holds a partial or unverified file.
+/// A failed download deletes the `.part` file; one left by a killed process is overwritten
+/// by the next attempt.
 pub async fn download_file_with_progress(
     url: &str,
     dest_path: &Path,
     expected_sha256: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let mut response = client.get(url).send().await?.error_for_status()?;
+    progress: Option<&dyn ProgressSink>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let part_path = partial_download_path(dest_path);
+    match download_to(url, &part_path, expected_sha256, progress.unwrap_or(&NoProgress)).await {
+        Ok(checksum_hex) => {
+            fs::rename(&part_path, dest_path)
+                .map_err(|e| format!("cannot move {} into place: {}", part_path.display(), e))?;
+            Ok(checksum_hex)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&part_path);
+            Err(e)
+        }
+    }
+}
 
-    // Get total file size from headers, if available.
-    let total_size = response.content_length().unwrap_or(0);
+/// URLs to download a package asset from, in the order to try them: `url` itself, then, when
+/// it points under `[repo] url` or one of its `[repo] mirrors`, the same path on each of the
+/// others. Any other URL (another host, an absolute CDN link) has no alternatives.
+pub fn download_candidates(cfg: &AppConfig, url: &str) -> Vec<String> {
+    let mut bases = vec![backend::normalize_repo_url(&cfg.repo_url)];
+    for mirror in &cfg.mirrors {
+        let mirror = backend::normalize_repo_url(mirror);
+        if !bases.contains(&mirror) {
+            bases.push(mirror);
+        }
+    }
+    let mut urls = vec![url.to_string()];
+    let Some((served_by, rest)) = bases.iter().find_map(|b| Some((b, url.strip_prefix(&format!("{}/", b))?))) else {
+        return urls;
+    };
+    urls.extend(bases.iter().filter(|b| *b != served_by).map(|b| format!("{}/{}", b, rest)));
+    urls
+}
+
+/// `download_file_with_progress` from the first of `urls` that succeeds, warning before moving
+/// on to the next one. The error of the last attempt is returned when all of them fail.
+pub async fn download_with_failover(
+    urls: &[String],
+    dest_path: &Path,
+    expected_sha256: Option<&str>,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // Only the message is kept across attempts: a boxed error would make the future non-Send
+    let mut last_err = String::from("no URL to download from");
+    for (i, url) in urls.iter().enumerate() {
+        match download_file_with_progress(url, dest_path, expected_sha256, progress).await {
+            Ok(checksum_hex) => return Ok(checksum_hex),
+            Err(e) => last_err = e.to_string(),
+        }
+        if let Some(next) = urls.get(i + 1) {
+            eprintln!("Warning: cannot download {} ({}); trying {}", url, last_err, next);
+        }
+    }
+    Err(last_err.into())
+}
 
-    // Create a progress bar.
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})")?
-            .progress_chars("#>-"),
-    );
+/// `<dest_path>.part`, where a download is written until it is verified.
+fn partial_download_path(dest_path: &Path) -> PathBuf {
+    let mut name = dest_path.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
 
+async fn download_to(
+    url: &str,
+    dest_path: &Path,
+    expected_sha256: Option<&str>,
+    progress: &dyn ProgressSink,
+) -> Result<String, Box<dyn std::error::Error>> {
     let mut dest_file = File::create(dest_path)?;
     let mut hasher = Sha256::new();
-    
-    // Stream the download chunk by chunk.
-    while let Some(chunk) = response.chunk().await? {
+
+    // Local repositories: copy straight from disk
+    if let Some(src) = backend::file_url_path(url) {
+        let mut src_file = File::open(&src).map_err(|e| format!("{}: {}", src.display(), e))?;
+        let mut buf = [0u8; 64 * 1024];
+        let mut received = 0u64;
+        loop {
+            let n = src_file.read(&mut buf)?;
+            if n == 0 { break; }
+            hasher.update(&buf[..n]);
+            dest_file.write_all(&buf[..n])?;
+            received += n as u64;
+        }
+        dest_file.sync_all()?;
+        check_complete(url, received, None)?;
+        let checksum_hex = hex::encode(hasher.finalize());
+        verify_checksum(dest_path, &checksum_hex, expected_sha256)?;
+        return Ok(checksum_hex);
+    }
+
+    let client = reqwest::Client::new();
+    let mut response = client.get(url).send().await?.error_for_status()?;
+
+    // Get total file size from headers, if available.
+    let content_length = response.content_length();
+    progress.on_download_progress(url, 0, content_length);
+
+    // Stream the download chunk by chunk. A connection closed before Content-Length bytes
+    // arrived surfaces as a body error here.
+    let mut received = 0u64;
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                progress.on_download_end(url, false, "Download failed: incomplete");
+                return Err(format!("incomplete download of {}: received {} bytes: {}", url, received, e).into());
+            }
+        };
         hasher.update(&chunk);
         dest_file.write_all(&chunk)?;
-        pb.inc(chunk.len() as u64);
+        received += chunk.len() as u64;
+        progress.on_download_progress(url, received, content_length);
+    }
+    dest_file.sync_all()?;
+    if let Err(e) = check_complete(url, received, content_length) {
+        progress.on_download_end(url, false, "Download failed: incomplete");
+        return Err(e.into());
     }
 
     // Finalize checksum and verify if provided
     let checksum_hex = hex::encode(hasher.finalize());
+    match verify_checksum(dest_path, &checksum_hex, expected_sha256) {
+        Ok(()) if expected_sha256.is_some() => progress.on_download_end(url, true, "Download complete (verified)"),
+        Ok(()) => progress.on_download_end(url, true, "Download complete"),
+        Err(e) => {
+            progress.on_download_end(url, false, "Download failed: SHA-256 mismatch");
+            return Err(e);
+        }
+    }
+
+    Ok(checksum_hex)
+}
+
+/// Reject an empty download, or one whose size differs from the announced Content-Length,
+/// before it reaches the checksum check or the extractor.
+fn check_complete(url: &str, received: u64, expected: Option<u64>) -> Result<(), String> {
+    match expected {
+        Some(expected) if received != expected => Err(format!(
+            "incomplete download of {}: received {} of {} bytes", url, received, expected
+        )),
+        _ if received == 0 => Err(format!("incomplete download of {}: received 0 bytes", url)),
+        _ => Ok(()),
+    }
+}
+
+/// Compare a downloaded file's checksum against the expected one, deleting the file on mismatch.
+fn verify_checksum(dest_path: &Path, checksum_hex: &str, expected_sha256: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
     if let Some(expected) = expected_sha256 {
         let expected_norm = expected.trim().to_lowercase();
         if checksum_hex != expected_norm {
-            pb.abandon_with_message("Download failed: SHA-256 mismatch");
             let _ = fs::remove_file(dest_path);
             return Err(format!(
                 "SHA-256 mismatch: expected {}, got {}",
                 expected_norm, checksum_hex
             ).into());
         }
-        pb.finish_with_message("Download complete (verified)");
-    } else {
-        pb.finish_with_message("Download complete");
     }
-
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const INDEX: &str = r#"{"packages":{"app":{"latest_version":"1.0","description":"demo"}}}"#;
+
+    /// Sign `INDEX` with a fixed key, returning the signature and the path of the public key.
+    fn signed_index(dir: &Path) -> (String, PathBuf) {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let pubkey = dir.join("nxpkg.pub");
+        fs::write(&pubkey, general_purpose::STANDARD.encode(key.verifying_key().to_bytes())).unwrap();
+        let sig = general_purpose::STANDARD.encode(key.sign(INDEX.as_bytes()).to_bytes());
+        (sig, pubkey)
+    }
+
+    #[test]
+    fn tampered_index_cache_fails_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sig, pubkey) = signed_index(dir.path());
+        let cache = dir.path().join("index.idx");
+        write_index_cache(&cache, &IndexArtifacts { bytes: INDEX.as_bytes().to_vec(), sig: Some(sig) }).unwrap();
+
+        let cached = read_index_cache(&cache).unwrap();
+        assert_eq!(cached.bytes, INDEX.as_bytes());
+        verify_and_parse_index(&cached.bytes, cached.sig.as_deref(), Some(&pubkey), true, false, false).unwrap();
+
+        let tampered = fs::read_to_string(&cache).unwrap().replace("1.0", "6.6");
+        fs::write(&cache, tampered).unwrap();
+        let cached = read_index_cache(&cache).unwrap();
+        let err = verify_and_parse_index(&cached.bytes, cached.sig.as_deref(), Some(&pubkey), true, false, false)
+            .unwrap_err();
+        assert_eq!(err, "index signature verification failed");
+    }
+
+    #[tokio::test]
+    async fn unreachable_repository_reverifies_its_cached_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sig, pubkey) = signed_index(dir.path());
+        let cfg = AppConfig {
+            cache_dir: dir.path().join("cache"),
+            pubkey_path: pubkey,
+            require_signed_index: true,
+            ..AppConfig::default()
+        };
+        // Nothing listens on the discard port, so every fetch falls back to the cache
+        let repo = "http://127.0.0.1:9/repo";
+        let cache = index_cache_path(&cfg, repo);
+        write_index_cache(&cache, &IndexArtifacts { bytes: INDEX.as_bytes().to_vec(), sig: Some(sig) }).unwrap();
+        let index = fetch_index_for(&cfg, repo).await.unwrap();
+        assert_eq!(index.packages["app"].latest_version, "1.0");
+
+        let tampered = fs::read_to_string(&cache).unwrap().replace("1.0", "6.6");
+        fs::write(&cache, tampered).unwrap();
+        let err = fetch_index_for(&cfg, repo).await.unwrap_err().to_string();
+        assert!(err.starts_with("cached index"), "{}", err);
+        assert!(err.ends_with("index signature verification failed"), "{}", err);
+    }
+
+    fn entry(architectures: serde_json::Value, download_url: Option<&str>) -> PackageEntry {
+        serde_json::from_value(serde_json::json!({
+            "latest_version": "1", "description": "",
+            "architectures": architectures, "download_url": download_url,
+        }))
+        .unwrap()
+    }
+
+    fn url_for(entry: &PackageEntry, arch: &str) -> Option<String> {
+        resolve_asset_for_arch(entry, arch).map(|(url, _, _)| url)
+    }
+
+    #[test]
+    fn assets_resolve_for_any_target_arch_through_aliases() {
+        let entry = entry(serde_json::json!({
+            "amd64": { "download_url": "https://r/x86_64" },
+            "arm64": { "download_url": "https://r/aarch64" },
+            "noarch": { "download_url": "https://r/any" },
+        }), None);
+        // Whatever the host, each architecture gets its own asset
+        assert_eq!(url_for(&entry, "x86_64").as_deref(), Some("https://r/x86_64"));
+        assert_eq!(url_for(&entry, "aarch64").as_deref(), Some("https://r/aarch64"));
+        assert_eq!(url_for(&entry, "arm64").as_deref(), Some("https://r/aarch64"));
+        assert_eq!(url_for(&entry, "riscv64").as_deref(), Some("https://r/any"));
+    }
+
+    #[test]
+    fn assets_without_a_match_fall_back_to_download_url() {
+        let arm_only = serde_json::json!({ "aarch64": { "download_url": "https://r/aarch64" } });
+        assert_eq!(url_for(&entry(arm_only.clone(), None), "x86_64"), None);
+        assert_eq!(url_for(&entry(arm_only, Some("https://r/legacy")), "x86_64").as_deref(), Some("https://r/legacy"));
+    }
+
+    #[test]
+    fn asset_declaring_another_arch_is_skipped() {
+        let entry = entry(serde_json::json!({
+            "x86_64": { "download_url": "https://r/swapped", "arch": "aarch64" },
+            "any": { "download_url": "https://r/any", "arch": "noarch" },
+        }), None);
+        assert_eq!(url_for(&entry, "amd64").as_deref(), Some("https://r/any"));
+    }
+
+    #[test]
+    fn version_assets_resolve_through_aliases() {
+        let version: VersionAssets = serde_json::from_value(serde_json::json!({
+            "architectures": { "x64": { "download_url": "https://r/v-x86_64" } },
+        }))
+        .unwrap();
+        assert_eq!(resolve_version_asset(&version, "x86_64").map(|a| a.0).as_deref(), Some("https://r/v-x86_64"));
+        assert_eq!(resolve_version_asset(&version, "aarch64"), None);
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[tokio::test]
+    async fn download_replaces_a_stale_part_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src.nxpkg");
+        fs::write(&src, b"package bytes").unwrap();
+        let dest = dir.path().join("app-1.nxpkg");
+        fs::write(partial_download_path(&dest), b"left over by a killed download, and longer").unwrap();
+
+        let url = format!("file://{}", src.display());
+        let sum = download_file_with_progress(&url, &dest, Some(&sha256_hex(b"package bytes")), None).await.unwrap();
+        assert_eq!(sum, sha256_hex(b"package bytes"));
+        assert_eq!(fs::read(&dest).unwrap(), b"package bytes");
+        assert!(!partial_download_path(&dest).exists());
+    }
+
+    #[tokio::test]
+    async fn failed_download_leaves_no_file_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src.nxpkg");
+        fs::write(&src, b"package bytes").unwrap();
+        let dest = dir.path().join("app-1.nxpkg");
+
+        let url = format!("file://{}", src.display());
+        let err = download_file_with_progress(&url, &dest, Some(&sha256_hex(b"other bytes")), None).await.unwrap_err();
+        assert!(err.to_string().contains("mismatch"), "{}", err);
+        assert!(!dest.exists());
+        assert!(!partial_download_path(&dest).exists());
+
+        let (base, _) = crate::test_support::mock_server(|_| (404, vec![], "gone".into())).await;
+        let err = download_file_with_progress(&format!("{}/app-1.nxpkg", base), &dest, None, None).await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+        assert!(!dest.exists());
+        assert!(!partial_download_path(&dest).exists());
+    }
+
+    #[test]
+    fn download_candidates_swap_the_repository_for_each_mirror() {
+        let cfg = AppConfig {
+            repo_url: "https://r.example/nx/".into(),
+            mirrors: vec!["https://m1.example/nx".into(), "https://m2.example/nx".into()],
+            ..AppConfig::default()
+        };
+        assert_eq!(
+            download_candidates(&cfg, "https://r.example/nx/pkgs/app-1.nxpkg"),
+            ["https://r.example/nx/pkgs/app-1.nxpkg", "https://m1.example/nx/pkgs/app-1.nxpkg", "https://m2.example/nx/pkgs/app-1.nxpkg"]
+        );
+        assert_eq!(
+            download_candidates(&cfg, "https://m2.example/nx/app-1.nxpkg"),
+            ["https://m2.example/nx/app-1.nxpkg", "https://r.example/nx/app-1.nxpkg", "https://m1.example/nx/app-1.nxpkg"]
+        );
+        assert_eq!(download_candidates(&cfg, "https://cdn.example/app-1.nxpkg"), ["https://cdn.example/app-1.nxpkg"]);
+    }
+
+    #[tokio::test]
+    async fn package_download_falls_back_to_a_mirror() {
+        let (primary, primary_log) = crate::test_support::mock_server(|_| (503, vec![], "down".into())).await;
+        let (mirror, mirror_log) = crate::test_support::mock_server(|req| match req.target.as_str() {
+            "/pkgs/app-1.nxpkg" => (200, vec![], "package bytes".into()),
+            _ => (404, vec![], String::new()),
+        })
+        .await;
+        let cfg = AppConfig { repo_url: primary.clone(), mirrors: vec![mirror.clone()], ..AppConfig::default() };
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("app-1.nxpkg");
+
+        let urls = download_candidates(&cfg, &format!("{}/pkgs/app-1.nxpkg", primary));
+        let sum = download_with_failover(&urls, &dest, Some(&sha256_hex(b"package bytes")), None).await.unwrap();
+        assert_eq!(sum, sha256_hex(b"package bytes"));
+        assert_eq!(fs::read(&dest).unwrap(), b"package bytes");
+        assert_eq!(primary_log.lock().unwrap().len(), 1);
+        assert_eq!(mirror_log.lock().unwrap()[0].target, "/pkgs/app-1.nxpkg");
+
+        // When every candidate fails, the last error is returned and nothing is left behind
+        let urls = download_candidates(&cfg, &format!("{}/pkgs/missing.nxpkg", primary));
+        let err = download_with_failover(&urls, &dest.with_file_name("missing.nxpkg"), None, None).await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+        assert!(!dir.path().join("missing.nxpkg").exists());
+    }
+
+    #[test]
+    fn check_complete_requires_the_announced_length() {
+        let url = "https://r/app-1.nxpkg";
+        assert_eq!(check_complete(url, 0, None).unwrap_err(), "incomplete download of https://r/app-1.nxpkg: received 0 bytes");
+        assert_eq!(
+            check_complete(url, 0, Some(10)).unwrap_err(),
+            "incomplete download of https://r/app-1.nxpkg: received 0 of 10 bytes"
+        );
+        assert_eq!(
+            check_complete(url, 4, Some(10)).unwrap_err(),
+            "incomplete download of https://r/app-1.nxpkg: received 4 of 10 bytes"
+        );
+        assert!(check_complete(url, 12, Some(10)).is_err());
+        assert!(check_complete(url, 10, Some(10)).is_ok());
+        assert!(check_complete(url, 10, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn connection_closed_early_is_an_incomplete_download() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/app-1.nxpkg", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            // Promise 100 bytes, send 5 and hang up
+            stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\nshort").await.unwrap();
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("app-1.nxpkg");
+        let err = download_file_with_progress(&url, &dest, None, None).await.unwrap_err().to_string();
+        assert!(err.starts_with(&format!("incomplete download of {}: received 5 bytes", url)), "{}", err);
+        assert!(!dest.exists());
+        assert!(!partial_download_path(&dest).exists());
+    }
+}
@@ -0,0 +1,117 @@
+//! src/db/history.rs
+//! Snapshots of verified repository indexes, kept so a repository can be rolled back to one.
+//!
+//! Each repository has its own directory, `<cache_dir>/index-history/<repo key>/`, holding
+//! `<serial>.json` (index.json exactly as served) and `<serial>.json.sig` when it was signed.
+//! Serials count up from 1 per repository; only the newest `[repo] index_history` are kept.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Serials of the snapshots in `dir`, oldest first.
+pub fn list_snapshots(dir: &Path) -> Vec<u64> {
+    let mut serials: Vec<u64> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str()?.strip_suffix(".json")?.parse().ok())
+        .collect();
+    serials.sort_unstable();
+    serials
+}
+
+fn snapshot_path(dir: &Path, serial: u64) -> PathBuf {
+    dir.join(format!("{}.json", serial))
+}
+
+fn sig_path(dir: &Path, serial: u64) -> PathBuf {
+    dir.join(format!("{}.json.sig", serial))
+}
+
+/// Store `bytes` (and its signature) as the next snapshot unless it is identical to the newest
+/// one, then delete the oldest beyond `keep`. Returns the new serial, if one was written.
+pub fn record(dir: &Path, bytes: &[u8], sig: Option<&str>, keep: usize) -> std::io::Result<Option<u64>> {
+    let serials = list_snapshots(dir);
+    if let Some(&newest) = serials.last() {
+        if fs::read(snapshot_path(dir, newest)).is_ok_and(|b| b == bytes) {
+            return Ok(None);
+        }
+    }
+    fs::create_dir_all(dir)?;
+    let serial = serials.last().map_or(1, |n| n + 1);
+    match sig {
+        Some(sig) => fs::write(sig_path(dir, serial), sig.trim())?,
+        None => { let _ = fs::remove_file(sig_path(dir, serial)); }
+    }
+    fs::write(snapshot_path(dir, serial), bytes)?;
+    let excess = (serials.len() + 1).saturating_sub(keep);
+    for old in serials.into_iter().take(excess) {
+        let _ = fs::remove_file(snapshot_path(dir, old));
+        let _ = fs::remove_file(sig_path(dir, old));
+    }
+    Ok(Some(serial))
+}
+
+/// The index bytes and signature text of snapshot `serial`.
+pub fn load(dir: &Path, serial: u64) -> Result<(Vec<u8>, Option<String>), String> {
+    let path = snapshot_path(dir, serial);
+    let bytes = fs::read(&path).map_err(|e| {
+        let available: Vec<String> = list_snapshots(dir).iter().map(u64::to_string).collect();
+        if available.is_empty() {
+            format!("cannot read {}: {} (no snapshots recorded)", path.display(), e)
+        } else {
+            format!("cannot read {}: {} (available: {})", path.display(), e, available.join(", "))
+        }
+    })?;
+    let sig = fs::read_to_string(sig_path(dir, serial)).ok();
+    Ok((bytes, sig))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_numbers_snapshots_and_keeps_the_newest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("repo");
+        for (i, body) in ["a", "b", "c", "d"].iter().enumerate() {
+            assert_eq!(record(&dir, body.as_bytes(), None, 3).unwrap(), Some(i as u64 + 1));
+        }
+
+        assert_eq!(list_snapshots(&dir), [2, 3, 4]);
+        assert_eq!(load(&dir, 2).unwrap().0, b"b");
+        assert_eq!(load(&dir, 4).unwrap().0, b"d");
+    }
+
+    #[test]
+    fn identical_index_is_not_recorded_twice() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(record(tmp.path(), b"same", Some("sig\n"), 5).unwrap(), Some(1));
+        assert_eq!(record(tmp.path(), b"same", Some("sig\n"), 5).unwrap(), None);
+        assert_eq!(load(tmp.path(), 1).unwrap(), (b"same".to_vec(), Some("sig".to_string())));
+    }
+
+    #[test]
+    fn snapshots_are_listed_oldest_first_by_number() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["10.json", "9.json", "10.json.sig", "2.json", "notes.txt", "x.json"] {
+            fs::write(tmp.path().join(name), "{}").unwrap();
+        }
+        assert_eq!(list_snapshots(tmp.path()), [2, 9, 10]);
+        assert!(list_snapshots(&tmp.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn loading_a_missing_snapshot_names_the_available_ones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let err = load(tmp.path(), 1).unwrap_err();
+        assert!(err.contains("no snapshots recorded"), "{}", err);
+
+        record(tmp.path(), b"a", None, 5).unwrap();
+        record(tmp.path(), b"b", None, 5).unwrap();
+        let err = load(tmp.path(), 7).unwrap_err();
+        assert!(err.contains("available: 1, 2"), "{}", err);
+        assert_eq!(load(tmp.path(), 1).unwrap().1, None);
+    }
+}
@@ -1,8 +1,25 @@
 use crate::buildins::meta::{BuildInfo, InstallInfo, PackageInfo, PackageRecipe};
 use crate::buildins::profile::BuildProfile;
 use rusqlite::{params, Connection, Result};
+pub mod backend;
+pub mod backup;
 pub mod download;
+pub mod history;
 pub mod upload;
+pub mod verify;
+
+/// Whether the linked SQLite can put a database in WAL mode (`nxpkg self-check`). Uses a
+/// scratch database in a temporary directory; the package database is not touched.
+pub fn sqlite_supports_wal() -> std::result::Result<(), String> {
+    let dir = tempfile::TempDir::new().map_err(|e| e.to_string())?;
+    let conn = Connection::open(dir.path().join("wal-check.db")).map_err(|e| e.to_string())?;
+    let mode: String = conn.query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0)).map_err(|e| e.to_string())?;
+    if mode.eq_ignore_ascii_case("wal") {
+        Ok(())
+    } else {
+        Err(format!("journal mode stayed '{}'", mode))
+    }
+}
 
 pub struct PackageManagerDB {
     pub db: Connection,
@@ -28,6 +45,12 @@ impl PackageManagerDB {
             )",
             [],
         )?;
+        Self::ensure_column(db, "packages", "deps_bypassed", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(db, "packages", "installed_dirs", "TEXT NOT NULL DEFAULT ''")?;
+        Self::ensure_column(db, "packages", "file_hashes", "TEXT NOT NULL DEFAULT '{}'")?;
+        Self::ensure_column(db, "packages", "replaces", "TEXT NOT NULL DEFAULT ''")?;
+        Self::ensure_column(db, "packages", "description", "TEXT NOT NULL DEFAULT ''")?;
+        Self::ensure_column(db, "packages", "conffiles", "TEXT NOT NULL DEFAULT ''")?;
         db.execute(
             "CREATE TABLE IF NOT EXISTS build_profiles (
                 name TEXT PRIMARY KEY,
@@ -38,6 +61,20 @@ impl PackageManagerDB {
             )",
             [],
         )?;
+        Self::ensure_column(db, "build_profiles", "dependencies", "TEXT NOT NULL DEFAULT '[]'")?;
+        Ok(())
+    }
+
+    /// Add a column to an existing table when an older database lacks it.
+    fn ensure_column(db: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+        let mut stmt = db.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|c| c.ok())
+            .any(|c| c == column);
+        if !exists {
+            db.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl), [])?;
+        }
         Ok(())
     }
 
@@ -47,11 +84,15 @@ impl PackageManagerDB {
         let build_commands = recipe.build.commands.join(";");
         let install_params = recipe.install.install_params.join(",");
         let installed_files = recipe.install.installed_files.join(";");
+        let installed_dirs = recipe.install.installed_dirs.join(";");
+        let file_hashes = serde_json::to_string(&recipe.install.file_hashes).unwrap_or_else(|_| "{}".to_string());
+        let replaces = recipe.package.replaces.join(",");
+        let conffiles = recipe.install.conffiles.join(";");
 
         self.db.execute(
-            "INSERT OR REPLACE INTO packages (name, version, architectures, dependencies, build_commands, install_params, installed_files)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            &[
+            "INSERT OR REPLACE INTO packages (name, version, architectures, dependencies, build_commands, install_params, installed_files, deps_bypassed, installed_dirs, file_hashes, replaces, description, conffiles)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
                 &recipe.package.name,
                 &recipe.package.version,
                 &architectures,
@@ -59,13 +100,19 @@ impl PackageManagerDB {
                 &build_commands,
                 &install_params,
                 &installed_files,
+                recipe.install.deps_bypassed,
+                &installed_dirs,
+                &file_hashes,
+                &replaces,
+                &recipe.package.description,
+                &conffiles,
             ],
         )?;
         Ok(())
     }
 
     pub fn get_package_metadata(&self, name: &str) -> Result<Option<PackageRecipe>> {
-        let mut stmt = self.db.prepare("SELECT version, architectures, dependencies, build_commands, install_params, installed_files FROM packages WHERE name = ?1")?;
+        let mut stmt = self.db.prepare("SELECT version, architectures, dependencies, build_commands, install_params, installed_files, deps_bypassed, installed_dirs, file_hashes, replaces, description, conffiles FROM packages WHERE name = ?1")?;
         
         let recipe_result = stmt.query_row([name], |row| {
             let architectures_str: String = row.get(1)?;
@@ -73,21 +120,39 @@ impl PackageManagerDB {
             let build_commands_str: String = row.get(3)?;
             let install_params_str: String = row.get(4)?;
             let installed_files_str: String = row.get::<_, String>(5).unwrap_or_else(|_| String::new()); // Safely handle old entries
+            let installed_dirs_str: String = row.get(7)?;
+            let file_hashes_str: String = row.get(8)?;
+            let replaces_str: String = row.get(9)?;
+            let conffiles_str: String = row.get(11)?;
             
             Ok(PackageRecipe {
                 package: PackageInfo {
                     name: name.to_string(),
                     version: row.get(0)?,
                     architectures: architectures_str.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                    replaces: replaces_str.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                    // Only checked before installing; not kept in the database
+                    min_nxpkg_version: None,
+                    description: row.get(10)?,
                 },
                 build: BuildInfo {
                     dependencies: dependencies_str.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                    // Installed packages record the effective list for their architecture
+                    arch_dependencies: Default::default(),
                     commands: build_commands_str.split(';').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
                 },
                 install: InstallInfo {
                     install_params: install_params_str.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                    // The patterns only matter when packing; installs record the resolved list
+                    config_files: Vec::new(),
+                    conffiles: conffiles_str.split(';').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
                     installed_files: installed_files_str.split(';').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
-                }
+                    installed_dirs: installed_dirs_str.split(';').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                    file_hashes: serde_json::from_str(&file_hashes_str).unwrap_or_default(),
+                    deps_bypassed: row.get(6)?,
+                },
+                // Unknown recipe keys only travel inside package files
+                extra: Default::default(),
             })
         });
 
@@ -98,62 +163,92 @@ impl PackageManagerDB {
         }
     }
 
-    pub fn rem_package_metadata(&self, name: &str) -> Result<()> {
+    /// Names of all installed packages, sorted.
+    pub fn installed_package_names(&self) -> Result<Vec<String>> {
+        let mut stmt = self.db.prepare("SELECT name FROM packages ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get(0))?.collect();
+        names
+    }
+
+    /// Removes a package's database entry; with `delete_files`, also deletes its files except
+    /// those in `keep`, and the directories it created once empty. Without it the files are left
+    /// in place, unmanaged. Recorded paths are resolved under `root`, the install root the
+    /// package was installed into.
+    pub fn rem_package_metadata(&self, name: &str, delete_files: bool, root: &std::path::Path, keep: &[String]) -> Result<()> {
         // First, retrieve the metadata to know which files to delete.
+        if let Some(recipe) = self.get_package_metadata(name)?.filter(|_| delete_files) {
+            let keep = keep.iter().map(String::as_str).collect();
+            Self::delete_package_files(&recipe, root, &keep);
+        }
+        
+        // Finally, remove the package entry from the database.
+        self.db.execute("DELETE FROM packages WHERE name = ?", [name])?;
+        Ok(())
+    }
+
+    /// Removes a package superseded by another (`[package] replaces`): like a full removal,
+    /// except files in `taken_over` (recorded paths now owned by the replacing package) stay.
+    pub fn rem_replaced_package(&self, name: &str, root: &std::path::Path, taken_over: &[String]) -> Result<()> {
         if let Some(recipe) = self.get_package_metadata(name)? {
-            // Iterate over the stored file paths and delete each one.
-            for file_path_str in &recipe.install.installed_files {
-                let file_path = std::path::Path::new(file_path_str);
-                if file_path.exists() {
-                    if let Err(e) = std::fs::remove_file(file_path) {
-                        // Log or handle the error, e.g., by collecting failures.
-                        // For now, we print to stderr. A more robust solution might be needed.
-                        eprintln!("Warning: could not remove file {}: {}", file_path.display(), e);
-                    }
+            let keep = taken_over.iter().map(String::as_str).collect();
+            Self::delete_package_files(&recipe, root, &keep);
+        }
+        self.db.execute("DELETE FROM packages WHERE name = ?", [name])?;
+        Ok(())
+    }
+
+    fn delete_package_files(recipe: &PackageRecipe, root: &std::path::Path, keep: &std::collections::HashSet<&str>) {
+        // Iterate over the stored file paths and delete each one.
+        for file_path_str in recipe.install.installed_files.iter().filter(|p| !keep.contains(p.as_str())) {
+            let file_path = crate::compress::path_in_root(root, std::path::Path::new(file_path_str));
+            if file_path.symlink_metadata().is_ok() {
+                if let Err(e) = std::fs::remove_file(&file_path) {
+                    // Log or handle the error, e.g., by collecting failures.
+                    // For now, we print to stderr. A more robust solution might be needed.
+                    eprintln!("Warning: could not remove file {}: {}", file_path.display(), e);
                 }
             }
-            
-            // After deleting files, try to remove now-empty parent directories.
-            // This is a simple approach. A more robust implementation would track directories
-            // created by the package manager and only remove those.
-            let mut dirs_to_check: std::collections::HashSet<_> = recipe.install.installed_files
-                .iter()
-                .filter_map(|p| std::path::Path::new(p).parent())
-                .map(|p| p.to_path_buf())
-                .collect();
-            
-            // Sort by path depth (longest first) to remove child directories before parents.
-            let mut sorted_dirs: Vec<_> = dirs_to_check.into_iter().collect();
-            sorted_dirs.sort_by_key(|b| std::cmp::Reverse(b.as_os_str().len()));
-
-            for dir in sorted_dirs {
-                if dir.is_dir() && dir.read_dir().map_or(false, |mut i| i.next().is_none()) {
-                    if let Err(e) = std::fs::remove_dir(&dir) {
-                        eprintln!("Warning: could not remove directory {}: {}", dir.display(), e);
-                    }
+        }
+        
+        // After deleting files, try to remove the directories the package created
+        // and any now-empty parent directories of its files. The root itself is never removed.
+        let dirs_to_check: std::collections::HashSet<_> = recipe.install.installed_files
+            .iter()
+            .filter_map(|p| std::path::Path::new(p).parent())
+            .chain(recipe.install.installed_dirs.iter().map(std::path::Path::new))
+            .filter(|p| p.parent().is_some())
+            .map(|p| crate::compress::path_in_root(root, p))
+            .collect();
+        
+        // Sort by path depth (longest first) to remove child directories before parents.
+        let mut sorted_dirs: Vec<_> = dirs_to_check.into_iter().collect();
+        sorted_dirs.sort_by_key(|b| std::cmp::Reverse(b.as_os_str().len()));
+
+        for dir in sorted_dirs {
+            if dir.is_dir() && dir.read_dir().is_ok_and(|mut i| i.next().is_none()) {
+                if let Err(e) = std::fs::remove_dir(&dir) {
+                    eprintln!("Warning: could not remove directory {}: {}", dir.display(), e);
                 }
             }
         }
-        
-        // Finally, remove the package entry from the database.
-        self.db.execute("DELETE FROM packages WHERE name = ?", [name])?;
-        Ok(())
     }
 
     pub fn save_build_profile(&self, profile: &BuildProfile) -> Result<()> {
         let configure_json = serde_json::to_string(&profile.configure_args).unwrap_or_else(|_| "[]".to_string());
         let build_json = serde_json::to_string(&profile.build_args).unwrap_or_else(|_| "[]".to_string());
         let install_json = serde_json::to_string(&profile.install_args).unwrap_or_else(|_| "[]".to_string());
+        let deps_json = serde_json::to_string(&profile.dependencies).unwrap_or_else(|_| "[]".to_string());
 
         self.db.execute(
-            "INSERT OR REPLACE INTO build_profiles (name, build_system, configure_args, build_args, install_args)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
+            "INSERT OR REPLACE INTO build_profiles (name, build_system, configure_args, build_args, install_args, dependencies)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
             params![
                 &profile.name,
                 profile.build_system.as_deref(),
                 configure_json,
                 build_json,
                 install_json,
+                deps_json,
             ],
         )?;
         Ok(())
@@ -161,7 +256,7 @@ impl PackageManagerDB {
 
     pub fn get_build_profile(&self, name: &str) -> Result<Option<BuildProfile>> {
         let mut stmt = self.db.prepare(
-            "SELECT build_system, configure_args, build_args, install_args
+            "SELECT build_system, configure_args, build_args, install_args, dependencies
              FROM build_profiles WHERE name = ?1",
         )?;
 
@@ -170,11 +265,13 @@ impl PackageManagerDB {
             let configure_args_raw: String = row.get(1)?;
             let build_args_raw: String = row.get(2)?;
             let install_args_raw: String = row.get(3)?;
+            let deps_raw: String = row.get(4)?;
 
             let build_system = build_system.filter(|s| !s.trim().is_empty());
             let configure_args: Vec<String> = serde_json::from_str(&configure_args_raw).unwrap_or_default();
             let build_args: Vec<String> = serde_json::from_str(&build_args_raw).unwrap_or_default();
             let install_args: Vec<String> = serde_json::from_str(&install_args_raw).unwrap_or_default();
+            let dependencies: Vec<String> = serde_json::from_str(&deps_raw).unwrap_or_default();
 
             Ok(BuildProfile {
                 name: name.to_string(),
@@ -182,6 +279,7 @@ impl PackageManagerDB {
                 configure_args,
                 build_args,
                 install_args,
+                dependencies,
             })
         });
 
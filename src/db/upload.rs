@@ -2,12 +2,16 @@
 //! Uploads .nxpkg files to a repository and updates index.json with checksum info.
 
 use crate::buildins::meta::PackageRecipe;
-use crate::db::download::{fetch_index_verified, PackageEntry, RepoIndex, ArchAsset};
+use crate::db::backend::{signature_target, RepoBackend};
+use crate::arch::{canonical_arch, host_arch};
+use crate::db::download::{fetch_index_verified, PackageEntry, RepoIndex, ArchAsset, VersionAssets};
 use hex;
+use crate::config::{AppConfig, UploadStrategy};
 use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_RANGE, ETAG};
 use sha2::{Digest, Sha256};
 use base64::{engine::general_purpose, Engine as _};
+use serde::Serialize;
 use ed25519_dalek::Signer;
 use std::fs::File;
 use std::io::{BufReader, Read};
@@ -27,12 +31,35 @@ pub fn sha256_file(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
     Ok(hex::encode(hasher.finalize()))
 }
 
+/// How `upload_file_put` sends large files; see `[network]` in CONFIGURATION.md.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadOptions {
+    pub strategy: UploadStrategy,
+    /// Files larger than this are split into parts of at most this many bytes
+    pub multipart_threshold: u64,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions { strategy: UploadStrategy::Put, multipart_threshold: 64 * 1024 * 1024 }
+    }
+}
+
+impl UploadOptions {
+    pub fn from_config(cfg: &AppConfig) -> Self {
+        UploadOptions { strategy: cfg.upload_strategy, multipart_threshold: cfg.multipart_threshold }
+    }
+}
+
 /// Upload a local file to an exact destination URL using HTTP PUT.
 /// If `bearer_token` is provided, include `Authorization: Bearer <token>` header.
+/// Files above `opts.multipart_threshold` are split into parts unless the strategy is `Put`;
+/// anything at or below it is always a single request.
 pub async fn upload_file_put(
     destination_url: &str,
     local_path: &Path,
     bearer_token: Option<&str>,
+    opts: &UploadOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
 
@@ -44,44 +71,232 @@ pub async fn upload_file_put(
         );
     }
 
-    let file = File::open(local_path)?;
-    let pb = ProgressBar::new(file.metadata()?.len());
+    let mut file = File::open(local_path)?;
+    let total = file.metadata()?.len();
+    let pb = crate::progress::bar(total);
     pb.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes}")?
             .progress_chars("#>-")
     );
 
-    // For simplicity, read into memory; for huge files, switch to streaming upload
-    let body = std::fs::read(local_path)?;
-    let resp = client
-        .put(destination_url)
-        .headers(headers)
-        .body(body)
-        .send()
-        .await?;
-
-    // We can't easily hook progress for PUT with Body-from-file here; we showed a static bar.
-    // For real-time progress, switch to a custom stream.
-    if !resp.status().is_success() {
-        pb.abandon_with_message("Upload failed");
-        return Err(format!(
-            "Upload failed (HTTP {}): {}",
-            resp.status(),
-            resp.text().await.unwrap_or_default()
-        ).into());
-    }
-
-    pb.finish_with_message("Upload complete");
+    let part_size = opts.multipart_threshold.max(1);
+    let result = match opts.strategy {
+        UploadStrategy::Chunked if total > part_size => {
+            upload_chunked(&client, destination_url, &headers, &mut file, total, part_size, &pb).await
+        }
+        UploadStrategy::S3 if total > part_size => {
+            upload_s3_multipart(&client, destination_url, &headers, &mut file, total, part_size, &pb).await
+        }
+        _ => {
+            // For simplicity, read into memory; for huge files, configure a multipart strategy
+            let body = std::fs::read(local_path)?;
+            let resp = client
+                .put(destination_url)
+                .headers(headers)
+                .body(body)
+                .send()
+                .await?;
+            pb.set_position(total);
+            check_upload_response(resp, "Upload").await.map(|_| ())
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            pb.finish_with_message("Upload complete");
+            Ok(())
+        }
+        Err(e) => {
+            pb.abandon_with_message("Upload failed");
+            Err(e)
+        }
+    }
+}
+
+/// Read the next part of at most `part_size` bytes.
+fn read_part(file: &mut File, part_size: u64) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(part_size as usize);
+    file.by_ref().take(part_size).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+async fn check_upload_response(
+    resp: reqwest::Response,
+    what: &str,
+) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    // 308 is the "resume incomplete" answer of chunked upload endpoints
+    if resp.status().is_success() || resp.status().as_u16() == 308 {
+        return Ok(resp);
+    }
+    Err(format!(
+        "{} failed (HTTP {}): {}",
+        what,
+        resp.status(),
+        resp.text().await.unwrap_or_default()
+    ).into())
+}
+
+/// PUT each part to `url` with `Content-Range: bytes <first>-<last>/<total>`, in order.
+async fn upload_chunked(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    file: &mut File,
+    total: u64,
+    part_size: u64,
+    pb: &ProgressBar,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut offset = 0u64;
+    let mut part = 1;
+    while offset < total {
+        let body = read_part(file, part_size)?;
+        if body.is_empty() {
+            return Err(format!("file shrank during upload ({} of {} bytes read)", offset, total).into());
+        }
+        let len = body.len() as u64;
+        let resp = client
+            .put(url)
+            .headers(headers.clone())
+            .header(CONTENT_RANGE, format!("bytes {}-{}/{}", offset, offset + len - 1, total))
+            .body(body)
+            .send()
+            .await?;
+        check_upload_response(resp, &format!("Upload of part {}", part)).await?;
+        offset += len;
+        part += 1;
+        pb.set_position(offset);
+    }
     Ok(())
 }
 
+/// S3 multipart upload: `POST ?uploads` returns an UploadId, each part is `PUT
+/// ?partNumber=N&uploadId=ID` and answers with an ETag, and `POST ?uploadId=ID` with the list of
+/// parts completes the object. A failed upload is aborted so the server can drop the parts.
+async fn upload_s3_multipart(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    file: &mut File,
+    total: u64,
+    part_size: u64,
+    pb: &ProgressBar,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let resp = client.post(format!("{}?uploads", url)).headers(headers.clone()).send().await?;
+    let body = check_upload_response(resp, "Starting multipart upload").await?.text().await?;
+    let upload_id = xml_element(&body, "UploadId")
+        .ok_or("Starting multipart upload failed: response has no UploadId")?;
+
+    let parts = async {
+        let mut etags = Vec::new();
+        let mut offset = 0u64;
+        while offset < total {
+            let body = read_part(file, part_size)?;
+            if body.is_empty() {
+                return Err(format!("file shrank during upload ({} of {} bytes read)", offset, total).into());
+            }
+            let len = body.len() as u64;
+            let part = etags.len() + 1;
+            let resp = client
+                .put(url)
+                .query(&[("partNumber", part.to_string()), ("uploadId", upload_id.clone())])
+                .headers(headers.clone())
+                .body(body)
+                .send()
+                .await?;
+            let resp = check_upload_response(resp, &format!("Upload of part {}", part)).await?;
+            let etag = resp.headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| format!("Upload of part {} failed: response has no ETag", part))?
+                .to_string();
+            etags.push(etag);
+            offset += len;
+            pb.set_position(offset);
+        }
+        Ok::<_, Box<dyn std::error::Error>>(etags)
+    }.await;
+
+    let completed = match parts {
+        Ok(etags) => {
+            let mut xml = String::from("<CompleteMultipartUpload>");
+            for (i, etag) in etags.iter().enumerate() {
+                xml.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag));
+            }
+            xml.push_str("</CompleteMultipartUpload>");
+            match client.post(url).query(&[("uploadId", &upload_id)]).headers(headers.clone()).body(xml).send().await {
+                Ok(resp) => check_upload_response(resp, "Completing multipart upload").await.map(|_| ()),
+                Err(e) => Err(e.into()),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    if completed.is_err() {
+        let _ = client.delete(url).query(&[("uploadId", &upload_id)]).headers(headers.clone()).send().await;
+    }
+    completed
+}
+
+/// Text of the first `<name>` element in a small XML response.
+fn xml_element(xml: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    let text = xml[start..end].trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// History record for an entry's current latest version, built from its top-level fields.
+/// A legacy entry with only `download_url` is filed under `any`, matching how clients fall back
+/// to that URL on every architecture. None when the entry has no asset at all.
+fn previous_version_assets(entry: &PackageEntry) -> Option<VersionAssets> {
+    let mut architectures = entry.architectures.clone().unwrap_or_default();
+    if architectures.is_empty() {
+        let url = entry.download_url.clone()?;
+        architectures.insert("any".to_string(), ArchAsset { download_url: url, sha256: entry.sha256.clone(), arch: None, size: entry.size });
+    }
+    Some(VersionAssets {
+        architectures,
+        dependencies: entry.dependencies.clone(),
+        arch_dependencies: entry.arch_dependencies.clone(),
+        min_nxpkg_version: entry.min_nxpkg_version.clone(),
+    })
+}
+
+/// Index keys for a package's asset: the recipe's declared architectures in canonical form
+/// (`noarch` becomes `any`), or the host architecture when the recipe declares none.
+fn index_arch_keys(recipe: &PackageRecipe) -> Vec<String> {
+    let mut keys: Vec<String> = recipe.package.architectures
+        .iter()
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty())
+        .map(canonical_arch)
+        .collect();
+    if keys.is_empty() {
+        keys.push(host_arch().to_string());
+    }
+    // A universal package needs no per-arch copies
+    if keys.iter().any(|k| k == "any") {
+        keys = vec!["any".to_string()];
+    }
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
 /// Publishes a built package: uploads its .nxpkg to repo and updates index.json.
-/// - repo_url: base URL of repository (e.g., https://host/releases)
+/// - repo_url: base URL of repository (e.g., https://host/releases or file:///srv/nxpkg)
 /// - nxpkg_path: local path to the built archive (e.g., /tmp/pkg-1.0.0.nxpkg)
 /// - recipe: the recipe used to build (for name/version/architectures)
 /// - description: optional description string to appear in index.json
 /// - bearer_token: optional Bearer token for auth
+/// - canonical: write (and sign) index.json in canonical JSON form
+/// - sig_path: where the signature is uploaded (`[security] sig_path`)
+/// - upload: how the .nxpkg is sent to HTTP repositories
+/// - force: upload and rewrite the index even when the index already lists this exact package
+/// - history: snapshot directory and count (`[repo] index_history`); the index as served before
+///   the publish and the one written are both recorded, so `rollback-index` can undo it
+#[allow(clippy::too_many_arguments)]
 pub async fn upload_and_update_index(
     repo_url: &str,
     nxpkg_path: &Path,
@@ -90,109 +305,461 @@ pub async fn upload_and_update_index(
     bearer_token: Option<&str>,
     // optional signing of the resulting index.json with an ed25519 private key (base64 keypair 64 bytes)
     sign_with_keypair_b64: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    canonical: bool,
+    sig_path: &str,
+    upload: UploadOptions,
+    force: bool,
+    history: Option<(&Path, usize)>,
+) -> Result<PublishResult, Box<dyn std::error::Error>> {
+    let backend = RepoBackend::from_url(repo_url, bearer_token).with_upload(upload);
     let filename = format!("{}-{}.nxpkg", recipe.package.name, recipe.package.version);
-    let download_url = format!(
-        "{}/{}",
-        repo_url.trim_end_matches('/'),
-        filename
-    );
+    let download_url = backend.url_for(&filename);
+    // Resolve the signature location up front so a bad sig_path fails before anything is uploaded
+    let (sig_backend, sig_name) = signature_target(repo_url, sig_path, bearer_token)?;
 
-    // 1) Compute checksum locally
+    // 1) Compute checksum and size locally
     let checksum = sha256_file(nxpkg_path)?;
+    let size = std::fs::metadata(nxpkg_path)?.len();
 
-    // 2) Upload the .nxpkg
-    upload_file_put(&download_url, nxpkg_path, bearer_token).await?;
-
-    // 3) Fetch or init index.json
-    let mut index: RepoIndex = match fetch_index_verified(repo_url, None, false).await {
+    // 2) Fetch or init index.json
+    let mut index: RepoIndex = match fetch_index_verified(repo_url, sig_path, None, false).await {
         Ok(idx) => idx,
         Err(_) => RepoIndex { packages: std::collections::HashMap::new() },
     };
 
-    // 4) Update entry with per-architecture asset
-    let arch_canonical = match std::env::consts::ARCH {
-        "x86_64" => "x86_64",
-        "aarch64" => "aarch64",
-        "arm" => "arm",
-        "i686" | "x86" => "i686",
-        other => other,
-    }.to_string();
+    // 3) Nothing to do when the index already lists this exact file and metadata
+    let arch_keys = index_arch_keys(recipe);
+    let unchanged = index.packages.get(&recipe.package.name)
+        .is_some_and(|entry| already_published(entry, recipe, &arch_keys, &checksum, description));
+    let mut result = PublishResult {
+        success: true,
+        name: Some(recipe.package.name.clone()),
+        version: Some(recipe.package.version.clone()),
+        download_url: Some(download_url.clone()),
+        sha256: Some(checksum.clone()),
+        arch_keys: arch_keys.clone(),
+        ..Default::default()
+    };
+    if unchanged && !force {
+        result.unchanged = true;
+        return Ok(result);
+    }
+
+    // 4) Upload the .nxpkg
+    backend.put_file(&filename, nxpkg_path).await?;
+
+    // 5) Update entry with per-architecture asset, filed under every arch the recipe declares
 
     let mut entry = index.packages.remove(&recipe.package.name).unwrap_or(PackageEntry{
         latest_version: recipe.package.version.clone(),
         description: description.unwrap_or("").to_string(),
         download_url: None,
         sha256: None,
+        size: None,
         architectures: Some(std::collections::HashMap::new()),
+        dependencies: Vec::new(),
+        arch_dependencies: std::collections::HashMap::new(),
+        min_nxpkg_version: None,
+        source_repo: None,
+        versions: std::collections::HashMap::new(),
     });
 
+    // Keep the version being superseded installable by pin, also when the index predates `versions`
+    if entry.latest_version != recipe.package.version && !entry.versions.contains_key(&entry.latest_version) {
+        if let Some(previous) = previous_version_assets(&entry) {
+            entry.versions.insert(entry.latest_version.clone(), previous);
+        }
+    }
+
     // Ensure architectures map exists
     if entry.architectures.is_none() { entry.architectures = Some(std::collections::HashMap::new()); }
     let map = entry.architectures.as_mut().unwrap();
-    map.insert(arch_canonical.clone(), ArchAsset { download_url: download_url.clone(), sha256: Some(checksum) });
+    for key in &arch_keys {
+        map.insert(key.clone(), ArchAsset {
+            download_url: download_url.clone(),
+            sha256: Some(checksum.clone()),
+            arch: Some(key.clone()),
+            size: Some(size),
+        });
+    }
 
     // Update metadata
     entry.latest_version = recipe.package.version.clone();
     entry.description = description.unwrap_or("").to_string();
+    entry.dependencies = recipe.build.dependencies.clone();
+    entry.arch_dependencies = recipe.build.arch_dependencies.clone().into_iter().collect();
+    entry.min_nxpkg_version = recipe.package.min_nxpkg_version.clone();
 
     // For backward compatibility, also set legacy fields to this asset
     entry.download_url = Some(download_url.clone());
-    entry.sha256 = map.get(&arch_canonical).and_then(|a| a.sha256.clone());
-
-    index.packages.insert(recipe.package.name.clone(), entry);
+    entry.sha256 = Some(checksum);
+    entry.size = Some(size);
 
-    // 5) Upload updated index.json via PUT
-    let client = reqwest::Client::new();
-    let mut headers = HeaderMap::new();
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    if let Some(tok) = bearer_token {
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {}", tok))?,
-        );
+    // Record this version in the history; other architectures of it published earlier stay
+    let version = entry.versions.entry(recipe.package.version.clone()).or_default();
+    for key in &arch_keys {
+        version.architectures.insert(key.clone(), ArchAsset {
+            download_url: download_url.clone(),
+            sha256: entry.sha256.clone(),
+            arch: Some(key.clone()),
+            size: Some(size),
+        });
     }
+    version.dependencies = recipe.build.dependencies.clone();
+    version.arch_dependencies = entry.arch_dependencies.clone();
+    version.min_nxpkg_version = recipe.package.min_nxpkg_version.clone();
 
-    let index_url = format!("{}/index.json", repo_url.trim_end_matches('/'));
-    let body = serde_json::to_vec(&index).unwrap();
-    let resp = client
-        .put(&index_url)
-        .headers(headers.clone())
-        .body(body.clone())
-        .send()
-        .await?;
+    index.packages.insert(recipe.package.name.clone(), entry);
 
-    if !resp.status().is_success() {
-        return Err(format!(
-            "Failed to upload index.json (HTTP {}): {}",
-            resp.status(),
-            resp.text().await.unwrap_or_default()
-        ).into());
+    // 6) Upload updated index.json (PUT for HTTP, write-then-rename for file://)
+    if let Some((dir, keep)) = history {
+        snapshot_served_index(&backend, &sig_backend, &sig_name, dir, keep).await;
+    }
+    let mut body = serde_json::to_vec(&index).unwrap();
+    if canonical {
+        // Serving the canonical form means clients verifying raw bytes accept it too
+        body = crate::trust::canonical_json(&body)?;
     }
+    backend.put("index.json", body.clone(), Some("application/json")).await?;
 
-    // If signing is requested, create index.json.sig and upload it next to index.json
+    // If signing is requested, sign index.json and upload the signature to sig_path
+    let mut signature = None;
     if let Some(kp_b64) = sign_with_keypair_b64 {
-        let keypair_bytes = general_purpose::STANDARD.decode(kp_b64.trim())?;
+        // The decoded bytes and the key are wiped when they go out of scope
+        let keypair_bytes = zeroize::Zeroizing::new(general_purpose::STANDARD.decode(kp_b64.trim())?);
         if keypair_bytes.len() != 64 { return Err("ed25519 keypair must be 64 bytes (base64)".into()); }
         let secret: ed25519_dalek::SigningKey = ed25519_dalek::SigningKey::from_bytes((&keypair_bytes[0..32]).try_into().unwrap());
         let sig = secret.sign(&body);
         let sig_b64 = general_purpose::STANDARD.encode(sig.to_bytes());
 
-        let sig_url = format!("{}.sig", &index_url);
-        let resp_sig = client
-            .put(&sig_url)
-            .headers(headers)
-            .body(sig_b64)
-            .send()
-            .await?;
-        if !resp_sig.status().is_success() {
-            return Err(format!(
-                "Failed to upload index.json.sig (HTTP {}): {}",
-                resp_sig.status(),
-                resp_sig.text().await.unwrap_or_default()
-            ).into());
+        sig_backend.put(&sig_name, sig_b64.clone().into_bytes(), Some("application/json")).await?;
+        result.signed = true;
+        signature = Some(sig_b64);
+    }
+    if let Some((dir, keep)) = history {
+        record_snapshot(dir, &body, signature.as_deref(), keep);
+    }
+
+    Ok(result)
+}
+
+/// Record the index.json currently served by `backend`, with its signature, as a snapshot in
+/// `dir`. A repository without an index yet has nothing to record; failures are warnings.
+async fn snapshot_served_index(backend: &RepoBackend, sig_backend: &RepoBackend, sig_name: &str, dir: &Path, keep: usize) {
+    let bytes = match backend.get("index.json").await {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Warning: could not snapshot the current index: {}", e);
+            return;
         }
+    };
+    let sig = sig_backend.get(sig_name).await.ok().flatten().map(|b| String::from_utf8_lossy(&b).into_owned());
+    record_snapshot(dir, &bytes, sig.as_deref(), keep);
+}
+
+fn record_snapshot(dir: &Path, bytes: &[u8], sig: Option<&str>, keep: usize) {
+    if let Err(e) = super::history::record(dir, bytes, sig, keep) {
+        eprintln!("Warning: could not snapshot index in {}: {}", dir.display(), e);
     }
+}
+
+/// What a publish did, as printed by `publish --json`. Fields that were not determined before
+/// a failure are null.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PublishResult {
+    pub success: bool,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    /// The index already listed this exact package; nothing was uploaded or written
+    pub unchanged: bool,
+    /// URL the package is downloaded from, as recorded in the index
+    pub download_url: Option<String>,
+    pub sha256: Option<String>,
+    /// Architecture keys the asset is filed under
+    pub arch_keys: Vec<String>,
+    /// A new index signature was uploaded
+    pub signed: bool,
+    pub error: Option<String>,
+}
 
+/// Whether `entry` already describes this package as publishing it would: the same version as
+/// the latest, the same checksum under every architecture key, and the same metadata.
+fn already_published(entry: &PackageEntry, recipe: &PackageRecipe, arch_keys: &[String], checksum: &str, description: Option<&str>) -> bool {
+    let Some(version) = entry.versions.get(&recipe.package.version) else { return false };
+    let arch_dependencies: std::collections::HashMap<String, Vec<String>> = recipe.build.arch_dependencies.clone().into_iter().collect();
+    entry.latest_version == recipe.package.version
+        && arch_keys.iter().all(|k| {
+            entry.architectures.as_ref().and_then(|m| m.get(k)).is_some_and(|a| a.sha256.as_deref() == Some(checksum))
+                && version.architectures.get(k).is_some_and(|a| a.sha256.as_deref() == Some(checksum))
+        })
+        && entry.description == description.unwrap_or("")
+        && entry.dependencies == recipe.build.dependencies
+        && entry.arch_dependencies == arch_dependencies
+        && entry.min_nxpkg_version == recipe.package.min_nxpkg_version
+}
+
+/// Replace the repository's index.json (and the signature at `sig_path`) with a previously
+/// published snapshot, byte for byte, so its original signature stays valid
+/// (`repo-remote rollback-index`). With `history`, the index being replaced and the one
+/// republished are recorded like a publish records them.
+pub async fn republish_index(
+    repo_url: &str,
+    sig_path: &str,
+    bearer_token: Option<&str>,
+    bytes: Vec<u8>,
+    sig: Option<String>,
+    history: Option<(&Path, usize)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let backend = RepoBackend::from_url(repo_url, bearer_token);
+    let (sig_backend, sig_name) = signature_target(repo_url, sig_path, bearer_token)?;
+    if let Some((dir, keep)) = history {
+        snapshot_served_index(&backend, &sig_backend, &sig_name, dir, keep).await;
+    }
+    // Signature first: clients fetching in between see the new signature with the old index
+    // and fail verification instead of trusting a mismatched pair
+    if let Some(sig) = &sig {
+        sig_backend.put(&sig_name, sig.clone().into_bytes(), Some("application/json")).await?;
+    }
+    backend.put("index.json", bytes.clone(), Some("application/json")).await?;
+    if let Some((dir, keep)) = history {
+        record_snapshot(dir, &bytes, sig.as_deref(), keep);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::history;
+    use crate::test_support::{build_package, mock_server, Received, Reply};
+
+    fn recipe_for(architectures: &str) -> PackageRecipe {
+        PackageRecipe::from_str(&format!("[package]\nname = p\nversion = 1\narchitectures = {}\n", architectures)).unwrap()
+    }
+
+    #[test]
+    fn noarch_recipe_publishes_one_any_asset() {
+        assert_eq!(index_arch_keys(&recipe_for("noarch")), vec!["any"]);
+        assert_eq!(index_arch_keys(&recipe_for("x86_64, any")), vec!["any"]);
+    }
+
+    #[test]
+    fn multi_arch_recipe_publishes_each_canonical_arch_once() {
+        assert_eq!(index_arch_keys(&recipe_for("amd64, arm64, x86_64")), vec!["aarch64", "x86_64"]);
+    }
+
+    #[test]
+    fn recipe_without_architectures_publishes_for_the_host_arch() {
+        assert_eq!(index_arch_keys(&recipe_for("")), vec![host_arch().to_string()]);
+    }
+
+    /// The index entry a publish of `recipe` with `checksum` under `arch_keys` would write.
+    fn published_entry(recipe: &PackageRecipe, arch_keys: &[String], checksum: &str) -> PackageEntry {
+        let asset = |key: &String| (key.clone(), ArchAsset {
+            download_url: format!("https://repo.example/{}-{}.nxpkg", recipe.package.name, recipe.package.version),
+            sha256: Some(checksum.to_string()),
+            arch: Some(key.clone()),
+            size: Some(1),
+        });
+        let mut entry: PackageEntry = serde_json::from_value(serde_json::json!({
+            "latest_version": recipe.package.version,
+            "description": "demo",
+            "dependencies": recipe.build.dependencies,
+        }))
+        .unwrap();
+        entry.architectures = Some(arch_keys.iter().map(asset).collect());
+        entry.versions.insert(recipe.package.version.clone(), VersionAssets {
+            architectures: arch_keys.iter().map(asset).collect(),
+            ..Default::default()
+        });
+        entry
+    }
+
+    #[test]
+    fn republishing_the_same_package_is_detected() {
+        let recipe = PackageRecipe::from_str("[package]\nname = p\nversion = 1.0\narchitectures = x86_64, arm64\n[build]\ndependencies = libc\n").unwrap();
+        let keys = index_arch_keys(&recipe);
+        let entry = published_entry(&recipe, &keys, "abc");
+
+        assert!(already_published(&entry, &recipe, &keys, "abc", Some("demo")));
+        assert!(!already_published(&entry, &recipe, &keys, "def", Some("demo")), "rebuilt file");
+        assert!(!already_published(&entry, &recipe, &keys, "abc", Some("new description")));
+        let mut with_deps = recipe.clone();
+        with_deps.build.dependencies.push("zlib".to_string());
+        assert!(!already_published(&entry, &with_deps, &keys, "abc", Some("demo")), "changed metadata");
+        let only_x86 = published_entry(&recipe, &keys[1..], "abc");
+        assert!(!already_published(&only_x86, &recipe, &keys, "abc", Some("demo")), "architecture not published yet");
+    }
+
+    #[test]
+    fn a_newer_version_is_not_already_published() {
+        let old = PackageRecipe::from_str("[package]\nname = p\nversion = 1.0\narchitectures = any\n").unwrap();
+        let new = PackageRecipe::from_str("[package]\nname = p\nversion = 1.1\narchitectures = any\n").unwrap();
+        let keys = index_arch_keys(&new);
+        let entry = published_entry(&old, &keys, "abc");
+
+        assert!(!already_published(&entry, &new, &keys, "abc", Some("demo")));
+        assert!(already_published(&entry, &old, &keys, "abc", Some("demo")));
+    }
+
+    #[tokio::test]
+    async fn publish_skips_an_unchanged_package_and_publishes_an_absent_one() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = tmp.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        let repo_url = format!("file://{}", repo.display());
+        let pkg = build_package(tmp.path(), "[package]\nname = app\nversion = 1.0\narchitectures = any\n", &[]);
+        let recipe = crate::compress::read_recipe_from_nxpkg(&pkg).unwrap();
+        let publish = |force: bool| upload_and_update_index(
+            &repo_url, &pkg, &recipe, Some("demo"), None, None, false, "index.json.sig", UploadOptions::default(), force, None,
+        );
+
+        let first = publish(false).await.unwrap();
+        assert!(!first.unchanged);
+        assert!(repo.join("app-1.0.nxpkg").is_file());
+
+        std::fs::remove_file(repo.join("app-1.0.nxpkg")).unwrap();
+        let second = publish(false).await.unwrap();
+        assert!(second.unchanged);
+        assert!(!repo.join("app-1.0.nxpkg").exists(), "an unchanged package is not uploaded again");
+
+        let forced = publish(true).await.unwrap();
+        assert!(!forced.unchanged);
+        assert!(repo.join("app-1.0.nxpkg").is_file());
+    }
+
+    /// S3-style answers: an UploadId for `?uploads`, an ETag per part (part `fail_part` gets a 500).
+    fn s3_reply(req: &Received, fail_part: Option<usize>) -> Reply {
+        if req.method == "POST" && req.target.ends_with("?uploads") {
+            return (200, vec![], "<InitiateMultipartUploadResult><UploadId>up-1</UploadId></InitiateMultipartUploadResult>".into());
+        }
+        if let Some(part) = req.target.split("partNumber=").nth(1) {
+            let part: usize = part.split('&').next().unwrap().parse().unwrap();
+            if Some(part) == fail_part {
+                return (500, vec![], "disk full".into());
+            }
+            return (200, vec![("etag", format!("\"etag-{}\"", part))], String::new());
+        }
+        (200, vec![], String::new())
+    }
+
+    fn payload(dir: &Path, len: usize) -> (std::path::PathBuf, Vec<u8>) {
+        let data: Vec<u8> = (0..len).map(|i| b'a' + (i % 26) as u8).collect();
+        let path = dir.join("p-1.nxpkg");
+        std::fs::write(&path, &data).unwrap();
+        (path, data)
+    }
+
+    #[tokio::test]
+    async fn file_at_or_below_the_threshold_is_one_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, data) = payload(dir.path(), 16);
+        let (base, log) = mock_server(|req| s3_reply(req, None)).await;
+        let url = format!("{}/pool/p-1.nxpkg", base);
+        let opts = UploadOptions { strategy: UploadStrategy::S3, multipart_threshold: 16 };
+        upload_file_put(&url, &path, Some("tok"), &opts).await.unwrap();
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!((log[0].method.as_str(), log[0].target.as_str()), ("PUT", "/pool/p-1.nxpkg"));
+        assert_eq!(log[0].header("authorization"), Some("Bearer tok"));
+        assert_eq!(log[0].body, data);
+    }
+
+    #[tokio::test]
+    async fn chunked_upload_sends_content_ranges_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, data) = payload(dir.path(), 40);
+        let (base, log) = mock_server(|_| (308, vec![], String::new())).await;
+        let url = format!("{}/pool/p-1.nxpkg", base);
+        let opts = UploadOptions { strategy: UploadStrategy::Chunked, multipart_threshold: 16 };
+        upload_file_put(&url, &path, None, &opts).await.unwrap();
+
+        let log = log.lock().unwrap();
+        let ranges: Vec<_> = log.iter().map(|r| r.header("content-range").unwrap()).collect();
+        assert_eq!(ranges, ["bytes 0-15/40", "bytes 16-31/40", "bytes 32-39/40"]);
+        assert_eq!(log.iter().flat_map(|r| r.body.clone()).collect::<Vec<_>>(), data);
+    }
+
+    #[tokio::test]
+    async fn s3_multipart_upload_sends_parts_and_completes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, data) = payload(dir.path(), 40);
+        let (base, log) = mock_server(|req| s3_reply(req, None)).await;
+        let url = format!("{}/pool/p-1.nxpkg", base);
+        let opts = UploadOptions { strategy: UploadStrategy::S3, multipart_threshold: 16 };
+        upload_file_put(&url, &path, None, &opts).await.unwrap();
+
+        let log = log.lock().unwrap();
+        let requests: Vec<_> = log.iter().map(|r| format!("{} {}", r.method, r.target)).collect();
+        assert_eq!(requests, [
+            "POST /pool/p-1.nxpkg?uploads",
+            "PUT /pool/p-1.nxpkg?partNumber=1&uploadId=up-1",
+            "PUT /pool/p-1.nxpkg?partNumber=2&uploadId=up-1",
+            "PUT /pool/p-1.nxpkg?partNumber=3&uploadId=up-1",
+            "POST /pool/p-1.nxpkg?uploadId=up-1",
+        ]);
+        assert_eq!(log[1..4].iter().flat_map(|r| r.body.clone()).collect::<Vec<_>>(), data);
+        assert_eq!(
+            String::from_utf8_lossy(&log[4].body),
+            "<CompleteMultipartUpload>\
+             <Part><PartNumber>1</PartNumber><ETag>\"etag-1\"</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>\"etag-2\"</ETag></Part>\
+             <Part><PartNumber>3</PartNumber><ETag>\"etag-3\"</ETag></Part>\
+             </CompleteMultipartUpload>"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_s3_part_aborts_the_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, _) = payload(dir.path(), 40);
+        let (base, log) = mock_server(|req| s3_reply(req, Some(2))).await;
+        let url = format!("{}/pool/p-1.nxpkg", base);
+        let opts = UploadOptions { strategy: UploadStrategy::S3, multipart_threshold: 16 };
+        let err = upload_file_put(&url, &path, None, &opts).await.unwrap_err().to_string();
+        assert!(err.starts_with("Upload of part 2 failed (HTTP 500"), "{}", err);
+
+        let log = log.lock().unwrap();
+        let requests: Vec<_> = log.iter().map(|r| format!("{} {}", r.method, r.target)).collect();
+        assert_eq!(requests, [
+            "POST /pool/p-1.nxpkg?uploads",
+            "PUT /pool/p-1.nxpkg?partNumber=1&uploadId=up-1",
+            "PUT /pool/p-1.nxpkg?partNumber=2&uploadId=up-1",
+            "DELETE /pool/p-1.nxpkg?uploadId=up-1",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn publish_snapshots_the_replaced_and_the_written_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = tmp.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        let before = br#"{"packages":{}}"#;
+        std::fs::write(repo.join("index.json"), before).unwrap();
+        let pkg = build_package(tmp.path(), "[package]\nname = app\nversion = 1.0\narchitectures = any\n", &[]);
+        let recipe = crate::compress::read_recipe_from_nxpkg(&pkg).unwrap();
+        let history = tmp.path().join("history");
+
+        upload_and_update_index(
+            &format!("file://{}", repo.display()),
+            &pkg,
+            &recipe,
+            None,
+            None,
+            None,
+            false,
+            "index.json.sig",
+            UploadOptions::default(),
+            false,
+            Some((history.as_path(), 5)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(history::list_snapshots(&history), [1, 2]);
+        assert_eq!(history::load(&history, 1).unwrap().0, before);
+        assert_eq!(history::load(&history, 2).unwrap().0, std::fs::read(repo.join("index.json")).unwrap());
+    }
+}
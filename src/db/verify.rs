@@ -0,0 +1,178 @@
+//! src/db/verify.rs
+//! Repository integrity audit: re-download every asset listed in index.json and compare its
+//! SHA-256 with the recorded one.
+
+use std::collections::BTreeMap;
+
+use reqwest::StatusCode;
+
+use crate::db::backend;
+use crate::arch::canonical_arch;
+use crate::db::download::{download_file_with_progress, RepoIndex};
+use crate::progress::DownloadBars;
+
+/// Outcome of checking one hosted asset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetStatus {
+    Ok,
+    /// Recorded and actual checksum differ
+    Mismatch { expected: String, actual: String },
+    /// The asset URL does not exist (HTTP 404 or no such local file)
+    Missing,
+    /// The index records no checksum; the asset exists but cannot be verified
+    Unverified,
+    /// Any other download failure
+    Error(String),
+}
+
+impl AssetStatus {
+    /// A discrepancy that makes `verify-repo` fail.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, AssetStatus::Mismatch { .. } | AssetStatus::Missing | AssetStatus::Error(_))
+    }
+}
+
+/// One asset reference in the index: a package's top-level `download_url` (`arch` is None) or
+/// one of its per-architecture assets.
+#[derive(Debug, Clone)]
+pub struct AssetCheck {
+    pub package: String,
+    pub arch: Option<String>,
+    pub url: String,
+    pub status: AssetStatus,
+}
+
+/// Download every asset referenced by `index` and check it against its recorded SHA-256.
+/// With `arch`, only assets filed under that architecture (aliases count as equal) are checked.
+/// An URL listed several times is downloaded once.
+pub async fn verify_index_assets(index: &RepoIndex, arch: Option<&str>) -> Vec<AssetCheck> {
+    let wanted = arch.map(canonical_arch);
+    let mut checks = Vec::new();
+    let mut names: Vec<&String> = index.packages.keys().collect();
+    names.sort();
+    for name in names {
+        let entry = &index.packages[name];
+        if wanted.is_none() {
+            if let Some(url) = &entry.download_url {
+                checks.push((name.clone(), None, url.clone(), entry.sha256.clone()));
+            }
+        }
+        let mut assets: Vec<_> = entry.architectures.iter().flatten().collect();
+        assets.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, asset) in assets {
+            if wanted.as_deref().is_some_and(|w| canonical_arch(key) != w) {
+                continue;
+            }
+            checks.push((name.clone(), Some(key.clone()), asset.download_url.clone(), asset.sha256.clone()));
+        }
+    }
+
+    let mut seen: BTreeMap<(String, Option<String>), AssetStatus> = BTreeMap::new();
+    let mut out = Vec::with_capacity(checks.len());
+    for (package, arch, url, sha256) in checks {
+        let key = (url.clone(), sha256.as_ref().map(|s| s.trim().to_lowercase()));
+        let status = match seen.get(&key) {
+            Some(s) => s.clone(),
+            None => {
+                let s = check_asset(&url, sha256.as_deref()).await;
+                seen.insert(key, s.clone());
+                s
+            }
+        };
+        out.push(AssetCheck { package, arch, url, status });
+    }
+    out
+}
+
+async fn check_asset(url: &str, expected: Option<&str>) -> AssetStatus {
+    if let Some(path) = backend::file_url_path(url) {
+        if !path.exists() {
+            return AssetStatus::Missing;
+        }
+    }
+    let tmp = match tempfile::NamedTempFile::new() {
+        Ok(t) => t,
+        Err(e) => return AssetStatus::Error(e.to_string()),
+    };
+    let actual = match download_file_with_progress(url, tmp.path(), None, Some(&DownloadBars::single())).await {
+        Ok(sha256) => sha256,
+        Err(e) => {
+            let not_found = e.downcast_ref::<reqwest::Error>().and_then(|e| e.status()) == Some(StatusCode::NOT_FOUND);
+            return if not_found { AssetStatus::Missing } else { AssetStatus::Error(e.to_string()) };
+        }
+    };
+    match expected.map(|s| s.trim().to_lowercase()) {
+        None => AssetStatus::Unverified,
+        Some(expected) if expected == actual => AssetStatus::Ok,
+        Some(expected) => AssetStatus::Mismatch { expected, actual },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn sha(content: &str) -> String {
+        use sha2::{Digest, Sha256};
+        hex::encode(Sha256::digest(content.as_bytes()))
+    }
+
+    fn asset(dir: &Path, name: &str, content: Option<&str>) -> String {
+        let path = dir.join(name);
+        if let Some(content) = content {
+            std::fs::write(&path, content).unwrap();
+        }
+        format!("file://{}", path.display())
+    }
+
+    #[tokio::test]
+    async fn reports_ok_mismatch_missing_and_unverified_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let good = asset(tmp.path(), "good.nxpkg", Some("good"));
+        let tampered = asset(tmp.path(), "tampered.nxpkg", Some("tampered"));
+        let gone = asset(tmp.path(), "gone.nxpkg", None);
+        let index: RepoIndex = serde_json::from_value(serde_json::json!({ "packages": {
+            "good": { "latest_version": "1.0", "description": "", "download_url": good, "sha256": sha("good").to_uppercase() },
+            "tampered": { "latest_version": "1.0", "description": "", "download_url": tampered, "sha256": sha("original") },
+            "gone": { "latest_version": "1.0", "description": "", "download_url": gone, "sha256": sha("gone") },
+            "unsigned": { "latest_version": "1.0", "description": "", "download_url": good },
+        }}))
+        .unwrap();
+
+        let checks = verify_index_assets(&index, None).await;
+
+        let status: BTreeMap<&str, &AssetStatus> = checks.iter().map(|c| (c.package.as_str(), &c.status)).collect();
+        assert_eq!(status["good"], &AssetStatus::Ok);
+        assert_eq!(status["tampered"], &AssetStatus::Mismatch { expected: sha("original"), actual: sha("tampered") });
+        assert_eq!(status["gone"], &AssetStatus::Missing);
+        assert_eq!(status["unsigned"], &AssetStatus::Unverified);
+        let failed: Vec<&str> = checks.iter().filter(|c| c.status.is_failure()).map(|c| c.package.as_str()).collect();
+        assert_eq!(failed, ["gone", "tampered"]);
+    }
+
+    #[tokio::test]
+    async fn arch_limits_the_check_to_that_architectures_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arm = asset(tmp.path(), "app-arm64.nxpkg", Some("arm"));
+        let x86 = asset(tmp.path(), "app-x86_64.nxpkg", None);
+        let index: RepoIndex = serde_json::from_value(serde_json::json!({ "packages": {
+            "app": {
+                "latest_version": "1.0",
+                "description": "",
+                "download_url": x86,
+                "architectures": {
+                    "aarch64": { "download_url": arm, "sha256": sha("arm") },
+                    "x86_64": { "download_url": x86, "sha256": sha("x86") },
+                },
+            },
+        }}))
+        .unwrap();
+
+        let checks = verify_index_assets(&index, Some("arm64")).await;
+
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].arch.as_deref(), Some("aarch64"));
+        assert_eq!(checks[0].status, AssetStatus::Ok);
+    }
+}
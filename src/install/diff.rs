@@ -0,0 +1,266 @@
+//! src/install/diff.rs
+//! Compares an installed package's files with the payload of a replacement package, and two
+//! package files with each other (`nxpkg diff`).
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use colored::*;
+
+use crate::buildins::meta::PackageRecipe;
+use crate::compress::{self, EntryKind, ManifestEntry};
+
+/// A file that an upgrade will overwrite.
+#[derive(Debug, Clone)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    /// The copy on disk no longer matches what was installed (user edit or missing file).
+    pub locally_modified: bool,
+}
+
+/// Files added, removed and modified by replacing one package version with another.
+#[derive(Debug, Default)]
+pub struct FileChanges {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub modified: Vec<ChangedFile>,
+}
+
+impl FileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    pub fn print(&self) {
+        for p in &self.added {
+            println!("  {} {}", "+".green(), p.display());
+        }
+        for p in &self.removed {
+            println!("  {} {}", "-".red(), p.display());
+        }
+        for f in &self.modified {
+            if f.locally_modified {
+                println!("  {} {} {}", "~".yellow(), f.path.display(), "(modified locally)".red());
+            } else {
+                println!("  {} {}", "~".yellow(), f.path.display());
+            }
+        }
+        println!(
+            "{} added, {} removed, {} modified",
+            self.added.len(),
+            self.removed.len(),
+            self.modified.len()
+        );
+    }
+}
+
+/// Diff the files recorded for `installed` against the payload `manifest` of the new version.
+///
+/// A file counts as modified when its new content differs from what was installed (the hash
+/// recorded at install time, or the file on disk for packages installed before hashes were
+/// recorded), or when the local copy was changed since install and would be overwritten.
+/// Files on disk are looked up under the install `root`.
+pub fn compute(installed: &PackageRecipe, manifest: &[ManifestEntry], root: &Path) -> FileChanges {
+    let old: HashSet<&Path> = installed.install.installed_files.iter().map(Path::new).collect();
+    let new: HashSet<&Path> = manifest
+        .iter()
+        .filter(|e| e.kind != EntryKind::Dir)
+        .map(|e| e.path.as_path())
+        .collect();
+
+    let mut changes = FileChanges::default();
+    for entry in manifest.iter().filter(|e| e.kind != EntryKind::Dir) {
+        if !old.contains(entry.path.as_path()) {
+            changes.added.push(entry.path.clone());
+            continue;
+        }
+        let Some(new_hash) = entry.sha256.as_deref() else { continue };
+        let recorded = installed.install.file_hashes.get(&*entry.path.to_string_lossy());
+        let on_disk = compress::sha256_path(&compress::path_in_root(root, &entry.path)).ok();
+        let locally_modified = recorded.is_some_and(|r| on_disk.as_ref() != Some(r));
+        let baseline = recorded.cloned().or(on_disk);
+        if locally_modified || baseline.as_deref() != Some(new_hash) {
+            changes.modified.push(ChangedFile { path: entry.path.clone(), locally_modified });
+        }
+    }
+    changes.removed = installed.install.installed_files
+        .iter()
+        .map(PathBuf::from)
+        .filter(|p| !new.contains(p.as_path()))
+        .collect();
+
+    changes.added.sort();
+    changes.removed.sort();
+    changes.modified.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Configuration files of `installed` whose copy under `root` was changed since install (or has
+/// no recorded hash to compare with). Files that are gone are not listed.
+pub fn modified_conffiles(installed: &PackageRecipe, root: &Path) -> Vec<String> {
+    installed.install.conffiles
+        .iter()
+        .filter(|path| {
+            let on_disk = compress::sha256_path(&compress::path_in_root(root, Path::new(path))).ok();
+            on_disk.is_some() && installed.install.file_hashes.get(*path) != on_disk.as_ref()
+        })
+        .cloned()
+        .collect()
+}
+
+/// Files and symlinks added, removed and modified from payload `a` to payload `b`. An entry is
+/// modified when its content hash, symlink target or kind differs.
+pub fn compare_manifests(a: &[ManifestEntry], b: &[ManifestEntry]) -> FileChanges {
+    let entries = |m: &[ManifestEntry]| -> HashMap<PathBuf, ManifestEntry> {
+        m.iter().filter(|e| e.kind != EntryKind::Dir).map(|e| (e.path.clone(), e.clone())).collect()
+    };
+    let (old, new) = (entries(a), entries(b));
+
+    let mut changes = FileChanges::default();
+    for (path, entry) in &new {
+        match old.get(path) {
+            None => changes.added.push(path.clone()),
+            Some(before) if before.kind != entry.kind || before.sha256 != entry.sha256 || before.link_target != entry.link_target => {
+                changes.modified.push(ChangedFile { path: path.clone(), locally_modified: false });
+            }
+            Some(_) => {}
+        }
+    }
+    changes.removed = old.keys().filter(|p| !new.contains_key(*p)).cloned().collect();
+
+    changes.added.sort();
+    changes.removed.sort();
+    changes.modified.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Recipe fields that differ between packages `a` and `b`, as (field, value in a, value in b).
+/// Lists are compared as written; an empty value shows as `<none>`.
+pub fn compare_recipes(a: &PackageRecipe, b: &PackageRecipe) -> Vec<(String, String, String)> {
+    let list = |v: &[String]| if v.is_empty() { "<none>".to_string() } else { v.join(", ") };
+    let mut fields = vec![
+        ("name".to_string(), a.package.name.clone(), b.package.name.clone()),
+        ("version".to_string(), a.package.version.clone(), b.package.version.clone()),
+        ("architectures".to_string(), list(&a.package.architectures), list(&b.package.architectures)),
+        ("replaces".to_string(), list(&a.package.replaces), list(&b.package.replaces)),
+        (
+            "min_nxpkg_version".to_string(),
+            a.package.min_nxpkg_version.clone().unwrap_or_else(|| "<none>".to_string()),
+            b.package.min_nxpkg_version.clone().unwrap_or_else(|| "<none>".to_string()),
+        ),
+        ("dependencies".to_string(), list(&a.build.dependencies), list(&b.build.dependencies)),
+    ];
+    let arches: BTreeSet<&String> = a.build.arch_dependencies.keys().chain(b.build.arch_dependencies.keys()).collect();
+    for arch in arches {
+        let deps = |r: &PackageRecipe| r.build.arch_dependencies.get(arch).map_or_else(|| "<none>".to_string(), |d| list(d));
+        fields.push((format!("dependencies.{}", arch), deps(a), deps(b)));
+    }
+    fields.push(("commands".to_string(), list(&a.build.commands), list(&b.build.commands)));
+    fields.push(("install_params".to_string(), list(&a.install.install_params), list(&b.install.install_params)));
+    fields.push(("conffiles".to_string(), list(&a.install.conffiles), list(&b.install.conffiles)));
+    fields.retain(|(_, old, new)| old != new);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha(content: &str) -> String {
+        use sha2::{Digest, Sha256};
+        hex::encode(Sha256::digest(content.as_bytes()))
+    }
+
+    fn file(path: &str, content: &str) -> ManifestEntry {
+        ManifestEntry { path: PathBuf::from(path), kind: EntryKind::File, sha256: Some(sha(content)), link_target: None }
+    }
+
+    fn paths(v: &[PathBuf]) -> Vec<&str> {
+        v.iter().map(|p| p.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn compute_sorts_files_into_added_removed_and_modified() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let mut installed = PackageRecipe::default();
+        for (path, content) in [("/usr/bin/same", "same"), ("/usr/bin/changed", "v1"), ("/etc/edited.conf", "v1"), ("/usr/bin/gone", "old")] {
+            installed.install.installed_files.push(path.to_string());
+            installed.install.file_hashes.insert(path.to_string(), sha(content));
+            let on_disk = compress::path_in_root(root, Path::new(path));
+            std::fs::create_dir_all(on_disk.parent().unwrap()).unwrap();
+            std::fs::write(on_disk, content).unwrap();
+        }
+        std::fs::write(root.join("etc/edited.conf"), "local edit").unwrap();
+        let manifest = vec![
+            file("/usr/bin/same", "same"),
+            file("/usr/bin/changed", "v2"),
+            file("/etc/edited.conf", "v1"),
+            file("/usr/bin/new", "new"),
+            ManifestEntry { path: PathBuf::from("/usr/share/new"), kind: EntryKind::Dir, sha256: None, link_target: None },
+        ];
+
+        let changes = compute(&installed, &manifest, root);
+
+        assert_eq!(paths(&changes.added), ["/usr/bin/new"]);
+        assert_eq!(paths(&changes.removed), ["/usr/bin/gone"]);
+        let modified: Vec<(&str, bool)> = changes.modified.iter().map(|f| (f.path.to_str().unwrap(), f.locally_modified)).collect();
+        assert_eq!(modified, [("/etc/edited.conf", true), ("/usr/bin/changed", false)]);
+    }
+
+    #[test]
+    fn compute_compares_with_disk_when_no_hash_was_recorded() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut installed = PackageRecipe::default();
+        installed.install.installed_files = vec!["/usr/bin/a".to_string(), "/usr/bin/b".to_string()];
+        std::fs::create_dir_all(tmp.path().join("usr/bin")).unwrap();
+        std::fs::write(tmp.path().join("usr/bin/a"), "a").unwrap();
+        std::fs::write(tmp.path().join("usr/bin/b"), "b").unwrap();
+
+        let changes = compute(&installed, &[file("/usr/bin/a", "a"), file("/usr/bin/b", "b2")], tmp.path());
+
+        assert!(changes.added.is_empty() && changes.removed.is_empty());
+        let modified: Vec<(&str, bool)> = changes.modified.iter().map(|f| (f.path.to_str().unwrap(), f.locally_modified)).collect();
+        assert_eq!(modified, [("/usr/bin/b", false)]);
+    }
+
+    #[test]
+    fn compare_manifests_detects_content_and_link_changes() {
+        let link = |target: &str| ManifestEntry {
+            path: PathBuf::from("/usr/lib/libx.so"),
+            kind: EntryKind::Symlink,
+            sha256: None,
+            link_target: Some(PathBuf::from(target)),
+        };
+        let a = vec![file("/usr/bin/x", "1"), file("/usr/bin/old", "o"), link("libx.so.1"), file("/etc/x", "x")];
+        let b = vec![file("/usr/bin/x", "2"), file("/usr/bin/new", "n"), link("libx.so.2"), file("/etc/x", "x")];
+
+        let changes = compare_manifests(&a, &b);
+
+        assert_eq!(paths(&changes.added), ["/usr/bin/new"]);
+        assert_eq!(paths(&changes.removed), ["/usr/bin/old"]);
+        let modified: Vec<&str> = changes.modified.iter().map(|f| f.path.to_str().unwrap()).collect();
+        assert_eq!(modified, ["/usr/bin/x", "/usr/lib/libx.so"]);
+        assert!(compare_manifests(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn compare_recipes_lists_only_changed_fields() {
+        let mut a = PackageRecipe::default();
+        a.package.name = "app".to_string();
+        a.package.version = "1.0".to_string();
+        a.build.dependencies = vec!["libc".to_string()];
+        let mut b = a.clone();
+        b.package.version = "1.1".to_string();
+        b.build.arch_dependencies.insert("arm64".to_string(), vec!["libarm".to_string()]);
+
+        assert_eq!(
+            compare_recipes(&a, &b),
+            [
+                ("version".to_string(), "1.0".to_string(), "1.1".to_string()),
+                ("dependencies.arm64".to_string(), "<none>".to_string(), "libarm".to_string()),
+            ]
+        );
+        assert!(compare_recipes(&a, &a).is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+//! src/install/list.rs
+//! Plaintext package lists for `install --from-file`.
+
+use std::fs;
+use std::path::Path;
+
+/// One requested package from a list file.
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    /// 1-based line number in the list file
+    pub line: usize,
+    pub name: String,
+    /// Exact version requested with `name@version`
+    pub version: Option<String>,
+}
+
+/// Parsed contents of a list file.
+#[derive(Debug, Default)]
+pub struct PackageList {
+    pub entries: Vec<ListEntry>,
+    /// Malformed lines as `(line, error)`
+    pub errors: Vec<(usize, String)>,
+}
+
+/// Read a package list: one `name` or `name@version` per line. Blank lines and `#` comments
+/// (whole-line or trailing) are ignored.
+pub fn read_package_list(path: &Path) -> Result<PackageList, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let mut list = PackageList::default();
+    for (i, raw) in content.lines().enumerate() {
+        let line = raw.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, version) = match line.split_once('@') {
+            Some((n, v)) => (n.trim(), Some(v.trim())),
+            None => (line, None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            list.errors.push((i + 1, format!("invalid package name '{}'", line)));
+            continue;
+        }
+        if version.is_some_and(|v| v.is_empty() || v.contains(char::is_whitespace)) {
+            list.errors.push((i + 1, format!("invalid version in '{}'", line)));
+            continue;
+        }
+        list.entries.push(ListEntry { line: i + 1, name: name.to_string(), version: version.map(str::to_string) });
+    }
+    Ok(list)
+}
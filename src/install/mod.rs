@@ -0,0 +1,638 @@
+//! src/install/mod.rs
+//! Installs a single package (download, extract, register) and checks its dependencies.
+
+pub mod diff;
+pub mod list;
+pub mod orphans;
+pub mod resolve;
+
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::audit::{self, AuditEvent};
+use crate::buildins::meta::PackageRecipe;
+use crate::compress::{self, ExtractOptions};
+use crate::config::AppConfig;
+use crate::db::download;
+use crate::db::PackageManagerDB;
+use crate::progress::{DownloadBars, NoProgress, ProgressSink, Spinner};
+use crate::version;
+use self::diff::FileChanges;
+use self::resolve::PlannedPackage;
+
+/// Where a package to be installed comes from.
+pub enum PackageSource {
+    /// A .nxpkg file already on disk (`install -L`).
+    Local(PathBuf),
+    /// A package from the repository index, downloaded into the cache first.
+    Remote(PlannedPackage),
+}
+
+/// Per-invocation switches for `install_from_source`.
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// Install despite unsatisfied dependencies, recording the package as installed with deps bypassed.
+    pub no_deps: bool,
+    /// Remove installed packages that the new one replaces without asking.
+    pub assume_yes: bool,
+}
+
+pub enum InstallOutcome {
+    Installed(PackageRecipe),
+    AlreadyInstalled(PackageRecipe),
+}
+
+/// Cache location of a downloaded package: `<cache_dir>/<name>-<version>.nxpkg`.
+pub fn cached_package_path(cfg: &AppConfig, pkg: &PlannedPackage) -> PathBuf {
+    cfg.cache_dir.join(format!("{}-{}.nxpkg", pkg.name, pkg.version))
+}
+
+/// Download `pkg` into the cache, verifying its checksum when the index provides one. A URL
+/// under the configured repository falls back to its mirrors (see `download::download_candidates`).
+/// Progress goes to `progress`, if given.
+pub async fn download_to_cache(cfg: &AppConfig, pkg: &PlannedPackage, progress: Option<&dyn ProgressSink>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = cached_package_path(cfg, pkg);
+    if let Some(progress) = progress {
+        progress.on_stage(&format!("Downloading '{}' {}", pkg.name, pkg.version));
+    }
+    let urls = download::download_candidates(cfg, &pkg.download_url);
+    download::download_with_failover(&urls, &path, pkg.sha256.as_deref(), progress)
+        .await
+        .map_err(|e| format!("Download of '{}' failed: {}", pkg.name, e))?;
+    Ok(path)
+}
+
+/// A package placed in the cache by `download_all_to_cache`.
+#[derive(Debug, Clone)]
+pub struct CachedPackage {
+    pub path: PathBuf,
+    /// SHA-256 of the cached file, taken from the download stream or the re-verification
+    pub sha256: String,
+    /// An existing cache file matched the index checksum and was kept instead of downloading
+    pub reused: bool,
+}
+
+/// Download every package of `plan` into the cache, at most `cfg.max_parallelism` at a time.
+/// A file already in the cache is re-verified (hashed on a blocking thread, under the same
+/// limit) and kept when it matches the index checksum. Downloads fall back to the repository's
+/// mirrors like `download_to_cache`. Returns the cached packages in plan
+/// order; the first failure is returned once all downloads settle. Progress of all downloads
+/// goes to `progress`, if given.
+pub async fn download_all_to_cache(
+    cfg: &AppConfig,
+    plan: &[PlannedPackage],
+    progress: Option<Arc<dyn ProgressSink>>,
+) -> Result<Vec<CachedPackage>, Box<dyn std::error::Error>> {
+    let permits = Arc::new(Semaphore::new(cfg.max_parallelism.max(1)));
+    let progress = progress.unwrap_or_else(|| Arc::new(NoProgress));
+    let mut tasks = JoinSet::new();
+    for (i, pkg) in plan.iter().enumerate() {
+        let path = cached_package_path(cfg, pkg);
+        let (name, version, sha256) = (pkg.name.clone(), pkg.version.clone(), pkg.sha256.clone());
+        let urls = download::download_candidates(cfg, &pkg.download_url);
+        let (permits, progress) = (permits.clone(), progress.clone());
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.map_err(|e| e.to_string())?;
+            if let Some(expected) = sha256.as_deref().filter(|_| path.is_file()) {
+                let hash_path = path.clone();
+                let actual = tokio::task::spawn_blocking(move || compress::sha256_path(&hash_path))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if let Some(actual) = actual.ok().filter(|a| a.eq_ignore_ascii_case(expected.trim())) {
+                    progress.on_stage(&format!("Using cached '{}' {}", name, version));
+                    return Ok((i, CachedPackage { path, sha256: actual, reused: true }));
+                }
+            }
+            progress.on_stage(&format!("Downloading '{}' {}", name, version));
+            let sha256 = download::download_with_failover(&urls, &path, sha256.as_deref(), Some(&*progress))
+                .await
+                .map_err(|e| format!("Download of '{}' failed: {}", name, e))?;
+            Ok::<_, String>((i, CachedPackage { path, sha256, reused: false }))
+        });
+    }
+
+    let mut cached = vec![None; plan.len()];
+    let mut first_err = None;
+    while let Some(joined) = tasks.join_next().await {
+        match joined.map_err(|e| e.to_string()).and_then(|r| r) {
+            Ok((i, pkg)) => cached[i] = Some(pkg),
+            Err(e) => { first_err.get_or_insert(e); }
+        }
+    }
+    match first_err {
+        Some(e) => Err(e.into()),
+        None => Ok(cached.into_iter().flatten().collect()),
+    }
+}
+
+/// Total size of the assets of a plan, for the confirmation before downloading.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DownloadEstimate {
+    pub bytes: u64,
+    /// Packages whose size neither the index nor the server reports
+    pub unknown: usize,
+}
+
+impl DownloadEstimate {
+    pub fn describe(&self) -> String {
+        match self.unknown {
+            0 => format!("Total download size: {}", format_size(self.bytes)),
+            n => format!("Total download size: at least {} (size of {} package(s) unknown)", format_size(self.bytes), n),
+        }
+    }
+}
+
+/// Sum the asset sizes of `plan`. Sizes missing from the index are asked from the server
+/// with a HEAD request.
+pub async fn estimate_download(plan: &[PlannedPackage]) -> DownloadEstimate {
+    let mut estimate = DownloadEstimate::default();
+    for pkg in plan {
+        let size = match pkg.size {
+            Some(size) => Some(size),
+            None => download::remote_size(&pkg.download_url).await,
+        };
+        match size {
+            Some(size) => estimate.bytes += size,
+            None => estimate.unknown += 1,
+        }
+    }
+    estimate
+}
+
+/// Ask whether to go ahead with a download of `estimate`. Without a terminal to ask on, or
+/// with `assume_yes`, the answer is yes.
+pub fn confirm_download(estimate: &DownloadEstimate, assume_yes: bool, pb: &Spinner) -> bool {
+    if assume_yes || !std::io::stdin().is_terminal() {
+        pb.println(estimate.describe());
+        return true;
+    }
+    let answer = pb.suspend(|| {
+        print!("{}. Proceed? [Y/n] ", estimate.describe());
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map(|_| line)
+    });
+    answer.is_ok_and(|a| matches!(a.trim().to_ascii_lowercase().as_str(), "" | "y" | "yes"))
+}
+
+/// `bytes` in binary units, e.g. "12.3 MiB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Returns the dependencies of `recipe` on `arch` that are not satisfied by the installed
+/// packages, paired with the installed version when the package exists but the constraint fails.
+pub fn unsatisfied_dependencies(recipe: &PackageRecipe, db: &PackageManagerDB, arch: &str) -> Vec<(String, Option<String>)> {
+    recipe.parsed_dependencies(arch)
+        .into_iter()
+        .filter_map(|dep| {
+            let installed = db.get_package_metadata(&dep.name).ok().flatten().map(|r| r.package.version);
+            match installed {
+                Some(v) if dep.satisfied_by(&v) => None,
+                other => Some((dep.to_string(), other)),
+            }
+        })
+        .collect()
+}
+
+/// Symlinks among `recipe`'s installed files whose target does not exist under `root`, as
+/// (recorded path, link target). Absolute targets are looked up under `root`, as they resolve
+/// once the root is the system. Used by `install --check-links` after a batch is installed,
+/// so links into packages installed later in the same batch are not reported.
+pub fn dangling_symlinks(root: &Path, recipe: &PackageRecipe) -> Vec<(String, PathBuf)> {
+    recipe.install.installed_files
+        .iter()
+        .filter_map(|recorded| {
+            let path = compress::path_in_root(root, Path::new(recorded));
+            if !path.symlink_metadata().ok()?.file_type().is_symlink() {
+                return None;
+            }
+            let target = std::fs::read_link(&path).ok()?;
+            let resolved = if target.is_absolute() {
+                compress::path_in_root(root, &target)
+            } else {
+                path.parent()?.join(&target)
+            };
+            (!resolved.exists()).then(|| (recorded.clone(), target))
+        })
+        .collect()
+}
+
+/// Install one package without resolving its dependencies; they must already be installed.
+/// Installs and failed attempts are written to the audit log.
+pub async fn install_from_source(
+    cfg: &AppConfig,
+    db: &PackageManagerDB,
+    source: &PackageSource,
+    opts: &InstallOptions,
+    pb: &Spinner,
+) -> Result<InstallOutcome, Box<dyn std::error::Error>> {
+    let result = install_package(cfg, db, source, opts, pb).await;
+    let event = match (&result, source) {
+        (Ok(InstallOutcome::Installed(r)), _) => {
+            Some(AuditEvent::new("install", &r.package.name, Some(&r.package.version), Ok(())))
+        }
+        (Ok(InstallOutcome::AlreadyInstalled(_)), _) => None,
+        (Err(e), PackageSource::Remote(p)) => {
+            Some(AuditEvent::new("install", &p.name, Some(&p.version), Err(e.to_string())))
+        }
+        (Err(e), PackageSource::Local(path)) => {
+            Some(AuditEvent::new("install", &path.to_string_lossy(), None, Err(e.to_string())))
+        }
+    };
+    if let Some(event) = event {
+        audit::record(cfg, &event);
+    }
+    result
+}
+
+/// Run `[security] verify_hook` on a package about to be extracted. The hook gets the package
+/// path as its only argument and NXPKG_PACKAGE_{NAME,VERSION,PATH} in its environment; any
+/// non-zero exit (or failure to start it) rejects the package.
+fn run_verify_hook(cfg: &AppConfig, nxpkg_path: &Path, recipe: &PackageRecipe, pb: &Spinner) -> Result<(), String> {
+    let Some(hook) = cfg.verify_hook.as_ref() else { return Ok(()) };
+    pb.set_message(format!("Running verify hook on '{}'...", recipe.package.name));
+    let status = pb.suspend(|| {
+        Command::new(hook)
+            .arg(nxpkg_path)
+            .env("NXPKG_PACKAGE_NAME", &recipe.package.name)
+            .env("NXPKG_PACKAGE_VERSION", &recipe.package.version)
+            .env("NXPKG_PACKAGE_PATH", nxpkg_path)
+            .status()
+    });
+    match status {
+        Ok(s) if s.success() => Ok(()),
+        Ok(s) => Err(format!(
+            "verify hook {} rejected '{}' v{} ({})",
+            hook.display(), recipe.package.name, recipe.package.version, s
+        )),
+        Err(e) => Err(format!("could not run verify hook {}: {}", hook.display(), e)),
+    }
+}
+
+async fn install_package(
+    cfg: &AppConfig,
+    db: &PackageManagerDB,
+    source: &PackageSource,
+    opts: &InstallOptions,
+    pb: &Spinner,
+) -> Result<InstallOutcome, Box<dyn std::error::Error>> {
+    let nxpkg_path = match source {
+        PackageSource::Local(path) => {
+            pb.set_message(format!("Installing from local package '{}'...", path.display()));
+            path.clone()
+        }
+        PackageSource::Remote(pkg) => {
+            pb.finish_and_clear();
+            let path = download_to_cache(cfg, pkg, Some(&DownloadBars::single())).await?;
+            pb.reset();
+            pb.set_message("Download complete. Continuing installation...");
+            path
+        }
+    };
+
+    let recipe = compress::read_recipe_from_nxpkg(&nxpkg_path)?;
+    if let Ok(Some(installed)) = db.get_package_metadata(&recipe.package.name) {
+        return Ok(InstallOutcome::AlreadyInstalled(installed));
+    }
+    version::check_client_version(&recipe.package.name, recipe.package.min_nxpkg_version.as_deref())?;
+
+    let deps_bypassed = check_dependencies(&recipe, db, &cfg.arch, opts, pb)?;
+    let replaced = replaced_packages(&recipe, db);
+    confirm_replacement(&recipe, &replaced, opts.assume_yes, pb)?;
+    run_verify_hook(cfg, &nxpkg_path, &recipe, pb)?;
+
+    pb.set_message(format!("Extracting package '{}'...", recipe.package.name));
+    let mut recipe = extract_and_record(cfg, &nxpkg_path, None, pb)?;
+    recipe.install.deps_bypassed = deps_bypassed;
+    if let PackageSource::Remote(pkg) = source {
+        recipe.package.description = pkg.description.clone();
+    }
+
+    for old in &replaced {
+        pb.set_message(format!("Removing replaced package '{}'...", old.package.name));
+        let removed = db.rem_replaced_package(&old.package.name, &cfg.root, &recipe.install.installed_files)
+            .map_err(|e| e.to_string());
+        audit::record(cfg, &AuditEvent::new("remove", &old.package.name, Some(&old.package.version), removed.clone()));
+        match removed {
+            Ok(()) => pb.println(format!("Removed '{}' v{}, replaced by '{}'.", old.package.name, old.package.version, recipe.package.name)),
+            Err(e) => return Err(format!("Could not remove replaced package '{}': {}", old.package.name, e).into()),
+        }
+    }
+
+    pb.set_message("Registering package in database...");
+    db.save_package_metadata(&recipe)
+        .map_err(|e| format!("Database registration failed: {}", e))?;
+
+    Ok(InstallOutcome::Installed(recipe))
+}
+
+/// Installed packages listed in `recipe`'s `[package] replaces`.
+fn replaced_packages(recipe: &PackageRecipe, db: &PackageManagerDB) -> Vec<PackageRecipe> {
+    recipe.package.replaces
+        .iter()
+        .filter(|name| **name != recipe.package.name)
+        .filter_map(|name| db.get_package_metadata(name).ok().flatten())
+        .collect()
+}
+
+/// Ask before removing the packages `recipe` replaces. Without a terminal to ask on, only
+/// `assume_yes` allows it.
+fn confirm_replacement(recipe: &PackageRecipe, replaced: &[PackageRecipe], assume_yes: bool, pb: &Spinner) -> Result<(), String> {
+    if replaced.is_empty() || assume_yes {
+        return Ok(());
+    }
+    let names: Vec<String> = replaced.iter().map(|r| format!("{} {}", r.package.name, r.package.version)).collect();
+    let question = format!("'{}' replaces installed {}, which will be removed.", recipe.package.name, names.join(", "));
+    if !std::io::stdin().is_terminal() {
+        return Err(format!("{} Re-run with --yes to confirm.", question));
+    }
+    let answer = pb.suspend(|| {
+        print!("{} Continue? [y/N] ", question);
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map(|_| line)
+    }).map_err(|e| e.to_string())?;
+    if matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+        Ok(())
+    } else {
+        Err(format!("Installation of '{}' cancelled.", recipe.package.name))
+    }
+}
+
+/// Replace the installed version of a package with the downloaded `nxpkg_path`.
+/// Files listed in `changes.removed` are deleted after the new payload is in place.
+/// `description` is the index's for the new version; None keeps the installed one.
+pub fn upgrade_from_file(
+    cfg: &AppConfig,
+    db: &PackageManagerDB,
+    nxpkg_path: &Path,
+    installed: &PackageRecipe,
+    description: Option<&str>,
+    changes: &FileChanges,
+    pb: &Spinner,
+) -> Result<PackageRecipe, Box<dyn std::error::Error>> {
+    let result = (|| -> Result<PackageRecipe, Box<dyn std::error::Error>> {
+        let recipe = compress::read_recipe_from_nxpkg(nxpkg_path)?;
+        version::check_client_version(&recipe.package.name, recipe.package.min_nxpkg_version.as_deref())?;
+        check_dependencies(&recipe, db, &cfg.arch, &InstallOptions::default(), pb)?;
+        run_verify_hook(cfg, nxpkg_path, &recipe, pb)?;
+
+        pb.set_message(format!("Upgrading '{}' to v{}...", recipe.package.name, recipe.package.version));
+        // Configuration the new version drops is still the admin's once edited
+        let modified_config = diff::modified_conffiles(installed, &cfg.root);
+        let mut recipe = extract_and_record(cfg, nxpkg_path, Some(installed), pb)?;
+        for path in &changes.removed {
+            if modified_config.iter().any(|c| Path::new(c) == path) {
+                pb.println(format!("Kept modified {}, which the new version no longer ships", compress::path_in_root(&cfg.root, path).display()));
+                continue;
+            }
+            let path = compress::path_in_root(&cfg.root, path);
+            if let Err(e) = std::fs::remove_file(&path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    pb.println(format!("Warning: could not remove {}: {}", path.display(), e));
+                }
+            }
+        }
+        recipe.package.description = description.unwrap_or(&installed.package.description).to_string();
+        // Directories created by the old version stay owned by the package
+        for dir in &installed.install.installed_dirs {
+            if !recipe.install.installed_dirs.contains(dir) {
+                recipe.install.installed_dirs.push(dir.clone());
+            }
+        }
+
+        pb.set_message("Registering package in database...");
+        db.save_package_metadata(&recipe)
+            .map_err(|e| format!("Database registration failed: {}", e))?;
+        Ok(recipe)
+    })();
+
+    let event = match &result {
+        Ok(r) => AuditEvent::new("upgrade", &r.package.name, Some(&r.package.version), Ok(())),
+        Err(e) => AuditEvent::new("upgrade", &installed.package.name, None, Err(e.to_string())),
+    };
+    audit::record(cfg, &event);
+    result
+}
+
+/// Refuse (or, under `--no-deps`, warn about) dependencies the installed packages don't satisfy.
+/// Returns whether any were left unsatisfied under `--no-deps`.
+fn check_dependencies(
+    recipe: &PackageRecipe,
+    db: &PackageManagerDB,
+    arch: &str,
+    opts: &InstallOptions,
+    pb: &Spinner,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let missing = unsatisfied_dependencies(recipe, db, arch);
+    if missing.is_empty() {
+        return Ok(false);
+    }
+    let list: Vec<String> = missing
+        .iter()
+        .map(|(dep, installed)| match installed {
+            Some(v) => format!("{} (installed: {})", dep, v),
+            None => format!("{} (not installed)", dep),
+        })
+        .collect();
+    if !opts.no_deps {
+        return Err(format!(
+            "'{}' has unsatisfied dependencies: {}",
+            recipe.package.name,
+            list.join(", ")
+        ).into());
+    }
+    pb.println(format!(
+        "Warning: installing '{}' with unsatisfied dependencies (--no-deps): {}",
+        recipe.package.name,
+        list.join(", ")
+    ));
+    Ok(true)
+}
+
+/// Extract a package onto the system and return its recipe with the installed paths filled in.
+/// Configuration files changed since `installed` put them there are kept, with a note.
+fn extract_and_record(
+    cfg: &AppConfig,
+    nxpkg_path: &Path,
+    installed: Option<&PackageRecipe>,
+    pb: &Spinner,
+) -> Result<PackageRecipe, Box<dyn std::error::Error>> {
+    let extract_opts = ExtractOptions {
+        prefix_map: cfg.prefix_map.clone(),
+        root: cfg.root.clone(),
+        arch: Some(cfg.arch.clone()),
+        installed_hashes: installed.map(|i| i.install.file_hashes.clone()).unwrap_or_default(),
+    };
+    let (mut recipe, unpacked) = compress::extract_nxpkg(nxpkg_path, &extract_opts)
+        .map_err(|e| format!("Failed to install package: {}", e))?;
+    for path in &unpacked.kept_config {
+        let path = compress::path_in_root(&cfg.root, path);
+        pb.println(format!("Kept modified {}; the packaged version is in {}.nxnew", path.display(), path.display()));
+    }
+
+    recipe.flatten_dependencies(&cfg.arch);
+
+    // Persist installed paths into the recipe so uninstall can remove them later
+    recipe.install.installed_files = unpacked.files
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+    recipe.install.installed_dirs = unpacked.dirs
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+    recipe.install.file_hashes = unpacked.hashes
+        .into_iter()
+        .map(|(p, h)| (p.to_string_lossy().to_string(), h))
+        .collect();
+    Ok(recipe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_package, config_in, open_db, Staged};
+
+    const APP: &str = "[package]\nname = app\nversion = 1.0\narchitectures = any\n";
+
+    async fn install_local(cfg: &AppConfig, db: &PackageManagerDB, path: &Path, assume_yes: bool) -> Result<InstallOutcome, Box<dyn std::error::Error>> {
+        install_with(cfg, db, path, &InstallOptions { no_deps: false, assume_yes }).await
+    }
+
+    async fn install_with(cfg: &AppConfig, db: &PackageManagerDB, path: &Path, opts: &InstallOptions) -> Result<InstallOutcome, Box<dyn std::error::Error>> {
+        install_from_source(cfg, db, &PackageSource::Local(path.to_path_buf()), opts, &Spinner::new("{msg}")).await
+    }
+
+    #[tokio::test]
+    async fn directory_only_package_creates_and_records_its_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config_in(tmp.path());
+        let db = open_db(&cfg);
+        let pkg = build_package(tmp.path(), APP, &[Staged::Dir("var/lib/app/empty")]);
+
+        install_local(&cfg, &db, &pkg, false).await.unwrap();
+
+        assert!(cfg.root.join("var/lib/app/empty").is_dir());
+        let recorded = db.get_package_metadata("app").unwrap().unwrap();
+        assert!(recorded.install.installed_files.is_empty());
+        assert!(recorded.install.installed_dirs.iter().any(|d| d == "/var/lib/app/empty"), "{:?}", recorded.install.installed_dirs);
+    }
+
+    #[tokio::test]
+    async fn empty_directory_next_to_files_is_recorded_and_removed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config_in(tmp.path());
+        let db = open_db(&cfg);
+        let pkg = build_package(tmp.path(), APP, &[Staged::File("usr/bin/app", "#!/bin/sh\n"), Staged::Dir("var/cache/app")]);
+
+        install_local(&cfg, &db, &pkg, false).await.unwrap();
+        let recorded = db.get_package_metadata("app").unwrap().unwrap();
+        assert_eq!(recorded.install.installed_files, vec!["/usr/bin/app".to_string()]);
+        assert!(recorded.install.installed_dirs.iter().any(|d| d == "/var/cache/app"));
+
+        db.rem_package_metadata("app", true, &cfg.root, &[]).unwrap();
+        assert!(!cfg.root.join("usr/bin/app").exists());
+        assert!(!cfg.root.join("var/cache/app").exists());
+        assert!(cfg.root.is_dir());
+    }
+
+    #[tokio::test]
+    async fn replacing_package_removes_the_one_it_replaces() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config_in(tmp.path());
+        let db = open_db(&cfg);
+        let old = build_package(
+            tmp.path(),
+            "[package]\nname = oldtool\nversion = 1.0\narchitectures = any\n",
+            &[Staged::File("usr/bin/tool", "old\n"), Staged::File("usr/share/oldtool/data", "x\n")],
+        );
+        let new = build_package(
+            tmp.path(),
+            "[package]\nname = newtool\nversion = 2.0\narchitectures = any\nreplaces = oldtool, newtool, absent\n",
+            &[Staged::File("usr/bin/tool", "new\n")],
+        );
+        install_local(&cfg, &db, &old, false).await.unwrap();
+
+        let recipe = compress::read_recipe_from_nxpkg(&new).unwrap();
+        let replaced: Vec<_> = replaced_packages(&recipe, &db).into_iter().map(|r| r.package.name).collect();
+        assert_eq!(replaced, vec!["oldtool".to_string()]);
+
+        install_local(&cfg, &db, &new, true).await.unwrap();
+        assert!(db.get_package_metadata("oldtool").unwrap().is_none());
+        assert!(db.get_package_metadata("newtool").unwrap().is_some());
+        assert!(!cfg.root.join("usr/share/oldtool").exists());
+        assert_eq!(std::fs::read_to_string(cfg.root.join("usr/bin/tool")).unwrap(), "new\n");
+    }
+
+    #[tokio::test]
+    async fn no_deps_records_bypass_only_when_a_dependency_is_unsatisfied() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config_in(tmp.path());
+        let db = open_db(&cfg);
+        let no_deps = InstallOptions { no_deps: true, assume_yes: false };
+        let lib = build_package(tmp.path(), "[package]\nname = lib\nversion = 1.0\narchitectures = any\n", &[Staged::File("usr/lib/lib.so", "lib\n")]);
+        let uses_lib = build_package(
+            tmp.path(),
+            "[package]\nname = uses-lib\nversion = 1.0\narchitectures = any\n[build]\ndependencies = lib\n",
+            &[Staged::File("usr/bin/uses-lib", "a\n")],
+        );
+        let uses_missing = build_package(
+            tmp.path(),
+            "[package]\nname = uses-missing\nversion = 1.0\narchitectures = any\n[build]\ndependencies = missing\n",
+            &[Staged::File("usr/bin/uses-missing", "b\n")],
+        );
+
+        install_with(&cfg, &db, &lib, &no_deps).await.unwrap();
+        install_with(&cfg, &db, &uses_lib, &no_deps).await.unwrap();
+        install_with(&cfg, &db, &uses_missing, &no_deps).await.unwrap();
+
+        let bypassed = |name: &str| db.get_package_metadata(name).unwrap().unwrap().install.deps_bypassed;
+        assert!(!bypassed("lib"));
+        assert!(!bypassed("uses-lib"));
+        assert!(bypassed("uses-missing"));
+    }
+
+    #[test]
+    fn symlink_targets_are_resolved_inside_the_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("usr/lib")).unwrap();
+        std::fs::write(root.join("usr/lib/libx.so.1"), "lib").unwrap();
+        let links = [
+            ("usr/lib/libx.so", "/usr/lib/libx.so.1"),
+            ("usr/lib/libx-rel.so", "libx.so.1"),
+            ("usr/lib/host-only", "/bin/sh"),
+            ("usr/lib/libgone.so", "libgone.so.1"),
+        ];
+        let mut recipe = PackageRecipe::default();
+        for (link, target) in links {
+            std::os::unix::fs::symlink(target, root.join(link)).unwrap();
+            recipe.install.installed_files.push(format!("/{}", link));
+        }
+        recipe.install.installed_files.push("/usr/lib/libx.so.1".to_string());
+
+        let dangling = dangling_symlinks(root, &recipe);
+
+        assert_eq!(dangling, [
+            ("/usr/lib/host-only".to_string(), PathBuf::from("/bin/sh")),
+            ("/usr/lib/libgone.so".to_string(), PathBuf::from("libgone.so.1")),
+        ]);
+    }
+}
@@ -0,0 +1,193 @@
+//! src/install/orphans.rs
+//! Finds files under a directory that no installed package owns.
+
+use std::collections::HashSet;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::compress;
+use crate::config::AppConfig;
+use crate::db::PackageManagerDB;
+
+/// A file on disk that is not in any package's `installed_files`.
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    /// Path as seen from inside the install root, like recorded package paths
+    pub path: PathBuf,
+    /// Where the file is on disk
+    pub host_path: PathBuf,
+    pub size: u64,
+}
+
+/// Walk `prefix` (a path inside the install root) and list the files and symlinks no installed
+/// package owns. nxpkg's own database, its backups and the cache are never reported. Symlinks
+/// are not followed and the walk stays on the prefix's filesystem, so `/proc` and other mounts
+/// are skipped.
+pub fn find_orphans(cfg: &AppConfig, db: &PackageManagerDB, prefix: &Path) -> Result<Vec<Orphan>, String> {
+    let root = cfg.root.canonicalize().unwrap_or_else(|_| cfg.root.clone());
+    let start = compress::path_in_root(&root, prefix);
+    if !start.is_dir() {
+        return Err(format!("{} is not a directory", start.display()));
+    }
+
+    let mut owned: HashSet<PathBuf> = HashSet::new();
+    for name in db.installed_package_names().map_err(|e| e.to_string())? {
+        if let Some(recipe) = db.get_package_metadata(&name).map_err(|e| e.to_string())? {
+            owned.extend(recipe.install.installed_files.iter().map(PathBuf::from));
+        }
+    }
+
+    let excluded = excluded_paths(cfg);
+    let mut orphans = Vec::new();
+    let walker = WalkDir::new(&start)
+        .follow_links(false)
+        .same_file_system(true)
+        .into_iter()
+        .filter_entry(|e| !is_excluded(e.path(), &excluded));
+    for entry in walker.filter_map(Result::ok) {
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let host_path = entry.path().to_path_buf();
+        let logical = Path::new("/").join(host_path.strip_prefix(&root).unwrap_or(&host_path));
+        if owned.contains(&logical) {
+            continue;
+        }
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        orphans.push(Orphan { path: logical, host_path, size });
+    }
+    orphans.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(orphans)
+}
+
+/// Ask before deleting `orphans`; without a terminal, `assume_yes` is required.
+pub fn confirm_removal(orphans: &[Orphan], assume_yes: bool) -> Result<(), String> {
+    let stdin = std::io::stdin();
+    confirm_with(orphans, assume_yes, stdin.is_terminal(), &mut stdin.lock())
+}
+
+/// `confirm_removal` reading the answer from `input`, which is a terminal when `interactive`.
+fn confirm_with(orphans: &[Orphan], assume_yes: bool, interactive: bool, input: &mut dyn BufRead) -> Result<(), String> {
+    if orphans.is_empty() || assume_yes {
+        return Ok(());
+    }
+    let bytes: u64 = orphans.iter().map(|o| o.size).sum();
+    let question = format!("{} unowned file(s) ({}) will be deleted.", orphans.len(), super::format_size(bytes));
+    if !interactive {
+        return Err(format!("{} Re-run with --yes to confirm.", question));
+    }
+    print!("{} Continue? [y/N] ", question);
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    input.read_line(&mut answer).map_err(|e| e.to_string())?;
+    if matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+        Ok(())
+    } else {
+        Err("Nothing was deleted.".to_string())
+    }
+}
+
+/// Delete `orphans` from disk; returns the ones that could not be removed with the reason.
+pub fn remove_orphans(orphans: &[Orphan]) -> Vec<(PathBuf, std::io::Error)> {
+    orphans.iter()
+        .filter_map(|o| std::fs::remove_file(&o.host_path).err().map(|e| (o.path.clone(), e)))
+        .collect()
+}
+
+/// Paths nxpkg keeps for itself: the cache directory, and the package database together with
+/// every file named after it in its directory (SQLite's side files, the rolling backups
+/// `<db>.bak*` and a restore being staged as `<db>.restore`).
+struct Excluded {
+    dirs: Vec<PathBuf>,
+    db_dir: PathBuf,
+    db_name: String,
+}
+
+fn excluded_paths(cfg: &AppConfig) -> Excluded {
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+    let db_dir = cfg.db_path.parent().map(canonical).unwrap_or_default();
+    let db_name = cfg.db_path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    Excluded { dirs: vec![canonical(&cfg.cache_dir)], db_dir, db_name }
+}
+
+fn is_excluded(path: &Path, excluded: &Excluded) -> bool {
+    if excluded.dirs.iter().any(|e| path.starts_with(e)) {
+        return true;
+    }
+    !excluded.db_name.is_empty()
+        && path.parent() == Some(excluded.db_dir.as_path())
+        && path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(&excluded.db_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::{install_from_source, InstallOptions, PackageSource};
+    use crate::progress::Spinner;
+    use crate::test_support::{build_package, config_in, open_db, Staged};
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn logical(orphans: &[Orphan]) -> Vec<&str> {
+        orphans.iter().map(|o| o.path.to_str().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn lists_only_files_no_package_owns() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config_in(tmp.path());
+        let db = open_db(&cfg);
+        let pkg = build_package(tmp.path(), "[package]\nname = app\nversion = 1.0\narchitectures = any\n", &[Staged::File("opt/app/bin/app", "app\n")]);
+        install_from_source(&cfg, &db, &PackageSource::Local(pkg), &InstallOptions::default(), &Spinner::new("{msg}")).await.unwrap();
+        write(&cfg.root.join("opt/app/stray.log"), "log\n");
+        write(&cfg.root.join("opt/app/data/nested/left.dat"), "x\n");
+        std::fs::create_dir_all(cfg.root.join("opt/app/empty")).unwrap();
+        std::os::unix::fs::symlink("/nonexistent", cfg.root.join("opt/app/link")).unwrap();
+
+        let orphans = find_orphans(&cfg, &db, Path::new("/opt")).unwrap();
+
+        assert_eq!(logical(&orphans), ["/opt/app/data/nested/left.dat", "/opt/app/link", "/opt/app/stray.log"]);
+        assert_eq!(orphans[0].host_path, cfg.root.canonicalize().unwrap().join("opt/app/data/nested/left.dat"));
+        assert_eq!(orphans.last().unwrap().size, 4);
+        assert!(find_orphans(&cfg, &db, Path::new("/opt/app/bin/app")).is_err());
+        assert!(find_orphans(&cfg, &db, Path::new("/missing")).is_err());
+    }
+
+    #[test]
+    fn never_lists_the_database_its_backups_or_the_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = config_in(tmp.path());
+        cfg.db_path = cfg.root.join("var/lib/nxpkg/nxpkg_meta.db");
+        cfg.cache_dir = cfg.root.join("var/cache/nxpkg");
+        std::fs::create_dir_all(cfg.db_path.parent().unwrap()).unwrap();
+        let db = open_db(&cfg);
+        write(&cfg.root.join("var/lib/nxpkg/nxpkg_meta.db-journal"), "");
+        crate::db::backup::rotate_and_backup(&rusqlite::Connection::open(&cfg.db_path).unwrap(), &cfg.db_path, 2).unwrap();
+        write(&crate::db::backup::backup_path(&cfg.db_path, 7), "old backup");
+        write(&cfg.root.join("var/lib/nxpkg/nxpkg_meta.db.restore"), "staged");
+        write(&cfg.cache_dir.join("app-1.0.nxpkg"), "pkg");
+        write(&cfg.root.join("var/lib/other/state"), "s");
+
+        let orphans = find_orphans(&cfg, &db, Path::new("/var")).unwrap();
+
+        assert_eq!(logical(&orphans), ["/var/lib/other/state"]);
+    }
+
+    #[test]
+    fn removal_needs_yes_without_a_terminal() {
+        let orphan = Orphan { path: PathBuf::from("/tmp/x"), host_path: PathBuf::from("/tmp/x"), size: 1 };
+        let orphans = [orphan];
+
+        let refused = confirm_with(&orphans, false, false, &mut "y\n".as_bytes()).unwrap_err();
+        assert!(refused.contains("--yes"), "{}", refused);
+        assert!(confirm_with(&orphans, true, false, &mut "".as_bytes()).is_ok());
+        assert!(confirm_with(&[], false, false, &mut "".as_bytes()).is_ok());
+        assert!(confirm_with(&orphans, false, true, &mut "yes\n".as_bytes()).is_ok());
+        assert!(confirm_with(&orphans, false, true, &mut "\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+//! src/install/resolve.rs
+//! Resolves a package's dependency tree against the repository index and the local DB.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::buildins::meta::{merge_arch_dependencies, Dependency};
+use crate::db::download::{resolve_asset_for_arch, resolve_version_asset, ArchAsset, PackageEntry, RepoIndex};
+use crate::version;
+
+/// A package selected for installation, with the asset to fetch for the target architecture.
+#[derive(Debug, Clone)]
+pub struct PlannedPackage {
+    pub name: String,
+    pub version: String,
+    pub download_url: String,
+    pub sha256: Option<String>,
+    /// Asset size recorded in the index, if any
+    pub size: Option<u64>,
+    /// The index entry's description, recorded with the installed package
+    pub description: String,
+}
+
+/// Split an install spec into the package name and the pinned version, if any:
+/// `foo@1.2` pins version 1.2, plain `foo` means the latest.
+pub fn split_pin(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    }
+}
+
+/// Compute the install order for `root` (dependencies first, `root` last). `root` may pin a
+/// version (`name@version`); a dependency resolves to the newest version satisfying its
+/// constraint, which is the latest one unless the constraint rules it out.
+///
+/// `installed` returns the locally installed version of a package, if any. Installed packages
+/// that satisfy their constraint are left alone; everything else must come from `index`.
+/// Assets and per-architecture dependencies are chosen for `arch`, normally the host's.
+pub fn resolve(
+    index: &RepoIndex,
+    root: &str,
+    arch: &str,
+    installed: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<PlannedPackage>, String> {
+    let mut resolver = Resolver::new(index, arch, installed);
+    resolver.visit(root)?;
+    Ok(resolver.plan)
+}
+
+/// Like `resolve`, also returning a step-by-step account of the attempt for `install --explain`:
+/// which package required what, the version the repository offers, and why a candidate was
+/// accepted or rejected. Nested lines are indented under the package that required them.
+pub fn resolve_explained(
+    index: &RepoIndex,
+    root: &str,
+    arch: &str,
+    installed: &dyn Fn(&str) -> Option<String>,
+) -> (Result<Vec<PlannedPackage>, String>, Vec<String>) {
+    let mut resolver = Resolver::new(index, arch, installed);
+    resolver.trace = Some(Vec::new());
+    let result = resolver.visit(root).map(|()| std::mem::take(&mut resolver.plan));
+    (result, resolver.trace.unwrap_or_default())
+}
+
+/// Resolve several roots into one install plan shared by all of them (dependencies first).
+///
+/// A root that cannot be resolved contributes nothing to the plan and is reported in the
+/// returned errors by its position in `roots`; the other roots are unaffected.
+pub fn resolve_batch(
+    index: &RepoIndex,
+    roots: &[&str],
+    arch: &str,
+    installed: &dyn Fn(&str) -> Option<String>,
+) -> (Vec<PlannedPackage>, Vec<(usize, String)>) {
+    let mut resolver = Resolver::new(index, arch, installed);
+    let mut errors = Vec::new();
+    for (i, root) in roots.iter().enumerate() {
+        let (name, pin) = split_pin(root);
+        if let Some(planned) = resolver.plan.iter().find(|p| p.name == name) {
+            if pin.is_some_and(|v| v != planned.version) {
+                errors.push((i, format!("'{}' {} is already selected, not the requested {}.", name, planned.version, pin.unwrap_or_default())));
+            }
+            continue;
+        }
+        let mark = resolver.plan.len();
+        if let Err(e) = resolver.visit(root) {
+            resolver.plan.truncate(mark);
+            resolver.visiting.clear();
+            errors.push((i, e));
+        }
+    }
+    (resolver.plan, errors)
+}
+
+/// Dry-run check of `deps` as required by `required_by`: each one must be installed at a
+/// satisfying version or installable from `index` (including its own dependencies).
+/// Returns the unsatisfiable dependencies with the reason; nothing is planned or installed.
+pub fn unresolvable(
+    index: &RepoIndex,
+    required_by: &str,
+    deps: &[Dependency],
+    arch: &str,
+    installed: &dyn Fn(&str) -> Option<String>,
+) -> Vec<(Dependency, String)> {
+    deps.iter()
+        .filter_map(|dep| {
+            let mut resolver = Resolver::new(index, arch, installed);
+            resolver.require(required_by, dep).err().map(|e| (dep.clone(), e))
+        })
+        .collect()
+}
+
+/// Plan the dependencies `deps` of `required_by` (dependencies first), for a package that is
+/// not itself installed from `index`, such as one built from source (`install --prefer-source`).
+pub fn resolve_dependencies(
+    index: &RepoIndex,
+    required_by: &str,
+    deps: &[Dependency],
+    arch: &str,
+    installed: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<PlannedPackage>, String> {
+    let mut resolver = Resolver::new(index, arch, installed);
+    for dep in deps {
+        resolver.require(required_by, dep)?;
+    }
+    Ok(resolver.plan)
+}
+
+/// Plan only `root` itself (`name` or `name@version`), ignoring its dependencies
+/// (`install --no-deps`).
+pub fn resolve_single(index: &RepoIndex, root: &str, arch: &str) -> Result<PlannedPackage, String> {
+    let (name, pin) = split_pin(root);
+    if index.packages.is_empty() {
+        return Err(format!("Package '{}' not found: the repository is empty.", name));
+    }
+    let entry = index.packages.get(name)
+        .ok_or_else(|| format!("Package '{}' not found in the repository.", name))?;
+    let candidate = Candidate::of(name, entry, pin, arch)?;
+    let (download_url, sha256, size) = candidate.asset.clone()
+        .ok_or_else(|| format!("No compatible asset for '{}' {} on arch {}.", name, candidate.version, arch))?;
+    version::check_client_version(name, candidate.min_nxpkg_version)?;
+    Ok(PlannedPackage {
+        name: name.to_string(),
+        version: candidate.version.to_string(),
+        download_url,
+        sha256,
+        size,
+        description: entry.description.clone(),
+    })
+}
+
+/// One version of a package as listed in the index. The latest version is described by the
+/// entry's top-level fields; a pinned one by its record in `versions`.
+struct Candidate<'a> {
+    version: &'a str,
+    /// Dependencies on the target architecture: the generic list plus that architecture's extras
+    dependencies: Vec<String>,
+    min_nxpkg_version: Option<&'a str>,
+    /// (url, sha256, size) of the asset for the target architecture
+    asset: Option<(String, Option<String>, Option<u64>)>,
+    architectures: Option<&'a HashMap<String, ArchAsset>>,
+}
+
+impl<'a> Candidate<'a> {
+    fn of(name: &str, entry: &'a PackageEntry, pin: Option<&str>, arch: &str) -> Result<Self, String> {
+        let Some(pin) = pin.filter(|v| *v != entry.latest_version || entry.versions.contains_key(*v)) else {
+            return Ok(Candidate {
+                version: &entry.latest_version,
+                dependencies: merge_arch_dependencies(&entry.dependencies, &entry.arch_dependencies, arch),
+                min_nxpkg_version: entry.min_nxpkg_version.as_deref(),
+                asset: resolve_asset_for_arch(entry, arch),
+                architectures: entry.architectures.as_ref(),
+            });
+        };
+        let Some((version, assets)) = entry.versions.get_key_value(pin) else {
+            return Err(format!(
+                "Version {} of '{}' is not in the repository (available: {}).",
+                pin, name, available_versions(entry).join(", ")
+            ));
+        };
+        Ok(Candidate {
+            version,
+            dependencies: merge_arch_dependencies(&assets.dependencies, &assets.arch_dependencies, arch),
+            min_nxpkg_version: assets.min_nxpkg_version.as_deref(),
+            asset: resolve_version_asset(assets, arch),
+            architectures: Some(&assets.architectures),
+        })
+    }
+}
+
+/// Every version of `entry` the index lists, oldest first.
+fn available_versions(entry: &PackageEntry) -> Vec<&str> {
+    let mut available: Vec<&str> = entry.versions.keys().map(String::as_str).collect();
+    if !available.contains(&entry.latest_version.as_str()) {
+        available.push(&entry.latest_version);
+    }
+    available.sort_by(|a, b| version::compare(a, b));
+    available
+}
+
+/// The newest version of `entry` satisfying `dep`, preferring one with an asset for `arch`.
+fn newest_satisfying<'e>(entry: &'e PackageEntry, dep: &Dependency, arch: &str) -> Option<&'e str> {
+    let satisfying: Vec<&str> = available_versions(entry).into_iter().rev().filter(|v| dep.satisfied_by(v)).collect();
+    satisfying.iter()
+        .copied()
+        .find(|v| Candidate::of(&dep.name, entry, Some(v), arch).is_ok_and(|c| c.asset.is_some()))
+        .or(satisfying.first().copied())
+}
+
+/// Why no asset of a candidate can be installed on `arch`, for explanations.
+fn asset_rejection(architectures: Option<&HashMap<String, ArchAsset>>, arch: &str) -> String {
+    let mut keys: Vec<String> = architectures.into_iter()
+        .flatten()
+        .map(|(k, a)| if a.arch_mismatch(k) { format!("{} (declares {}, ignored)", k, a.arch.as_deref().unwrap_or_default()) } else { k.clone() })
+        .collect();
+    keys.sort();
+    if keys.is_empty() {
+        format!("no asset for arch {} and no architecture-independent download_url", arch)
+    } else {
+        format!("no asset for arch {}; assets exist for: {}", arch, keys.join(", "))
+    }
+}
+
+struct Resolver<'a> {
+    index: &'a RepoIndex,
+    arch: &'a str,
+    installed: &'a dyn Fn(&str) -> Option<String>,
+    plan: Vec<PlannedPackage>,
+    visiting: HashSet<String>,
+    /// Explanation lines, collected only for `resolve_explained`
+    trace: Option<Vec<String>>,
+    depth: usize,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(index: &'a RepoIndex, arch: &'a str, installed: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        Resolver { index, arch, installed, plan: Vec::new(), visiting: HashSet::new(), trace: None, depth: 0 }
+    }
+
+    fn note(&mut self, msg: impl FnOnce() -> String) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(format!("{}{}", "  ".repeat(self.depth), msg()));
+        }
+    }
+
+    fn visit(&mut self, spec: &str) -> Result<(), String> {
+        let (name, pin) = split_pin(spec);
+        let (index, arch) = (self.index, self.arch);
+        let planned = match resolve_single(index, spec, arch) {
+            Ok(p) => p,
+            Err(e) => {
+                self.note(|| match index.packages.get(name).map(|entry| Candidate::of(name, entry, pin, arch)) {
+                    Some(Ok(c)) if c.asset.is_none() => {
+                        format!("✗ {} {}: rejected, {}", name, c.version, asset_rejection(c.architectures, arch))
+                    }
+                    Some(Ok(c)) => format!(
+                        "✗ {} {}: rejected, requires nxpkg >= {} (this is {})",
+                        name, c.version, c.min_nxpkg_version.unwrap_or_default(), crate::VERSION
+                    ),
+                    Some(Err(_)) => format!("✗ {}: version {} is not in the repository", name, pin.unwrap_or_default()),
+                    None if index.packages.is_empty() => format!("✗ {}: not in the repository, which has no packages", name),
+                    None => format!("✗ {}: not in the repository", name),
+                });
+                return Err(e);
+            }
+        };
+        let dependencies = Candidate::of(name, &index.packages[name], pin, arch)?.dependencies;
+        self.note(|| match dependencies.len() {
+            0 => format!("{} {}: candidate, no dependencies", name, planned.version),
+            _ => format!("{} {}: candidate, depends on {}", name, planned.version, dependencies.join(", ")),
+        });
+
+        self.visiting.insert(name.to_string());
+        self.depth += 1;
+        // When explaining, keep checking the remaining dependencies so every problem is reported
+        let mut result = Ok(());
+        for spec in &dependencies {
+            let required = match Dependency::parse(spec) {
+                Ok(dep) => self.require(name, &dep),
+                Err(e) => {
+                    self.note(|| format!("✗ '{}': {}", spec, e));
+                    Err(format!("Package '{}' has an invalid dependency: {}", name, e))
+                }
+            };
+            if let Err(e) = required {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+                if self.trace.is_none() {
+                    break;
+                }
+            }
+        }
+        self.depth -= 1;
+        if let Err(e) = result {
+            self.note(|| format!("✗ {} {}: rejected, a dependency cannot be satisfied", name, planned.version));
+            return Err(e);
+        }
+        self.visiting.remove(name);
+
+        self.plan.push(planned);
+        Ok(())
+    }
+
+    fn require(&mut self, required_by: &str, dep: &Dependency) -> Result<(), String> {
+        if let Some(version) = (self.installed)(&dep.name) {
+            if dep.satisfied_by(&version) {
+                self.note(|| format!("{}: installed {} satisfies it", dep, version));
+                return Ok(());
+            }
+            self.note(|| format!("✗ {} (required by {}): conflicts with installed {} {}", dep, required_by, dep.name, version));
+            return Err(format!(
+                "'{}' requires '{}', but {} {} is installed.",
+                required_by, dep, dep.name, version
+            ));
+        }
+
+        if let Some(planned) = self.plan.iter().find(|p| p.name == dep.name) {
+            let version = planned.version.clone();
+            if dep.satisfied_by(&version) {
+                self.note(|| format!("{}: already selected {} satisfies it", dep, version));
+                return Ok(());
+            }
+            self.note(|| format!("✗ {} (required by {}): conflicts with {} {} selected by another dependency", dep, required_by, dep.name, version));
+            return Err(format!(
+                "'{}' requires '{}', but {} {} is selected by another dependency.",
+                required_by, dep, dep.name, version
+            ));
+        }
+
+        let Some(entry) = self.index.packages.get(&dep.name) else {
+            self.note(|| format!("✗ {} (required by {}): not installed, and no versions are available in the repository", dep, required_by));
+            return Err(format!(
+                "'{}' requires '{}', which is not installed and not available in the repository.",
+                required_by, dep
+            ));
+        };
+        let Some(version) = newest_satisfying(entry, dep, self.arch) else {
+            let available = available_versions(entry).join(", ");
+            self.note(|| format!("✗ {} (required by {}): repository offers only {}, rejected by the version constraint", dep, required_by, available));
+            return Err(format!(
+                "'{}' requires '{}', but the repository only provides {} {}.",
+                required_by, dep, dep.name, available
+            ));
+        };
+        // Dependency cycle: the package is already being resolved further up the stack.
+        if self.visiting.contains(&dep.name) {
+            self.note(|| format!("{}: already being resolved (dependency cycle)", dep));
+            return Ok(());
+        }
+        if version == entry.latest_version {
+            return self.visit(&dep.name);
+        }
+        self.note(|| format!("{}: latest {} is rejected by the version constraint, using {}", dep, entry.latest_version, version));
+        self.visit(&format!("{}@{}", dep.name, version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(packages: serde_json::Value) -> RepoIndex {
+        serde_json::from_value(serde_json::json!({ "packages": packages })).unwrap()
+    }
+
+    fn nothing_installed(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn empty_repository_is_named_in_errors_and_explanations() {
+        let empty = index(serde_json::json!({}));
+        assert_eq!(resolve_single(&empty, "app", "x86_64").unwrap_err(), "Package 'app' not found: the repository is empty.");
+
+        let (result, trace) = resolve_explained(&empty, "app@2", "x86_64", &nothing_installed);
+        assert_eq!(result.unwrap_err(), "Package 'app' not found: the repository is empty.");
+        assert_eq!(trace, ["✗ app: not in the repository, which has no packages"]);
+    }
+
+    #[test]
+    fn missing_package_in_a_populated_repository_is_not_called_empty() {
+        let index = index(serde_json::json!({ "other": { "latest_version": "1", "description": "" } }));
+        assert_eq!(resolve_single(&index, "app", "x86_64").unwrap_err(), "Package 'app' not found in the repository.");
+        let (_, trace) = resolve_explained(&index, "app", "x86_64", &nothing_installed);
+        assert_eq!(trace, ["✗ app: not in the repository"]);
+    }
+
+    /// `app` depending on `dep`, which has versions 1.0 (x86_64 only), 1.5, 2.0 and 3.0 (latest).
+    fn versioned_index(constraint: &str) -> RepoIndex {
+        let asset = |v: &str, arch: &str| serde_json::json!({ "architectures": { arch: { "download_url": format!("https://r/dep-{}", v) } } });
+        index(serde_json::json!({
+            "app": {
+                "latest_version": "1", "description": "",
+                "download_url": "https://r/app-1",
+                "dependencies": [format!("dep {}", constraint)],
+            },
+            "dep": {
+                "latest_version": "3.0", "description": "",
+                "architectures": { "any": { "download_url": "https://r/dep-3.0" } },
+                "versions": {
+                    "1.0": asset("1.0", "x86_64"),
+                    "1.5": asset("1.5", "any"),
+                    "2.0": asset("2.0", "aarch64"),
+                    "3.0": asset("3.0", "any"),
+                },
+            },
+        }))
+    }
+
+    fn planned(plan: &[PlannedPackage]) -> Vec<(String, String, String)> {
+        plan.iter().map(|p| (p.name.clone(), p.version.clone(), p.download_url.clone())).collect()
+    }
+
+    fn entry(name: &str, version: &str, url: &str) -> (String, String, String) {
+        (name.to_string(), version.to_string(), url.to_string())
+    }
+
+    #[test]
+    fn dependency_uses_the_latest_version_when_it_satisfies() {
+        let plan = resolve(&versioned_index(">= 1.0"), "app", "x86_64", &nothing_installed).unwrap();
+        assert_eq!(planned(&plan), [entry("dep", "3.0", "https://r/dep-3.0"), entry("app", "1", "https://r/app-1")]);
+    }
+
+    #[test]
+    fn dependency_falls_back_to_the_newest_satisfying_version() {
+        let plan = resolve(&versioned_index("< 3.0"), "app", "aarch64", &nothing_installed).unwrap();
+        assert_eq!(planned(&plan)[0], entry("dep", "2.0", "https://r/dep-2.0"));
+
+        // 2.0 has no x86_64 asset, so the newest installable one is 1.5
+        let (plan, trace) = resolve_explained(&versioned_index("< 3.0"), "app", "x86_64", &nothing_installed);
+        assert_eq!(planned(&plan.unwrap())[0], entry("dep", "1.5", "https://r/dep-1.5"));
+        assert_eq!(trace[1], "  dep < 3.0: latest 3.0 is rejected by the version constraint, using 1.5");
+        assert_eq!(trace[2], "  dep 1.5: candidate, no dependencies");
+
+        let plan = resolve(&versioned_index("= 1.0"), "app", "x86_64", &nothing_installed).unwrap();
+        assert_eq!(planned(&plan)[0], entry("dep", "1.0", "https://r/dep-1.0"));
+    }
+
+    #[test]
+    fn unsatisfiable_constraint_lists_the_available_versions() {
+        let (result, trace) = resolve_explained(&versioned_index(">= 4"), "app", "x86_64", &nothing_installed);
+        assert_eq!(result.unwrap_err(), "'app' requires 'dep >= 4', but the repository only provides dep 1.0, 1.5, 2.0, 3.0.");
+        assert_eq!(trace[1], "  ✗ dep >= 4 (required by app): repository offers only 1.0, 1.5, 2.0, 3.0, rejected by the version constraint");
+    }
+
+    #[test]
+    fn arch_dependencies_follow_the_target_arch() {
+        let index = index(serde_json::json!({
+            "app": {
+                "latest_version": "1", "description": "",
+                "architectures": { "any": { "download_url": "https://r/app" } },
+                "dependencies": ["libc"],
+                "arch_dependencies": { "x86_64": ["libx86"], "aarch64": ["libarm"] },
+            },
+            "libc": { "latest_version": "1", "description": "", "download_url": "https://r/libc" },
+            "libx86": { "latest_version": "1", "description": "", "download_url": "https://r/libx86" },
+            "libarm": { "latest_version": "1", "description": "", "download_url": "https://r/libarm" },
+        }));
+        let names = |arch| -> Vec<String> {
+            resolve(&index, "app", arch, &nothing_installed).unwrap().into_iter().map(|p| p.name).collect()
+        };
+        assert_eq!(names("x86_64"), ["libc", "libx86", "app"]);
+        assert_eq!(names("amd64"), ["libc", "libx86", "app"]);
+        assert_eq!(names("aarch64"), ["libc", "libarm", "app"]);
+        assert_eq!(names("riscv64"), ["libc", "app"]);
+    }
+}
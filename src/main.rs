@@ -1,18 +1,31 @@
+mod arch;
 mod db;
 mod compress;
 mod buildins;
 mod repo;
 mod config;
 mod trust;
+mod install;
+mod version;
+mod audit;
+mod progress;
+#[cfg(test)]
+mod test_support;
 use crate::db::download;
 use crate::db::upload;
+use crate::db::verify::{self, AssetStatus};
 use crate::buildins::buildpkg;
-use crate::buildins::chroot::ChrootEnv;
-use crate::buildins::meta::{BuildInfo, InstallInfo, PackageInfo, PackageRecipe};
+use crate::buildins::chroot::{BuildUser, ChrootEnv, NeedsPrivilege};
+use crate::buildins::sandbox::{BuildSandbox, ChrootSandbox, ContainerSandbox};
+use crate::buildins::detect::{self, BuildSystemKind, BuildSystemMatch};
+use crate::buildins::guard;
+use crate::buildins::meta::{BuildInfo, Dependency, InstallInfo, PackageInfo, PackageRecipe};
 use crate::buildins::profile::BuildProfile;
-use crate::config::AppConfig;
+use crate::config::{AppConfig, ConfigSource, SandboxKind};
+use crate::install::{diff, resolve, InstallOptions, InstallOutcome, PackageSource};
+use crate::progress::{DownloadBars, Spinner};
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 #[cfg(unix)]
 use std::os::unix::fs::{symlink, PermissionsExt};
@@ -20,9 +33,8 @@ use std::os::unix::fs::{symlink, PermissionsExt};
 
 pub use compress::decompress_tarball;
 pub use db::PackageManagerDB;
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Parser, Subcommand};
 use rusqlite::Connection;
-use indicatif::{ProgressBar, ProgressStyle};
 use colored::*;
 // Indicates version of the nxpkg source code for every ".rs" file
 pub const VERSION: &str = "v0.1.0";
@@ -31,8 +43,22 @@ pub const VERSION: &str = "v0.1.0";
 #[derive(Parser)]
 #[command(name = "nxpkg")]
 #[command(about = "NeoniX PacKaGe Manager for Neonix v1.0")]
-
 struct Cli {
+    /// Don't show spinners or progress bars (they are also hidden when stdout is not a terminal)
+    #[arg(short = 'q', long = "quiet", global = true)]
+    quiet: bool,
+    /// Maximum number of parallel jobs (overrides [performance] max_parallelism)
+    #[arg(short = 'j', long = "jobs", global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    /// Install into and remove from this directory instead of /, keeping its database and cache inside it (overrides NXPKG_ROOT)
+    #[arg(long = "root", global = true)]
+    root: Option<PathBuf>,
+    /// Read this config file instead of /etc/nxpkg/config.cfg and the user's config.cfg
+    #[arg(long = "config", global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Resolve and validate packages as if running on ARCH (overrides NXPKG_ARCH; for testing and cross-staging)
+    #[arg(long = "assume-arch", global = true, value_name = "ARCH")]
+    assume_arch: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -40,26 +66,142 @@ struct Cli {
 enum Commands {
     /// Installs Package
     Install {
-        /// Package name
+        /// Package name; `name@version` installs that published version instead of the latest
         name: Option<String>,
 
         /// Install files locally
         #[arg(short = 'L', long = "local")]
         local: Option<String>,
+
+        /// Install from this binary repo remote (by name) instead of the active one
+        #[arg(long = "repo")]
+        repo: Option<String>,
+
+        /// Install only the named package, skipping dependency resolution
+        #[arg(long = "no-deps")]
+        no_deps: bool,
+
+        /// Download the package and its dependencies into the cache without installing
+        #[arg(long = "download-only", conflicts_with = "local")]
+        download_only: bool,
+
+        /// Install every package listed in this file (one `name` or `name@version` per line)
+        #[arg(long = "from-file", conflicts_with_all = ["name", "local", "download_only"])]
+        from_file: Option<PathBuf>,
+
+        /// Answer yes to confirmations: the download size of dependencies, removal of replaced packages
+        #[arg(short = 'y', long = "yes")]
+        yes: bool,
+
+        /// When dependency resolution fails, print the full resolution attempt
+        #[arg(long = "explain", conflicts_with_all = ["no_deps", "local", "from_file"])]
+        explain: bool,
+
+        /// Resolve packages for this architecture instead of the host's (e.g. aarch64, arm64);
+        /// a foreign architecture needs --download-only or a --root staging directory
+        #[arg(long = "arch", value_name = "ARCH")]
+        arch: Option<String>,
+
+        /// After installing, warn about installed symlinks whose target is missing (also [install] check_links)
+        #[arg(long = "check-links")]
+        check_links: bool,
+
+        /// Build the package from its source repository when the index has no binary asset for
+        /// the architecture (also [install] source_fallback)
+        #[arg(long = "prefer-source", conflicts_with_all = ["local", "from_file", "download_only"])]
+        prefer_source: bool,
+
+        /// Do not verify the repository index signature for this command (prints a warning)
+        #[arg(long = "insecure-skip-verify")]
+        insecure_skip_verify: bool,
     },
     /// Removes Packgage
     Remove {
         /// Package name
         name: String,
+        /// Only forget the package: delete its database entry but leave its files in place
+        #[arg(long = "keep-files")]
+        keep_files: bool,
     },
     Purge {
         /// Package name
         name: String,
     },
+    /// Upgrades an installed package to the latest version in the repository
+    Upgrade {
+        /// Package name
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+
+        /// Upgrade every installed package that has a newer version; a failure rolls back the
+        /// packages already upgraded in this run
+        #[arg(long = "all", conflicts_with_all = ["name", "show_changes"])]
+        all: bool,
+
+        /// Show which files would be added, removed or modified, without upgrading
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+
+        /// Show the file changes before upgrading
+        #[arg(long = "show-changes")]
+        show_changes: bool,
+    },
+    /// Reinstall every installed package at its installed version from the repository,
+    /// restoring missing or modified files
+    ReinstallAll,
+    /// Lists installed packages, noting those installed with unsatisfied dependencies
+    List,
     /// Searches for packages in the remote repository
     Search {
         /// The search term
         term: String,
+
+        /// Print matches as stable tab-separated `name<TAB>version<TAB>description` lines, sorted by name
+        #[arg(long = "porcelain")]
+        porcelain: bool,
+
+        /// Search the index of every configured binary repo remote, labelling each match with its remotes
+        #[arg(long = "all-repos")]
+        all_repos: bool,
+
+        /// Do not verify the repository index signature for this command (prints a warning)
+        #[arg(long = "insecure-skip-verify")]
+        insecure_skip_verify: bool,
+    },
+    /// Show the repository's index entry for a package: versions, assets, checksums, sizes
+    Show {
+        /// Package name
+        name: String,
+
+        /// Read the index of this binary repo remote (by name) instead of the active one
+        #[arg(long = "repo")]
+        repo: Option<String>,
+
+        /// Print the index entry as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
+    /// List every package (with version) needed to install a package on an empty system, without
+    /// installing anything, e.g. to fill an offline mirror
+    Closure {
+        /// Package name; `name@version` resolves that published version instead of the latest
+        name: String,
+
+        /// Resolve against this binary repo remote (by name) instead of the active one
+        #[arg(long = "repo")]
+        repo: Option<String>,
+
+        /// Resolve for this architecture instead of the host's (e.g. aarch64, arm64)
+        #[arg(long = "arch", value_name = "ARCH")]
+        arch: Option<String>,
+
+        /// Also download every package of the closure into the cache
+        #[arg(long = "download-only")]
+        download_only: bool,
+
+        /// Print the closure as JSON: name, version, asset URL, SHA-256 and size of each package
+        #[arg(long = "json")]
+        json: bool,
     },
     Debug1 {
         /// Package name
@@ -100,6 +242,30 @@ enum Commands {
         /// Ignore any stored build profile for this package
         #[arg(long = "no-profile")]
         no_profile: bool,
+        /// Only search this source for the repository (default: configured, then GitHub and GitLab)
+        #[arg(long = "source", value_enum)]
+        source: Option<repo::SearchSource>,
+        /// Dependency of the built package, `name` or `name <op> version` (repeatable)
+        #[arg(long = "depends")]
+        depends: Vec<String>,
+        /// Build even if some dependencies are neither installed nor in the repository
+        #[arg(long = "ignore-missing-deps")]
+        ignore_missing_deps: bool,
+        /// If cloning over HTTPS keeps failing, try the SSH URL (GitHub/GitLab only)
+        #[arg(long = "ssh-fallback")]
+        ssh_fallback: bool,
+        /// Do not ask: take the top search result (unless --pick is given) and run the build commands
+        #[arg(short = 'y', long = "yes")]
+        yes: bool,
+        /// Take the n-th search result (1-based) instead of asking
+        #[arg(long = "pick", value_name = "N")]
+        pick: Option<usize>,
+        /// Only consider configured repos in this `[repos.<group>]` section of repos.cfg
+        #[arg(long = "group", conflicts_with = "source")]
+        group: Option<String>,
+        /// Build with this recipe file instead of the repository's package.cfg/nxpkg.cfg
+        #[arg(long = "recipe", value_name = "PATH")]
+        recipe: Option<PathBuf>,
     },
     /// Build and package a local project into .nxpkg
     Buildpkg {
@@ -135,6 +301,59 @@ enum Commands {
         /// Ignore any stored build profile for this package
         #[arg(long = "no-profile")]
         no_profile: bool,
+        /// Dependency of the built package, `name` or `name <op> version` (repeatable)
+        #[arg(long = "depends")]
+        depends: Vec<String>,
+    },
+
+    /// Show which build system buildpkg would use for a source tree
+    Detect {
+        /// Path to the source tree
+        path: PathBuf,
+    },
+
+    /// Unpack files from a .nxpkg into a directory without installing it
+    Extract {
+        /// The .nxpkg file
+        file: PathBuf,
+        /// Only extract members matching this glob, e.g. 'usr/bin/*' (repeatable)
+        #[arg(long = "only", visible_alias = "files")]
+        only: Vec<String>,
+        /// Directory to extract into (default: current directory)
+        #[arg(long = "dest")]
+        dest: Option<PathBuf>,
+    },
+
+    /// Compare two .nxpkg files: recipe metadata and added, removed and changed payload files
+    Diff {
+        /// The old package
+        a: PathBuf,
+        /// The new package
+        b: PathBuf,
+    },
+
+    /// List files under a directory that no installed package owns
+    CleanOrphans {
+        /// Directory to scan, as seen inside the install root (e.g. /usr/local)
+        prefix: PathBuf,
+        /// Delete the listed files after confirmation
+        #[arg(long = "delete")]
+        delete: bool,
+        /// Do not ask for confirmation before deleting
+        #[arg(short = 'y', long = "yes", requires = "delete")]
+        yes: bool,
+    },
+
+    /// Restore the package database from one of its automatic backups
+    RestoreDb {
+        /// Backup number as shown by --list (0 = newest)
+        number: Option<usize>,
+        /// Restore from this file instead of a numbered backup
+        #[arg(long = "from", conflicts_with = "number")]
+        from: Option<PathBuf>,
+        /// List the available backups instead of restoring
+        #[arg(long = "list", conflicts_with_all = ["number", "from"])]
+        list: bool,
     },
 
     /// Manage and select source repositories (from repos.cfg)
@@ -149,9 +368,18 @@ enum Commands {
         action: RepoRemoteAction,
     },
 
+    /// Inspect the effective nxpkg configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
     // Show version of the nxpkg
     Version,
 
+    /// Check that this nxpkg build works: architecture detection, signing, SQLite, the package database
+    SelfCheck,
+
     /// Health check (periodic diagnostics)
     Health {
         /// Skip network (don't fetch repository index)
@@ -160,6 +388,13 @@ enum Commands {
         /// Check chroot prerequisites (check required tools in PATH)
         #[arg(long = "check-chroot")]
         check_chroot: bool,
+        /// Give up on the repository index fetch after this many seconds
+        #[arg(long = "timeout", value_name = "SECS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+        timeout: u64,
+
+        /// Do not verify the repository index signature for this command (prints a warning)
+        #[arg(long = "insecure-skip-verify")]
+        insecure_skip_verify: bool,
     },
 
     /// Publish a built .nxpkg to the repository and update index.json (optionally sign)
@@ -175,12 +410,19 @@ enum Commands {
         /// Bearer token for upload (or set env NXPKG_TOKEN)
         #[arg(long = "token")]
         token: Option<String>,
-        /// Base64 ed25519 keypair (64 bytes) for signing index.json (or env NXPKG_SIGN_KEYPAIR_B64)
-        #[arg(long = "sign-keypair-b64")]
+        /// Base64 ed25519 keypair (64 bytes) for signing index.json; visible in the process list,
+        /// prefer --sign-keypair-file or env NXPKG_SIGN_KEYPAIR_B64
+        #[arg(long = "sign-keypair-b64", conflicts_with = "sign_keypair_file")]
         sign_keypair_b64: Option<String>,
-        /// Read base64 ed25519 keypair from file path
+        /// Read base64 ed25519 keypair from a file only its owner can read (.age/.gpg files are decrypted)
         #[arg(long = "sign-keypair-file")]
         sign_keypair_file: Option<String>,
+        /// Upload and rewrite the index even if it already lists this exact package
+        #[arg(long = "force")]
+        force: bool,
+        /// Print the result (asset URL, checksum, architecture keys, signing, error) as JSON
+        #[arg(long = "json")]
+        json: bool,
     },
 }
 
@@ -190,11 +432,16 @@ enum RepoAction {
     /// List configured repositories from repos.cfg
     List,
     /// Add or update an entry in user repos.cfg (~/.config/nxpkg/repos.cfg)
-    Add { name: String, url: String },
-    /// Remove an entry from user repos.cfg
-    Remove { name: String },
-    /// Choose a repo from configured repos (optionally filter by term)
-    Choose { term: Option<String>, #[arg(long = "build")] build: bool, #[arg(long = "print-url")] print_url: bool },
+    Add { name: String, url: String, #[arg(long = "group")] group: Option<String> },
+    /// Remove an entry from user repos.cfg (from every group unless --group is given)
+    Remove { name: String, #[arg(long = "group")] group: Option<String> },
+    /// Choose a repo from configured repos (optionally filter by term and group)
+    Choose {
+        term: Option<String>,
+        #[arg(long = "build")] build: bool,
+        #[arg(long = "print-url")] print_url: bool,
+        #[arg(long = "group")] group: Option<String>,
+    },
 }
 
 // Binary repo remote management
@@ -210,134 +457,50 @@ enum RepoRemoteAction {
     Choose { name: String },
     /// Show current effective repo URL
     Current,
+    /// Download every asset in the index and check it against its recorded SHA-256
+    VerifyRepo {
+        /// Check this remote (by name) instead of the active one
+        #[arg(long = "repo")]
+        repo: Option<String>,
+        /// Only check assets filed under this architecture
+        #[arg(long = "arch")]
+        arch: Option<String>,
+    },
+    /// Publish a snapshot of an earlier index again (needs `[repo] index_history`)
+    RollbackIndex {
+        /// Snapshot serial as shown by --list
+        serial: Option<u64>,
+        /// List the recorded snapshots instead of publishing one
+        #[arg(long = "list", conflicts_with = "serial")]
+        list: bool,
+        /// Roll back this remote (by name) instead of the active one
+        #[arg(long = "repo")]
+        repo: Option<String>,
+        /// Bearer token for the upload (default: NXPKG_TOKEN)
+        #[arg(long = "token")]
+        token: Option<String>,
+        /// Do not ask for confirmation
+        #[arg(short = 'y', long = "yes")]
+        yes: bool,
+    },
 }
 
-// Helper enum and function for build system detection
-use walkdir::WalkDir;
-
-#[derive(Debug, Clone, Copy, ValueEnum)]
-enum BuildSystemKind {
-    Cargo,
-    Meson,
-    Cmake,
-    Scons,
-    Make,
-}
-
-impl BuildSystemKind {
-    fn priority(self) -> u8 {
-        match self {
-            BuildSystemKind::Cargo => 0,
-            BuildSystemKind::Meson => 1,
-            BuildSystemKind::Cmake => 2,
-            BuildSystemKind::Scons => 3,
-            BuildSystemKind::Make => 4,
-        }
-    }
-
-    fn as_str(self) -> &'static str {
-        match self {
-            BuildSystemKind::Cargo => "cargo",
-            BuildSystemKind::Meson => "meson",
-            BuildSystemKind::Cmake => "cmake",
-            BuildSystemKind::Scons => "scons",
-            BuildSystemKind::Make => "make",
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-struct BuildSystemMatch {
-    kind: BuildSystemKind,
-    path: PathBuf,
-    depth: usize,
-}
-
-fn parse_build_system(s: &str) -> Option<BuildSystemKind> {
-    match s.trim().to_lowercase().as_str() {
-        "cargo" => Some(BuildSystemKind::Cargo),
-        "meson" => Some(BuildSystemKind::Meson),
-        "cmake" => Some(BuildSystemKind::Cmake),
-        "scons" => Some(BuildSystemKind::Scons),
-        "make" => Some(BuildSystemKind::Make),
-        _ => None,
-    }
-}
-
-/// Recursively finds the best build system in the cloned repository.
-fn find_build_systems(root_path: &Path) -> Vec<BuildSystemMatch> {
-    let mut candidates: Vec<BuildSystemMatch> = Vec::new();
-
-    for entry in WalkDir::new(root_path).into_iter().filter_map(Result::ok) {
-        let path = entry.path();
-        if !path.is_file() {
-            continue;
-        }
-        let file_name = match path.file_name().and_then(|s| s.to_str()) {
-            Some(name) => name,
-            None => continue,
-        };
-
-        let kind = match file_name {
-            "Cargo.toml" => Some(BuildSystemKind::Cargo),
-            "meson.build" => Some(BuildSystemKind::Meson),
-            "CMakeLists.txt" => Some(BuildSystemKind::Cmake),
-            "SConstruct" | "SConscript" => Some(BuildSystemKind::Scons),
-            "Makefile" | "makefile" | "GNUmakefile" => Some(BuildSystemKind::Make),
-            _ => None,
-        };
-
-        if let Some(kind) = kind {
-            if let Some(parent) = path.parent() {
-                let depth = parent.strip_prefix(root_path)
-                    .map(|p| p.components().count())
-                    .unwrap_or(usize::MAX);
-                candidates.push(BuildSystemMatch {
-                    kind,
-                    path: parent.to_path_buf(),
-                    depth,
-                });
-            }
-        }
-    }
-
-    candidates
-}
-
-fn pick_build_system(
-    candidates: &[BuildSystemMatch],
-    preferred: Option<BuildSystemKind>,
-) -> Option<BuildSystemMatch> {
-    if let Some(kind) = preferred {
-        let mut matches: Vec<BuildSystemMatch> = candidates
-            .iter()
-            .filter(|c| c.kind == kind)
-            .cloned()
-            .collect();
-        matches.sort_by_key(|c| c.depth);
-        return matches.into_iter().next();
-    }
-
-    let mut matches = candidates.to_vec();
-    matches.sort_by_key(|c| (c.kind.priority(), c.depth));
-    matches.into_iter().next()
+// Configuration inspection
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Show resolved settings and where each value came from (default/system/user/remote/env)
+    Show {
+        /// Print as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
 }
 
-fn arch_alias() -> &'static str {
-    match std::env::consts::ARCH {
-        "x86_64" => "x86_64",
-        "aarch64" => "aarch64",
-        "arm" => "arm",
-        "x86" | "i686" => "i686",
-        "powerpc64" => "ppc64",
-        "powerpc64le" => "ppc64le",
-        other => other,
-    }
-}
+use walkdir::WalkDir;
 
 fn auto_package_name(repo_name_only: &str) -> Option<String> {
     let lower = repo_name_only.to_lowercase();
-    let arch = arch_alias();
+    let arch = arch::host_arch();
     if lower.contains("mesa") {
         Some(format!("mesa-{}", arch))
     } else if lower == "linux" || lower.contains("kernel") {
@@ -348,6 +511,9 @@ fn auto_package_name(repo_name_only: &str) -> Option<String> {
 }
 
 fn prompt_for_package_name() -> io::Result<String> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "it could not be detected and stdin is not a terminal; pass --package"));
+    }
     print!("Enter package name: ");
     io::stdout().flush()?;
     let mut input = String::new();
@@ -359,14 +525,42 @@ fn prompt_for_package_name() -> io::Result<String> {
     Ok(trimmed.to_string())
 }
 
-fn run_chroot_command(
-    chroot_env: &ChrootEnv,
+fn run_sandbox_command(
+    sandbox: &dyn BuildSandbox,
     command: &str,
     args: &[String],
     cwd: Option<&Path>,
 ) -> io::Result<std::process::ExitStatus> {
     let args_ref: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-    chroot_env.run_command(command, &args_ref, cwd)
+    sandbox.run_command(command, &args_ref, cwd)
+}
+
+/// The sandbox selected by `[build] sandbox`. Container builds fall back to the chroot when
+/// neither the configured runtime nor podman/docker is installed.
+fn build_sandbox(cfg: &AppConfig, staging_dir: &Path) -> Result<Box<dyn BuildSandbox>, String> {
+    if cfg.sandbox == SandboxKind::Container {
+        let image = cfg.container_image.as_deref()
+            .ok_or("[build] sandbox = container requires [build] container_image")?;
+        match ContainerSandbox::detect_runtime(cfg.container_runtime.as_deref()) {
+            Some(runtime) => {
+                let sandbox = ContainerSandbox::new(&runtime, image, Path::new("/tmp/nxpkg-container"))
+                    .with_mount(staging_dir);
+                return Ok(Box::new(sandbox));
+            }
+            None => eprintln!(
+                "{} no container runtime found ({}); building in the chroot instead",
+                "Warning:".yellow(),
+                cfg.container_runtime.as_deref().unwrap_or("podman or docker")
+            ),
+        }
+    }
+    let build_user = BuildUser::resolve(cfg.build_user.as_deref())
+        .map_err(|e| format!("Invalid [build] build_user: {}", e))?;
+    let chroot_path = Path::new("/tmp/nxpkg-chroot");
+    let chroot_env = ChrootEnv::new(chroot_path)
+        .with_build_user(build_user)
+        .with_parallelism(cfg.max_parallelism);
+    Ok(Box::new(ChrootSandbox::new(chroot_env, chroot_path)))
 }
 
 fn resolve_staging_dir(input: Option<String>) -> Result<PathBuf, String> {
@@ -435,6 +629,72 @@ fn resolve_package_version(
     "0.0.0".to_string()
 }
 
+/// Where a source build takes its recipe from.
+enum BuildRecipe {
+    /// Detect the build system; recipe files in the source are ignored
+    Detect,
+    /// The first of `SOURCE_RECIPE_FILES` at the source root, else detect the build system
+    FromSource,
+    /// A recipe given on the command line
+    Given(Box<PackageRecipe>),
+}
+
+/// Where the package name given to a source build came from, i.e. whether a recipe may replace it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameOrigin {
+    /// `--package`, `--recipe` or typed in by the user; kept even if the source ships a recipe
+    Given,
+    /// Derived from the repository name; the source's recipe, if any, names the package instead
+    Detected,
+    /// A placeholder, as nobody could be asked; the build fails unless the source's recipe names the package
+    Missing,
+}
+
+/// Recipe files a source tree may ship at its root, in order of preference.
+const SOURCE_RECIPE_FILES: &[&str] = &["package.cfg", "nxpkg.cfg"];
+
+/// The recipe shipped at the root of `source_dir`, with its file name. A recipe that exists
+/// but does not parse is an error rather than a reason to fall back to detection.
+fn find_source_recipe(source_dir: &Path) -> Result<Option<(&'static str, PackageRecipe)>, String> {
+    for file in SOURCE_RECIPE_FILES {
+        let path = source_dir.join(file);
+        if path.is_file() {
+            return PackageRecipe::from_file(&path).map(|r| Some((*file, r)));
+        }
+    }
+    Ok(None)
+}
+
+/// Split a recipe's `commands` into build settings and shell commands. Entries of the form
+/// nxpkg itself records (`build_system=`, `configure_args=`, `build_args=`) fill in what
+/// `profile` leaves unset; everything else is returned, to be run in order instead of the
+/// detected build system's commands.
+fn apply_recipe_commands(recipe: &PackageRecipe, profile: &mut BuildProfile) -> Vec<String> {
+    let mut shell = Vec::new();
+    for command in &recipe.build.commands {
+        let args = |v: &str| v.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        match command.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("build_system", v)) => {
+                if profile.build_system.is_none() {
+                    profile.build_system = Some(v.to_string());
+                }
+            }
+            Some(("configure_args", v)) => {
+                if profile.configure_args.is_empty() {
+                    profile.configure_args = args(v);
+                }
+            }
+            Some(("build_args", v)) => {
+                if profile.build_args.is_empty() {
+                    profile.build_args = args(v);
+                }
+            }
+            _ => shell.push(command.clone()),
+        }
+    }
+    shell
+}
+
 fn build_recipe(
     package_name: &str,
     version: &str,
@@ -454,19 +714,77 @@ fn build_recipe(
         package: PackageInfo {
             name: package_name.to_string(),
             version: version.to_string(),
-            architectures: vec![arch_alias().to_string()],
+            architectures: vec![arch::host_arch().to_string()],
+            replaces: Vec::new(),
+            min_nxpkg_version: None,
+            description: String::new(),
         },
         build: BuildInfo {
-            dependencies: Vec::new(),
+            dependencies: profile.dependencies.clone(),
+            arch_dependencies: Default::default(),
             commands: build_commands,
         },
         install: InstallInfo {
             install_params: profile.install_args.clone(),
+            config_files: Vec::new(),
+            conffiles: Vec::new(),
             installed_files: Vec::new(),
+            installed_dirs: Vec::new(),
+            file_hashes: Default::default(),
+            deps_bypassed: false,
         },
+        extra: Default::default(),
+    }
+}
+
+/// The recipe written into a built package. With a source recipe, its metadata and commands
+/// are kept, with the name and version actually built and `profile`'s extra dependencies.
+/// `build_kind` is None when the recipe's own commands did the build.
+fn packaged_recipe(
+    package_name: &str,
+    version: &str,
+    build_kind: Option<BuildSystemKind>,
+    profile: &BuildProfile,
+    source: Option<&PackageRecipe>,
+) -> PackageRecipe {
+    let detected = build_kind.map(|kind| build_recipe(package_name, version, kind, profile));
+    let Some(source) = source else {
+        return detected.expect("a build without a recipe has a build system");
+    };
+    let mut recipe = source.clone();
+    recipe.package.name = package_name.to_string();
+    recipe.package.version = version.to_string();
+    // Built binaries are for this host, unless the recipe declares the package arch-independent
+    let arch_independent = !recipe.package.architectures.is_empty()
+        && recipe.package.architectures.iter().all(|a| arch::canonical_arch(a) == "any");
+    if !arch_independent {
+        recipe.package.architectures = vec![arch::host_arch().to_string()];
+    }
+    for dep in &profile.dependencies {
+        if !recipe.build.dependencies.contains(dep) {
+            recipe.build.dependencies.push(dep.clone());
+        }
+    }
+    if let Some(detected) = detected {
+        recipe.build.commands = detected.build.commands;
+    }
+    recipe.install = InstallInfo {
+        install_params: if profile.install_args.is_empty() { source.install.install_params.clone() } else { profile.install_args.clone() },
+        config_files: source.install.config_files.clone(),
+        ..InstallInfo::default()
+    };
+    recipe
+}
+
+fn save_build_profile(db: &PackageManagerDB, profile: &BuildProfile, package_name: &str) {
+    if let Err(e) = db.save_build_profile(profile) {
+        eprintln!("{} {}", "Failed to save build profile:".red(), e);
+    } else {
+        println!("Saved build profile for '{}'.", package_name.cyan());
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn load_build_profile(
     db: &PackageManagerDB,
     package_name: &str,
@@ -475,6 +793,7 @@ fn load_build_profile(
     configure_args: Vec<String>,
     build_args: Vec<String>,
     install_args: Vec<String>,
+    dependencies: Vec<String>,
 ) -> BuildProfile {
     let mut profile = if no_profile {
         BuildProfile::new(package_name)
@@ -502,10 +821,249 @@ fn load_build_profile(
     if !install_args.is_empty() {
         profile.install_args = install_args;
     }
+    if !dependencies.is_empty() {
+        profile.dependencies = dependencies;
+    }
 
     profile
 }
 
+/// Clone attempts per URL, and the delay before the first retry (doubled for each further one).
+const CLONE_ATTEMPTS: u32 = 3;
+const CLONE_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// `git clone` into `dest`, retrying each URL with backoff before moving on to the next one.
+/// On failure returns git's stderr from the last attempt.
+async fn clone_with_retry(urls: &[String], dest: &Path, pb: &Spinner) -> Result<(), String> {
+    let mut last_err = String::new();
+    for url in urls {
+        for attempt in 1..=CLONE_ATTEMPTS {
+            let _ = fs::remove_dir_all(dest);
+            if attempt == 1 {
+                pb.set_message(format!("Cloning from {}...", url));
+            } else {
+                pb.set_message(format!("Cloning from {} (attempt {}/{})...", url, attempt, CLONE_ATTEMPTS));
+            }
+            let output = tokio::process::Command::new("git")
+                .arg("clone")
+                .arg(url)
+                .arg(dest)
+                .stdin(std::process::Stdio::null())
+                .output()
+                .await;
+            match output {
+                Ok(out) if out.status.success() => return Ok(()),
+                Ok(out) => {
+                    last_err = format!("git clone {} failed ({}):\n{}", url, out.status, String::from_utf8_lossy(&out.stderr));
+                }
+                Err(e) => last_err = format!("could not run git: {}", e),
+            }
+            if attempt < CLONE_ATTEMPTS {
+                tokio::time::sleep(CLONE_RETRY_DELAY * 2u32.pow(attempt - 1)).await;
+            }
+        }
+        pb.println(format!("{} cloning from {} failed after {} attempts", "Warning:".yellow(), url, CLONE_ATTEMPTS));
+    }
+    Err(last_err)
+}
+
+/// Pre-flight for `buildins`: report the profile's dependencies that are neither installed
+/// nor available from the repository before anything is cloned. Returns false to abort.
+async fn check_build_dependencies(cfg: &AppConfig, db: &PackageManagerDB, profile: &BuildProfile, ignore_missing: bool) -> bool {
+    if profile.dependencies.is_empty() {
+        return true;
+    }
+    let mut deps = Vec::new();
+    for spec in &profile.dependencies {
+        match Dependency::parse(spec) {
+            Ok(dep) => deps.push(dep),
+            Err(e) => {
+                eprintln!("{} {}", "Invalid --depends:".red(), e);
+                return false;
+            }
+        }
+    }
+    let index = match download::fetch_index_for(cfg, &cfg.repo_url).await {
+        Ok(index) => index,
+        Err(e) => {
+            eprintln!("{} could not fetch repository index, checking installed packages only: {}", "Warning:".yellow(), e);
+            download::RepoIndex { packages: Default::default() }
+        }
+    };
+    let installed_version = |n: &str| db.get_package_metadata(n).ok().flatten().map(|r| r.package.version);
+    let missing = resolve::unresolvable(&index, &profile.name, &deps, &cfg.arch, &installed_version);
+    if missing.is_empty() {
+        return true;
+    }
+    eprintln!("{}", format!("{} dependenc{} of '{}' cannot be satisfied:", missing.len(), if missing.len() == 1 { "y" } else { "ies" }, profile.name).yellow());
+    for (dep, reason) in &missing {
+        eprintln!("  {}: {}", dep.to_string().bold(), reason);
+    }
+    if ignore_missing {
+        eprintln!("{}", "Continuing anyway (--ignore-missing-deps).".yellow());
+        return true;
+    }
+    eprintln!("Aborting before cloning; pass {} to build anyway.", "--ignore-missing-deps".bold());
+    false
+}
+
+/// Stage of a source build a command belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildPhase {
+    Configure,
+    Build,
+    Install,
+}
+
+/// One command of a source build, run inside the sandbox.
+struct BuildStep {
+    phase: BuildPhase,
+    /// Progress message, e.g. "Building with 'cargo'"
+    message: String,
+    command: String,
+    args: Vec<String>,
+    /// Working directory inside the sandbox
+    cwd: Option<PathBuf>,
+}
+
+impl BuildStep {
+    fn new(phase: BuildPhase, message: &str, command: &str, args: Vec<String>, cwd: Option<&Path>) -> Self {
+        BuildStep {
+            phase,
+            message: message.to_string(),
+            command: command.to_string(),
+            args,
+            cwd: cwd.map(Path::to_path_buf),
+        }
+    }
+
+    /// Shell-like rendering for the preview, e.g. `(cd /build/foo) cargo build --release`.
+    fn display(&self) -> String {
+        let words: Vec<String> = std::iter::once(&self.command).chain(&self.args).map(|w| shell_quote(w)).collect();
+        match &self.cwd {
+            Some(dir) => format!("(cd {}) {}", shell_quote(&dir.to_string_lossy()), words.join(" ")),
+            None => words.join(" "),
+        }
+    }
+}
+
+/// Quote `word` for display when it contains characters a shell would interpret.
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c)) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// The commands a build with `kind` runs, in order. `has_configure` says whether the source
+/// ships a `./configure` script (make builds only).
+fn plan_build_steps(
+    kind: BuildSystemKind,
+    has_configure: bool,
+    profile: &BuildProfile,
+    src_dir: &Path,
+    build_dir: &Path,
+    staging_dir: &Path,
+) -> Vec<BuildStep> {
+    use BuildPhase::*;
+    let src = src_dir.to_string_lossy().to_string();
+    let build = build_dir.to_string_lossy().to_string();
+    let staging = staging_dir.to_string_lossy().to_string();
+    let with = |mut base: Vec<String>, extra: &[String]| {
+        base.extend(extra.iter().cloned());
+        base
+    };
+    let words = |w: &[&str]| -> Vec<String> { w.iter().map(|s| s.to_string()).collect() };
+
+    match kind {
+        BuildSystemKind::Cargo => vec![
+            BuildStep::new(Build, "Building with 'cargo'", "cargo", with(words(&["build", "--release"]), &profile.build_args), Some(src_dir)),
+            BuildStep::new(Install, "Installing with 'cargo'", "cargo", with(words(&["install", "--path", &src, "--root", &staging]), &profile.install_args), None),
+        ],
+        BuildSystemKind::Meson => vec![
+            BuildStep::new(Configure, "Configuring with 'meson'", "meson", with(words(&["setup", &build, &src, "--prefix=/usr"]), &profile.configure_args), None),
+            BuildStep::new(Build, "Building with 'meson'", "meson", with(words(&["compile", "-C", &build]), &profile.build_args), None),
+            BuildStep::new(Install, "Installing with 'meson'", "meson", with(words(&["install", "-C", &build, "--destdir", &staging]), &profile.install_args), None),
+        ],
+        BuildSystemKind::Cmake => {
+            let mut build_args = words(&["--build", &build]);
+            if !profile.build_args.is_empty() {
+                build_args.push("--".to_string());
+                build_args.extend(profile.build_args.iter().cloned());
+            }
+            let configure = words(&["-S", &src, "-B", &build, "-DCMAKE_BUILD_TYPE=Release", "-DCMAKE_INSTALL_PREFIX=/usr"]);
+            let install = vec![format!("DESTDIR={}", staging), "cmake".to_string(), "--install".to_string(), build.clone(), "--prefix".to_string(), "/usr".to_string()];
+            vec![
+                BuildStep::new(Configure, "Configuring with 'cmake'", "cmake", with(configure, &profile.configure_args), None),
+                BuildStep::new(Build, "Building with 'cmake'", "cmake", build_args, None),
+                BuildStep::new(Install, "Installing with 'cmake'", "env", with(install, &profile.install_args), None),
+            ]
+        }
+        BuildSystemKind::Scons => vec![
+            BuildStep::new(Build, "Building with 'scons'", "scons", profile.build_args.clone(), Some(src_dir)),
+            BuildStep::new(Install, "Installing with 'scons'", "scons", with(vec!["install".to_string(), format!("DESTDIR={}", staging), "PREFIX=/usr".to_string()], &profile.install_args), Some(src_dir)),
+        ],
+        BuildSystemKind::Make => {
+            let mut steps = Vec::new();
+            if has_configure {
+                steps.push(BuildStep::new(Configure, "Running configure script", "./configure", with(words(&["--prefix=/usr"]), &profile.configure_args), Some(src_dir)));
+            }
+            steps.push(BuildStep::new(Build, "Building with 'make'", "make", profile.build_args.clone(), Some(src_dir)));
+            steps.push(BuildStep::new(Install, "Installing with 'make'", "make", with(vec!["install".to_string(), format!("DESTDIR={}", staging), "PREFIX=/usr".to_string()], &profile.install_args), Some(src_dir)));
+            steps
+        }
+    }
+}
+
+/// Print the commands about to run and, when `ask` is set and stdin is a terminal, ask
+/// whether to go on. Without a terminal the build proceeds after printing them.
+fn confirm_build_steps(steps: &[BuildStep], label: &str, ask: bool) -> bool {
+    println!("Commands to run in the {}:", label);
+    for step in steps {
+        println!("  {}", step.display());
+    }
+    if !ask || !io::stdin().is_terminal() {
+        return true;
+    }
+    print!("Run these commands? [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// `[build] command_guard`: list the steps that look dangerous and ask whether to run them
+/// anyway, even under `--yes`. Without a terminal to ask on, flagged steps stop the build.
+fn guard_build_steps(steps: &[BuildStep], staging_dir: &Path, allow: &[String]) -> bool {
+    let commands: Vec<String> = steps
+        .iter()
+        .map(|s| std::iter::once(&s.command).chain(&s.args).cloned().collect::<Vec<_>>().join(" "))
+        .collect();
+    let findings = guard::scan(&commands, &[Path::new("/build"), staging_dir], allow);
+    if findings.is_empty() {
+        return true;
+    }
+    eprintln!("{} the command guard flagged {} build command(s):", "Warning:".yellow(), findings.len());
+    for finding in &findings {
+        eprintln!("  {} ({})", finding.command, finding.reason.red());
+    }
+    if !io::stdin().is_terminal() {
+        eprintln!("Refusing to run them; review the recipe, or allow the command with [build] command_guard_allow.");
+        return false;
+    }
+    print!("Run them anyway? [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[allow(clippy::too_many_arguments)]
 fn build_and_package(
     source_path: &Path,
     source_dir_name: &str,
@@ -517,37 +1075,45 @@ fn build_and_package(
     mut profile: BuildProfile,
     save_profile: bool,
     db: &PackageManagerDB,
+    cfg: &AppConfig,
     move_source: bool,
-) -> bool {
-    let pb_build = ProgressBar::new_spinner();
-    pb_build.enable_steady_tick(std::time::Duration::from_millis(120));
-    pb_build.set_style(ProgressStyle::with_template("{spinner:.yellow} {elapsed_precise} {msg}").unwrap());
-
-    // --- Chroot Setup ---
-    let chroot_path = Path::new("/tmp/nxpkg-chroot");
-    let chroot_env = ChrootEnv::new(&chroot_path);
+    confirm: bool,
+    recipe: BuildRecipe,
+    name_origin: NameOrigin,
+) -> Option<PathBuf> {
+    let pb_build = Spinner::new("{spinner:.yellow} {elapsed_precise} {msg}");
+
+    // --- Sandbox Setup ---
+    let sandbox = match build_sandbox(cfg, &staging_dir_in_chroot) {
+        Ok(s) => s,
+        Err(e) => {
+            pb_build.finish_with_message(e.red().to_string());
+            return None;
+        }
+    };
+    let sandbox_root = sandbox.root();
 
-    if let Err(e) = chroot_env.prepare() {
-        pb_build.finish_with_message(format!("Failed to prepare chroot environment: {}", e).red().to_string());
-        let _ = chroot_env.cleanup();
-        return false;
+    if let Err(e) = sandbox.prepare() {
+        pb_build.finish_with_message(format!("Failed to prepare {} environment: {}", sandbox.label(), e).red().to_string());
+        let _ = sandbox.cleanup();
+        return None;
     }
 
-    let chroot_build_dir = chroot_path.join("build");
+    let chroot_build_dir = sandbox_root.join("build");
     if let Err(e) = std::fs::create_dir_all(&chroot_build_dir) {
         pb_build.finish_with_message(format!("Failed to create build dir: {}", e).red().to_string());
-        let _ = chroot_env.cleanup();
-        return false;
+        let _ = sandbox.cleanup();
+        return None;
     }
 
-    let staging_host_path = chroot_path.join(
+    let staging_host_path = sandbox_root.join(
         staging_dir_in_chroot.strip_prefix("/").unwrap_or(&staging_dir_in_chroot)
     );
     let _ = std::fs::remove_dir_all(&staging_host_path);
     if let Err(e) = std::fs::create_dir_all(&staging_host_path) {
         pb_build.finish_with_message(format!("Failed to create staging dir: {}", e).red().to_string());
-        let _ = chroot_env.cleanup();
-        return false;
+        let _ = sandbox.cleanup();
+        return None;
     }
 
     let new_repo_path = chroot_build_dir.join(source_dir_name);
@@ -560,217 +1126,177 @@ fn build_and_package(
     if !moved {
         if let Err(e) = copy_dir_recursive(source_path, &new_repo_path) {
             pb_build.finish_with_message(format!("Failed to copy source: {}", e).red().to_string());
-            let _ = chroot_env.cleanup();
-            return false;
+            let _ = sandbox.cleanup();
+            return None;
         }
         if move_source {
             let _ = std::fs::remove_dir_all(source_path);
         }
     }
 
-    pb_build.set_message(format!("Detecting build system for {}...", source_label));
-
-    let candidates = find_build_systems(&new_repo_path);
-    let preferred_kind = profile.build_system.as_deref().and_then(parse_build_system);
-    if preferred_kind.is_none() {
-        if let Some(ref bs) = profile.build_system {
-            eprintln!("{} {}", "Warning: unknown build system in profile:".yellow(), bs);
-            profile.build_system = None;
-        }
-    }
-    let mut selected_build = pick_build_system(&candidates, preferred_kind);
-    if selected_build.is_none() {
-        if let Some(kind) = preferred_kind {
-            selected_build = Some(BuildSystemMatch {
-                kind,
-                path: new_repo_path.clone(),
-                depth: 0,
-            });
-        }
-    }
-
-    let Some(selected_build) = selected_build else {
-        pb_build.finish_with_message(format!("Could not detect a known build system in {}.", source_label).red().to_string());
-        let _ = chroot_env.cleanup();
-        return false;
+    let source_recipe = match recipe {
+        BuildRecipe::Detect => None,
+        BuildRecipe::Given(recipe) => Some(*recipe),
+        BuildRecipe::FromSource => match find_source_recipe(&new_repo_path) {
+            Ok(Some((file, recipe))) => {
+                pb_build.println(format!("Using recipe {} from {}.", file, source_label));
+                Some(recipe)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                pb_build.finish_with_message(format!("Invalid recipe in {}: {}", source_label, e).red().to_string());
+                let _ = sandbox.cleanup();
+                return None;
+            }
+        },
     };
-    let package_version = resolve_package_version(version_override, &selected_build.path);
-
-    if save_profile {
-        if profile.build_system.is_none() {
-            profile.build_system = Some(selected_build.kind.as_str().to_string());
-        }
-        if let Err(e) = db.save_build_profile(&profile) {
-            eprintln!("{} {}", "Failed to save build profile:".red(), e);
-        } else {
-            println!("Saved build profile for '{}'.", package_name.cyan());
+    // The recipe names and versions the package unless the command line did
+    let package_name = match &source_recipe {
+        Some(r) if name_origin != NameOrigin::Given => r.package.name.clone(),
+        None if name_origin == NameOrigin::Missing => {
+            pb_build.finish_with_message(format!(
+                "Could not determine the package name: {} has no recipe file and stdin is not a terminal; pass --package", source_label
+            ).red().to_string());
+            let _ = sandbox.cleanup();
+            return None;
         }
-    }
+        _ => package_name.to_string(),
+    };
+    let version_override = version_override.or_else(|| source_recipe.as_ref().map(|r| r.package.version.clone()));
+    let stored_profile = profile.clone();
+    let recipe_commands = match &source_recipe {
+        Some(r) => apply_recipe_commands(r, &mut profile),
+        None => Vec::new(),
+    };
 
     let build_path_in_chroot = Path::new("/build").join(source_dir_name);
-    let rel = selected_build.path.strip_prefix(&new_repo_path).unwrap_or(Path::new(""));
-    let src_dir_chroot = if rel.as_os_str().is_empty() {
-        build_path_in_chroot.clone()
+    let (steps, package_version, build_kind) = if !recipe_commands.is_empty() {
+        // The recipe's own commands run from the source root with DESTDIR set to the staging dir
+        let steps = recipe_commands.iter()
+            .map(|command| BuildStep::new(
+                BuildPhase::Build,
+                "Running recipe command",
+                "env",
+                vec![format!("DESTDIR={}", staging_dir_in_chroot.display()), "sh".to_string(), "-c".to_string(), command.clone()],
+                Some(&build_path_in_chroot),
+            ))
+            .collect::<Vec<_>>();
+        if save_profile {
+            save_build_profile(db, &stored_profile, &package_name);
+        }
+        (steps, resolve_package_version(version_override, &new_repo_path), None)
     } else {
-        build_path_in_chroot.join(rel)
-    };
+        pb_build.set_message(format!("Detecting build system for {}...", source_label));
+
+        let candidates = detect::find_build_systems(&new_repo_path);
+        let preferred_kind = profile.build_system.as_deref().and_then(detect::parse_build_system);
+        if preferred_kind.is_none() {
+            if let Some(ref bs) = profile.build_system {
+                eprintln!("{} {}", "Warning: unknown build system in profile:".yellow(), bs);
+                profile.build_system = None;
+            }
+        }
+        let mut selected_build = detect::pick_build_system(&candidates, preferred_kind);
+        if selected_build.is_none() {
+            if let Some(kind) = preferred_kind {
+                selected_build = Some(BuildSystemMatch {
+                    kind,
+                    path: new_repo_path.clone(),
+                    depth: 0,
+                });
+            }
+        }
 
-    let _ = std::fs::create_dir_all(selected_build.path.join("build"));
-    let build_dir_chroot = src_dir_chroot.join("build");
+        let Some(selected_build) = selected_build else {
+            pb_build.finish_with_message(format!("Could not detect a known build system in {}.", source_label).red().to_string());
+            let _ = sandbox.cleanup();
+            return None;
+        };
+        let package_version = resolve_package_version(version_override, &selected_build.path);
 
-    let run = |command: &str, args: Vec<String>, cwd: Option<&Path>| -> bool {
-        match run_chroot_command(&chroot_env, command, &args, cwd) {
-            Ok(exit_status) => exit_status.success(),
-            Err(e) => {
-                eprintln!("{} {}: {}", "Command failed".red(), command, e);
-                false
+        if save_profile {
+            let mut stored_profile = stored_profile;
+            if stored_profile.build_system.is_none() {
+                stored_profile.build_system = Some(selected_build.kind.as_str().to_string());
             }
+            save_build_profile(db, &stored_profile, &package_name);
         }
-    };
 
-    let mut build_successful = false;
-    let mut install_successful = false;
-    match selected_build.kind {
-        BuildSystemKind::Cargo => {
-            pb_build.set_message("Building with 'cargo' in chroot...");
-            let mut args = vec!["build".to_string(), "--release".to_string()];
-            args.extend(profile.build_args.clone());
-            build_successful = run("cargo", args, Some(&src_dir_chroot));
-            if build_successful {
-                pb_build.set_message("Installing with 'cargo' in chroot...");
-                let mut install = vec![
-                    "install".to_string(),
-                    "--path".to_string(),
-                    src_dir_chroot.to_string_lossy().to_string(),
-                    "--root".to_string(),
-                    staging_dir_in_chroot.to_string_lossy().to_string(),
-                ];
-                install.extend(profile.install_args.clone());
-                install_successful = run("cargo", install, None);
-            }
-        }
-        BuildSystemKind::Meson => {
-            pb_build.set_message("Configuring with 'meson' in chroot...");
-            let mut setup_args = vec![
-                "setup".to_string(),
-                build_dir_chroot.to_string_lossy().to_string(),
-                src_dir_chroot.to_string_lossy().to_string(),
-                "--prefix=/usr".to_string(),
-            ];
-            setup_args.extend(profile.configure_args.clone());
-            if run("meson", setup_args, None) {
-                pb_build.set_message("Building with 'meson' in chroot...");
-                let mut compile_args = vec![
-                    "compile".to_string(),
-                    "-C".to_string(),
-                    build_dir_chroot.to_string_lossy().to_string(),
-                ];
-                compile_args.extend(profile.build_args.clone());
-                build_successful = run("meson", compile_args, None);
-                if build_successful {
-                    pb_build.set_message("Installing with 'meson' in chroot...");
-                    let mut install_args_vec = vec![
-                        "install".to_string(),
-                        "-C".to_string(),
-                        build_dir_chroot.to_string_lossy().to_string(),
-                        "--destdir".to_string(),
-                        staging_dir_in_chroot.to_string_lossy().to_string(),
-                    ];
-                    install_args_vec.extend(profile.install_args.clone());
-                    install_successful = run("meson", install_args_vec, None);
-                }
-            }
-        }
-        BuildSystemKind::Cmake => {
-            pb_build.set_message("Configuring with 'cmake' in chroot...");
-            let mut cmake_args = vec![
-                "-S".to_string(),
-                src_dir_chroot.to_string_lossy().to_string(),
-                "-B".to_string(),
-                build_dir_chroot.to_string_lossy().to_string(),
-                "-DCMAKE_BUILD_TYPE=Release".to_string(),
-                "-DCMAKE_INSTALL_PREFIX=/usr".to_string(),
-            ];
-            cmake_args.extend(profile.configure_args.clone());
-            if run("cmake", cmake_args, None) {
-                pb_build.set_message("Building with 'cmake' in chroot...");
-                let mut build_args_vec = vec![
-                    "--build".to_string(),
-                    build_dir_chroot.to_string_lossy().to_string(),
-                ];
-                if !profile.build_args.is_empty() {
-                    build_args_vec.push("--".to_string());
-                    build_args_vec.extend(profile.build_args.clone());
-                }
-                build_successful = run("cmake", build_args_vec, None);
-                if build_successful {
-                    pb_build.set_message("Installing with 'cmake' in chroot...");
-                    let mut install_args_vec = vec![
-                        format!("DESTDIR={}", staging_dir_in_chroot.to_string_lossy()),
-                        "cmake".to_string(),
-                        "--install".to_string(),
-                        build_dir_chroot.to_string_lossy().to_string(),
-                        "--prefix".to_string(),
-                        "/usr".to_string(),
-                    ];
-                    install_args_vec.extend(profile.install_args.clone());
-                    install_successful = run("env", install_args_vec, None);
-                }
-            }
-        }
-        BuildSystemKind::Scons => {
-            pb_build.set_message("Building with 'scons' in chroot...");
-            let args = profile.build_args.clone();
-            build_successful = run("scons", args, Some(&src_dir_chroot));
-            if build_successful {
-                pb_build.set_message("Installing with 'scons' in chroot...");
-                let mut install = vec![
-                    "install".to_string(),
-                    format!("DESTDIR={}", staging_dir_in_chroot.to_string_lossy()),
-                    "PREFIX=/usr".to_string(),
-                ];
-                install.extend(profile.install_args.clone());
-                install_successful = run("scons", install, Some(&src_dir_chroot));
-            }
-        }
-        BuildSystemKind::Make => {
-            let configure_script = selected_build.path.join("configure");
-            if configure_script.exists() {
-                pb_build.set_message("Running configure script in chroot...");
-                let mut cfg_args = vec!["--prefix=/usr".to_string()];
-                cfg_args.extend(profile.configure_args.clone());
-                if !run("./configure", cfg_args, Some(&src_dir_chroot)) {
-                    build_successful = false;
-                    pb_build.finish_with_message("Configure step failed.".red().to_string());
-                }
-            }
+        let rel = selected_build.path.strip_prefix(&new_repo_path).unwrap_or(Path::new(""));
+        let src_dir_chroot = if rel.as_os_str().is_empty() {
+            build_path_in_chroot.clone()
+        } else {
+            build_path_in_chroot.join(rel)
+        };
 
-            if !pb_build.is_finished() {
-                pb_build.set_message("Building with 'make' in chroot...");
-                let args = profile.build_args.clone();
-                build_successful = run("make", args, Some(&src_dir_chroot));
-                if build_successful {
-                    pb_build.set_message("Installing with 'make' in chroot...");
-                    let mut install = vec![
-                        "install".to_string(),
-                        format!("DESTDIR={}", staging_dir_in_chroot.to_string_lossy()),
-                        "PREFIX=/usr".to_string(),
-                    ];
-                    install.extend(profile.install_args.clone());
-                    install_successful = run("make", install, Some(&src_dir_chroot));
-                }
+        let _ = std::fs::create_dir_all(selected_build.path.join("build"));
+        let build_dir_chroot = src_dir_chroot.join("build");
+
+        let steps = plan_build_steps(
+            selected_build.kind,
+            selected_build.path.join("configure").exists(),
+            &profile,
+            &src_dir_chroot,
+            &build_dir_chroot,
+            &staging_dir_in_chroot,
+        );
+        (steps, package_version, Some(selected_build.kind))
+    };
+    if cfg.command_guard
+        && !pb_build.suspend(|| guard_build_steps(&steps, &staging_dir_in_chroot, &cfg.command_guard_allow))
+    {
+        pb_build.finish_with_message(format!("Build of {} stopped by the command guard.", package_name).red().to_string());
+        let _ = sandbox.cleanup();
+        return None;
+    }
+    if !pb_build.suspend(|| confirm_build_steps(&steps, sandbox.label(), confirm)) {
+        pb_build.finish_with_message(format!("Build of {} cancelled.", package_name).yellow().to_string());
+        let _ = sandbox.cleanup();
+        return None;
+    }
+
+    // Steps run in order; the phase of the first failing one decides what is reported
+    let mut failed_phase = None;
+    for step in &steps {
+        pb_build.set_message(format!("{} in {}...", step.message, sandbox.label()));
+        let ok = match run_sandbox_command(sandbox.as_ref(), &step.command, &step.args, step.cwd.as_deref()) {
+            Ok(exit_status) => exit_status.success(),
+            Err(e) if NeedsPrivilege::is(&e) => {
+                // No other build can get further in this environment either
+                pb_build.finish_and_clear();
+                let _ = sandbox.cleanup();
+                eprintln!(
+                    "{} {}. Run nxpkg as root, or build in a container with [build] sandbox = container.",
+                    "Error:".red(),
+                    e
+                );
+                std::process::exit(1);
             }
+            Err(e) => {
+                eprintln!("{} {}: {}", "Command failed".red(), step.command, e);
+                false
+            }
+        };
+        if !ok {
+            failed_phase = Some(step.phase);
+            break;
         }
     }
+    if failed_phase == Some(BuildPhase::Configure) {
+        pb_build.finish_with_message("Configure step failed.".red().to_string());
+    }
+    let build_successful = matches!(failed_phase, None | Some(BuildPhase::Install));
+    let install_successful = failed_phase.is_none();
 
-    let mut success = false;
+    let mut packaged = None;
     if build_successful && install_successful {
         pb_build.set_message("Packaging artifacts...");
-        let recipe = build_recipe(package_name, &package_version, selected_build.kind, &profile);
-        match buildpkg::create_package(&chroot_path, &staging_dir_in_chroot, &output_dir, &recipe) {
+        let recipe = packaged_recipe(&package_name, &package_version, build_kind, &profile, source_recipe.as_ref());
+        let pack_opts = compress::PackOptions { file_mode: cfg.package_file_mode };
+        match buildpkg::create_package(sandbox_root, &staging_dir_in_chroot, &output_dir, &recipe, &pack_opts) {
             Ok(path) => {
                 pb_build.finish_with_message(format!("Packaged {} -> {}", package_name, path.display()).green().to_string());
-                success = true;
+                packaged = Some(path);
             }
             Err(e) => {
                 pb_build.finish_with_message(format!("Packaging failed: {}", e).red().to_string());
@@ -782,11 +1308,90 @@ fn build_and_package(
         pb_build.finish_with_message(format!("Build process for {} failed.", package_name).red().to_string());
     }
 
-    if let Err(e) = chroot_env.cleanup() {
-        eprintln!("{} {}", "Warning: Failed to cleanup chroot environment:".yellow(), e);
+    if let Err(e) = sandbox.cleanup() {
+        eprintln!("{} {}", "Warning: Failed to cleanup build environment:".yellow(), e);
     }
 
-    success
+    packaged
+}
+
+/// Clone `selected_repo` (with its submodules) and build and package it in the sandbox, as
+/// `buildins` does: with `recipe`, else the clone's own recipe file, else the detected build
+/// system. Returns the path of the new .nxpkg, or None when any step failed.
+#[allow(clippy::too_many_arguments)]
+async fn build_from_repo(
+    cfg: &AppConfig,
+    db: &PackageManagerDB,
+    selected_repo: &repo::RepoInfo,
+    package_name: &str,
+    version: Option<String>,
+    output_dir: PathBuf,
+    staging_dir_in_chroot: PathBuf,
+    profile: BuildProfile,
+    save_profile: bool,
+    ssh_fallback: bool,
+    confirm: bool,
+    recipe: Option<PackageRecipe>,
+    name_origin: NameOrigin,
+) -> Option<PathBuf> {
+    use std::process::Command;
+
+    let repo_name_only = selected_repo.name.split('/').next_back().unwrap_or(&selected_repo.name);
+    let pb_clone = Spinner::new("{spinner:.green} {elapsed_precise} {msg}");
+
+    let clone_path = format!("/tmp/{}", repo_name_only);
+
+    let _ = std::fs::remove_dir_all(&clone_path);
+
+    let mut clone_urls = vec![selected_repo.clone_url.clone()];
+    if ssh_fallback {
+        clone_urls.extend(selected_repo.ssh_clone_url());
+    }
+    if let Err(e) = clone_with_retry(&clone_urls, Path::new(&clone_path), &pb_clone).await {
+        pb_clone.finish_with_message(format!("Failed to clone {}.", selected_repo.name).red().to_string());
+        eprintln!("{}", e.trim_end());
+        return None;
+    }
+    pb_clone.finish_with_message(format!("Successfully cloned {}.", selected_repo.name).green().to_string());
+
+    let clone_path_obj = std::path::Path::new(&clone_path);
+    if clone_path_obj.join(".gitmodules").exists() {
+        let pb_submodule = Spinner::new("{spinner:.cyan} {elapsed_precise} {msg}");
+        pb_submodule.set_message("Initializing and updating submodules...");
+
+        let submodule_status = pb_submodule.suspend(|| {
+            Command::new("git")
+                .arg("submodule")
+                .arg("update")
+                .arg("--init")
+                .arg("--recursive")
+                .current_dir(&clone_path)
+                .status()
+        });
+
+        if !submodule_status.is_ok_and(|s| s.success()) {
+            pb_submodule.finish_with_message("Failed to update submodules.".red().to_string());
+            return None;
+        }
+        pb_submodule.finish_with_message("Submodules updated successfully.".green().to_string());
+    }
+    build_and_package(
+        Path::new(&clone_path),
+        repo_name_only,
+        &selected_repo.name,
+        package_name,
+        version,
+        output_dir,
+        staging_dir_in_chroot,
+        profile,
+        save_profile,
+        db,
+        cfg,
+        true,
+        confirm,
+        recipe.map_or(BuildRecipe::FromSource, |r| BuildRecipe::Given(Box::new(r))),
+        name_origin,
+    )
 }
 
 fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
@@ -797,7 +1402,7 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
 
     for entry in WalkDir::new(src).follow_links(false).into_iter().filter_map(Result::ok) {
         let rel = entry.path().strip_prefix(src).map_err(|_| {
-            io::Error::new(io::ErrorKind::Other, "failed to strip prefix")
+            io::Error::other("failed to strip prefix")
         })?;
         if rel.as_os_str().is_empty() {
             continue;
@@ -833,22 +1438,1125 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
             #[cfg(unix)]
             symlink(&target, &dest_path)?;
         } else {
-            return Err(io::Error::new(io::ErrorKind::Other, "unsupported file type in source tree"));
+            return Err(io::Error::other("unsupported file type in source tree"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Index URL for `install --repo <name>`, or the active repository when no remote is named.
+fn remote_repo_url(cfg: &AppConfig, repo: Option<&str>) -> Result<String, String> {
+    match repo {
+        Some(r) => cfg.repo_remotes.get(r).cloned().ok_or_else(|| format!("Unknown repo remote '{}'.", r)),
+        None => Ok(cfg.repo_url.clone()),
+    }
+}
+
+/// Print the resolver's account of a failed resolution (`install --explain`).
+fn print_resolution_trace(trace: &[String], arch: &str) {
+    let whose = if arch::canonical_arch(arch) == arch::current_arch() { "host" } else { "target" };
+    eprintln!("Resolution attempt ({} arch {}):", whose, arch);
+    for line in trace {
+        if line.trim_start().starts_with('✗') {
+            eprintln!("  {}", line.red());
+        } else {
+            eprintln!("  {}", line);
+        }
+    }
+}
+
+/// `show`: print a package's entry in a repository index, and the installed version if any.
+/// Falls back to the installed package's record when the index cannot be fetched or does not
+/// list the package; returns false when there is neither.
+async fn show_package(cfg: &AppConfig, db: &PackageManagerDB, name: &str, repo: Option<&str>, json: bool) -> bool {
+    let repo_url = match remote_repo_url(cfg, repo) {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("{}", e.red());
+            return false;
+        }
+    };
+    let installed = db.get_package_metadata(name).ok().flatten();
+    let index = match download::fetch_index_for(cfg, &repo_url).await {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{}", format!("Failed to fetch repository index: {}", e).red());
+            return installed.is_some_and(|r| show_installed_package(&r, json));
+        }
+    };
+    let Some(entry) = index.packages.get(name) else {
+        eprintln!("{}", format!("'{}' is not available in the repository at {}.", name, repo_url).red());
+        return installed.is_some_and(|r| show_installed_package(&r, json));
+    };
+    let deps_bypassed = installed.as_ref().is_some_and(|r| r.install.deps_bypassed);
+    let installed_version = installed.map(|r| r.package.version);
+
+    if json {
+        let mut out = serde_json::to_value(entry).unwrap_or_default();
+        if let Some(fields) = out.as_object_mut() {
+            fields.insert("name".to_string(), name.into());
+            fields.insert("repo".to_string(), repo_url.into());
+            fields.insert("installed_version".to_string(), installed_version.into());
+            fields.insert("deps_bypassed".to_string(), deps_bypassed.into());
+        }
+        println!("{}", serde_json::to_string_pretty(&out).unwrap_or_default());
+        return true;
+    }
+
+    let print_assets = |assets: &std::collections::HashMap<String, download::ArchAsset>, indent: &str| {
+        let mut keys: Vec<&String> = assets.keys().collect();
+        keys.sort();
+        for key in keys {
+            let asset = &assets[key];
+            println!("{}{} {}", indent, key.cyan(), asset.download_url);
+            println!(
+                "{}  sha256: {}  size: {}",
+                indent,
+                asset.sha256.as_deref().unwrap_or("none"),
+                asset.size.map_or("unknown".to_string(), install::format_size),
+            );
+        }
+    };
+    let print_deps = |deps: &[String], arch_deps: &std::collections::HashMap<String, Vec<String>>, indent: &str| {
+        if !deps.is_empty() {
+            println!("{}Dependencies: {}", indent, deps.join(", "));
+        }
+        let mut arches: Vec<&String> = arch_deps.keys().collect();
+        arches.sort();
+        for arch in arches {
+            println!("{}Dependencies on {}: {}", indent, arch, arch_deps[arch].join(", "));
+        }
+    };
+
+    println!("{} {}", name.bold().cyan(), entry.latest_version);
+    println!("Repository: {}", repo_url);
+    if let Some(v) = &installed_version {
+        println!("Installed: {}{}", v, deps_bypassed_note(deps_bypassed));
+    }
+    if !entry.description.is_empty() {
+        println!("Description: {}", entry.description);
+    }
+    print_deps(&entry.dependencies, &entry.arch_dependencies, "");
+    if let Some(min) = &entry.min_nxpkg_version {
+        println!("Requires nxpkg: {} or newer", min);
+    }
+    if let Some(url) = &entry.download_url {
+        println!("Download URL: {}", url);
+        println!(
+            "  sha256: {}  size: {}",
+            entry.sha256.as_deref().unwrap_or("none"),
+            entry.size.map_or("unknown".to_string(), install::format_size),
+        );
+    }
+    if let Some(assets) = entry.architectures.as_ref().filter(|a| !a.is_empty()) {
+        println!("Assets:");
+        print_assets(assets, "  ");
+    }
+    if !entry.versions.is_empty() {
+        let mut versions: Vec<&String> = entry.versions.keys().collect();
+        versions.sort_by(|a, b| version::compare(b, a));
+        println!("Versions:");
+        for v in versions {
+            let assets = &entry.versions[v];
+            let latest = if *v == entry.latest_version { " (latest)" } else { "" };
+            println!("  {}{}", v.bold(), latest);
+            print_deps(&assets.dependencies, &assets.arch_dependencies, "    ");
+            if let Some(min) = &assets.min_nxpkg_version {
+                println!("    Requires nxpkg: {} or newer", min);
+            }
+            print_assets(&assets.architectures, "    ");
+        }
+    }
+    true
+}
+
+/// `show` for an installed package the repository cannot describe (offline, or no longer
+/// published): what the package database recorded at install time. Always returns true.
+fn show_installed_package(recipe: &PackageRecipe, json: bool) -> bool {
+    if json {
+        let out = serde_json::json!({
+            "name": recipe.package.name,
+            "installed_version": recipe.package.version,
+            "deps_bypassed": recipe.install.deps_bypassed,
+            "description": recipe.package.description,
+            "dependencies": recipe.build.dependencies,
+        });
+        println!("{}", serde_json::to_string_pretty(&out).unwrap_or_default());
+        return true;
+    }
+    eprintln!("Showing the installed package instead.");
+    println!("{} {}", recipe.package.name.bold().cyan(), recipe.package.version);
+    println!("Installed: {}{}", recipe.package.version, deps_bypassed_note(recipe.install.deps_bypassed));
+    if !recipe.package.description.is_empty() {
+        println!("Description: {}", recipe.package.description);
+    }
+    if !recipe.build.dependencies.is_empty() {
+        println!("Dependencies: {}", recipe.build.dependencies.join(", "));
+    }
+    true
+}
+
+/// Suffix for packages installed with `--no-deps` while a dependency was unsatisfied.
+fn deps_bypassed_note(deps_bypassed: bool) -> String {
+    if deps_bypassed {
+        format!(" {}", "(dependencies bypassed with --no-deps)".yellow())
+    } else {
+        String::new()
+    }
+}
+
+/// `list`: print installed packages with their versions, one per line.
+fn list_installed(db: &PackageManagerDB) -> bool {
+    let names = match db.installed_package_names() {
+        Ok(names) => names,
+        Err(e) => {
+            eprintln!("{}", format!("Failed to read the package database: {}", e).red());
+            return false;
+        }
+    };
+    if names.is_empty() {
+        println!("{}", "No packages are installed.".yellow());
+    }
+    for recipe in names.iter().filter_map(|n| db.get_package_metadata(n).ok().flatten()) {
+        println!(
+            "{} {}{}",
+            recipe.package.name.bold().cyan(),
+            recipe.package.version,
+            deps_bypassed_note(recipe.install.deps_bypassed)
+        );
+    }
+    true
+}
+
+/// `repo-remote rollback-index`: list the index snapshots recorded for a repository, or
+/// publish one of them again in place of the current index.
+async fn rollback_index(cfg: &AppConfig, serial: Option<u64>, list: bool, repo: Option<&str>, token: Option<&str>, yes: bool) -> bool {
+    if cfg.index_history == 0 {
+        eprintln!("{}", "Index snapshots are disabled; set [repo] index_history to the number to keep.".red());
+        return false;
+    }
+    let repo_url = match remote_repo_url(cfg, repo) {
+        Ok(u) if !u.trim().is_empty() => u,
+        Ok(_) => {
+            eprintln!("{}", "No repository configured.".red());
+            return false;
+        }
+        Err(e) => {
+            eprintln!("{}", e.red());
+            return false;
+        }
+    };
+    let dir = download::index_history_dir(cfg, &repo_url);
+    let Some(serial) = serial.filter(|_| !list) else {
+        let serials = db::history::list_snapshots(&dir);
+        if serials.is_empty() {
+            println!("No index snapshots recorded for {}.", repo_url);
+        }
+        for n in serials.iter().rev() {
+            let (bytes, sig) = db::history::load(&dir, *n).unwrap_or_default();
+            let packages = serde_json::from_slice::<download::RepoIndex>(&bytes).map_or(0, |i| i.packages.len());
+            let signed = if sig.is_some() { "signed" } else { "unsigned" };
+            println!("{:>4}  {} package(s), {}, {}", n, packages, install::format_size(bytes.len() as u64), signed);
+        }
+        return true;
+    };
+    let (bytes, sig) = match db::history::load(&dir, serial) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e.red());
+            return false;
+        }
+    };
+    if sig.is_none() {
+        eprintln!("{} snapshot {} is unsigned; any signature at {} is left as is and will not match.", "Warning:".yellow(), serial, cfg.sig_path);
+    }
+    let question = format!("index.json at {} will be replaced by snapshot {}.", repo_url, serial);
+    if !yes {
+        if !io::stdin().is_terminal() {
+            eprintln!("{} Re-run with --yes to confirm.", question);
+            return false;
+        }
+        print!("{} Continue? [y/N] ", question);
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).is_err() || !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+            println!("Nothing was published.");
+            return false;
+        }
+    }
+    match upload::republish_index(&repo_url, &cfg.sig_path, token, bytes, sig, Some((dir.as_path(), cfg.index_history))).await {
+        Ok(()) => {
+            println!("{}", format!("Published index snapshot {} to {}.", serial, repo_url).green());
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", format!("Failed to publish snapshot {}: {}", serial, e).red());
+            false
+        }
+    }
+}
+
+/// `repo-remote verify-repo`: re-download the repository's assets and report any whose
+/// checksum does not match index.json. Returns false on any discrepancy.
+async fn verify_repo(cfg: &AppConfig, repo: Option<&str>, arch: Option<&str>) -> bool {
+    let repo_url = match remote_repo_url(cfg, repo) {
+        Ok(u) if !u.trim().is_empty() => u,
+        Ok(_) => {
+            eprintln!("{}", "No repository configured.".red());
+            return false;
+        }
+        Err(e) => {
+            eprintln!("{}", e.red());
+            return false;
+        }
+    };
+    // Audit the live index: no fallback to the cached copy
+    let index = match download::fetch_index_with(
+        &repo_url,
+        &cfg.sig_path,
+        Some(&cfg.pubkey_path),
+        cfg.require_signed_index,
+        cfg.lenient_index,
+        cfg.canonical_signing,
+    ).await {
+        Ok(idx) => idx,
+        Err(e) => {
+            eprintln!("{}", format!("Failed to fetch index from {}: {}", repo_url, e).red());
+            return false;
+        }
+    };
+
+    let checks = verify::verify_index_assets(&index, arch).await;
+    for check in &checks {
+        let label = match &check.arch {
+            Some(a) => format!("{} [{}]", check.package, a),
+            None => check.package.clone(),
+        };
+        match &check.status {
+            AssetStatus::Ok => {}
+            AssetStatus::Unverified => println!("{} {}: no sha256 recorded ({})", "UNVERIFIED".yellow(), label, check.url),
+            AssetStatus::Mismatch { expected, actual } => {
+                println!("{} {}: expected {}, got {} ({})", "MISMATCH".red(), label, expected, actual, check.url)
+            }
+            AssetStatus::Missing => println!("{} {}: {}", "MISSING".red(), label, check.url),
+            AssetStatus::Error(e) => println!("{} {}: {} ({})", "ERROR".red(), label, e, check.url),
+        }
+    }
+    let failed = checks.iter().filter(|c| c.status.is_failure()).count();
+    let ok = checks.iter().filter(|c| c.status == AssetStatus::Ok).count();
+    let unverified = checks.len() - ok - failed;
+    let summary = format!(
+        "{} asset(s) checked: {} ok, {} failed, {} without checksum.",
+        checks.len(), ok, failed, unverified
+    );
+    if failed > 0 { println!("{}", summary.red()); } else { println!("{}", summary.green()); }
+    failed == 0
+}
+
+/// A step of `upgrade --all` that has been applied, kept so it can be undone.
+enum AppliedStep<'a> {
+    /// A dependency that was not installed before; undone by removing it
+    Installed(String),
+    /// An upgrade; undone by reinstalling `previous` from `previous_pkg`
+    Upgraded { previous: &'a PackageRecipe, previous_pkg: Option<PathBuf> },
+}
+
+/// The `installed` packages whose latest version in `index` is newer, in the given order.
+fn outdated_packages(index: &download::RepoIndex, installed: Vec<PackageRecipe>) -> Vec<PackageRecipe> {
+    installed
+        .into_iter()
+        .filter(|recipe| {
+            index.packages.get(&recipe.package.name)
+                .is_some_and(|e| version::compare(&e.latest_version, &recipe.package.version) == std::cmp::Ordering::Greater)
+        })
+        .collect()
+}
+
+/// `upgrade --all`: upgrade every installed package the repository has a newer version of.
+/// Everything (new versions, new dependencies and the current versions, for rollback) is
+/// downloaded before anything is changed. When a step fails, the steps already applied are
+/// undone in reverse order. Returns false if the batch failed.
+async fn upgrade_all(cfg: &AppConfig, db: &PackageManagerDB, dry_run: bool) -> bool {
+    let pb = Spinner::new("{spinner:.blue} {elapsed_precise} {msg}");
+    pb.set_message("Fetching repository index...");
+    let index = match download::fetch_index_for(cfg, &cfg.repo_url).await {
+        Ok(i) => i,
+        Err(e) => {
+            pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
+            return false;
+        }
+    };
+
+    let installed = db.installed_package_names()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|name| db.get_package_metadata(name).ok().flatten())
+        .collect();
+    let outdated = outdated_packages(&index, installed);
+    if outdated.is_empty() {
+        pb.finish_with_message("All installed packages are up to date.".green().to_string());
+        return true;
+    }
+
+    pb.set_message("Resolving dependencies...");
+    let names: Vec<&str> = outdated.iter().map(|r| r.package.name.as_str()).collect();
+    let installed_version = |n: &str| db.get_package_metadata(n).ok().flatten().map(|r| r.package.version);
+    let (plan, errors) = resolve::resolve_batch(&index, &names, &cfg.arch, &installed_version);
+    if !errors.is_empty() {
+        pb.finish_and_clear();
+        for (i, e) in errors {
+            eprintln!("{} {}", format!("Cannot upgrade '{}':", names[i]).red(), e);
+        }
+        eprintln!("Nothing was upgraded.");
+        return false;
+    }
+    let previous_of = |name: &str| outdated.iter().find(|r| r.package.name == name);
+
+    if dry_run {
+        pb.finish_and_clear();
+        for pkg in &plan {
+            match previous_of(&pkg.name) {
+                Some(old) => println!("  {} {} -> {}", pkg.name.cyan(), old.package.version, pkg.version),
+                None => println!("  {} {} (new dependency)", pkg.name.cyan(), pkg.version),
+            }
+        }
+        return true;
+    }
+
+    // Stage everything first: the new packages, and the installed versions to roll back to
+    let mut rollback_plan = Vec::new();
+    let mut missing_rollback = Vec::new();
+    for old in &outdated {
+        let current = format!("{}@{}", old.package.name, old.package.version);
+        match resolve::resolve_single(&index, &current, &cfg.arch) {
+            Ok(p) => rollback_plan.push(p),
+            Err(_) => {
+                let cached = cfg.cache_dir.join(format!("{}-{}.nxpkg", old.package.name, old.package.version));
+                if !cached.is_file() {
+                    missing_rollback.push(current);
+                }
+            }
+        }
+    }
+    pb.finish_and_clear();
+    let staged = match install::download_all_to_cache(cfg, &plan, Some(std::sync::Arc::new(DownloadBars::concurrent()))).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{} {}", "Download failed, nothing was upgraded:".red(), e);
+            return false;
+        }
+    };
+    if let Err(e) = install::download_all_to_cache(cfg, &rollback_plan, Some(std::sync::Arc::new(DownloadBars::concurrent()))).await {
+        eprintln!("{} could not download the installed versions for rollback: {}", "Warning:".yellow(), e);
+    }
+    if !missing_rollback.is_empty() {
+        eprintln!(
+            "{} no package to roll back to for {}; they stay at the new version if the batch fails.",
+            "Warning:".yellow(), missing_rollback.join(", ")
+        );
+    }
+
+    pb.reset();
+    let mut applied: Vec<AppliedStep> = Vec::new();
+    let mut failure = None;
+    for (pkg, cached) in plan.iter().zip(&staged) {
+        let Some(previous) = previous_of(&pkg.name) else {
+            match install::install_from_source(cfg, db, &PackageSource::Local(cached.path.clone()), &InstallOptions::default(), &pb).await {
+                Ok(InstallOutcome::Installed(mut r)) => {
+                    // Installed from the staged file, so record the index description here
+                    r.package.description = pkg.description.clone();
+                    if let Err(e) = db.save_package_metadata(&r) {
+                        pb.println(format!("Warning: could not record the description of '{}': {}", r.package.name, e));
+                    }
+                    pb.println(format!("Installed dependency '{}' v{}.", r.package.name, r.package.version));
+                    applied.push(AppliedStep::Installed(r.package.name));
+                }
+                Ok(InstallOutcome::AlreadyInstalled(_)) => {}
+                Err(e) => {
+                    failure = Some(format!("'{}': {}", pkg.name, e));
+                    break;
+                }
+            }
+            continue;
+        };
+        let previous_pkg = Some(cfg.cache_dir.join(format!("{}-{}.nxpkg", pkg.name, previous.package.version)))
+            .filter(|p| p.is_file());
+        let step = AppliedStep::Upgraded { previous, previous_pkg };
+        match upgrade_package_file(cfg, db, &cached.path, previous, Some(&pkg.description), &pb) {
+            Ok(r) => {
+                pb.println(format!("Upgraded '{}' {} -> {}.", pkg.name, previous.package.version, r.package.version).green().to_string());
+                applied.push(step);
+            }
+            Err(e) => {
+                // The failed upgrade may have extracted part of the new version; restore it too
+                applied.push(step);
+                failure = Some(format!("'{}': {}", pkg.name, e));
+                break;
+            }
+        }
+    }
+
+    let Some(failure) = failure else {
+        pb.finish_with_message(format!("Upgraded {} package(s).", outdated.len()).green().to_string());
+        return true;
+    };
+    pb.println(format!("Upgrade failed for {}", failure).red().to_string());
+    pb.set_message("Rolling back...");
+    let mut rolled_back = Vec::new();
+    let mut not_rolled_back = Vec::new();
+    for step in applied.into_iter().rev() {
+        match step {
+            AppliedStep::Installed(name) => match db.rem_package_metadata(&name, true, &cfg.root, &[]) {
+                Ok(()) => rolled_back.push(format!("{} (removed)", name)),
+                Err(e) => not_rolled_back.push(format!("{} ({})", name, e)),
+            },
+            AppliedStep::Upgraded { previous, previous_pkg: None } => {
+                not_rolled_back.push(format!("{} (no package for v{})", previous.package.name, previous.package.version));
+            }
+            AppliedStep::Upgraded { previous, previous_pkg: Some(path) } => {
+                let name = &previous.package.name;
+                let result = match db.get_package_metadata(name) {
+                    Ok(Some(current)) => upgrade_package_file(cfg, db, &path, &current, Some(&previous.package.description), &pb),
+                    _ => Err(format!("'{}' is no longer recorded", name).into()),
+                };
+                match result {
+                    Ok(_) => rolled_back.push(format!("{} {}", name, previous.package.version)),
+                    Err(e) => not_rolled_back.push(format!("{} ({})", name, e)),
+                }
+            }
+        }
+    }
+    pb.finish_and_clear();
+    if !rolled_back.is_empty() {
+        println!("Rolled back: {}", rolled_back.join(", "));
+    }
+    if !not_rolled_back.is_empty() {
+        eprintln!("{} {}", "Could not roll back:".red(), not_rolled_back.join(", "));
+    }
+    false
+}
+
+/// Replace `installed` with the package at `nxpkg_path`, removing files the new package no
+/// longer ships. `description` is the index's (None keeps the installed one).
+fn upgrade_package_file(
+    cfg: &AppConfig,
+    db: &PackageManagerDB,
+    nxpkg_path: &Path,
+    installed: &PackageRecipe,
+    description: Option<&str>,
+    pb: &Spinner,
+) -> Result<PackageRecipe, Box<dyn std::error::Error>> {
+    let extract_opts = compress::ExtractOptions { prefix_map: cfg.prefix_map.clone(), arch: Some(cfg.arch.clone()), ..Default::default() };
+    let (_, manifest) = compress::read_manifest(nxpkg_path, &extract_opts)?;
+    let changes = diff::compute(installed, &manifest, &cfg.root);
+    install::upgrade_from_file(cfg, db, nxpkg_path, installed, description, &changes, pb)
+}
+
+/// `reinstall-all`: download each installed package at its installed version and install it
+/// over itself, like an upgrade to the same version. A package that cannot be downloaded or
+/// reinstalled is reported and the rest continue. Returns false if any failed.
+async fn reinstall_all(cfg: &AppConfig, db: &PackageManagerDB) -> bool {
+    let pb = Spinner::new("{spinner:.blue} {elapsed_precise} {msg}");
+    pb.set_message("Fetching repository index...");
+    let index = match download::fetch_index_for(cfg, &cfg.repo_url).await {
+        Ok(i) => i,
+        Err(e) => {
+            pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
+            return false;
+        }
+    };
+
+    let names = db.installed_package_names().unwrap_or_default();
+    let mut reinstalled = 0;
+    let mut failures: Vec<String> = Vec::new();
+    for name in &names {
+        let recipe = match db.get_package_metadata(name) {
+            Ok(Some(r)) => r,
+            Ok(None) => continue,
+            Err(e) => {
+                failures.push(format!("{}: {}", name, e));
+                continue;
+            }
+        };
+        let spec = format!("{}@{}", name, recipe.package.version);
+        pb.set_message(format!("Reinstalling {}...", spec));
+        let planned = match resolve::resolve_single(&index, &spec, &cfg.arch) {
+            Ok(p) => p,
+            Err(e) => {
+                pb.println(format!("Cannot reinstall '{}': {}", spec, e).red().to_string());
+                failures.push(format!("{}: {}", spec, e));
+                continue;
+            }
+        };
+        let result = match install::download_all_to_cache(cfg, std::slice::from_ref(&planned), Some(std::sync::Arc::new(DownloadBars::concurrent()))).await {
+            Ok(cached) => upgrade_package_file(cfg, db, &cached[0].path, &recipe, Some(&planned.description), &pb),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => {
+                pb.println(format!("Reinstalled '{}' v{}.", name, recipe.package.version).green().to_string());
+                reinstalled += 1;
+            }
+            Err(e) => {
+                pb.println(format!("Cannot reinstall '{}': {}", spec, e).red().to_string());
+                failures.push(format!("{}: {}", spec, e));
+            }
+        }
+    }
+    pb.finish_and_clear();
+
+    println!("Reinstalled {} of {} package(s).", reinstalled, names.len());
+    if failures.is_empty() {
+        return true;
+    }
+    eprintln!("{}", format!("{} package(s) failed:", failures.len()).red());
+    for failure in failures {
+        eprintln!("  {}", failure);
+    }
+    false
+}
+
+/// `install --from-file`: install every package in the list as one resolved batch.
+/// Lines that fail are reported without aborting the others; returns false if any failed.
+async fn install_from_list(
+    cfg: &AppConfig,
+    db: &PackageManagerDB,
+    list_path: &Path,
+    repo: Option<&str>,
+    install_opts: &InstallOptions,
+    pb: &Spinner,
+) -> bool {
+    let list = match install::list::read_package_list(list_path) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            pb.finish_with_message(e.red().to_string());
+            return false;
+        }
+    };
+    // (line, message); line is None for dependencies pulled in by the list
+    let mut failures: Vec<(Option<usize>, String)> = list.errors.into_iter().map(|(l, e)| (Some(l), e)).collect();
+
+    let repo_url = match remote_repo_url(cfg, repo) {
+        Ok(url) => url,
+        Err(e) => {
+            pb.finish_with_message(e.red().to_string());
+            return false;
+        }
+    };
+    pb.set_message("Fetching repository index...");
+    let index = match download::fetch_index_for(cfg, &repo_url).await {
+        Ok(i) => i,
+        Err(e) => {
+            pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
+            return false;
+        }
+    };
+
+    let installed_version = |n: &str| db.get_package_metadata(n).ok().flatten().map(|r| r.package.version);
+    let mut roots = Vec::new();
+    for entry in &list.entries {
+        match (installed_version(&entry.name), entry.version.as_deref()) {
+            (Some(v), Some(want)) if v != want => failures.push((
+                Some(entry.line),
+                format!("'{}' v{} is installed, not the requested {}.", entry.name, v, want),
+            )),
+            (Some(v), _) => pb.println(format!("'{}' v{} is already installed.", entry.name, v).yellow().to_string()),
+            (None, _) => roots.push(entry),
+        }
+    }
+
+    pb.set_message("Resolving dependencies...");
+    // Requested versions are resolved as pins (`name@version`) against the index history
+    let specs: Vec<String> = roots.iter()
+        .map(|e| match &e.version {
+            Some(v) => format!("{}@{}", e.name, v),
+            None => e.name.clone(),
+        })
+        .collect();
+    let names: Vec<&str> = specs.iter().map(String::as_str).collect();
+    let (plan, resolve_errors) = if install_opts.no_deps {
+        let mut plan: Vec<resolve::PlannedPackage> = Vec::new();
+        let mut errors = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            match resolve::resolve_single(&index, name, &cfg.arch) {
+                Ok(p) => match plan.iter().find(|q| q.name == p.name) {
+                    None => plan.push(p),
+                    Some(q) if q.version != p.version => {
+                        errors.push((i, format!("'{}' {} is already selected, not the requested {}.", p.name, q.version, p.version)));
+                    }
+                    Some(_) => {}
+                },
+                Err(e) => errors.push((i, e)),
+            }
+        }
+        (plan, errors)
+    } else {
+        resolve::resolve_batch(&index, &names, &cfg.arch, &installed_version)
+    };
+    failures.extend(resolve_errors.into_iter().map(|(i, e)| (Some(roots[i].line), e)));
+
+    let mut installed = Vec::new();
+    for pkg in plan {
+        let name = pkg.name.clone();
+        match install::install_from_source(cfg, db, &PackageSource::Remote(pkg), install_opts, pb).await {
+            Ok(InstallOutcome::Installed(recipe)) => {
+                pb.println(format!("Successfully installed '{}' v{}.", recipe.package.name, recipe.package.version).green().to_string());
+                installed.push(recipe);
+            }
+            Ok(InstallOutcome::AlreadyInstalled(recipe)) => {
+                pb.println(format!("'{}' v{} is already installed.", recipe.package.name, recipe.package.version).yellow().to_string());
+            }
+            Err(e) => {
+                let line = roots.iter().find(|r| r.name == name).map(|r| r.line);
+                failures.push((line, e.to_string()));
+            }
+        }
+    }
+    pb.finish_and_clear();
+    if cfg.check_links {
+        warn_dangling_links(cfg, &installed);
+    }
+
+    println!("Installed {} package(s) from {}.", installed.len(), list_path.display());
+    if failures.is_empty() {
+        return true;
+    }
+    failures.sort_by_key(|(line, _)| line.unwrap_or(usize::MAX));
+    eprintln!("{}", format!("{} entr{} failed:", failures.len(), if failures.len() == 1 { "y" } else { "ies" }).red());
+    for (line, msg) in failures {
+        match line {
+            Some(l) => eprintln!("  line {}: {}", l, msg),
+            None => eprintln!("  dependency: {}", msg),
+        }
+    }
+    false
+}
+
+/// `install --prefer-source` (or `[install] source_fallback`) for a package the index has no
+/// binary asset of for `cfg.arch`: build it from its source repository (the index entry's
+/// `source_repo`, else the repos.cfg entry of the same name) and return what to install, the
+/// dependencies listed in the index first. Returns None after saying why that is not possible.
+async fn plan_source_build(
+    cfg: &AppConfig,
+    db: &PackageManagerDB,
+    index: &download::RepoIndex,
+    name: &str,
+    no_deps: bool,
+    yes: bool,
+) -> Option<Vec<PackageSource>> {
+    let entry = index.packages.get(name)?;
+    if arch::canonical_arch(&cfg.arch) != arch::host_arch() {
+        eprintln!(
+            "{}",
+            format!("'{}' has no binary asset for {}, and a source build here would produce {} packages.", name, cfg.arch, arch::host_arch()).red()
+        );
+        return None;
+    }
+    let source = match &entry.source_repo {
+        Some(url) => repo::RepoInfo {
+            name: name.to_string(),
+            owner: String::new(),
+            clone_url: url.clone(),
+            source: "index".to_string(),
+            group: None,
+        },
+        None => match repo::configured_repo_named(name) {
+            Some(r) => r,
+            None => {
+                eprintln!(
+                    "{}",
+                    format!("'{}' has no binary asset for {} and no known source repository (no source_repo in the index, no repos.cfg entry named '{}').", name, cfg.arch, name).red()
+                );
+                return None;
+            }
+        },
+    };
+
+    // Resolve the binary dependencies first, so an unsatisfiable one fails before building
+    let deps: Vec<Dependency> = buildins::meta::merge_arch_dependencies(&entry.dependencies, &entry.arch_dependencies, &cfg.arch)
+        .iter()
+        .filter_map(|d| Dependency::parse(d).ok())
+        .collect();
+    let installed_version = |n: &str| db.get_package_metadata(n).ok().flatten().map(|r| r.package.version);
+    let dep_plan = if no_deps {
+        Vec::new()
+    } else {
+        match resolve::resolve_dependencies(index, name, &deps, &cfg.arch, &installed_version) {
+            Ok(plan) => plan,
+            Err(e) => {
+                eprintln!("{}", format!("Cannot install '{}': {}", name, e).red());
+                return None;
+            }
+        }
+    };
+
+    println!(
+        "No binary asset for '{}' on {}; building it from {} ({}).",
+        name.cyan(), cfg.arch, source.name, source.clone_url
+    );
+    let profile = load_build_profile(db, name, false, None, Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    if !check_build_dependencies(cfg, db, &profile, false).await {
+        return None;
+    }
+    let output_dir = match resolve_output_dir(Some(cfg.cache_dir.join("source-builds").to_string_lossy().into_owned())) {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("{} {}", "Invalid output dir:".red(), e);
+            return None;
+        }
+    };
+    let staging_dir = resolve_staging_dir(None).ok()?;
+    let Some(built) = build_from_repo(cfg, db, &source, name, None, output_dir, staging_dir, profile, false, false, !yes, None, NameOrigin::Given).await else {
+        eprintln!("{}", format!("Building '{}' from {} failed; nothing was installed.", name, source.clone_url).red());
+        return None;
+    };
+
+    let mut sources: Vec<PackageSource> = dep_plan.into_iter().map(PackageSource::Remote).collect();
+    sources.push(PackageSource::Local(built));
+    Some(sources)
+}
+
+/// `install --check-links`: warn about symlinks of the packages just installed that point
+/// at nothing, typically a packaging error or a dependency that is missing.
+fn warn_dangling_links(cfg: &AppConfig, installed: &[PackageRecipe]) {
+    for recipe in installed {
+        let dangling = install::dangling_symlinks(&cfg.root, recipe);
+        if dangling.is_empty() {
+            continue;
+        }
+        eprintln!("{} '{}' installed {} broken symlink(s):", "Warning:".yellow(), recipe.package.name, dangling.len());
+        for (link, target) in dangling {
+            eprintln!("  {} -> {} (missing)", link, target.display());
+        }
+    }
+}
+
+/// `closure`: resolve `spec` and everything it depends on as if nothing were installed, and
+/// print the packages in install order (dependencies first); with `download_only` they are
+/// fetched into the cache too. Returns false when resolution or a download fails.
+async fn print_closure(cfg: &AppConfig, spec: &str, repo: Option<&str>, download_only: bool, json: bool) -> bool {
+    let repo_url = match remote_repo_url(cfg, repo) {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("{}", e.red());
+            return false;
+        }
+    };
+    let pb = Spinner::new("{spinner:.blue} {elapsed_precise} {msg}");
+    pb.set_message("Fetching repository index...");
+    let index = match download::fetch_index_for(cfg, &repo_url).await {
+        Ok(i) => i,
+        Err(e) => {
+            pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
+            return false;
+        }
+    };
+    pb.set_message("Resolving dependencies...");
+    // The closure is for another system, so what is installed here does not count
+    let plan = match resolve::resolve(&index, spec, &cfg.arch, &|_| None) {
+        Ok(plan) => plan,
+        Err(e) => {
+            pb.finish_with_message(format!("Cannot resolve '{}': {}", spec, e).red().to_string());
+            return false;
+        }
+    };
+    pb.finish_and_clear();
+    let arch = arch::canonical_arch(&cfg.arch);
+
+    let cached = if download_only {
+        match install::download_all_to_cache(cfg, &plan, Some(std::sync::Arc::new(DownloadBars::concurrent()))).await {
+            Ok(cached) => Some(cached),
+            Err(e) => {
+                eprintln!("{}", e.to_string().red());
+                return false;
+            }
+        }
+    } else {
+        None
+    };
+    let cached_at = |i: usize| cached.as_ref().and_then(|c| c.get(i));
+
+    if json {
+        let packages: Vec<serde_json::Value> = plan
+            .iter()
+            .enumerate()
+            .map(|(i, pkg)| {
+                let mut out = serde_json::json!({
+                    "name": pkg.name,
+                    "version": pkg.version,
+                    "url": pkg.download_url,
+                    "sha256": cached_at(i).map(|c| c.sha256.clone()).or_else(|| pkg.sha256.clone()),
+                    "size": pkg.size,
+                });
+                if let (Some(fields), Some(c)) = (out.as_object_mut(), cached_at(i)) {
+                    fields.insert("path".to_string(), c.path.display().to_string().into());
+                }
+                out
+            })
+            .collect();
+        let out = serde_json::json!({
+            "package": spec,
+            "arch": arch,
+            "repo": repo_url,
+            "packages": packages,
+        });
+        println!("{}", serde_json::to_string_pretty(&out).unwrap_or_default());
+        return true;
+    }
+
+    println!("{} package(s) needed for '{}' on {}:", plan.len(), spec, arch);
+    for (i, pkg) in plan.iter().enumerate() {
+        println!("  {} {}", pkg.name.cyan(), pkg.version);
+        println!("    {}", pkg.download_url.dimmed());
+        match cached_at(i) {
+            Some(c) => println!("    {} {}", c.path.display(), format!("(sha256 {})", c.sha256).dimmed()),
+            None => println!("    {}", format!("sha256 {}", pkg.sha256.as_deref().unwrap_or("none")).dimmed()),
+        }
+    }
+    true
+}
+
+/// `search --all-repos`: search every configured remote's index. A name and version served by
+/// several remotes is listed once with all of them; other versions of the same name get lines
+/// of their own. Returns false when no remote's index could be fetched.
+async fn search_all_remotes(cfg: &AppConfig, term: &str, porcelain: bool) -> bool {
+    let pb = Spinner::new("{spinner:.blue} {elapsed_precise} {msg}");
+    pb.set_message(format!("Fetching the indexes of {} remote(s)...", cfg.repo_remotes.len()));
+    let indexes = download::fetch_remote_indexes(cfg).await;
+    pb.finish_and_clear();
+    if indexes.is_empty() {
+        eprintln!("{}", "Failed to fetch the index of any configured remote.".red());
+        return false;
+    }
+
+    let term = term.to_lowercase();
+    // (name, version) -> (description, remotes serving it)
+    let mut matches: std::collections::BTreeMap<(String, String), (String, Vec<String>)> = Default::default();
+    for (remote, index) in &indexes {
+        for (name, entry) in &index.packages {
+            if !name.to_lowercase().contains(&term) && !entry.description.to_lowercase().contains(&term) {
+                continue;
+            }
+            let (_, remotes) = matches
+                .entry((name.clone(), entry.latest_version.clone()))
+                .or_insert_with(|| (entry.description.clone(), Vec::new()));
+            remotes.push(remote.clone());
+        }
+    }
+    let mut results: Vec<_> = matches.into_iter().collect();
+    results.sort_by(|((a, av), _), ((b, bv), _)| a.cmp(b).then_with(|| version::compare(bv, av)));
+
+    if porcelain {
+        for ((name, version), (description, remotes)) in results {
+            println!("{}\t{}\t{}\t{}", name, version, porcelain_field(&description), remotes.join(","));
+        }
+    } else if results.is_empty() {
+        println!("{}", "No packages found matching your search term.".yellow());
+    } else {
+        println!("Found {} package version(s) in {} remote(s):", results.len(), indexes.len());
+        for ((name, version), (description, remotes)) in results {
+            println!(
+                "  {} {} - {} {}",
+                name.bold().cyan(),
+                version.dimmed(),
+                description,
+                format!("[{}]", remotes.join(", ")).green()
+            );
+        }
+    }
+    true
+}
+
+/// `remove`/`purge`: delete the package's files and database entry, or with `keep_files`
+/// only the entry, leaving the files unmanaged. `remove` leaves configuration files that were
+/// changed since install in place; `purge` deletes them too.
+fn remove_package(cfg: &AppConfig, db: &PackageManagerDB, name: &str, keep_files: bool, purge: bool) {
+    let pb = Spinner::new("{spinner:.blue} {msg}");
+    pb.set_message(format!("Removing {}...", name));
+    let Ok(Some(recipe)) = db.get_package_metadata(name) else {
+        pb.finish_with_message(format!("{} package is not found.", name).red().to_string());
+        return;
+    };
+    let kept_config = if purge { Vec::new() } else { diff::modified_conffiles(&recipe, &cfg.root) };
+    let removed = db.rem_package_metadata(name, !keep_files, &cfg.root, &kept_config).map_err(|e| e.to_string());
+    if removed.is_ok() && !keep_files && !kept_config.is_empty() {
+        pb.println(format!("Kept {} modified configuration file(s) of {} (purge deletes them):", kept_config.len(), name));
+        for path in &kept_config {
+            pb.println(format!("  {}", compress::path_in_root(&cfg.root, Path::new(path)).display()));
+        }
+    }
+    let action = if keep_files { "deregister" } else { "remove" };
+    audit::record(cfg, &audit::AuditEvent::new(action, name, Some(&recipe.package.version), removed.clone()));
+    match removed {
+        Ok(()) if keep_files => pb.finish_with_message(format!(
+            "{} is no longer managed by nxpkg; its {} file(s) were left in place.",
+            name,
+            recipe.install.installed_files.len()
+        ).green().to_string()),
+        Ok(()) => pb.finish_with_message(format!("{} package is {}.", name, if purge { "purged" } else { "removed" }).green().to_string()),
+        Err(e) => pb.finish_with_message(format!("Failed to remove {}: {}", name, e).red().to_string()),
+    }
+}
+
+/// Commands that write to the package database; a backup is taken before they run
+/// (`[storage] db_backups`).
+fn mutates_db(command: &Commands) -> bool {
+    match command {
+        Commands::Install { download_only, .. } => !download_only,
+        Commands::Remove { .. } | Commands::Purge { .. } => true,
+        Commands::Upgrade { dry_run, .. } => !dry_run,
+        Commands::ReinstallAll => true,
+        Commands::Buildins { save_profile, .. } | Commands::Buildpkg { save_profile, .. } => *save_profile,
+        _ => false,
+    }
+}
+
+/// Whether `command` would put packages for another architecture than the host's onto the
+/// live system. `cfg.arch` counts whatever set it (`--arch`, `--assume-arch` or `NXPKG_ARCH`);
+/// `--download-only`, `--dry-run` and a `--root` other than `/` install nothing there.
+fn installs_foreign_binaries(command: &Commands, cfg: &AppConfig) -> bool {
+    let installs = match command {
+        Commands::Install { download_only, .. } => !download_only,
+        Commands::Upgrade { dry_run, .. } => !dry_run,
+        Commands::ReinstallAll => true,
+        _ => false,
+    };
+    installs && cfg.root == Path::new("/") && arch::canonical_arch(&cfg.arch) != arch::host_arch()
+}
+
+/// `self-check`: print PASS/FAIL for each built-in capability nxpkg relies on, and whether the
+/// package database opens. Returns false when any check failed.
+fn self_check(cfg: &AppConfig) -> bool {
+    let host = arch::host_arch();
+    let mut checks: Vec<(&str, Result<String, String>)> = vec![
+        ("version", Ok(VERSION.to_string())),
+        ("host arch", Ok(std::env::consts::ARCH.to_string())),
+        ("canonical arch", if arch::is_known_arch(host) {
+            Ok(host.to_string())
+        } else {
+            Err(format!("'{}' is not in the architecture table; only exact index keys will match", host))
+        }),
+        ("ed25519 sign/verify", trust::signing_round_trip().map(|()| "round trip with an ephemeral key".to_string())),
+        ("sqlite WAL", db::sqlite_supports_wal().map(|()| format!("SQLite {}", rusqlite::version()))),
+        ("package database", check_database(&cfg.db_path)),
+    ];
+    if arch::current_arch() != host {
+        checks.push(("assumed arch", Ok(format!("{} (--assume-arch or NXPKG_ARCH)", arch::current_arch()))));
+    }
+    let mut ok = true;
+    for (name, result) in checks {
+        match result {
+            Ok(detail) => println!("{} {}: {}", "PASS".green(), name, detail),
+            Err(e) => {
+                ok = false;
+                println!("{} {}: {}", "FAIL".red(), name, e);
+            }
         }
     }
+    ok
+}
+
+/// Open the package database read-write, without creating it, and read its schema. A database
+/// that does not exist yet passes when its directory is writable.
+fn check_database(db_path: &Path) -> Result<String, String> {
+    if !db_path.exists() {
+        let dir = db_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        return tempfile::NamedTempFile::new_in(dir)
+            .map(|_| format!("{} (not created yet)", db_path.display()))
+            .map_err(|e| format!("cannot create {}: {}", db_path.display(), e));
+    }
+    Connection::open_with_flags(db_path, rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .and_then(|db| db.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0)))
+        .map(|_| db_path.display().to_string())
+        .map_err(|e| format!("cannot open {}: {}", db_path.display(), e))
+}
 
-    Ok(())
+/// A value for `--porcelain` output: tabs and line breaks would split the record, so they
+/// become spaces.
+fn porcelain_field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+/// What `search` prints instead of its results when the repository has no packages at all.
+fn empty_repository_notice(index: &download::RepoIndex, repo_url: &str) -> Option<String> {
+    index.packages.is_empty().then(|| format!("The repository at {} has no packages yet.", repo_url))
+}
+
+/// The configuration from `--config <path>` when given, otherwise from the standard locations.
+/// A `--config` file that cannot be read is fatal.
+fn load_config(path: Option<&Path>) -> AppConfig {
+    match path {
+        Some(path) => AppConfig::load_from(path).unwrap_or_else(|e| {
+            eprintln!("{} {}", "Error:".red(), e);
+            std::process::exit(1);
+        }),
+        None => AppConfig::load(),
+    }
 }
 
 // REPO_URL artık /etc veya kullanıcı konfigürasyonundan okunuyor (config::AppConfig)
 
 #[tokio::main]
 async fn main() {
-    let cfg = AppConfig::load();
+    let cli = Cli::parse();
+    if let Some(assumed) = &cli.assume_arch {
+        arch::assume_arch(assumed);
+    }
+    let config_path = cli.config.clone();
+    let mut cfg = load_config(config_path.as_deref());
+    if let Some(root) = cli.root.clone() {
+        cfg.set_root(root, ConfigSource::Cli);
+    }
     let _ = fs::create_dir_all(cfg.cache_dir.clone());
     if let Some(parent) = cfg.db_path.parent() { let _ = fs::create_dir_all(parent); }
 
-    let cli = Cli::parse();
+    progress::init(cli.quiet);
+    if let Some(jobs) = cli.jobs {
+        cfg.max_parallelism = jobs as usize;
+        cfg.sources.insert("max_parallelism", ConfigSource::Cli);
+    }
+    if let Commands::Install { insecure_skip_verify: true, .. }
+        | Commands::Search { insecure_skip_verify: true, .. }
+        | Commands::Health { insecure_skip_verify: true, .. } = &cli.command
+    {
+        cfg.insecure_skip_verify = true;
+        eprintln!(
+            "{}",
+            "WARNING: --insecure-skip-verify: the repository index signature is NOT verified for this command. \
+             A tampered index can make nxpkg install arbitrary packages."
+                .red()
+                .bold()
+        );
+    }
+    if let Commands::Install { arch: Some(arch), .. } = &cli.command {
+        cfg.arch = arch.clone();
+    }
+    if installs_foreign_binaries(&cli.command, &cfg) {
+        eprintln!(
+            "{} refusing to install {} packages onto this {} host; use --download-only, or --root to stage them elsewhere.",
+            "Error:".red(), cfg.arch, arch::host_arch()
+        );
+        std::process::exit(1);
+    }
+    if let Commands::Closure { arch: Some(arch), .. } = &cli.command {
+        cfg.arch = arch.clone();
+    }
+    if let Commands::Install { check_links: true, .. } = &cli.command {
+        cfg.check_links = true;
+        cfg.sources.insert("check_links", ConfigSource::Cli);
+    }
+    if let Commands::Install { prefer_source: true, .. } = &cli.command {
+        cfg.source_fallback = true;
+        cfg.sources.insert("source_fallback", ConfigSource::Cli);
+    }
+    // self-check reports a database that cannot be opened instead of stopping here
+    if let Commands::SelfCheck = cli.command {
+        if !self_check(&cfg) {
+            std::process::exit(1);
+        }
+        return;
+    }
     let Some(_val) = Connection::open(&cfg.db_path).ok() else { return };
     let db1 = match PackageManagerDB::new(cfg.db_path.to_str().unwrap_or("nxpkg_meta.db")) {
         Ok(db) => db,
@@ -857,115 +2565,308 @@ async fn main() {
             return;
         }
     };
+    if mutates_db(&cli.command) {
+        if let Err(e) = db::backup::rotate_and_backup(&db1.db, &cfg.db_path, cfg.db_backups) {
+            eprintln!("{} could not back up the package database: {}", "Warning:".yellow(), e);
+        }
+    }
 
     match cli.command {
-        Commands::Install { name, local } => {
-            let pb = ProgressBar::new_spinner();
-            pb.enable_steady_tick(std::time::Duration::from_millis(120));
-            pb.set_style(ProgressStyle::with_template("{spinner:.blue} {elapsed_precise} {msg}").unwrap());
+        Commands::Install { name, local, repo, no_deps, download_only, from_file, yes, explain, .. } => {
+            let pb = Spinner::new("{spinner:.blue} {elapsed_precise} {msg}");
+
+            if let Some(list_path) = from_file {
+                if !install_from_list(&cfg, &db1, &list_path, repo.as_deref(), &InstallOptions { no_deps, assume_yes: yes }, &pb).await {
+                    std::process::exit(1);
+                }
+                return;
+            }
 
-            let nxpkg_path: PathBuf;
-            let package_name_from_source: String;
+            let sources: Vec<PackageSource>;
 
             if let Some(local_path_str) = local {
-                nxpkg_path = PathBuf::from(&local_path_str);
-                package_name_from_source = nxpkg_path.file_stem().unwrap_or_default().to_str().unwrap_or_default().to_string();
-                pb.set_message(format!("Installing from local package '{}'...", nxpkg_path.display()));
-            
+                sources = vec![PackageSource::Local(PathBuf::from(&local_path_str))];
+
             } else if let Some(remote_name) = name {
-                pb.set_message(format!("Fetching repository index..."));
-                
-                let index = match download::fetch_index_verified(&cfg.repo_url, Some(&cfg.pubkey_path), cfg.require_signed_index).await {
-                    Ok(i) => i,
+                let (package_name, _) = resolve::split_pin(&remote_name);
+                let repo_url = match remote_repo_url(&cfg, repo.as_deref()) {
+                    Ok(url) => url,
                     Err(e) => {
-                        pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
+                        pb.finish_with_message(e.red().to_string());
                         return;
                     }
                 };
 
-                let package_entry = match index.packages.get(&remote_name) {
-                    Some(entry) => entry,
-                    None => {
-                        pb.finish_with_message(format!("Package '{}' not found in the repository.", remote_name).red().to_string());
-                        return;
+                if repo.is_none() && cfg.detect_duplicates && cfg.repo_remotes.len() > 1 {
+                    pb.set_message("Checking configured remotes for duplicates...");
+                    let hits = download::find_in_remotes(&cfg, package_name).await;
+                    if download::hits_conflict(&hits) {
+                        pb.finish_and_clear();
+                        eprintln!("{} '{}' is provided by multiple remotes with different contents:", "Warning:".yellow(), package_name);
+                        for h in &hits {
+                            eprintln!("  {} {} (sha256: {})", h.remote.cyan(), h.version, h.sha256.as_deref().unwrap_or("none"));
+                        }
+                        eprintln!("Re-run with {} to choose one.", "--repo <name>".bold());
+                        std::process::exit(1);
                     }
-                };
+                }
+
+                pb.set_message("Fetching repository index...".to_string());
 
-                // Resolve proper asset for current architecture
-                let (asset_url, asset_sha) = match download::resolve_asset_for_current_arch(package_entry) {
-                    Some(x) => x,
-                    None => {
-                        pb.finish_with_message(format!("No compatible asset for '{}' on arch {}.", remote_name, std::env::consts::ARCH).red().to_string());
+                let index = match download::fetch_index_for(&cfg, &repo_url).await {
+                    Ok(i) => i,
+                    Err(e) => {
+                        pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
                         return;
                     }
                 };
-                
-                package_name_from_source = remote_name;
-                nxpkg_path = cfg.cache_dir.join(format!("{}.nxpkg", package_name_from_source));
 
-                pb.finish_and_clear();
-                
-                if let Err(e) = download::download_file_with_progress(&asset_url, &nxpkg_path, asset_sha.as_deref()).await {
-                    eprintln!("{}", format!("\nDownload failed: {}", e).red());
+                if download_only {
+                    // Fetch the full closure: the target host may lack what is installed here
+                    pb.set_message("Resolving dependencies...");
+                    let (resolved, trace) = if no_deps {
+                        (resolve::resolve_single(&index, &remote_name, &cfg.arch).map(|p| vec![p]), Vec::new())
+                    } else if explain {
+                        resolve::resolve_explained(&index, &remote_name, &cfg.arch, &|_| None)
+                    } else {
+                        (resolve::resolve(&index, &remote_name, &cfg.arch, &|_| None), Vec::new())
+                    };
+                    let plan = match resolved {
+                        Ok(plan) => plan,
+                        Err(e) => {
+                            pb.finish_with_message(format!("Cannot download '{}': {}", remote_name, e).red().to_string());
+                            if explain { print_resolution_trace(&trace, &cfg.arch); }
+                            return;
+                        }
+                    };
+                    pb.finish_and_clear();
+                    let cached = match install::download_all_to_cache(&cfg, &plan, Some(std::sync::Arc::new(DownloadBars::concurrent()))).await {
+                        Ok(cached) => cached,
+                        Err(e) => {
+                            eprintln!("{}", e.to_string().red());
+                            return;
+                        }
+                    };
+                    println!("{}", format!("Downloaded {} package(s) into the cache:", cached.len()).green());
+                    for pkg in cached {
+                        let note = if pkg.reused { format!("(already cached, sha256 {})", pkg.sha256) } else { format!("(sha256 {})", pkg.sha256) };
+                        println!("  {} {}", pkg.path.display(), note.dimmed());
+                    }
                     return;
                 }
-                
-                pb.reset();
-                pb.set_message("Download complete. Continuing installation...");
+
+                if let Ok(Some(installed_recipe)) = db1.get_package_metadata(package_name) {
+                    pb.finish_with_message(format!("'{}' v{} is already installed.", installed_recipe.package.name, installed_recipe.package.version).yellow().to_string());
+                    return;
+                }
+
+                let no_binary = remote_name == package_name
+                    && index.packages.get(package_name).is_some_and(|e| download::resolve_asset_for_arch(e, &cfg.arch).is_none());
+                if no_binary && cfg.source_fallback {
+                    pb.finish_and_clear();
+                    let Some(planned) = plan_source_build(&cfg, &db1, &index, package_name, no_deps, yes).await else {
+                        std::process::exit(1);
+                    };
+                    pb.reset();
+                    sources = planned;
+                } else {
+                    pb.set_message("Resolving dependencies...");
+                    let installed_version = |n: &str| db1.get_package_metadata(n).ok().flatten().map(|r| r.package.version);
+                    let (resolved, trace) = if no_deps {
+                        (resolve::resolve_single(&index, &remote_name, &cfg.arch).map(|p| vec![p]), Vec::new())
+                    } else if explain {
+                        resolve::resolve_explained(&index, &remote_name, &cfg.arch, &installed_version)
+                    } else {
+                        (resolve::resolve(&index, &remote_name, &cfg.arch, &installed_version), Vec::new())
+                    };
+                    let plan = match resolved {
+                        Ok(plan) => plan,
+                        Err(e) => {
+                            pb.finish_with_message(format!("Cannot install '{}': {}", remote_name, e).red().to_string());
+                            if explain {
+                                print_resolution_trace(&trace, &cfg.arch);
+                            } else if !no_deps && index.packages.contains_key(package_name) {
+                                eprintln!("Re-run with {} for the full resolution attempt.", "--explain".bold());
+                            }
+                            return;
+                        }
+                    };
+                    if plan.len() > 1 {
+                        let deps: Vec<String> = plan[..plan.len() - 1].iter().map(|p| format!("{} {}", p.name, p.version)).collect();
+                        pb.println(format!("Installing dependencies: {}", deps.join(", ")));
+                    }
+                    pb.set_message("Checking download size...");
+                    let estimate = install::estimate_download(&plan).await;
+                    // Only ask when dependencies make the install larger than what was asked for
+                    if !install::confirm_download(&estimate, yes || plan.len() == 1, &pb) {
+                        pb.finish_with_message(format!("Installation of '{}' cancelled.", remote_name).yellow().to_string());
+                        return;
+                    }
+                    sources = plan.into_iter().map(PackageSource::Remote).collect();
+                }
 
             } else {
-                eprintln!("{}", "Error: Must specify a package name or a local file with -L.".red());
+                eprintln!("{}", "Error: Must specify a package name, a local file with -L, or --from-file.".red());
                 return;
             }
 
-            if let Ok(Some(installed_recipe)) = db1.get_package_metadata(&package_name_from_source) {
-                pb.finish_with_message(format!("'{}' v{} is already installed.", installed_recipe.package.name, installed_recipe.package.version).yellow().to_string());
+            let install_opts = InstallOptions { no_deps, assume_yes: yes };
+            let total = sources.len();
+            let mut newly_installed = Vec::new();
+            for (i, source) in sources.iter().enumerate() {
+                let message = match install::install_from_source(&cfg, &db1, source, &install_opts, &pb).await {
+                    Ok(InstallOutcome::Installed(recipe)) => {
+                        let message = format!("Successfully installed '{}' v{}.", recipe.package.name, recipe.package.version).green().to_string();
+                        newly_installed.push(recipe);
+                        message
+                    }
+                    Ok(InstallOutcome::AlreadyInstalled(recipe)) => {
+                        format!("'{}' v{} is already installed.", recipe.package.name, recipe.package.version).yellow().to_string()
+                    }
+                    Err(e) => {
+                        pb.finish_with_message(e.to_string().red().to_string());
+                        return;
+                    }
+                };
+                if i + 1 == total {
+                    pb.finish_with_message(message);
+                } else {
+                    pb.println(message);
+                }
+            }
+            if cfg.check_links {
+                warn_dangling_links(&cfg, &newly_installed);
+            }
+        }
+        Commands::Remove { name, keep_files } => remove_package(&cfg, &db1, &name, keep_files, false),
+        Commands::Purge { name } => remove_package(&cfg, &db1, &name, false, true),
+        Commands::ReinstallAll => {
+            if !reinstall_all(&cfg, &db1).await {
+                std::process::exit(1);
+            }
+        }
+
+        Commands::Upgrade { name, all, dry_run, show_changes } => {
+            if all {
+                if !upgrade_all(&cfg, &db1, dry_run).await {
+                    std::process::exit(1);
+                }
                 return;
             }
+            let Some(name) = name else { return };
+            let pb = Spinner::new("{spinner:.blue} {elapsed_precise} {msg}");
 
-            pb.set_message(format!("Extracting package '{}'...", package_name_from_source));
-            let (mut recipe, installed_files) = match compress::extract_nxpkg(&nxpkg_path) {
-                Ok(r) => r,
-                Err(e) => {
-                    pb.finish_with_message(format!("Failed to install package: {}", e).red().to_string());
+            let installed = match db1.get_package_metadata(&name) {
+                Ok(Some(r)) => r,
+                _ => {
+                    pb.finish_with_message(format!("'{}' is not installed.", name).red().to_string());
                     return;
                 }
             };
 
-            // Persist installed file paths into the recipe so uninstall can remove them later
-            recipe.install.installed_files = installed_files
-                .into_iter()
-                .map(|p| p.to_string_lossy().to_string())
-                .collect();
-            
-            pb.set_message("Registering package in database...");
-            if let Err(e) = db1.save_package_metadata(&recipe) {
-                pb.finish_with_message(format!("Database registration failed: {}", e).red().to_string());
+            pb.set_message("Fetching repository index...");
+            let index = match download::fetch_index_for(&cfg, &cfg.repo_url).await {
+                Ok(i) => i,
+                Err(e) => {
+                    pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
+                    return;
+                }
+            };
+            let Some(entry) = index.packages.get(&name) else {
+                pb.finish_with_message(format!("'{}' is not available in the repository.", name).red().to_string());
+                return;
+            };
+            if version::compare(&entry.latest_version, &installed.package.version) != std::cmp::Ordering::Greater {
+                pb.finish_with_message(format!("'{}' v{} is up to date.", name, installed.package.version).green().to_string());
                 return;
             }
-            
-            pb.finish_with_message(format!("Successfully installed '{}' v{}.", recipe.package.name, recipe.package.version).green().to_string());
-        }
-        Commands::Remove { name } | Commands::Purge { name } => {
-            let pb = ProgressBar::new_spinner();
-            pb.enable_steady_tick(std::time::Duration::from_millis(120));
-            pb.set_style(ProgressStyle::with_template("{spinner:.blue} {msg}").unwrap());
-            pb.set_message(format!("Removing {}...", name));
-            if let Ok(Some(_)) = db1.get_package_metadata(&name) {
-                let _ = db1.rem_package_metadata(&name);
-                pb.finish_with_message(format!("{} package is purged.", name).green().to_string());
-            } else {
-                pb.finish_with_message(format!("{} package is not found.", name).red().to_string());
+
+            pb.set_message("Resolving dependencies...");
+            let installed_version = |n: &str| db1.get_package_metadata(n).ok().flatten().map(|r| r.package.version);
+            let mut plan = match resolve::resolve(&index, &name, &cfg.arch, &installed_version) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    pb.finish_with_message(format!("Cannot upgrade '{}': {}", name, e).red().to_string());
+                    return;
+                }
+            };
+            let Some(target) = plan.pop() else { return };
+
+            pb.finish_and_clear();
+            let nxpkg_path = match install::download_to_cache(&cfg, &target, Some(&DownloadBars::single())).await {
+                Ok(p) => p,
+                Err(e) => {
+                    eprintln!("{}", e.to_string().red());
+                    return;
+                }
+            };
+            pb.reset();
+
+            let extract_opts = compress::ExtractOptions { prefix_map: cfg.prefix_map.clone(), ..Default::default() };
+            let changes = match compress::read_manifest(&nxpkg_path, &extract_opts) {
+                Ok((_, manifest)) => diff::compute(&installed, &manifest, &cfg.root),
+                Err(e) => {
+                    pb.finish_with_message(format!("Failed to read package: {}", e).red().to_string());
+                    return;
+                }
+            };
+            if dry_run || show_changes {
+                pb.suspend(|| {
+                    println!("Changes for {} {} -> {}:", name.cyan(), installed.package.version, target.version);
+                    if changes.is_empty() { println!("  (no file changes)"); } else { changes.print(); }
+                    if !plan.is_empty() {
+                        let deps: Vec<String> = plan.iter().map(|p| format!("{} {}", p.name, p.version)).collect();
+                        println!("New dependencies: {}", deps.join(", "));
+                    }
+                });
+                if dry_run {
+                    pb.finish_and_clear();
+                    return;
+                }
+            }
+
+            let install_opts = InstallOptions::default();
+            for dep in plan {
+                match install::install_from_source(&cfg, &db1, &PackageSource::Remote(dep), &install_opts, &pb).await {
+                    Ok(InstallOutcome::Installed(r)) => pb.println(format!("Installed dependency '{}' v{}.", r.package.name, r.package.version)),
+                    Ok(InstallOutcome::AlreadyInstalled(_)) => {}
+                    Err(e) => {
+                        pb.finish_with_message(e.to_string().red().to_string());
+                        return;
+                    }
+                }
+            }
+
+            match install::upgrade_from_file(&cfg, &db1, &nxpkg_path, &installed, Some(&target.description), &changes, &pb) {
+                Ok(r) => pb.finish_with_message(format!("Upgraded '{}' {} -> {}.", name, installed.package.version, r.package.version).green().to_string()),
+                Err(e) => pb.finish_with_message(format!("Upgrade failed: {}", e).red().to_string()),
+            }
+        }
+        Commands::List => {
+            if !list_installed(&db1) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Show { name, repo, json } => {
+            if !show_package(&cfg, &db1, &name, repo.as_deref(), json).await {
+                std::process::exit(1);
             }
         }
-        Commands::Search { term } => {
-            let pb = ProgressBar::new_spinner();
-            pb.enable_steady_tick(std::time::Duration::from_millis(120));
-            pb.set_style(ProgressStyle::with_template("{spinner:.blue} {elapsed_precise} {msg}").unwrap());
+        Commands::Closure { name, repo, download_only, json, .. } => {
+            if !print_closure(&cfg, &name, repo.as_deref(), download_only, json).await {
+                std::process::exit(1);
+            }
+        }
+        Commands::Search { term, porcelain, all_repos: true, .. } if !cfg.repo_remotes.is_empty() => {
+            if !search_all_remotes(&cfg, &term, porcelain).await {
+                std::process::exit(1);
+            }
+        }
+        Commands::Search { term, porcelain, .. } => {
+            let pb = Spinner::new("{spinner:.blue} {elapsed_precise} {msg}");
             pb.set_message("Fetching repository index...");
 
-                            let index = match download::fetch_index_verified(&cfg.repo_url, Some(&cfg.pubkey_path), cfg.require_signed_index).await {
-
+            let index = match download::fetch_index_for(&cfg, &cfg.repo_url).await {
                 Ok(i) => i,
                 Err(e) => {
                     pb.finish_with_message(format!("Failed to fetch repository index: {}", e).red().to_string());
@@ -974,14 +2875,24 @@ async fn main() {
             };
             pb.finish_and_clear();
 
+            if let Some(notice) = empty_repository_notice(&index, &cfg.repo_url).filter(|_| !porcelain) {
+                println!("{}", notice.yellow());
+                return;
+            }
+
             let term = term.to_lowercase();
-            let results: Vec<_> = index.packages.iter()
+            let mut results: Vec<_> = index.packages.iter()
                 .filter(|(name, entry)| 
                     name.to_lowercase().contains(&term) || entry.description.to_lowercase().contains(&term)
                 )
                 .collect();
+            results.sort_by(|a, b| a.0.cmp(b.0));
 
-            if results.is_empty() {
+            if porcelain {
+                for (name, entry) in results {
+                    println!("{}\t{}\t{}", name, entry.latest_version, porcelain_field(&entry.description));
+                }
+            } else if results.is_empty() {
                 println!("{}", "No packages found matching your search term.".yellow());
             } else {
                 println!("Found {} package(s):", results.len());
@@ -1007,8 +2918,24 @@ async fn main() {
             install_args,
             save_profile,
             no_profile,
+            source,
+            depends,
+            ignore_missing_deps,
+            ssh_fallback,
+            yes,
+            pick,
+            group,
+            recipe,
         } => {
-            let selected_repo = match repo::find_and_select_repo(&name) {
+            let recipe = match recipe.as_deref().map(PackageRecipe::from_file).transpose() {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("{} {}", "Invalid --recipe:".red(), e);
+                    return;
+                }
+            };
+            let pick = pick.or(yes.then_some(1));
+            let selected_repo = match repo::find_and_select_repo(&name, source, cfg.max_parallelism, pick, group.as_deref()) {
                 Ok(repo) => repo,
                 Err(e) => {
                     eprintln!("{}", format!("\nBuild process failed: {}", e).red());
@@ -1016,15 +2943,16 @@ async fn main() {
                 }
             };
 
-            use std::process::Command;
-
-            let repo_name_only = selected_repo.name.split('/').last().unwrap_or(&selected_repo.name);
-            let package_name = match package {
-                Some(name) => name,
-                None => match auto_package_name(repo_name_only) {
-                    Some(auto_name) => auto_name,
+            let repo_name_only = selected_repo.name.split('/').next_back().unwrap_or(&selected_repo.name);
+            // Unless given, the name may still come from a recipe file in the clone
+            let (package_name, name_origin) = match (package, &recipe) {
+                (Some(name), _) => (name, NameOrigin::Given),
+                (None, Some(r)) => (r.package.name.clone(), NameOrigin::Given),
+                (None, None) => match auto_package_name(repo_name_only) {
+                    Some(auto_name) => (auto_name, NameOrigin::Detected),
                     None => match prompt_for_package_name() {
-                        Ok(name) => name,
+                        Ok(name) => (name, NameOrigin::Given),
+                        Err(e) if e.kind() == io::ErrorKind::InvalidInput => (repo_name_only.to_string(), NameOrigin::Missing),
                         Err(e) => {
                             eprintln!("{} {}", "Failed to read package name:".red(), e);
                             return;
@@ -1061,70 +2989,34 @@ async fn main() {
                 configure_args,
                 build_args,
                 install_args,
+                depends,
             );
-
-            let pb_clone = ProgressBar::new_spinner();
-            pb_clone.enable_steady_tick(std::time::Duration::from_millis(120));
-            pb_clone.set_style(ProgressStyle::with_template("{spinner:.green} {elapsed_precise} {msg}").unwrap());
-
-            let clone_path = format!("/tmp/{}", repo_name_only);
-
-            let _ = std::fs::remove_dir_all(&clone_path);
-
-            pb_clone.set_message(format!("Cloning from {}...", selected_repo.clone_url));
-
-            let clone_status = pb_clone.suspend(|| {
-                Command::new("git")
-                    .arg("clone")
-                    .arg(&selected_repo.clone_url)
-                    .arg(&clone_path)
-                    .status()
-            });
-
-            if !clone_status.map_or(false, |s| s.success()) {
-                pb_clone.finish_with_message(format!("Failed to clone {}.", selected_repo.name).red().to_string());
+            // Dependencies declared by --recipe are checked along with --depends
+            let mut checked = profile.clone();
+            for dep in recipe.iter().flat_map(|r| &r.build.dependencies) {
+                if !checked.dependencies.contains(dep) {
+                    checked.dependencies.push(dep.clone());
+                }
+            }
+            if !check_build_dependencies(&cfg, &db1, &checked, ignore_missing_deps).await {
                 return;
             }
-            pb_clone.finish_with_message(format!("Successfully cloned {}.", selected_repo.name).green().to_string());
-
-            let clone_path_obj = std::path::Path::new(&clone_path);
-            if clone_path_obj.join(".gitmodules").exists() {
-                let pb_submodule = ProgressBar::new_spinner();
-                pb_submodule.enable_steady_tick(std::time::Duration::from_millis(120));
-                pb_submodule.set_style(ProgressStyle::with_template("{spinner:.cyan} {elapsed_precise} {msg}").unwrap());
-                pb_submodule.set_message("Initializing and updating submodules...");
-
-                let submodule_status = pb_submodule.suspend(|| {
-                    Command::new("git")
-                        .arg("submodule")
-                        .arg("update")
-                        .arg("--init")
-                        .arg("--recursive")
-                        .current_dir(&clone_path)
-                        .status()
-                });
 
-                if !submodule_status.map_or(false, |s| s.success()) {
-                    pb_submodule.finish_with_message("Failed to update submodules.".red().to_string());
-                    return;
-                }
-                pb_submodule.finish_with_message("Submodules updated successfully.".green().to_string());
-            }
-            let source_label = selected_repo.name.clone();
-            let _ = build_and_package(
-                Path::new(&clone_path),
-                repo_name_only,
-                &source_label,
+            let _ = build_from_repo(
+                &cfg,
+                &db1,
+                &selected_repo,
                 &package_name,
                 version,
                 output_dir,
                 staging_dir_in_chroot,
                 profile,
                 save_profile,
-                &db1,
-                true,
-            );
-
+                ssh_fallback,
+                !yes,
+                recipe,
+                name_origin,
+            ).await;
         }
         Commands::Buildpkg {
             path,
@@ -1138,6 +3030,7 @@ async fn main() {
             install_args,
             save_profile,
             no_profile,
+            depends,
         } => {
             let source_path = path.unwrap_or_else(|| ".".to_string());
             let source_path = match std::fs::canonicalize(&source_path) {
@@ -1190,6 +3083,7 @@ async fn main() {
                 configure_args,
                 build_args,
                 install_args,
+                depends,
             );
             let source_label = source_path.display().to_string();
             let _ = build_and_package(
@@ -1203,14 +3097,18 @@ async fn main() {
                 profile,
                 save_profile,
                 &db1,
+                &cfg,
                 false,
+                false,
+                BuildRecipe::Detect,
+                NameOrigin::Given,
             );
         }
 
         Commands::RepoRemote { action } => {
             match action {
                 RepoRemoteAction::List => {
-                    let cfg_now = AppConfig::load();
+                    let cfg_now = load_config(config_path.as_deref());
                     let active = cfg_now.active_repo.clone();
                     if cfg_now.repo_remotes.is_empty() {
                         println!("{}", "No binary repo remotes configured.".yellow());
@@ -1240,16 +3138,131 @@ async fn main() {
                 RepoRemoteAction::Choose { name } => {
                     match AppConfig::set_active_repo(&name) {
                         Ok(_) => {
-                            let cfg_now = AppConfig::load();
+                            let cfg_now = load_config(config_path.as_deref());
                             println!("Active binary remote set to '{}' -> {}", name.cyan(), cfg_now.repo_url);
                         }
                         Err(e) => eprintln!("{} {}", "Failed to set active remote:".red(), e),
                     }
                 }
                 RepoRemoteAction::Current => {
-                    let cfg_now = AppConfig::load();
+                    let cfg_now = load_config(config_path.as_deref());
                     println!("{}", cfg_now.repo_url);
                 }
+                RepoRemoteAction::VerifyRepo { repo, arch } => {
+                    if !verify_repo(&cfg, repo.as_deref(), arch.as_deref()).await {
+                        std::process::exit(1);
+                    }
+                }
+                RepoRemoteAction::RollbackIndex { serial, list, repo, token, yes } => {
+                    let token = token.or_else(|| std::env::var("NXPKG_TOKEN").ok());
+                    if !rollback_index(&cfg, serial, list, repo.as_deref(), token.as_deref(), yes).await {
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+
+        Commands::Config { action } => {
+            match action {
+                ConfigAction::Show { json } => {
+                    let prefix_map: Vec<String> = cfg.prefix_map.iter()
+                        .map(|m| format!("{}=>{}", m.from.display(), m.to.display()))
+                        .collect();
+                    if json {
+                        let value = |v: serde_json::Value, key: &str| serde_json::json!({ "value": v, "source": cfg.source_of(key) });
+                        let remotes: Vec<serde_json::Value> = cfg.repo_remotes.iter()
+                            .map(|(name, url)| serde_json::json!({
+                                "name": name,
+                                "url": url,
+                                "active": cfg.active_repo.as_deref() == Some(name.as_str()),
+                            }))
+                            .collect();
+                        let out = serde_json::json!({
+                            "repo_url": value(cfg.repo_url.clone().into(), "repo_url"),
+                            "db_path": value(cfg.db_path.to_string_lossy().into(), "db_path"),
+                            "cache_dir": value(cfg.cache_dir.to_string_lossy().into(), "cache_dir"),
+                            "db_backups": value(cfg.db_backups.into(), "db_backups"),
+                            "require_signed_index": value(cfg.require_signed_index.into(), "require_signed_index"),
+                            "pubkey_path": value(cfg.pubkey_path.to_string_lossy().into(), "pubkey_path"),
+                            "sig_path": value(cfg.sig_path.clone().into(), "sig_path"),
+                            "mirrors": value(cfg.mirrors.clone().into(), "mirrors"),
+                            "rank_mirrors": value(cfg.rank_mirrors.into(), "rank_mirrors"),
+                            "root": value(cfg.root.to_string_lossy().into(), "root"),
+                            "prefix_map": value(prefix_map.into(), "prefix_map"),
+                            "check_links": value(cfg.check_links.into(), "check_links"),
+                            "source_fallback": value(cfg.source_fallback.into(), "source_fallback"),
+                            "detect_duplicates": value(cfg.detect_duplicates.into(), "detect_duplicates"),
+                            "lenient_index": value(cfg.lenient_index.into(), "lenient_index"),
+                            "index_history": value(cfg.index_history.into(), "index_history"),
+                            "build_user": value(cfg.build_user.clone().into(), "build_user"),
+                            "sandbox": value(cfg.sandbox.as_str().into(), "sandbox"),
+                            "container_image": value(cfg.container_image.clone().into(), "container_image"),
+                            "container_runtime": value(cfg.container_runtime.clone().into(), "container_runtime"),
+                            "command_guard": value(cfg.command_guard.into(), "command_guard"),
+                            "command_guard_allow": value(cfg.command_guard_allow.clone().into(), "command_guard_allow"),
+                            "audit_file": value(cfg.audit_file.as_ref().map(|p| p.to_string_lossy().to_string()).into(), "audit_file"),
+                            "default_file_mode": value(cfg.package_file_mode.map(|m| format!("{:04o}", m)).into(), "default_file_mode"),
+                            "canonical_signing": value(cfg.canonical_signing.into(), "canonical_signing"),
+                            "strict_arch_assets": value(cfg.strict_arch_assets.into(), "strict_arch_assets"),
+                            "verify_hook": value(cfg.verify_hook.as_ref().map(|p| p.to_string_lossy().to_string()).into(), "verify_hook"),
+                            "upload_strategy": value(cfg.upload_strategy.as_str().into(), "upload_strategy"),
+                            "multipart_threshold": value(cfg.multipart_threshold.into(), "multipart_threshold"),
+                            "max_parallelism": value(cfg.max_parallelism.into(), "max_parallelism"),
+                            "repo_remotes": remotes,
+                        });
+                        match serde_json::to_string_pretty(&out) {
+                            Ok(s) => println!("{}", s),
+                            Err(e) => eprintln!("{} {}", "Failed to serialize config:".red(), e),
+                        }
+                    } else {
+                        let row = |key: &str, v: String| {
+                            println!("{:<22} {} {}", key, v, format!("({})", cfg.source_of(key)).dimmed());
+                        };
+                        let repo_url = if cfg.repo_url.is_empty() { "<unset>".to_string() } else { cfg.repo_url.clone() };
+                        row("repo_url", repo_url);
+                        row("db_path", cfg.db_path.display().to_string());
+                        row("cache_dir", cfg.cache_dir.display().to_string());
+                        row("db_backups", if cfg.db_backups == 0 { "0 (disabled)".to_string() } else { cfg.db_backups.to_string() });
+                        row("require_signed_index", cfg.require_signed_index.to_string());
+                        row("pubkey_path", cfg.pubkey_path.display().to_string());
+                        row("sig_path", cfg.sig_path.clone());
+                        row("mirrors", if cfg.mirrors.is_empty() { "<none>".to_string() } else { cfg.mirrors.join(", ") });
+                        row("rank_mirrors", cfg.rank_mirrors.to_string());
+                        row("root", cfg.root.display().to_string());
+                        row("prefix_map", if prefix_map.is_empty() { "<none>".to_string() } else { prefix_map.join(", ") });
+                        row("check_links", cfg.check_links.to_string());
+                        row("source_fallback", cfg.source_fallback.to_string());
+                        row("detect_duplicates", cfg.detect_duplicates.to_string());
+                        row("lenient_index", cfg.lenient_index.to_string());
+                        row("index_history", if cfg.index_history == 0 { "0 (disabled)".to_string() } else { cfg.index_history.to_string() });
+                        row("build_user", cfg.build_user.clone().unwrap_or_else(|| "<nobody>".to_string()));
+                        row("sandbox", cfg.sandbox.as_str().to_string());
+                        row("container_image", cfg.container_image.clone().unwrap_or_else(|| "<unset>".to_string()));
+                        row("container_runtime", cfg.container_runtime.clone().unwrap_or_else(|| "<podman or docker>".to_string()));
+                        row("command_guard", cfg.command_guard.to_string());
+                        row("command_guard_allow", if cfg.command_guard_allow.is_empty() { "<none>".to_string() } else { cfg.command_guard_allow.join(", ") });
+                        row("audit_file", cfg.audit_file.as_ref().map_or("<disabled>".to_string(), |p| p.display().to_string()));
+                        row("default_file_mode", cfg.package_file_mode.map_or("<staging modes>".to_string(), |m| format!("{:04o}", m)));
+                        row("canonical_signing", cfg.canonical_signing.to_string());
+                        row("strict_arch_assets", cfg.strict_arch_assets.to_string());
+                        row("verify_hook", cfg.verify_hook.as_ref().map_or("<none>".to_string(), |p| p.display().to_string()));
+                        row("upload_strategy", cfg.upload_strategy.as_str().to_string());
+                        row("multipart_threshold", cfg.multipart_threshold.to_string());
+                        row("max_parallelism", cfg.max_parallelism.to_string());
+                        if cfg.repo_remotes.is_empty() {
+                            println!("{:<22} <none>", "repo_remotes");
+                        } else {
+                            println!("repo_remotes:");
+                            for (name, url) in cfg.repo_remotes.iter() {
+                                if cfg.active_repo.as_deref() == Some(name.as_str()) {
+                                    println!("* {} -> {} {}", name.cyan(), url, "(active)".green());
+                                } else {
+                                    println!("  {} -> {}", name.cyan(), url);
+                                }
+                            }
+                        }
+                    }
+                }
             }
         }
 
@@ -1260,28 +3273,33 @@ async fn main() {
                     if list.is_empty() { println!("{}", "No configured repositories.".yellow()); }
                     else {
                         println!("Configured repositories ({}):", list.len());
-                        for r in list { println!("- {} -> {}", r.name.cyan(), r.clone_url); }
+                        for r in list {
+                            match &r.group {
+                                Some(g) => println!("- {} -> {} {}", r.name.cyan(), r.clone_url, format!("[{}]", g).dimmed()),
+                                None => println!("- {} -> {}", r.name.cyan(), r.clone_url),
+                            }
+                        }
                     }
                 }
-                RepoAction::Add { name, url } => {
-                    match repo::add_repo_entry(&name, &url) {
+                RepoAction::Add { name, url, group } => {
+                    match repo::add_repo_entry(&name, &url, group.as_deref()) {
                         Ok(_) => println!("{} {} -> {}", "Added/updated:".green(), name, url),
                         Err(e) => eprintln!("{} {}", "Failed to add repo:".red(), e),
                     }
                 }
-                RepoAction::Remove { name } => {
-                    match repo::remove_repo_entry(&name) {
+                RepoAction::Remove { name, group } => {
+                    match repo::remove_repo_entry(&name, group.as_deref()) {
                         Ok(_) => println!("{} {}", "Removed:".green(), name),
                         Err(e) => eprintln!("{} {}", "Failed to remove repo:".red(), e),
                     }
                 }
-                RepoAction::Choose { term, build, print_url } => {
-                    match repo::select_repo_from_config(term.as_deref()) {
+                RepoAction::Choose { term, build, print_url, group } => {
+                    match repo::select_repo_from_config(term.as_deref(), group.as_deref()) {
                         Ok(selected) => {
                             println!("Selected: {} -> {}", selected.name.cyan(), selected.clone_url);
                             if print_url { println!("{}", selected.clone_url); }
                             if build {
-                                println!("{} {}", "Tip:".yellow(), format!("Run: nxpkg buildins '{}'", selected.name));
+                                println!("{} Run: nxpkg buildins '{}'", "Tip:".yellow(), selected.name);
                             }
                         }
                         Err(e) => eprintln!("{} {}", "Selection failed:".red(), e),
@@ -1304,13 +3322,133 @@ async fn main() {
             println!("{}", "NeoniX PacKaGe Manager for Neonix v1.x".blue());
             println!("{}", "This is designed especially for Neonix family Linux distro. Compact and community oriented.".yellow());
         }
+        Commands::Detect { path } => {
+            if !path.is_dir() {
+                eprintln!("{} {} is not a directory", "Error:".red(), path.display());
+                std::process::exit(1);
+            }
+            let mut candidates = detect::find_build_systems(&path);
+            match detect::pick_build_system(&candidates, None) {
+                Some(m) => println!("{} ({})", m.kind.as_str().green(), m.path.display()),
+                None => println!("none"),
+            }
+            candidates.sort_by_key(|c| (c.depth, c.kind.priority()));
+            if !candidates.is_empty() {
+                println!("Candidates:");
+                for c in &candidates {
+                    println!("  {:<6} depth {:<3} {}", c.kind.as_str(), c.depth, c.path.display());
+                }
+            }
+        }
+        Commands::Extract { file, only, dest } => {
+            let dest = dest.unwrap_or_else(|| PathBuf::from("."));
+            let unpacked = match compress::extract_nxpkg_members(&file, &dest, &only) {
+                Ok((_, unpacked)) => unpacked,
+                Err(e) => {
+                    eprintln!("{} {}: {}", "Error:".red(), file.display(), e);
+                    std::process::exit(1);
+                }
+            };
+            for path in &unpacked.files {
+                println!("{}", path.display());
+            }
+            if unpacked.files.is_empty() && !only.is_empty() {
+                eprintln!("{} no member of {} matches {}", "Error:".red(), file.display(), only.join(", "));
+                std::process::exit(1);
+            }
+            println!("{}", format!("Extracted {} file(s) into {}", unpacked.files.len(), dest.display()).green());
+        }
+        Commands::Diff { a, b } => {
+            let read = |path: &Path| {
+                compress::read_package_manifest(path).unwrap_or_else(|e| {
+                    eprintln!("{} {}: {}", "Error:".red(), path.display(), e);
+                    std::process::exit(2);
+                })
+            };
+            let ((recipe_a, manifest_a), (recipe_b, manifest_b)) = (read(&a), read(&b));
+            let metadata = diff::compare_recipes(&recipe_a, &recipe_b);
+            let files = diff::compare_manifests(&manifest_a, &manifest_b);
+            if metadata.is_empty() && files.is_empty() {
+                println!("{}", "The packages have the same metadata and payload.".green());
+                return;
+            }
+            if !metadata.is_empty() {
+                println!("Metadata:");
+                for (field, old, new) in &metadata {
+                    println!("  {}: {} -> {}", field.bold(), old.red(), new.green());
+                }
+            }
+            if !files.is_empty() {
+                println!("Files:");
+                files.print();
+            }
+            std::process::exit(1);
+        }
+        Commands::RestoreDb { number, from, list } => {
+            if list {
+                let backups = db::backup::list_backups(&cfg.db_path, cfg.db_backups);
+                if backups.is_empty() {
+                    println!("No backups of {}.", cfg.db_path.display());
+                }
+                for (n, path) in backups {
+                    let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                    println!("{:>2}  {}  {}", n, path.display(), install::format_size(size));
+                }
+                return;
+            }
+            let backup = from.unwrap_or_else(|| db::backup::backup_path(&cfg.db_path, number.unwrap_or(0)));
+            if !backup.is_file() {
+                eprintln!("{} no backup at {}", "Error:".red(), backup.display());
+                std::process::exit(1);
+            }
+            match db::backup::restore(&db1.db, &cfg.db_path, &backup, cfg.db_backups) {
+                Ok(()) => println!("{}", format!("Restored {} from {}.", cfg.db_path.display(), backup.display()).green()),
+                Err(e) => {
+                    eprintln!("{} {}", "Error:".red(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::CleanOrphans { prefix, delete, yes } => {
+            let orphans = match install::orphans::find_orphans(&cfg, &db1, &prefix) {
+                Ok(o) => o,
+                Err(e) => {
+                    eprintln!("{} {}", "Error:".red(), e);
+                    std::process::exit(1);
+                }
+            };
+            for o in &orphans {
+                println!("{}  {}", o.path.display(), install::format_size(o.size));
+            }
+            if orphans.is_empty() {
+                println!("{}", format!("No unowned files under {}.", prefix.display()).green());
+                return;
+            }
+            let bytes: u64 = orphans.iter().map(|o| o.size).sum();
+            println!("{} unowned file(s), {}", orphans.len(), install::format_size(bytes));
+            if !delete {
+                println!("Run again with --delete to remove them.");
+                return;
+            }
+            if let Err(e) = install::orphans::confirm_removal(&orphans, yes) {
+                eprintln!("{}", e.yellow());
+                std::process::exit(1);
+            }
+            let failed = install::orphans::remove_orphans(&orphans);
+            for (path, e) in &failed {
+                eprintln!("{} could not remove {}: {}", "Warning:".yellow(), path.display(), e);
+            }
+            println!("{}", format!("Deleted {} file(s).", orphans.len() - failed.len()).green());
+            if !failed.is_empty() {
+                std::process::exit(1);
+            }
+        }
         Commands::Version => {
             println!("Neonix {} ({})", VERSION, std::env::consts::ARCH);
         }
-        Commands::Health { no_network, check_chroot } => {
-            let pb = ProgressBar::new_spinner();
-            pb.enable_steady_tick(std::time::Duration::from_millis(120));
-            pb.set_style(ProgressStyle::with_template("{spinner:.green} {elapsed_precise} {msg}").unwrap());
+        Commands::SelfCheck => unreachable!("self-check runs before the database is opened"),
+        Commands::Health { no_network, check_chroot, timeout, .. } => {
+            let pb = Spinner::new("{spinner:.green} {elapsed_precise} {msg}");
             pb.set_message("Running health checks...");
 
             let mut ok = true;
@@ -1324,7 +3462,7 @@ async fn main() {
                 Ok(_name) => {}
                 Err(rusqlite::Error::QueryReturnedNoRows) => {
                     ok = false;
-                    eprintln!("{} {}", "DB check failed:".red(), "packages table missing");
+                    eprintln!("{} packages table missing", "DB check failed:".red());
                 }
                 Err(e) => {
                     ok = false;
@@ -1341,9 +3479,20 @@ async fn main() {
 
             // 3) Network + repo index (unless skipped)
             if !no_network {
-                match download::fetch_index_verified(&cfg.repo_url, Some(&cfg.pubkey_path), cfg.require_signed_index).await {
-                    Ok(_) => {}
-                    Err(e) => { ok = false; eprintln!("{} {}", "Repo index fetch failed:".red(), e); }
+                // Check the live repository: a cached index must not hide an outage
+                let (pubkey, require) = if cfg.insecure_skip_verify {
+                    (None, false)
+                } else {
+                    (Some(cfg.pubkey_path.as_path()), cfg.require_signed_index)
+                };
+                let fetch = download::fetch_index_with(&cfg.repo_url, &cfg.sig_path, pubkey, require, cfg.lenient_index, cfg.canonical_signing);
+                match tokio::time::timeout(std::time::Duration::from_secs(timeout), fetch).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => { ok = false; eprintln!("{} {}", "Repo index fetch failed:".red(), e); }
+                    Err(_) => {
+                        ok = false;
+                        eprintln!("{} {} (timed out after {}s)", "Repo unreachable:".red(), cfg.repo_url, timeout);
+                    }
                 }
             }
 
@@ -1369,10 +3518,19 @@ async fn main() {
                 std::process::exit(1);
             }
         }
-        Commands::Publish { file, desc, repo, token, sign_keypair_b64, sign_keypair_file } => {
+        Commands::Publish { file, desc, repo, token, sign_keypair_b64, sign_keypair_file, force, json } => {
+            // With --json, failures are reported as a PublishResult on stdout and exit non-zero
+            let fail = |message: String| {
+                if json {
+                    let result = upload::PublishResult { error: Some(message), ..Default::default() };
+                    println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+                    std::process::exit(1);
+                }
+                eprintln!("{}", message.red());
+            };
             let nxpkg_path = PathBuf::from(&file);
             if !nxpkg_path.exists() {
-                eprintln!("{}", format!("Package file not found: {}", nxpkg_path.display()).red());
+                fail(format!("Package file not found: {}", nxpkg_path.display()));
                 return;
             }
             // Determine repo URL
@@ -1382,42 +3540,178 @@ async fn main() {
                 .or_else(|| std::env::var("NXPKG_TOKEN").ok());
             // Determine signing keypair
             let keypair_b64 = if let Some(p) = sign_keypair_file {
-                match std::fs::read_to_string(p) {
+                match trust::read_signing_keypair(Path::new(&p)) {
                     Ok(s) => Some(s),
                     Err(e) => {
-                        eprintln!("{}", format!("Failed to read sign keypair file: {}", e).red());
+                        fail(format!("Failed to read sign keypair file: {}", e));
                         return;
                     }
                 }
             } else {
-                sign_keypair_b64.or_else(|| std::env::var("NXPKG_SIGN_KEYPAIR_B64").ok())
+                if sign_keypair_b64.is_some() {
+                    eprintln!(
+                        "{}",
+                        "Warning: --sign-keypair-b64 exposes the private key in the process list and shell history; \
+                         use --sign-keypair-file or NXPKG_SIGN_KEYPAIR_B64 instead."
+                            .yellow()
+                    );
+                }
+                sign_keypair_b64.or_else(|| std::env::var("NXPKG_SIGN_KEYPAIR_B64").ok()).map(zeroize::Zeroizing::new)
             };
 
             // Read recipe (without installing)
             let recipe = match compress::read_recipe_from_nxpkg(&nxpkg_path) {
                 Ok(r) => r,
                 Err(e) => {
-                    eprintln!("{}", format!("Failed to read recipe from package: {}", e).red());
+                    fail(format!("Failed to read recipe from package: {}", e));
                     return;
                 }
             };
 
-            let pb = ProgressBar::new_spinner();
-            pb.enable_steady_tick(std::time::Duration::from_millis(120));
-            pb.set_style(ProgressStyle::with_template("{spinner:.green} {elapsed_precise} {msg}").unwrap());
+            let pb = Spinner::new("{spinner:.green} {elapsed_precise} {msg}");
             pb.set_message("Uploading package and updating index...");
 
-            match upload::upload_and_update_index(
+            let history_dir = download::index_history_dir(&cfg, &repo_url);
+            let result = upload::upload_and_update_index(
                 &repo_url,
                 &nxpkg_path,
                 &recipe,
                 desc.as_deref(),
                 token_effective.as_deref(),
-                keypair_b64.as_deref(),
-            ).await {
+                keypair_b64.as_ref().map(|k| k.as_str()),
+                cfg.canonical_signing,
+                &cfg.sig_path,
+                upload::UploadOptions::from_config(&cfg),
+                force,
+                (cfg.index_history > 0).then_some((history_dir.as_path(), cfg.index_history)),
+            ).await;
+            if json {
+                pb.finish_and_clear();
+                let result = result.unwrap_or_else(|e| upload::PublishResult {
+                    name: Some(recipe.package.name.clone()),
+                    version: Some(recipe.package.version.clone()),
+                    error: Some(e.to_string()),
+                    ..Default::default()
+                });
+                println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+                if !result.success {
+                    std::process::exit(1);
+                }
+                return;
+            }
+            match result {
+                Ok(r) if r.unchanged => pb.finish_with_message(format!(
+                    "'{}' v{} is already published, unchanged; nothing uploaded (--force to publish anyway)",
+                    recipe.package.name, recipe.package.version
+                ).yellow().to_string()),
                 Ok(_) => pb.finish_with_message("Publish complete".green().to_string()),
                 Err(e) => pb.finish_with_message(format!("Publish failed: {}", e).red().to_string()),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_package, config_in, open_db, Staged};
+
+    #[test]
+    fn foreign_arch_installs_onto_the_live_root_are_refused() {
+        let foreign = if arch::host_arch() == "aarch64" { "x86_64" } else { "aarch64" };
+        let command = |args: &[&str]| Cli::parse_from([&["nxpkg"], args].concat()).command;
+        let mut cfg = AppConfig { root: PathBuf::from("/"), arch: foreign.to_string(), ..AppConfig::default() };
+
+        assert!(installs_foreign_binaries(&command(&["install", "app"]), &cfg));
+        assert!(installs_foreign_binaries(&command(&["upgrade", "--all"]), &cfg));
+        assert!(!installs_foreign_binaries(&command(&["install", "app", "--download-only"]), &cfg));
+        assert!(!installs_foreign_binaries(&command(&["upgrade", "--all", "--dry-run"]), &cfg));
+        assert!(!installs_foreign_binaries(&command(&["search", "app"]), &cfg));
+
+        cfg.root = PathBuf::from("/srv/rootfs");
+        assert!(!installs_foreign_binaries(&command(&["install", "app"]), &cfg));
+        cfg.root = PathBuf::from("/");
+        cfg.arch = arch::host_arch().to_uppercase();
+        assert!(!installs_foreign_binaries(&command(&["install", "app"]), &cfg));
+    }
+
+    #[test]
+    fn upgrade_all_selects_installed_packages_with_a_newer_indexed_version() {
+        let index: download::RepoIndex = serde_json::from_value(serde_json::json!({ "packages": {
+            "newer": { "latest_version": "1.10", "description": "" },
+            "same": { "latest_version": "2.0", "description": "" },
+            "older": { "latest_version": "0.9", "description": "" },
+            "not-installed": { "latest_version": "5.0", "description": "" },
+        }}))
+        .unwrap();
+        let installed = [("newer", "1.9"), ("same", "2.0"), ("older", "1.0"), ("local-only", "1.0")]
+            .iter()
+            .map(|(name, version)| {
+                let mut recipe = PackageRecipe::default();
+                recipe.package.name = name.to_string();
+                recipe.package.version = version.to_string();
+                recipe
+            })
+            .collect();
+
+        let outdated: Vec<String> = outdated_packages(&index, installed).into_iter().map(|r| r.package.name).collect();
+
+        assert_eq!(outdated, ["newer"]);
+    }
+
+    #[tokio::test]
+    async fn install_and_remove_stay_inside_the_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config_in(tmp.path());
+        let db = open_db(&cfg);
+        let pkg = build_package(
+            tmp.path(),
+            "[package]\nname = rooted\nversion = 1.0\narchitectures = any\n",
+            &[Staged::File("usr/bin/nxpkg-rooted-test", "#!/bin/sh\n"), Staged::Dir("var/lib/nxpkg-rooted-test")],
+        );
+        let opts = InstallOptions { no_deps: true, assume_yes: true };
+        install::install_from_source(&cfg, &db, &PackageSource::Local(pkg), &opts, &Spinner::new("{msg}")).await.unwrap();
+
+        let bin = compress::path_in_root(&cfg.root, Path::new("/usr/bin/nxpkg-rooted-test"));
+        assert_eq!(bin, cfg.root.join("usr/bin/nxpkg-rooted-test"));
+        assert!(bin.is_file());
+        assert!(cfg.root.join("var/lib/nxpkg-rooted-test").is_dir());
+        assert!(!Path::new("/usr/bin/nxpkg-rooted-test").exists());
+
+        remove_package(&cfg, &db, "rooted", false, false);
+        assert!(db.get_package_metadata("rooted").unwrap().is_none());
+        assert!(!bin.exists());
+        assert!(!cfg.root.join("var/lib/nxpkg-rooted-test").exists());
+        assert!(cfg.root.is_dir());
+    }
+
+    #[tokio::test]
+    async fn deregistering_leaves_files_in_the_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config_in(tmp.path());
+        let db = open_db(&cfg);
+        let pkg = build_package(
+            tmp.path(),
+            "[package]\nname = kept\nversion = 1.0\narchitectures = any\n",
+            &[Staged::File("etc/kept.conf", "x = 1\n")],
+        );
+        let opts = InstallOptions { no_deps: true, assume_yes: true };
+        install::install_from_source(&cfg, &db, &PackageSource::Local(pkg), &opts, &Spinner::new("{msg}")).await.unwrap();
+
+        remove_package(&cfg, &db, "kept", true, false);
+        assert!(db.get_package_metadata("kept").unwrap().is_none());
+        assert!(cfg.root.join("etc/kept.conf").is_file());
+    }
+
+    #[test]
+    fn search_reports_an_empty_repository() {
+        let empty = download::RepoIndex { packages: Default::default() };
+        assert_eq!(
+            empty_repository_notice(&empty, "https://repo.example/nx").as_deref(),
+            Some("The repository at https://repo.example/nx has no packages yet.")
+        );
+        let index: download::RepoIndex =
+            serde_json::from_str(r#"{"packages":{"app":{"latest_version":"1","description":""}}}"#).unwrap();
+        assert_eq!(empty_repository_notice(&index, "https://repo.example/nx"), None);
+    }
+}
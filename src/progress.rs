@@ -0,0 +1,143 @@
+//! src/progress.rs
+//! Spinners and progress bars that stay out of redirected output (logs, CI) and `--quiet` runs.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::ops::Deref;
+use std::sync::{Mutex, OnceLock};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+static INTERACTIVE: OnceLock<bool> = OnceLock::new();
+
+/// Decide once, at startup, whether progress is animated: stdout must be a terminal and
+/// `--quiet` not given.
+pub fn init(quiet: bool) {
+    let _ = INTERACTIVE.set(!quiet && std::io::stdout().is_terminal());
+}
+
+fn interactive() -> bool {
+    *INTERACTIVE.get_or_init(|| std::io::stdout().is_terminal())
+}
+
+/// A progress bar for `total` bytes, hidden when progress is not animated.
+pub fn bar(total: u64) -> ProgressBar {
+    if interactive() { ProgressBar::new(total) } else { ProgressBar::hidden() }
+}
+
+/// A steadily ticking spinner. When progress is not animated the spinner is hidden, and the
+/// status lines it would have shown (`println`, `finish_with_message`) are printed as plain lines.
+pub struct Spinner {
+    pb: ProgressBar,
+}
+
+impl Spinner {
+    /// `template` is an indicatif template, e.g. `"{spinner:.blue} {elapsed_precise} {msg}"`.
+    pub fn new(template: &str) -> Self {
+        if !interactive() {
+            return Spinner { pb: ProgressBar::hidden() };
+        }
+        let pb = ProgressBar::new_spinner();
+        pb.enable_steady_tick(std::time::Duration::from_millis(120));
+        pb.set_style(ProgressStyle::with_template(template).unwrap());
+        Spinner { pb }
+    }
+
+    pub fn println(&self, msg: impl AsRef<str>) {
+        if self.pb.is_hidden() {
+            println!("{}", msg.as_ref());
+        } else {
+            self.pb.println(msg);
+        }
+    }
+
+    pub fn finish_with_message(&self, msg: impl Into<Cow<'static, str>>) {
+        let msg = msg.into();
+        if self.pb.is_hidden() {
+            println!("{}", msg);
+        }
+        self.pb.finish_with_message(msg);
+    }
+}
+
+impl Deref for Spinner {
+    type Target = ProgressBar;
+
+    fn deref(&self) -> &ProgressBar {
+        &self.pb
+    }
+}
+
+/// Receives progress from the download code, so it can be shown by something other than the
+/// terminal bars (a GUI, a TUI, a log). Downloads are told apart by URL, since several may run
+/// at once. Every method does nothing by default.
+pub trait ProgressSink: Send + Sync {
+    /// `done` bytes of `url` have arrived, of `total` when the server announced a size.
+    fn on_download_progress(&self, _url: &str, _done: u64, _total: Option<u64>) {}
+    /// The download of `url` ended; `ok` is false when it failed. `msg` says how it ended.
+    fn on_download_end(&self, _url: &str, _ok: bool, _msg: &str) {}
+    /// A new step began, e.g. "Downloading 'foo' 1.2".
+    fn on_stage(&self, _msg: &str) {}
+}
+
+/// The sink used when the caller passes none.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// The CLI's sink: one indicatif bar per download, hidden when progress is not animated.
+/// Stages are not shown; the commands print their own status lines.
+pub struct DownloadBars {
+    /// Draws concurrent downloads on separate lines
+    multi: Option<MultiProgress>,
+    bars: Mutex<HashMap<String, ProgressBar>>,
+}
+
+impl DownloadBars {
+    /// Bars for downloads that run one at a time.
+    pub fn single() -> Self {
+        DownloadBars { multi: None, bars: Mutex::new(HashMap::new()) }
+    }
+
+    /// Bars for downloads that run concurrently.
+    pub fn concurrent() -> Self {
+        DownloadBars { multi: Some(MultiProgress::new()), ..Self::single() }
+    }
+
+    fn bar_for(&self, url: &str, total: Option<u64>) -> ProgressBar {
+        let mut bars = self.bars.lock().unwrap_or_else(|e| e.into_inner());
+        bars.entry(url.to_string())
+            .or_insert_with(|| {
+                let pb = bar(total.unwrap_or(0));
+                let pb = match &self.multi {
+                    Some(m) => m.add(pb),
+                    None => pb,
+                };
+                pb.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})")
+                        .unwrap()
+                        .progress_chars("#>-"),
+                );
+                pb
+            })
+            .clone()
+    }
+}
+
+impl ProgressSink for DownloadBars {
+    fn on_download_progress(&self, url: &str, done: u64, total: Option<u64>) {
+        self.bar_for(url, total).set_position(done);
+    }
+
+    fn on_download_end(&self, url: &str, ok: bool, msg: &str) {
+        let pb = self.bar_for(url, None);
+        self.bars.lock().unwrap_or_else(|e| e.into_inner()).remove(url);
+        if ok {
+            pb.finish_with_message(msg.to_string());
+        } else {
+            pb.abandon_with_message(msg.to_string());
+        }
+    }
+}
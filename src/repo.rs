@@ -1,7 +1,7 @@
 use serde::Deserialize;
 use colored::*;
-use std::io::{self, Write};
-use std::path::{Path, PathBuf};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
 use std::collections::BTreeMap;
 use std::fs;
 // src/buildins/mod.rs
@@ -16,6 +16,32 @@ pub struct RepoInfo {
     pub owner: String,
     pub clone_url: String,
     pub source: String, // "GitHub" or "GitLab"
+    /// `[repos.<group>]` section the entry came from; None for `[repos]` and forge results
+    pub group: Option<String>,
+}
+
+impl RepoInfo {
+    /// SSH form of an HTTPS GitHub/GitLab clone URL (`git@github.com:owner/repo.git`).
+    pub fn ssh_clone_url(&self) -> Option<String> {
+        let rest = self.clone_url.strip_prefix("https://")?;
+        let (host, path) = rest.split_once('/')?;
+        if !matches!(host, "github.com" | "gitlab.com") || path.is_empty() {
+            return None;
+        }
+        let path = if path.ends_with(".git") { path.to_string() } else { format!("{}.git", path) };
+        Some(format!("git@{}:{}", host, path))
+    }
+}
+
+/// Where `find_and_select_repo` looks for source repositories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchSource {
+    /// Only repos from repos.cfg
+    Configured,
+    /// Only GitHub
+    Github,
+    /// Only GitLab
+    Gitlab,
 }
 
 // Structs for deserializing the GitHub API response
@@ -67,6 +93,7 @@ fn search_github(term: &str) -> Result<Vec<RepoInfo>, Box<dyn std::error::Error>
         owner: repo.owner.login,
         clone_url: repo.clone_url,
         source: "GitHub".to_string(),
+        group: None,
     }).collect();
 
     Ok(repos)
@@ -84,6 +111,7 @@ fn search_gitlab(term: &str) -> Result<Vec<RepoInfo>, Box<dyn std::error::Error>
         owner: repo.owner.map_or_else(|| "Unknown".to_string(), |o| o.name),
         clone_url: repo.http_url_to_repo,
         source: "GitLab".to_string(),
+        group: None,
     }).collect();
 
     Ok(repos)
@@ -111,21 +139,51 @@ fn default_repo_cfg_paths() -> Vec<PathBuf> {
     paths
 }
 
-fn parse_repo_cfg(content: &str) -> Vec<RepoInfo> {
-    let mut in_repos = false;
-    let mut out = Vec::new();
+/// Group of a repos.cfg section: `[repos]` is the ungrouped list (`Some(None)`),
+/// `[repos.<group>]` a named group; any other section is not a repo list (`None`).
+fn repo_section_group(section: &str) -> Option<Option<String>> {
+    let section = section.trim();
+    if section.eq_ignore_ascii_case("repos") {
+        return Some(None);
+    }
+    let (head, group) = section.split_once('.')?;
+    let group = group.trim();
+    (head.trim().eq_ignore_ascii_case("repos") && !group.is_empty()).then(|| Some(group.to_string()))
+}
+
+/// The entries of each repo section in `content`, in file order. A section appearing twice
+/// (group names compare case-insensitively) is merged under its first spelling; a later value
+/// for the same name wins.
+fn parse_repo_sections(content: &str) -> Vec<(Option<String>, BTreeMap<String, String>)> {
+    let mut sections: Vec<(Option<String>, BTreeMap<String, String>)> = Vec::new();
+    let mut current: Option<usize> = None;
     for line in content.lines() {
         let line = line.trim();
         if line.is_empty() || line.starts_with('#') || line.starts_with(';') { continue; }
         if line.starts_with('[') && line.ends_with(']') {
-            let sec = &line[1..line.len()-1];
-            in_repos = sec.eq_ignore_ascii_case("repos");
+            current = repo_section_group(&line[1..line.len()-1]).map(|group| {
+                match sections.iter().position(|(g, _)| same_group(g.as_deref(), group.as_deref())) {
+                    Some(i) => i,
+                    None => {
+                        sections.push((group, BTreeMap::new()));
+                        sections.len() - 1
+                    }
+                }
+            });
             continue;
         }
-        if !in_repos { continue; }
+        let Some(i) = current else { continue };
         if let Some((name, url)) = line.split_once('=') {
-            let name = name.trim().to_string();
-            let url = url.trim().to_string();
+            sections[i].1.insert(name.trim().to_string(), url.trim().to_string());
+        }
+    }
+    sections
+}
+
+fn parse_repo_cfg(content: &str) -> Vec<RepoInfo> {
+    let mut out = Vec::new();
+    for (group, entries) in parse_repo_sections(content) {
+        for (name, url) in entries {
             // Heuristic parse to fill owner/source
             let lower = url.to_lowercase();
             let source = if lower.contains("github.com") { "GitHub" } else if lower.contains("gitlab.com") { "GitLab" } else { "Custom" };
@@ -139,18 +197,69 @@ fn parse_repo_cfg(content: &str) -> Vec<RepoInfo> {
             };
             // Normalize display name as owner/repo if possible
             let display_name = if !owner.is_empty() {
-                let rest = url.split('/').rev().next().unwrap_or("");
+                let rest = url.split('/').next_back().unwrap_or("");
                 let repo = rest.trim_end_matches(".git");
                 format!("{}/{}", owner, repo)
             } else {
                 name.clone()
             };
-            out.push(RepoInfo { name: display_name, owner, clone_url: url, source: source.to_string() });
+            out.push(RepoInfo { name: display_name, owner, clone_url: url, source: source.to_string(), group: group.clone() });
         }
     }
     out
 }
 
+/// Group names compare case-insensitively, like section names.
+fn same_group(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => false,
+    }
+}
+
+/// Set `name` to `url` in the section of `group`, adding the section when there is none.
+fn upsert_repo(sections: &mut Vec<(Option<String>, BTreeMap<String, String>)>, name: &str, url: &str, group: Option<&str>) {
+    let group = group.map(str::trim);
+    let i = match sections.iter().position(|(g, _)| same_group(g.as_deref(), group)) {
+        Some(i) => i,
+        None => {
+            sections.push((group.map(str::to_string), BTreeMap::new()));
+            sections.len() - 1
+        }
+    };
+    sections[i].1.insert(name.trim().to_string(), url.trim().to_string());
+}
+
+/// Drop `name` from the section of `group`, or from every section without a group.
+fn remove_repo(sections: &mut [(Option<String>, BTreeMap<String, String>)], name: &str, group: Option<&str>) {
+    for (g, entries) in sections.iter_mut() {
+        if group.is_none_or(|wanted| same_group(g.as_deref(), Some(wanted.trim()))) {
+            entries.remove(name);
+        }
+    }
+}
+
+/// Write the repo sections back to the user repos.cfg, ungrouped `[repos]` first.
+fn write_repo_sections(path: &std::path::Path, mut sections: Vec<(Option<String>, BTreeMap<String, String>)>) -> io::Result<()> {
+    sections.retain(|(g, entries)| g.is_none() || !entries.is_empty());
+    if !sections.iter().any(|(g, _)| g.is_none()) {
+        sections.insert(0, (None, BTreeMap::new()));
+    }
+    sections.sort_by_key(|(g, _)| g.is_some());
+    let mut out = String::new();
+    for (group, entries) in sections {
+        if !out.is_empty() { out.push('\n'); }
+        match group {
+            Some(g) => out.push_str(&format!("[repos.{}]\n", g)),
+            None => out.push_str("[repos]\n"),
+        }
+        for (k, v) in entries { out.push_str(&format!("{} = {}\n", k, v)); }
+    }
+    if let Some(parent) = path.parent() { let _ = fs::create_dir_all(parent); }
+    fs::write(path, out)
+}
+
 pub fn configured_repos() -> Vec<RepoInfo> {
     let mut repos = Vec::new();
     for p in default_repo_cfg_paths() {
@@ -164,73 +273,52 @@ pub fn configured_repos() -> Vec<RepoInfo> {
     repos
 }
 
-pub fn search_config_repos(term: &str) -> Vec<RepoInfo> {
+/// The configured repo named exactly `name` (or whose name ends in `/<name>`), if any.
+pub fn configured_repo_named(name: &str) -> Option<RepoInfo> {
+    configured_repos()
+        .into_iter()
+        .find(|r| r.name == name || r.name.rsplit('/').next() == Some(name))
+}
+
+/// Configured repos whose name or URL contains `term`, limited to `group` when given.
+pub fn search_config_repos(term: &str, group: Option<&str>) -> Vec<RepoInfo> {
     let t = term.to_lowercase();
     configured_repos()
         .into_iter()
+        .filter(|r| in_group(r, group))
         .filter(|r| r.name.to_lowercase().contains(&t) || r.clone_url.to_lowercase().contains(&t))
         .collect()
 }
 
 // --- Config management helpers ---
 
-pub fn add_repo_entry(name: &str, url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut map: BTreeMap<String, String> = BTreeMap::new();
+/// Add or update `name` in the user repos.cfg, in `[repos.<group>]` or, without a group, `[repos]`.
+pub fn add_repo_entry(name: &str, url: &str, group: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
     let user_path = user_repo_cfg_path();
-    if let Ok(content) = fs::read_to_string(&user_path) {
-        let mut in_repos = false;
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') || line.starts_with(';') { continue; }
-            if line.starts_with('[') && line.ends_with(']') {
-                in_repos = &line[1..line.len()-1] == "repos";
-                continue;
-            }
-            if !in_repos { continue; }
-            if let Some((k, v)) = line.split_once('=') {
-                map.insert(k.trim().to_string(), v.trim().to_string());
-            }
-        }
-    }
-    map.insert(name.trim().to_string(), url.trim().to_string());
-
-    if let Some(parent) = user_path.parent() { let _ = fs::create_dir_all(parent); }
-    let mut out = String::new();
-    out.push_str("[repos]\n");
-    for (k, v) in map { out.push_str(&format!("{} = {}\n", k, v)); }
-    fs::write(&user_path, out)?;
+    let mut sections = parse_repo_sections(&fs::read_to_string(&user_path).unwrap_or_default());
+    upsert_repo(&mut sections, name, url, group);
+    write_repo_sections(&user_path, sections)?;
     Ok(())
 }
 
-pub fn remove_repo_entry(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Remove `name` from the user repos.cfg: from `[repos.<group>]` when a group is given,
+/// otherwise from every section. Other entries and groups are kept.
+pub fn remove_repo_entry(name: &str, group: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
     let user_path = user_repo_cfg_path();
-    let mut map: BTreeMap<String, String> = BTreeMap::new();
-    if let Ok(content) = fs::read_to_string(&user_path) {
-        let mut in_repos = false;
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') || line.starts_with(';') { continue; }
-            if line.starts_with('[') && line.ends_with(']') {
-                in_repos = &line[1..line.len()-1] == "repos";
-                continue;
-            }
-            if !in_repos { continue; }
-            if let Some((k, v)) = line.split_once('=') {
-                let key = k.trim().to_string();
-                if key != name { map.insert(key, v.trim().to_string()); }
-            }
-        }
-    }
-    if let Some(parent) = user_path.parent() { let _ = fs::create_dir_all(parent); }
-    let mut out = String::new();
-    out.push_str("[repos]\n");
-    for (k, v) in map { out.push_str(&format!("{} = {}\n", k, v)); }
-    fs::write(&user_path, out)?;
+    let mut sections = parse_repo_sections(&fs::read_to_string(&user_path).unwrap_or_default());
+    remove_repo(&mut sections, name, group);
+    write_repo_sections(&user_path, sections)?;
     Ok(())
 }
 
-pub fn select_repo_from_config(term: Option<&str>) -> Result<RepoInfo, Box<dyn std::error::Error>> {
+/// True if `repo` belongs to `group`; any repo matches when no group is asked for.
+pub fn in_group(repo: &RepoInfo, group: Option<&str>) -> bool {
+    group.is_none_or(|g| same_group(repo.group.as_deref(), Some(g)))
+}
+
+pub fn select_repo_from_config(term: Option<&str>, group: Option<&str>) -> Result<RepoInfo, Box<dyn std::error::Error>> {
     let mut list = configured_repos();
+    list.retain(|r| in_group(r, group));
     if let Some(t) = term {
         let tl = t.to_lowercase();
         list.retain(|r| r.name.to_lowercase().contains(&tl) || r.clone_url.to_lowercase().contains(&tl));
@@ -260,33 +348,87 @@ pub fn select_repo_from_config(term: Option<&str>) -> Result<RepoInfo, Box<dyn s
     }
 }
 
+/// Index of the one result named exactly `term`: the full `owner/name` when `term` has a
+/// `/`, else the repository name alone. None when no result or more than one matches.
+fn unique_exact_match(repos: &[RepoInfo], term: &str) -> Option<usize> {
+    let mut matches = repos.iter().enumerate().filter(|(_, r)| {
+        let name = if term.contains('/') { r.name.as_str() } else { r.name.rsplit('/').next().unwrap_or(&r.name) };
+        name.eq_ignore_ascii_case(term)
+    });
+    let (i, _) = matches.next()?;
+    matches.next().is_none().then_some(i)
+}
+
 // --- Public API ---
 
-/// Finds a repository by searching GitHub and GitLab, then prompts the user to select one.
-pub fn find_and_select_repo(term: &str) -> Result<RepoInfo, Box<dyn std::error::Error>> {
-    // Prefer configured repos first
-    let mut all_repos = search_config_repos(term);
-    if !all_repos.is_empty() {
-        println!("{}", "Found matches in configured repos".cyan());
-    } else {
-        // Fallback to remote searches
-        println!("{}", "Searching on GitHub...".cyan());
-        match search_github(term) {
-            Ok(repos) => all_repos.extend(repos),
-            Err(e) => eprintln!("{} {}", "GitHub search failed:".yellow(), e),
+/// Finds a repository by searching configured repos, then GitHub and GitLab, and prompts the
+/// user to select one. `source` limits the search to a single place; `None` searches everything.
+/// With `jobs > 1` the GitHub and GitLab searches run concurrently.
+///
+/// The only result whose `owner/name` (or name, when `term` has no `/`) equals `term` is
+/// selected without asking; several such results are treated like any other matches.
+/// Otherwise `pick` selects the n-th result (1-based) instead of prompting; without it and
+/// without a terminal this fails rather than waiting for input.
+///
+/// With `group`, only configured repos of that `[repos.<group>]` section are considered.
+pub fn find_and_select_repo(
+    term: &str,
+    source: Option<SearchSource>,
+    jobs: usize,
+    pick: Option<usize>,
+    group: Option<&str>,
+) -> Result<RepoInfo, Box<dyn std::error::Error>> {
+    let mut all_repos = Vec::new();
+    if group.is_some() || matches!(source, None | Some(SearchSource::Configured)) {
+        // Prefer configured repos first
+        all_repos = search_config_repos(term, group);
+        if !all_repos.is_empty() {
+            println!("{}", "Found matches in configured repos".cyan());
         }
-
-        println!("{}", "Searching on GitLab...".cyan());
-        match search_gitlab(term) {
-            Ok(repos) => all_repos.extend(repos),
-            Err(e) => eprintln!("{} {}", "GitLab search failed:".yellow(), e),
+    }
+    // Fall back to remote searches only when configured repos had nothing
+    if all_repos.is_empty() && group.is_none() {
+        let github = matches!(source, None | Some(SearchSource::Github));
+        let gitlab = matches!(source, None | Some(SearchSource::Gitlab));
+        let (github_result, gitlab_result) = if github && gitlab && jobs > 1 {
+            println!("{}", "Searching on GitHub and GitLab...".cyan());
+            std::thread::scope(|scope| {
+                let gh = scope.spawn(|| search_github(term).map_err(|e| e.to_string()));
+                let gl = search_gitlab(term).map_err(|e| e.to_string());
+                let gh = gh.join().unwrap_or_else(|_| Err("search thread panicked".to_string()));
+                (Some(gh), Some(gl))
+            })
+        } else {
+            let gh = github.then(|| {
+                println!("{}", "Searching on GitHub...".cyan());
+                search_github(term).map_err(|e| e.to_string())
+            });
+            let gl = gitlab.then(|| {
+                println!("{}", "Searching on GitLab...".cyan());
+                search_gitlab(term).map_err(|e| e.to_string())
+            });
+            (gh, gl)
+        };
+
+        match github_result {
+            Some(Ok(repos)) => all_repos.extend(repos),
+            Some(Err(e)) => eprintln!("{} {}", "GitHub search failed:".yellow(), e),
+            None => {}
+        }
+        match gitlab_result {
+            Some(Ok(repos)) => all_repos.extend(repos),
+            Some(Err(e)) => eprintln!("{} {}", "GitLab search failed:".yellow(), e),
+            None => {}
         }
     }
 
     // --- Process Results ---
 
     if all_repos.is_empty() {
-        return Err("No repositories found.".into());
+        return Err(match group {
+            Some(g) => format!("No repositories found in group '{}'.", g),
+            None => "No repositories found.".to_string(),
+        }.into());
     }
 
     if all_repos.len() == 1 {
@@ -294,6 +436,21 @@ pub fn find_and_select_repo(term: &str) -> Result<RepoInfo, Box<dyn std::error::
         return Ok(all_repos.remove(0));
     }
 
+    if let Some(i) = unique_exact_match(&all_repos, term) {
+        let repo = all_repos.remove(i);
+        println!("{}", format!("Selected exact match {} ({}).", repo.name, repo.source).green());
+        return Ok(repo);
+    }
+
+    if let Some(n) = pick {
+        if n == 0 || n > all_repos.len() {
+            return Err(format!("--pick {} is out of range: {} repositories found.", n, all_repos.len()).into());
+        }
+        let repo = all_repos.remove(n - 1);
+        println!("{}", format!("Selected result {}: {} ({}).", n, repo.name, repo.source).green());
+        return Ok(repo);
+    }
+
     // --- Prompt User for Selection ---
     
     println!("\n{}", "Multiple repositories found. Please choose one:".green());
@@ -314,12 +471,18 @@ pub fn find_and_select_repo(term: &str) -> Result<RepoInfo, Box<dyn std::error::
         println!("  [{}] {}", "11".bold(), "Show all contributors/options... (Not implemented yet)".dimmed());
     }
 
+    if !io::stdin().is_terminal() {
+        return Err("Multiple repositories match and stdin is not a terminal; re-run with --pick <n> or --yes.".into());
+    }
+
     loop {
         print!("{}", "\nEnter your choice (number): ".bold());
         io::stdout().flush()?; // Ensure the prompt is shown before reading input
 
         let mut choice = String::new();
-        io::stdin().read_line(&mut choice)?;
+        if io::stdin().read_line(&mut choice)? == 0 {
+            return Err("No repository selected.".into());
+        }
 
         match choice.trim().parse::<usize>() {
             Ok(n) if n > 0 && n <= display_count => {
@@ -331,3 +494,95 @@ pub fn find_and_select_repo(term: &str) -> Result<RepoInfo, Box<dyn std::error::
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Listing<'a> = Vec<(Option<&'a str>, Vec<(&'a str, &'a str)>)>;
+
+    fn listing(sections: &[(Option<String>, BTreeMap<String, String>)]) -> Listing<'_> {
+        sections.iter()
+            .map(|(g, entries)| (g.as_deref(), entries.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()))
+            .collect()
+    }
+
+    fn found(names: &[&str]) -> Vec<RepoInfo> {
+        names.iter()
+            .map(|n| RepoInfo {
+                name: n.to_string(),
+                owner: n.split('/').next().unwrap().to_string(),
+                clone_url: format!("https://github.com/{}.git", n),
+                source: "GitHub".to_string(),
+                group: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exact_match_is_selected_only_when_unique() {
+        let repos = found(&["alice/ripgrep-extras", "BurntSushi/ripgrep", "bob/ripgrep-all"]);
+        assert_eq!(unique_exact_match(&repos, "ripgrep"), Some(1));
+        assert_eq!(unique_exact_match(&repos, "burntsushi/RIPGREP"), Some(1));
+        assert_eq!(unique_exact_match(&repos, "rip"), None);
+
+        let forks = found(&["BurntSushi/ripgrep", "carol/ripgrep"]);
+        assert_eq!(unique_exact_match(&forks, "ripgrep"), None);
+        assert_eq!(unique_exact_match(&forks, "carol/ripgrep"), Some(1));
+        assert_eq!(unique_exact_match(&forks, "dave/ripgrep"), None);
+    }
+
+    #[test]
+    fn flat_repos_cfg_is_one_ungrouped_section() {
+        let sections = parse_repo_sections("# mine\n[repos]\nfoo = https://github.com/a/foo.git\nbar=https://x/bar\n");
+        assert_eq!(listing(&sections), [(None, vec![("bar", "https://x/bar"), ("foo", "https://github.com/a/foo.git")])]);
+
+        let repos = parse_repo_cfg("[repos]\nfoo = https://github.com/a/foo.git\n");
+        assert_eq!((repos[0].name.as_str(), repos[0].owner.as_str(), repos[0].group.as_deref()), ("a/foo", "a", None));
+    }
+
+    #[test]
+    fn grouped_repos_cfg_keeps_groups_and_skips_other_sections() {
+        let cfg = "[repos]\nfoo = https://x/foo\n[repos.trusted]\nbar = https://x/bar\n\
+                   [other]\nignored = https://x/no\n[REPOS.Trusted]\nbaz = https://x/baz\n[repos.]\nnone = https://x/no\n";
+        let sections = parse_repo_sections(cfg);
+        assert_eq!(listing(&sections), [
+            (None, vec![("foo", "https://x/foo")]),
+            (Some("trusted"), vec![("bar", "https://x/bar"), ("baz", "https://x/baz")]),
+        ]);
+
+        let repos = parse_repo_cfg(cfg);
+        let trusted: Vec<_> = repos.iter().filter(|r| in_group(r, Some("TRUSTED"))).map(|r| r.name.as_str()).collect();
+        assert_eq!(trusted, ["bar", "baz"]);
+        assert_eq!(repos.iter().filter(|r| in_group(r, None)).count(), 3);
+        assert_eq!(repos.iter().filter(|r| in_group(r, Some("other"))).count(), 0);
+    }
+
+    #[test]
+    fn editing_a_group_ignores_case() {
+        let mut sections = parse_repo_sections("[repos.Trusted]\nbar = https://x/bar\n");
+        upsert_repo(&mut sections, "baz", "https://x/baz", Some(" trusted "));
+        upsert_repo(&mut sections, "qux", "https://x/qux", None);
+        assert_eq!(listing(&sections), [
+            (Some("Trusted"), vec![("bar", "https://x/bar"), ("baz", "https://x/baz")]),
+            (None, vec![("qux", "https://x/qux")]),
+        ]);
+
+        remove_repo(&mut sections, "bar", Some("TRUSTED"));
+        remove_repo(&mut sections, "qux", Some("trusted"));
+        assert_eq!(listing(&sections), [
+            (Some("Trusted"), vec![("baz", "https://x/baz")]),
+            (None, vec![("qux", "https://x/qux")]),
+        ]);
+    }
+
+    #[test]
+    fn written_sections_parse_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nxpkg/repos.cfg");
+        let mut sections = parse_repo_sections("[repos.trusted]\nbar = https://x/bar\n");
+        upsert_repo(&mut sections, "foo", "https://x/foo", None);
+        write_repo_sections(&path, sections).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[repos]\nfoo = https://x/foo\n\n[repos.trusted]\nbar = https://x/bar\n");
+    }
+}
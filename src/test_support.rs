@@ -0,0 +1,129 @@
+//! src/test_support.rs
+//! Fixtures shared by the unit tests: packages built from a throwaway staging directory and a
+//! configuration that keeps the root, database and cache inside a temporary directory, and a
+//! scripted HTTP server.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::buildins::meta::PackageRecipe;
+use crate::compress::{self, PackOptions};
+use crate::config::AppConfig;
+use crate::db::PackageManagerDB;
+
+/// A staged payload entry: a file with its contents, or a directory.
+pub enum Staged<'a> {
+    File(&'a str, &'a str),
+    Dir(&'a str),
+}
+
+/// Pack `entries` (paths relative to `/`) with the recipe `recipe_cfg` into
+/// `<dir>/<name>-<version>.nxpkg`.
+pub fn build_package(dir: &Path, recipe_cfg: &str, entries: &[Staged]) -> PathBuf {
+    let recipe = PackageRecipe::from_str(recipe_cfg).unwrap();
+    let staging = dir.join(format!("stage-{}-{}", recipe.package.name, recipe.package.version));
+    fs::create_dir_all(&staging).unwrap();
+    for entry in entries {
+        match entry {
+            Staged::File(path, contents) => {
+                let path = staging.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            Staged::Dir(path) => fs::create_dir_all(staging.join(path)).unwrap(),
+        }
+    }
+    let out = dir.join(format!("{}-{}.nxpkg", recipe.package.name, recipe.package.version));
+    compress::create_nxpkg(&staging, &recipe, &out, &PackOptions::default()).unwrap();
+    out
+}
+
+/// A configuration installing into `<dir>/root`, with its database and cache under `dir`.
+pub fn config_in(dir: &Path) -> AppConfig {
+    let mut cfg = AppConfig {
+        root: dir.join("root"),
+        db_path: dir.join("db/nxpkg_meta.db"),
+        cache_dir: dir.join("cache"),
+        require_signed_index: false,
+        ..AppConfig::default()
+    };
+    cfg.arch = crate::arch::host_arch().to_string();
+    fs::create_dir_all(&cfg.root).unwrap();
+    fs::create_dir_all(cfg.db_path.parent().unwrap()).unwrap();
+    cfg
+}
+
+/// The package database of `cfg`.
+pub fn open_db(cfg: &AppConfig) -> PackageManagerDB {
+    PackageManagerDB::new(cfg.db_path.to_str().unwrap()).unwrap()
+}
+
+/// A request received by `mock_server`.
+pub struct Received {
+    pub method: String,
+    pub target: String,
+    /// Header names are lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Received {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Status, extra headers and body of a response.
+pub type Reply = (u16, Vec<(&'static str, String)>, String);
+
+/// Serve HTTP/1.1 on a local port, answering each request with `respond` and recording it.
+/// Returns the server's base URL (`http://127.0.0.1:<port>`) and the request log.
+pub async fn mock_server(
+    respond: impl Fn(&Received) -> Reply + Send + Sync + 'static,
+) -> (String, Arc<Mutex<Vec<Received>>>) {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let respond = Arc::new(respond);
+    let server_log = log.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { return };
+            let (log, respond) = (server_log.clone(), respond.clone());
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                loop {
+                    let mut line = String::new();
+                    if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let mut words = line.split_whitespace();
+                    let method = words.next().unwrap_or_default().to_string();
+                    let target = words.next().unwrap_or_default().to_string();
+                    let mut headers = Vec::new();
+                    loop {
+                        let mut line = String::new();
+                        stream.read_line(&mut line).await.unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(':') else { break };
+                        headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+                    }
+                    let mut req = Received { method, target, headers, body: Vec::new() };
+                    let len = req.header("content-length").map_or(0, |v| v.parse().unwrap());
+                    req.body.resize(len, 0);
+                    stream.read_exact(&mut req.body).await.unwrap();
+                    let (status, extra, body) = respond(&req);
+                    log.lock().unwrap().push(req);
+                    let mut head = format!("HTTP/1.1 {} X\r\ncontent-length: {}\r\n", status, body.len());
+                    for (name, value) in extra {
+                        head.push_str(&format!("{}: {}\r\n", name, value));
+                    }
+                    head.push_str("\r\n");
+                    stream.get_mut().write_all(format!("{}{}", head, body).as_bytes()).await.unwrap();
+                }
+            });
+        }
+    });
+    (base, log)
+}
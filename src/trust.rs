@@ -1,10 +1,147 @@
 // src/trust.rs
 // Minimal Ed25519 signature verification for repository index authenticity.
 
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
 use ed25519_dalek::{Signature, VerifyingKey};
+use zeroize::Zeroizing;
 
 pub fn verify_ed25519_index(index_bytes: &[u8], sig_bytes: &[u8], pubkey_bytes: &[u8]) -> bool {
     let Ok(vk) = VerifyingKey::from_bytes(pubkey_bytes.try_into().unwrap_or(&[0u8; 32])) else { return false };
     let Ok(sig) = Signature::from_slice(sig_bytes) else { return false };
     vk.verify_strict(index_bytes, &sig).is_ok()
 }
+
+/// Sign and verify a message with a freshly generated key (`nxpkg self-check`), confirming
+/// the ed25519 implementation works in this build. Also checks a tampered message is rejected.
+pub fn signing_round_trip() -> Result<(), String> {
+    let mut seed = Zeroizing::new([0u8; 32]);
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut seed[..]))
+        .map_err(|e| format!("cannot read /dev/urandom: {}", e))?;
+    let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+    let message = b"nxpkg self-check";
+    let sig = ed25519_dalek::Signer::sign(&key, message).to_bytes();
+    let pubkey = key.verifying_key().to_bytes();
+    if !verify_ed25519_index(message, &sig, &pubkey) {
+        return Err("a valid signature was rejected".to_string());
+    }
+    if verify_ed25519_index(b"nxpkg self-check!", &sig, &pubkey) {
+        return Err("a signature over different data was accepted".to_string());
+    }
+    Ok(())
+}
+
+/// Canonical form of a JSON document: object keys sorted, no insignificant whitespace.
+/// Used by `[security] canonical_signing` so re-serializing the index keeps signatures valid.
+pub fn canonical_json(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    // serde_json's Map is a BTreeMap (no `preserve_order`), so re-serializing sorts keys
+    let value: serde_json::Value = serde_json::from_slice(bytes).map_err(|e| format!("invalid JSON: {}", e))?;
+    serde_json::to_vec(&value).map_err(|e| e.to_string())
+}
+
+/// Read a base64 signing keypair from `path`. Files ending in `.age` or `.gpg`/`.asc` are
+/// decrypted with `age -d` / `gpg -d` first; their passphrase prompts go to the terminal. A
+/// plaintext key file must not be accessible to group or others (like an ssh private key); an
+/// encrypted one is protected by its passphrase, so its mode is not checked. The key material is
+/// wiped from memory when the returned value is dropped.
+pub fn read_signing_keypair(path: &Path) -> Result<Zeroizing<String>, String> {
+    let decrypt: Option<&[&str]> = match path.extension().and_then(|e| e.to_str()) {
+        Some("age") => Some(&["age", "--decrypt"]),
+        Some("gpg") | Some("asc") => Some(&["gpg", "--quiet", "--decrypt"]),
+        _ => None,
+    };
+    read_keypair_with(path, decrypt)
+}
+
+/// `read_signing_keypair` with the decryption command (`path` is appended) or None for plaintext.
+fn read_keypair_with(path: &Path, decrypt: Option<&[&str]>) -> Result<Zeroizing<String>, String> {
+    let Some(argv) = decrypt else {
+        let mode = std::fs::metadata(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "{} is accessible to other users (mode {:04o}); restrict it with chmod 600",
+                path.display(),
+                mode & 0o7777
+            ));
+        }
+        return std::fs::read_to_string(path)
+            .map(Zeroizing::new)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e));
+    };
+    let output = Command::new(argv[0])
+        .args(&argv[1..])
+        .arg(path)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("cannot run {} to decrypt {}: {}", argv[0], path.display(), e))?;
+    let plaintext = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        return Err(format!("{} could not decrypt {}", argv[0], path.display()));
+    }
+    String::from_utf8(plaintext.to_vec())
+        .map(Zeroizing::new)
+        .map_err(|_| format!("decrypted {} is not base64 text", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const KEY: &str = "c2lnbmluZy1rZXk=";
+
+    fn key_file(dir: &Path, name: &str, contents: &str, mode: u32) -> std::path::PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn plaintext_key_is_read_when_owner_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = key_file(tmp.path(), "publish.key", KEY, 0o600);
+        assert_eq!(read_signing_keypair(&path).unwrap().as_str(), KEY);
+    }
+
+    #[test]
+    fn plaintext_key_readable_by_others_is_refused() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = key_file(tmp.path(), "publish.key", KEY, 0o644);
+        let err = read_signing_keypair(&path).unwrap_err();
+        assert!(err.contains("mode 0644"), "{}", err);
+    }
+
+    #[test]
+    fn encrypted_key_is_decrypted_whatever_its_mode() {
+        let tmp = tempfile::tempdir().unwrap();
+        // rot13 stands in for age/gpg: the command gets the file and prints the plaintext
+        let rot13: &[&str] = &["sh", "-c", "tr a-zA-Z n-za-mN-ZA-M < \"$1\"", "sh"];
+        let encrypted: String = KEY.chars().map(|c| match c {
+            'a'..='m' | 'A'..='M' => (c as u8 + 13) as char,
+            'n'..='z' | 'N'..='Z' => (c as u8 - 13) as char,
+            _ => c,
+        }).collect();
+        let path = key_file(tmp.path(), "publish.key.age", &encrypted, 0o644);
+
+        assert_eq!(read_keypair_with(&path, Some(rot13)).unwrap().as_str(), KEY);
+    }
+
+    #[test]
+    fn failed_decryption_is_an_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        // age and gpg exit non-zero on a wrong passphrase
+        let wrong_passphrase: &[&str] = &["sh", "-c", "exit 2", "sh"];
+        let path = key_file(tmp.path(), "publish.key.gpg", "ciphertext", 0o600);
+
+        let err = read_keypair_with(&path, Some(wrong_passphrase)).unwrap_err();
+        assert!(err.contains("could not decrypt"), "{}", err);
+    }
+}
@@ -0,0 +1,188 @@
+//! src/version.rs
+//! Version comparison and version constraints used by dependency resolution.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Compare two version strings segment by segment.
+///
+/// Segments are split on `.`, `-`, `_` and `+`; numeric segments compare numerically,
+/// anything else compares lexically (case-insensitive). When one version runs out of
+/// segments, its missing numeric segments count as 0: a remaining non-zero number makes the
+/// longer version newer (`1.0.1 > 1.0`, but `1.0.0 == 1.0`), while a remaining alphabetic
+/// segment marks a pre-release (`1.0-rc1 < 1.0`).
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let sa = segments(a);
+    let sb = segments(b);
+    for i in 0..sa.len().max(sb.len()) {
+        let ord = match (sa.get(i), sb.get(i)) {
+            (Some(x), Some(y)) => compare_segment(x, y),
+            (Some(x), None) => tail_order(x),
+            (None, Some(y)) => tail_order(y).reverse(),
+            (None, None) => Ordering::Equal,
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+fn segments(v: &str) -> Vec<&str> {
+    let v = v.trim();
+    let v = v.strip_prefix('v').or_else(|| v.strip_prefix('V')).unwrap_or(v);
+    v.split(['.', '-', '_', '+']).filter(|s| !s.is_empty()).collect()
+}
+
+fn compare_segment(a: &str, b: &str) -> Ordering {
+    match (is_numeric(a), is_numeric(b)) {
+        (true, true) => {
+            let a = a.trim_start_matches('0');
+            let b = b.trim_start_matches('0');
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        }
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()),
+    }
+}
+
+fn tail_order(extra: &str) -> Ordering {
+    if is_numeric(extra) { compare_segment(extra, "0") } else { Ordering::Less }
+}
+
+fn is_numeric(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Refuse a package that declares a `min_nxpkg_version` newer than this nxpkg.
+pub fn check_client_version(package: &str, min_nxpkg_version: Option<&str>) -> Result<(), String> {
+    match min_nxpkg_version {
+        Some(min) if compare(crate::VERSION, min) == Ordering::Less => Err(format!(
+            "'{}' requires nxpkg >= {} (this is {}); upgrade nxpkg first.",
+            package, min, crate::VERSION
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Comparison operator of a version constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+}
+
+impl Op {
+    pub fn parse(s: &str) -> Option<Op> {
+        match s {
+            "=" | "==" => Some(Op::Eq),
+            ">=" => Some(Op::Ge),
+            ">" => Some(Op::Gt),
+            "<=" => Some(Op::Le),
+            "<" => Some(Op::Lt),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ge => ">=",
+            Op::Gt => ">",
+            Op::Le => "<=",
+            Op::Lt => "<",
+        }
+    }
+}
+
+/// A version requirement such as `>= 2.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub op: Op,
+    pub version: String,
+}
+
+impl Constraint {
+    /// True if `version` satisfies this constraint.
+    pub fn matches(&self, version: &str) -> bool {
+        let ord = compare(version, &self.version);
+        match self.op {
+            Op::Eq => ord == Ordering::Equal,
+            Op::Ge => ord != Ordering::Less,
+            Op::Gt => ord == Ordering::Greater,
+            Op::Le => ord != Ordering::Greater,
+            Op::Lt => ord == Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.op.as_str(), self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_orders_versions() {
+        let cases = [
+            ("1.0", "1.0", Ordering::Equal),
+            ("1.0.0", "1.0", Ordering::Equal),
+            ("2.0", "2.0.0.0", Ordering::Equal),
+            ("1.0.1", "1.0", Ordering::Greater),
+            ("1.10", "1.9", Ordering::Greater),
+            ("1.01", "1.1", Ordering::Equal),
+            ("v1.2", "1.2", Ordering::Equal),
+            ("1.0-rc1", "1.0", Ordering::Less),
+            ("1.0.0-rc1", "1.0", Ordering::Less),
+            ("1.0-rc2", "1.0-rc1", Ordering::Greater),
+            ("1.0-rc1", "0.9", Ordering::Greater),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(compare(a, b), expected, "{} vs {}", a, b);
+            assert_eq!(compare(b, a), expected.reverse(), "{} vs {}", b, a);
+        }
+    }
+
+    #[test]
+    fn constraints_match_sample_versions() {
+        let c = |op, v: &str| Constraint { op, version: v.to_string() };
+        let cases = [
+            (c(Op::Ge, "2.0"), "2.0", true),
+            (c(Op::Ge, "2.0"), "2.0.0", true),
+            (c(Op::Ge, "2.0.0"), "2.0", true),
+            (c(Op::Ge, "2.0"), "2.1", true),
+            (c(Op::Ge, "2.0"), "1.9.9", false),
+            (c(Op::Ge, "2.0"), "2.0-rc1", false),
+            (c(Op::Eq, "1.0"), "1.0.0", true),
+            (c(Op::Eq, "1.0"), "1.0.1", false),
+            (c(Op::Eq, "1.0"), "1.0-rc1", false),
+            (c(Op::Lt, "2.0"), "1.9", true),
+            (c(Op::Lt, "2.0"), "2.0-rc1", true),
+            (c(Op::Lt, "2.0"), "2.0.0", false),
+            (c(Op::Gt, "1.0"), "1.0.0", false),
+            (c(Op::Le, "1.0"), "1.0.0", true),
+        ];
+        for (constraint, version, expected) in cases {
+            assert_eq!(constraint.matches(version), expected, "{} against {}", version, constraint);
+        }
+    }
+
+    #[test]
+    fn client_version_gate() {
+        assert_eq!(
+            check_client_version("app", Some("999.0")).unwrap_err(),
+            format!("'app' requires nxpkg >= 999.0 (this is {}); upgrade nxpkg first.", crate::VERSION)
+        );
+        assert!(check_client_version("app", Some(crate::VERSION)).is_ok());
+        assert!(check_client_version("app", Some("0.0.1")).is_ok());
+        assert!(check_client_version("app", None).is_ok());
+    }
+}